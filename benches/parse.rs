@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: MIT
+
+//! Benchmarks of the hot paths on generated images of common flash sizes:
+//! finding the EFS, parsing the directories, reporting and diffing
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use romulan::amd::Rom;
+use romulan::patch;
+use romulan::report::Report;
+use romulan::testutil::AmdFixture;
+
+const SIZES: [usize; 3] = [0x100_0000, 0x200_0000, 0x400_0000];
+
+/// Image of `size` bytes with combo, level 2 and A/B recovery directories
+fn image(size: usize) -> Vec<u8> {
+    AmdFixture {
+        size,
+        combo: true,
+        level2: true,
+        ab_recovery: true,
+        ..AmdFixture::default()
+    }
+    .build()
+}
+
+fn label(size: usize) -> String {
+    format!("{}M", size >> 20)
+}
+
+fn efs_scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("efs_scan");
+    for size in SIZES.iter() {
+        let data = image(*size);
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(
+            BenchmarkId::new("aligned", label(*size)),
+            &data,
+            |b, data| b.iter(|| Rom::new(black_box(data)).unwrap().efs_offset()),
+        );
+        // Dropping the first byte moves the EFS off every aligned offset,
+        // so it is only found by the unaligned search
+        let carved = &data[1..];
+        group.bench_with_input(
+            BenchmarkId::new("unaligned", label(*size)),
+            carved,
+            |b, carved| b.iter(|| Rom::new(black_box(carved)).unwrap().efs_offset()),
+        );
+    }
+    group.finish();
+}
+
+fn directories(c: &mut Criterion) {
+    let mut group = c.benchmark_group("directories");
+    for size in SIZES.iter() {
+        let data = image(*size);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(label(*size)),
+            &data,
+            |b, data| b.iter(|| Rom::new(black_box(data)).unwrap().directories().len()),
+        );
+    }
+    group.finish();
+}
+
+fn report(c: &mut Criterion) {
+    let mut group = c.benchmark_group("report");
+    for size in SIZES.iter() {
+        let data = image(*size);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(label(*size)),
+            &data,
+            |b, data| b.iter(|| serde_json::to_string(&Report::new(black_box(data))).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("diff");
+    for size in SIZES.iter() {
+        let original = image(*size);
+        // A few scattered changes, as between two builds of one release
+        let mut edited = original.clone();
+        for offset in (0..*size).step_by(*size / 8) {
+            edited[offset + 0x123] ^= 0xFF;
+        }
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(label(*size)),
+            &(original, edited),
+            |b, (original, edited)| {
+                b.iter(|| patch::diff(black_box(original), black_box(edited)).unwrap())
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, efs_scan, directories, report, diff);
+criterion_main!(benches);
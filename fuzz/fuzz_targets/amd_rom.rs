@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use romulan::amd::walk::RomVisitor;
+
+struct Visitor;
+
+impl RomVisitor for Visitor {}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(rom) = romulan::amd::Rom::new(data) {
+        rom.directories();
+        rom.layout();
+        rom.verify();
+        rom.walk(&mut Visitor);
+        rom.extract_all();
+    }
+});
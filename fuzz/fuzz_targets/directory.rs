@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use romulan::amd::directory::Directory;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(directory) = Directory::new(data) {
+        // A parsed directory is stored as the bytes it was parsed from
+        let bytes = directory.to_bytes();
+        assert_eq!(bytes.len(), directory.size());
+        assert_eq!(&data[..bytes.len()], &bytes[..]);
+    }
+});
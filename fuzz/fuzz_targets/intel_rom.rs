@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(rom) = romulan::intel::Rom::new(data) {
+        let _ = romulan::report::IntelReport::new(&rom);
+        rom.verify();
+        rom.extract_all();
+    }
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use romulan::amd::directory::{DirectoryHeader, PspDirectory, PspDirectoryEntry};
+use zerocopy::AsBytes;
+
+fuzz_target!(|input: (DirectoryHeader, Vec<PspDirectoryEntry>)| {
+    let (mut header, entries) = input;
+    header.magic = u32::from_le_bytes(*b"$PSP");
+    header.entries = entries.len() as u32;
+    let mut data = header.as_bytes().to_vec();
+    for entry in entries.iter() {
+        data.extend_from_slice(entry.as_bytes());
+    }
+
+    let directory = PspDirectory::new(&data).expect("directory built from parts parses");
+    assert_eq!(directory.entries().len(), entries.len());
+    assert_eq!(directory.to_bytes(), data);
+});
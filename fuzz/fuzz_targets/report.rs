@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use romulan::identify::Database;
+
+// Everything the romulan tool prints of an image, of either vendor, wrapped
+// or not, such as truncated dumps read by flashrom
+fuzz_target!(|data: &[u8]| {
+    if let Ok(report) = romulan::report::Report::unwrapped(data, &Database::new()) {
+        let mut text = String::new();
+        let _ = report.render_verbose(&mut text);
+    }
+    romulan::tree::tree(data);
+    if let Ok(map) = romulan::coverage::CoverageMap::of(data) {
+        map.report(data);
+    }
+    romulan::summary::Summary::new(data);
+    romulan::audit::audit(data);
+    romulan::timeline::versions(data);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Sections are parsed from file data, GUID defined ones including their
+// encoded payload
+fuzz_target!(|data: &[u8]| {
+    romulan::report::sections(data, 0);
+});
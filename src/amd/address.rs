@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: MIT
+
+//! Addresses of directories and entries, either as offsets in the flash or
+//! memory mapped at the top of the 4G address space. Only up to 16M of the
+//! flash are mapped below 4G, by default the first 16M of larger flashes,
+//! which second generation SoCs also map whole above 4G. Images holding only
+//! a region of the flash start at an offset in it.
+
+use core::{fmt, ops::Range};
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// End of the window the flash is memory mapped to
+const MMIO_END: u64 = 0x1_0000_0000;
+
+/// Largest part of the flash mapped below 4G
+pub const MMIO_WINDOW_SIZE: usize = 0x100_0000;
+
+/// Address the whole flash is mapped to above 4G, in the ROM3 window of
+/// second generation SoCs
+pub const ROM3_BASE: u64 = 0xFD_0000_0000;
+
+/// Offset in an image, which is the offset in the flash for an image of the
+/// whole flash
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct FlashAddress(pub usize);
+
+/// Address the flash is read at by the CPU, the last byte of the flash being
+/// mapped to 0xFFFF_FFFF
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct MmioAddress(pub u64);
+
+/// Mask of the address bits selecting a byte of a flash of `size` bytes,
+/// rounded up to a power of two
+pub fn flash_mask(size: usize) -> u64 {
+    size.next_power_of_two() as u64 - 1
+}
+
+/// Translates the addresses stored in the EFS and in directories into image
+/// offsets and back for a flash of a given size
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AddrTranslator {
+    flash_size: usize,
+    window_offset: usize,
+    image_offset: usize,
+}
+
+impl AddrTranslator {
+    /// Translator of an image of the whole flash, whose first 16M are
+    /// mapped below 4G
+    pub fn new(flash_size: usize) -> Self {
+        Self::with_mapping(flash_size, 0, 0)
+    }
+
+    /// Translator of a flash of which the 16M at `window_offset` are mapped
+    /// below 4G, for an image starting at `image_offset` in the flash
+    pub fn with_mapping(flash_size: usize, window_offset: usize, image_offset: usize) -> Self {
+        AddrTranslator {
+            flash_size,
+            window_offset,
+            image_offset,
+        }
+    }
+
+    pub fn flash_size(&self) -> usize {
+        self.flash_size
+    }
+
+    /// Offset in the flash of the part mapped below 4G
+    pub fn window_offset(&self) -> usize {
+        self.window_offset
+    }
+
+    /// Offset in the flash the image starts at
+    pub fn image_offset(&self) -> usize {
+        self.image_offset
+    }
+
+    /// Size of the part of the flash mapped below 4G
+    pub fn window_size(&self) -> usize {
+        self.flash_size.min(MMIO_WINDOW_SIZE)
+    }
+
+    /// Converts an address, memory mapped below 4G or in the ROM3 window or
+    /// a flash offset, into an image offset. Other addresses, such as those
+    /// memory mapped for a flash of another size, have the bits above the
+    /// flash size dropped. Addresses of the flash before the image resolve
+    /// to the end of the flash, past the end of the image.
+    pub fn offset(&self, address: u64) -> FlashAddress {
+        let window = MMIO_END - self.window_size() as u64..MMIO_END;
+        let rom3 = ROM3_BASE..ROM3_BASE + self.flash_size as u64;
+        let flash = if window.contains(&address) {
+            self.window_offset + (address - window.start) as usize
+        } else if rom3.contains(&address) {
+            (address - ROM3_BASE) as usize
+        } else {
+            (address & flash_mask(self.flash_size)) as usize
+        };
+        FlashAddress(
+            flash
+                .checked_sub(self.image_offset)
+                .unwrap_or(self.flash_size),
+        )
+    }
+
+    /// Address below 4G an image offset is memory mapped to
+    pub fn mmio(&self, offset: FlashAddress) -> Result<MmioAddress, Error> {
+        let window = MmioAddress::window(self.flash_size)?;
+        let mapped = offset
+            .0
+            .checked_add(self.image_offset)
+            .and_then(|flash| flash.checked_sub(self.window_offset))
+            .filter(|mapped| (*mapped as u64) < window.end - window.start)
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "offset {} outside of the {:#X} bytes of flash memory mapped",
+                    offset,
+                    self.window_size()
+                ))
+            })?;
+        Ok(MmioAddress(window.start + mapped as u64))
+    }
+}
+
+impl FlashAddress {
+    /// Converts an address as stored in the EFS or a directory, memory
+    /// mapped or a flash offset, as `AddrTranslator::offset` does
+    pub fn from_raw(address: u64, flash_size: usize) -> Self {
+        AddrTranslator::new(flash_size).offset(address)
+    }
+
+    /// Memory mapped address of the offset in a flash of `flash_size` bytes
+    pub fn to_mmio(self, flash_size: usize) -> Result<MmioAddress, Error> {
+        AddrTranslator::new(flash_size).mmio(self)
+    }
+
+    /// Range of `size` bytes at the offset, if it lies within an image of
+    /// `image_size` bytes
+    pub fn range(self, size: usize, image_size: usize) -> Result<Range<usize>, Error> {
+        match self.0.checked_add(size) {
+            Some(end) if end <= image_size => Ok(self.0..end),
+            _ => Err(Error::OutOfBounds {
+                offset: self.0,
+                size,
+                have: image_size,
+            }),
+        }
+    }
+}
+
+impl MmioAddress {
+    /// Addresses a flash of `flash_size` bytes is memory mapped to below
+    /// 4G, its first 16M at most. Flash sizes are powers of two.
+    pub fn window(flash_size: usize) -> Result<Range<u64>, Error> {
+        if !flash_size.is_power_of_two() {
+            return Err(Error::InvalidArgument(format!(
+                "flash size {:#X} is not a power of two",
+                flash_size
+            )));
+        }
+        let size = flash_size.min(MMIO_WINDOW_SIZE) as u64;
+        Ok(MMIO_END - size..MMIO_END)
+    }
+
+    /// Offset in a flash of `flash_size` bytes the address is mapped to
+    pub fn to_flash(self, flash_size: usize) -> Result<FlashAddress, Error> {
+        let window = Self::window(flash_size)?;
+        if !window.contains(&self.0) {
+            return Err(Error::InvalidArgument(format!(
+                "address {} outside of flash of {:#X} bytes",
+                self, flash_size
+            )));
+        }
+        Ok(FlashAddress((self.0 - window.start) as usize))
+    }
+}
+
+impl fmt::Display for FlashAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#X}", self.0)
+    }
+}
+
+impl fmt::Display for MmioAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#X}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_requires_a_power_of_two() {
+        assert_eq!(
+            MmioAddress::window(0x80_0000),
+            Ok(0xFF80_0000..0x1_0000_0000)
+        );
+        assert_eq!(
+            MmioAddress::window(0x200_0000),
+            Ok(0xFF00_0000..0x1_0000_0000)
+        );
+        for size in [0, 0x30_0000] {
+            assert!(matches!(
+                MmioAddress::window(size),
+                Err(Error::InvalidArgument(_))
+            ));
+            assert!(MmioAddress(0xFFFF_F000).to_flash(size).is_err());
+        }
+        assert_eq!(
+            MmioAddress(0xFFFF_F000).to_flash(0x100_0000),
+            Ok(FlashAddress(0xFF_F000))
+        );
+    }
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: MIT
+
+//! Rebuilding of a parsed image through a series of edits, each applied to
+//! the image the edits before it left
+
+use alloc::vec::Vec;
+
+use super::platform::Platform;
+use super::Rom;
+use crate::Error;
+
+/// Image being rebuilt from a parsed image. Every edit parses the image as
+/// the edits before it left it, so directories and entries are addressed
+/// by their offsets and indexes after those edits.
+///
+/// ```ignore
+/// let image = RomBuilder::new(&rom)
+///     .replace_entry(0x22000, 3, &smu_firmware)?
+///     .delete_entry(0x24000, 5)?
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct RomBuilder {
+    image: Vec<u8>,
+    efs_offset: usize,
+    platform: Platform,
+}
+
+impl RomBuilder {
+    /// Starts from a copy of the image of `rom`, parsed from its EFS for
+    /// its platform
+    pub fn new(rom: &Rom) -> Self {
+        RomBuilder {
+            image: rom.data().to_vec(),
+            efs_offset: rom.efs_offset(),
+            platform: rom.platform(),
+        }
+    }
+
+    /// The image as edited so far
+    pub fn rom(&self) -> Result<Rom<'_>, Error> {
+        Rom::with_efs_and_platform(&self.image, self.efs_offset, self.platform)
+    }
+
+    /// Applies an edit of `Rom` returning the modified image
+    pub fn edit<F>(mut self, edit: F) -> Result<Self, Error>
+    where
+        F: FnOnce(&Rom) -> Result<Vec<u8>, Error>,
+    {
+        let image = edit(&self.rom()?)?;
+        self.image = image;
+        Ok(self)
+    }
+
+    /// Replaces the data of an entry, as `Rom::replace_entry` does
+    pub fn replace_entry(self, directory: usize, index: usize, data: &[u8]) -> Result<Self, Error> {
+        self.edit(|rom| rom.replace_entry(directory, index, data))
+    }
+
+    /// Removes an entry, as `Rom::delete_entry` does
+    pub fn delete_entry(self, directory: usize, index: usize) -> Result<Self, Error> {
+        self.edit(|rom| rom.delete_entry(directory, index))
+    }
+
+    /// Moves the data of an entry, as `Rom::move_entry` does
+    pub fn move_entry(self, directory: usize, index: usize, offset: usize) -> Result<Self, Error> {
+        self.edit(|rom| rom.move_entry(directory, index, offset))
+    }
+
+    /// Moves a directory, as `Rom::move_directory` does
+    pub fn move_directory(self, from: usize, to: usize) -> Result<Self, Error> {
+        self.edit(|rom| rom.move_directory(from, to))
+    }
+
+    /// Sets or clears a Soft Fuse Chain bit, as `Rom::set_soft_fuse_bit`
+    /// does
+    pub fn set_soft_fuse_bit(self, bit: u8, value: bool) -> Result<Self, Error> {
+        self.edit(|rom| rom.set_soft_fuse_bit(bit, value))
+    }
+
+    /// The rebuilt image
+    pub fn build(self) -> Vec<u8> {
+        self.image
+    }
+}
@@ -0,0 +1,692 @@
+// SPDX-License-Identifier: MIT
+
+//! Differences between the EFS and the directories of two images. Directories
+//! are matched by the path they are reached by from the EFS, entries by their
+//! type, sub program and instance, so that images of different layouts can
+//! be compared.
+//!
+//! The directories of a combo directory are reached by the ID of their combo
+//! entry, each ID having them even if they are shared with another ID. When
+//! only one image has a combo directory, the PSP or BIOS directory of the
+//! other image is compared with each directory of the combo directory.
+//!
+//! Directories that fail to parse, such as blank or truncated A/B recovery
+//! directories, are left out of the comparison and listed as errors.
+//!
+//! `compare` reports every directory instead, sorting all of its entries,
+//! for callers presenting the comparison their own way.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use serde::{Deserialize, Serialize};
+
+use super::directory::{
+    BiosDirectoryEntry, Checksum, ComboDirectoryEntry, Directory, PspDirectoryEntry,
+};
+use super::walk::RomVisitor;
+use super::{Rom, DIR_UNSET};
+use crate::cbfs;
+use crate::compare::Fingerprint;
+use crate::extract::join;
+use crate::report::EntryReport;
+use crate::Error;
+
+/// EFS field whose value differs between the images
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EfsDiff {
+    pub field: String,
+    pub before: u32,
+    pub after: u32,
+}
+
+/// An entry added, changed or removed between the images. Added entries are
+/// not before, removed ones not after.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EntryDiff {
+    pub before: Option<EntryReport>,
+    pub after: Option<EntryReport>,
+}
+
+/// A directory of either image that differs from the other image, is only
+/// in one of them, or has a checksum that is invalid
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DirectoryDiff {
+    /// Names of the directories above the directory and its own, separated
+    /// by slashes, each name of a directory an entry points to following
+    /// the type of the entry or, for a combo entry, its ID
+    pub path: String,
+    /// Offset of the directory in the image before
+    pub before: Option<usize>,
+    /// Offset of the directory in the image after
+    pub after: Option<usize>,
+    pub before_checksum: Option<Checksum>,
+    pub after_checksum: Option<Checksum>,
+    pub entries: Vec<EntryDiff>,
+}
+
+impl DirectoryDiff {
+    /// True if the directory is in both images, unchanged and with valid
+    /// checksums. Directories may move without changing.
+    pub fn is_unchanged(&self) -> bool {
+        let valid = |checksum: &Option<Checksum>| checksum.is_some_and(|c| c.is_valid());
+        valid(&self.before_checksum) && valid(&self.after_checksum) && self.entries.is_empty()
+    }
+}
+
+/// A directory of either image that fails to parse and is not compared
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DirectoryError {
+    /// Path of the directory, named by the entry pointing to it
+    pub path: String,
+    /// Offset of the directory in the image before
+    pub before: Option<usize>,
+    /// Offset of the directory in the image after
+    pub after: Option<usize>,
+    pub error: String,
+}
+
+/// Differences between two images
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RomDiff {
+    pub efs: Vec<EfsDiff>,
+    /// Directories that differ, in the order they are reached in the image
+    /// before, those only in the image after last
+    pub directories: Vec<DirectoryDiff>,
+    /// CBFS files that differ, for images with a flash map
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cbfs: Vec<cbfs::FileDiff>,
+    /// Directories that fail to parse, those of the image before first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<DirectoryError>,
+}
+
+impl RomDiff {
+    pub fn is_empty(&self) -> bool {
+        self.efs.is_empty() && self.directories.is_empty() && self.cbfs.is_empty()
+    }
+}
+
+/// Compares the EFS pointers, the directories and the CBFS files of the
+/// images
+pub fn diff(before: &Rom, after: &Rom) -> RomDiff {
+    let cbfs = cbfs::diff(before.data(), after.data());
+    let before_fields = before.efs().pointer_fields();
+    let after_fields = after.efs().pointer_fields();
+    // Fields reserved in a first generation EFS compare as unset
+    let pointer = |fields: &[(&str, u32)], i: usize| fields.get(i).map_or(DIR_UNSET, |f| f.1);
+    let names = if before_fields.len() >= after_fields.len() {
+        &before_fields
+    } else {
+        &after_fields
+    };
+    let efs = names
+        .iter()
+        .enumerate()
+        .map(|(i, (field, _))| (field, pointer(&before_fields, i), pointer(&after_fields, i)))
+        .filter(|(_, before, after)| before != after)
+        .map(|(field, before, after)| EfsDiff {
+            field: String::from(*field),
+            before,
+            after,
+        })
+        .collect();
+
+    let before = Collector::collect(before);
+    let after = Collector::collect(after);
+    let errors = before
+        .errors
+        .into_iter()
+        .map(|(path, offset, error)| DirectoryError {
+            path,
+            before: Some(offset),
+            after: None,
+            error,
+        })
+        .chain(
+            after
+                .errors
+                .into_iter()
+                .map(|(path, offset, error)| DirectoryError {
+                    path,
+                    before: None,
+                    after: Some(offset),
+                    error,
+                }),
+        )
+        .collect();
+    let mut directories: Vec<DirectoryDiff> = pair(before.directories, after.directories)
+        .into_iter()
+        .map(|(before, after)| diff_directory(before, after))
+        .collect();
+    directories.retain(|directory| !directory.is_unchanged());
+    RomDiff {
+        efs,
+        directories,
+        cbfs,
+        errors,
+    }
+}
+
+fn diff_directory(before: Option<Visited>, after: Option<Visited>) -> DirectoryDiff {
+    let path = before
+        .as_ref()
+        .or(after.as_ref())
+        .map(|visited| visited.path.clone())
+        .unwrap_or_default();
+    let (before_offset, before_checksum, before) = match before {
+        Some(visited) => (
+            Some(visited.offset),
+            Some(visited.checksum),
+            visited.entries,
+        ),
+        None => (None, None, Vec::new()),
+    };
+    let (after_offset, after_checksum, after) = match after {
+        Some(visited) => (
+            Some(visited.offset),
+            Some(visited.checksum),
+            visited.entries,
+        ),
+        None => (None, None, Vec::new()),
+    };
+
+    let entries = entries(before, after)
+        .into_iter()
+        .filter(|pair| !matches!(pair, (Some(old), Some(new)) if new.is_same(old)))
+        .map(|(old, new)| EntryDiff {
+            before: old.map(|old| old.report),
+            after: new.map(|new| new.report),
+        })
+        .collect();
+    DirectoryDiff {
+        path,
+        before: before_offset,
+        after: after_offset,
+        before_checksum,
+        after_checksum,
+        entries,
+    }
+}
+
+/// An entry of image A and the matching entry of image B
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EntryPair {
+    pub a: EntryReport,
+    pub b: EntryReport,
+}
+
+/// A directory of either image and how its entries compare. Unlike
+/// `DirectoryDiff`, which keeps what differs, every entry is sorted into
+/// those unchanged, those only in one image and those changed.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DiffReport {
+    /// Path of the directory, as in `DirectoryDiff`
+    pub path: String,
+    /// Offset of the directory in image A
+    pub a: Option<usize>,
+    /// Offset of the directory in image B
+    pub b: Option<usize>,
+    pub checksum_a: Option<Checksum>,
+    pub checksum_b: Option<Checksum>,
+    /// Entries holding the same data in both images, which may have moved
+    pub common: Vec<EntryPair>,
+    pub only_a: Vec<EntryReport>,
+    pub only_b: Vec<EntryReport>,
+    pub changed: Vec<EntryPair>,
+}
+
+impl DiffReport {
+    /// True if the directory is in both images with the same entries
+    pub fn is_unchanged(&self) -> bool {
+        self.a.is_some()
+            && self.b.is_some()
+            && self.only_a.is_empty()
+            && self.only_b.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+/// Compares every directory of the images, entry by entry, in the order
+/// they are reached in image A, those only in image B last
+pub fn compare(a: &Rom, b: &Rom) -> Vec<DiffReport> {
+    directories(a, b)
+        .into_iter()
+        .map(|(a, b)| compare_directory(a, b))
+        .collect()
+}
+
+fn compare_directory(a: Option<Visited>, b: Option<Visited>) -> DiffReport {
+    let mut report = DiffReport {
+        path: a
+            .as_ref()
+            .or(b.as_ref())
+            .map(|visited| visited.path.clone())
+            .unwrap_or_default(),
+        a: a.as_ref().map(|visited| visited.offset),
+        b: b.as_ref().map(|visited| visited.offset),
+        checksum_a: a.as_ref().map(|visited| visited.checksum),
+        checksum_b: b.as_ref().map(|visited| visited.checksum),
+        common: Vec::new(),
+        only_a: Vec::new(),
+        only_b: Vec::new(),
+        changed: Vec::new(),
+    };
+    let a = a.map(|visited| visited.entries).unwrap_or_default();
+    let b = b.map(|visited| visited.entries).unwrap_or_default();
+    for pair in entries(a, b) {
+        match pair {
+            (Some(a), Some(b)) if a.is_same(&b) => report.common.push(EntryPair {
+                a: a.report,
+                b: b.report,
+            }),
+            (Some(a), Some(b)) => report.changed.push(EntryPair {
+                a: a.report,
+                b: b.report,
+            }),
+            (Some(a), None) => report.only_a.push(a.report),
+            (None, Some(b)) => report.only_b.push(b.report),
+            (None, None) => (),
+        }
+    }
+    report
+}
+
+/// Directories of both images paired by path, in the order they are
+/// reached in the image before, those only in the image after last
+fn directories(before: &Rom, after: &Rom) -> Vec<(Option<Visited>, Option<Visited>)> {
+    pair(
+        Collector::collect(before).directories,
+        Collector::collect(after).directories,
+    )
+}
+
+/// Directories of both images paired by path, see `directories`
+fn pair(
+    mut before: Vec<Visited>,
+    mut after: Vec<Visited>,
+) -> Vec<(Option<Visited>, Option<Visited>)> {
+    for (combo, name) in COMBOS {
+        let (expanded_before, expanded_after) = (
+            expand(&before, &after, combo, name),
+            expand(&after, &before, combo, name),
+        );
+        before = expanded_before.unwrap_or(before);
+        after = expanded_after.unwrap_or(after);
+    }
+    let mut pairs = Vec::new();
+    for old in before {
+        let new = after
+            .iter()
+            .position(|new| new.path == old.path)
+            .map(|i| after.remove(i));
+        pairs.push((Some(old), new));
+    }
+    pairs.extend(after.into_iter().map(|new| (None, Some(new))));
+    pairs
+}
+
+/// Combo directories and the directories their entries point to
+const COMBOS: [(&str, &str); 2] = [
+    ("PSP Combo Directory", "PSP Directory"),
+    ("BIOS Combo Directory", "BIOS Directory"),
+];
+
+/// Directories of an image without the combo directory `combo` but with a
+/// directory `name` reached from the EFS, when the other image has the combo
+/// directory and not the directory. The directory and those below it are
+/// put under every directory `name` of the combo directory.
+fn expand(
+    directories: &[Visited],
+    other: &[Visited],
+    combo: &str,
+    name: &str,
+) -> Option<Vec<Visited>> {
+    let has_root = |directories: &[Visited], root: &str| {
+        directories.iter().any(|directory| directory.path == root)
+    };
+    if has_root(directories, combo) || !has_root(directories, name) {
+        return None;
+    }
+    if !has_root(other, combo) || has_root(other, name) {
+        return None;
+    }
+    // Paths of the directories of the combo directory
+    let prefix = format!("{}/", combo);
+    let targets: Vec<&str> = other
+        .iter()
+        .filter(|directory| directory.name == name)
+        .map(|directory| directory.path.as_str())
+        .filter(|path| {
+            path.strip_prefix(&prefix)
+                .is_some_and(|rest| !rest.contains('/'))
+        })
+        .collect();
+    if targets.is_empty() {
+        return None;
+    }
+
+    let below = format!("{}/", name);
+    let in_tree =
+        |directory: &Visited| directory.path == name || directory.path.starts_with(&below);
+    let position = directories.iter().position(in_tree)?;
+    let mut expanded: Vec<Visited> = directories
+        .iter()
+        .filter(|directory| !in_tree(directory))
+        .cloned()
+        .collect();
+    let tree = targets.iter().flat_map(|target| {
+        directories
+            .iter()
+            .filter(|directory| in_tree(directory))
+            .map(move |directory| Visited {
+                path: format!("{}{}", target, &directory.path[name.len()..]),
+                ..directory.clone()
+            })
+    });
+    expanded.splice(position..position, tree);
+    Some(expanded)
+}
+
+/// Entries of two directories paired by key, in the order of the directory
+/// before, those only in the directory after last
+fn entries(
+    before: Vec<VisitedEntry>,
+    mut after: Vec<VisitedEntry>,
+) -> Vec<(Option<VisitedEntry>, Option<VisitedEntry>)> {
+    let mut pairs = Vec::new();
+    for old in before {
+        let new = after
+            .iter()
+            .position(|new| new.key == old.key)
+            .map(|i| after.remove(i));
+        pairs.push((Some(old), new));
+    }
+    pairs.extend(after.into_iter().map(|new| (None, Some(new))));
+    pairs
+}
+
+/// Entry of a visited directory
+#[derive(Clone)]
+struct VisitedEntry {
+    /// Type, sub program, instance and how many entries of them come before
+    key: (u8, u8, u8, usize),
+    report: EntryReport,
+    /// Fingerprint of the data of the entry
+    fingerprint: Option<Fingerprint>,
+    /// Value a PSP entry holds instead of data, or destination of a BIOS
+    /// entry
+    value: u64,
+}
+
+impl VisitedEntry {
+    /// True if the entries hold the same data, which may have moved
+    fn is_same(&self, other: &VisitedEntry) -> bool {
+        self.fingerprint == other.fingerprint
+            && self.value == other.value
+            && self.report.size == other.report.size
+            && self.report.version == other.report.version
+    }
+}
+
+#[derive(Clone)]
+struct Visited {
+    path: String,
+    /// Name of the kind of directory
+    name: &'static str,
+    offset: usize,
+    checksum: Checksum,
+    entries: Vec<VisitedEntry>,
+}
+
+/// Collects the directories of an image with their paths
+struct Collector {
+    directories: Vec<Visited>,
+    /// Path, offset and error of the directories that fail to parse
+    errors: Vec<(String, usize, String)>,
+    /// Indexes of the directories being visited, innermost last
+    stack: Vec<usize>,
+    /// Offset of the directory the last entry visited points to, and how it
+    /// is named in the path
+    pointer: Option<(usize, String)>,
+}
+
+impl Collector {
+    fn collect(rom: &Rom) -> Collector {
+        let mut collector = Collector {
+            directories: Vec::new(),
+            errors: Vec::new(),
+            stack: Vec::new(),
+            pointer: None,
+        };
+        rom.walk(&mut collector);
+        collector
+    }
+
+    /// Path of the directory at `offset` about to be visited, from the
+    /// parent directory and the entry pointing to it, if it was the last
+    /// entry visited
+    fn path(&mut self, offset: usize, name: &str) -> String {
+        let pointer = self.pointer.take();
+        let parent = self
+            .stack
+            .last()
+            .map(|i| self.directories[*i].path.as_str())
+            .unwrap_or("");
+        let name = match pointer {
+            Some((pointed, label)) if pointed == offset && !parent.is_empty() => {
+                format!("{} {}", label, name)
+            }
+            _ => String::from(name),
+        };
+        join(parent, &name)
+    }
+
+    fn push_entry(
+        &mut self,
+        directory: usize,
+        report: EntryReport,
+        fingerprint: Option<Fingerprint>,
+        value: u64,
+    ) {
+        let visited = match self.stack.last() {
+            Some(i) if self.directories[*i].offset == directory => &mut self.directories[*i],
+            _ => return,
+        };
+        let (kind, sub_program, instance) = (report.kind, report.sub_program, report.instance);
+        let count = visited
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.key.0 == kind && entry.key.1 == sub_program && entry.key.2 == instance
+            })
+            .count();
+        visited.entries.push(VisitedEntry {
+            key: (kind, sub_program, instance, count),
+            report,
+            fingerprint,
+            value,
+        });
+    }
+}
+
+impl RomVisitor for Collector {
+    fn visit_directory(&mut self, offset: usize, _depth: usize, directory: &Directory) {
+        let path = self.path(offset, directory.name());
+        self.stack.push(self.directories.len());
+        self.directories.push(Visited {
+            path,
+            name: directory.name(),
+            offset,
+            checksum: directory.verify_checksum(),
+            entries: Vec::new(),
+        });
+    }
+
+    fn leave_directory(&mut self, _offset: usize, _depth: usize, _directory: &Directory) {
+        self.stack.pop();
+    }
+
+    fn visit_error(&mut self, offset: usize, _depth: usize, err: &Error) {
+        let path = self.path(offset, "Directory");
+        self.errors.push((path, offset, err.to_string()));
+    }
+
+    fn visit_psp_entry(
+        &mut self,
+        directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if let (true, Some(offset)) = (entry.is_directory(), offset) {
+            self.pointer = Some((offset, format!("{:02X}", entry.kind)));
+        }
+        let value = match entry.size {
+            0xFFFF_FFFF => entry.value,
+            _ => 0,
+        };
+        // Level 2 directories are compared on their own
+        let fingerprint = data.filter(|_| !entry.is_directory()).map(Fingerprint::of);
+        let report = EntryReport::psp(entry, offset, data);
+        self.push_entry(directory, report, fingerprint, value);
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        directory: usize,
+        entry: &BiosDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if let (0x70, Some(offset)) = (entry.kind, offset) {
+            self.pointer = Some((offset, format!("{:02X}", entry.kind)));
+        }
+        let report = EntryReport::bios(entry, offset, data);
+        let fingerprint = data.filter(|_| entry.kind != 0x70).map(Fingerprint::of);
+        self.push_entry(directory, report, fingerprint, entry.destination);
+    }
+
+    fn visit_combo_entry(
+        &mut self,
+        _directory: usize,
+        entry: &ComboDirectoryEntry,
+        offset: Option<usize>,
+    ) {
+        let offset = match offset {
+            Some(offset) => offset,
+            None => return,
+        };
+        let label = format!("{:08X}", entry.id);
+        // A directory shared with an earlier ID is not visited again, so the
+        // directories reached through it are copied under this ID
+        let shared = match self
+            .directories
+            .iter()
+            .find(|visited| visited.offset == offset)
+        {
+            Some(shared) => shared,
+            None => {
+                self.pointer = Some((offset, label));
+                return;
+            }
+        };
+        let parent = match self.stack.last() {
+            Some(i) => self.directories[*i].path.clone(),
+            None => return,
+        };
+        let from = shared.path.clone();
+        let to = join(&parent, &format!("{} {}", label, shared.name));
+        let below = format!("{}/", from);
+        let copies: Vec<Visited> = self
+            .directories
+            .iter()
+            .filter(|visited| visited.path == from || visited.path.starts_with(&below))
+            .map(|visited| Visited {
+                path: format!("{}{}", to, &visited.path[from.len()..]),
+                ..visited.clone()
+            })
+            .collect();
+        self.directories.extend(copies);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amd::directory::Directory;
+    use crate::testutil::{AmdFixture, PSP_OFFSET};
+
+    /// Fixture whose SMU firmware, the last PSP entry, differs in one byte
+    fn images() -> (Vec<u8>, Vec<u8>) {
+        let a = AmdFixture::default().build();
+        let rom = Rom::new(&a).unwrap();
+        let smu = match rom.directory(PSP_OFFSET).unwrap() {
+            Directory::Psp(dir) => dir.entries()[3],
+            directory => panic!("{} at {:#X}", directory.name(), PSP_OFFSET),
+        };
+        let start = rom.resolve_address(PSP_OFFSET, smu.value).unwrap();
+        let mut b = a.clone();
+        b[start + 0x200] ^= 0xFF;
+        (a, b)
+    }
+
+    #[test]
+    fn compare_sorts_entries() {
+        let (a, b) = images();
+        let reports = compare(&Rom::new(&a).unwrap(), &Rom::new(&b).unwrap());
+        let psp = reports
+            .iter()
+            .find(|report| report.path == "PSP Directory")
+            .unwrap();
+        assert_eq!(psp.a, Some(PSP_OFFSET));
+        assert_eq!(psp.b, Some(PSP_OFFSET));
+        assert_eq!(psp.common.len(), 3);
+        assert!(psp.only_a.is_empty() && psp.only_b.is_empty());
+        assert_eq!(psp.changed.len(), 1);
+        assert_eq!(psp.changed[0].a.kind, 0x08);
+        assert!(!psp.is_unchanged());
+        assert!(reports
+            .iter()
+            .filter(|report| report.path != "PSP Directory")
+            .all(|report| report.is_unchanged()));
+    }
+
+    #[test]
+    fn compare_reports_entries_of_one_image() {
+        let (a, _) = images();
+        let b = Rom::new(&a).unwrap().delete_entry(PSP_OFFSET, 1).unwrap();
+        let reports = compare(&Rom::new(&a).unwrap(), &Rom::new(&b).unwrap());
+        let psp = &reports[0];
+        assert_eq!(psp.path, "PSP Directory");
+        assert_eq!(psp.only_a.len(), 1);
+        assert_eq!(psp.only_a[0].kind, 0x01);
+        assert!(psp.only_b.is_empty() && psp.changed.is_empty());
+    }
+
+    #[test]
+    fn diff_keeps_changed_entries() {
+        let (a, b) = images();
+        let diff = diff(&Rom::new(&a).unwrap(), &Rom::new(&b).unwrap());
+        assert!(diff.efs.is_empty());
+        assert_eq!(diff.directories.len(), 1);
+        let entries = &diff.directories[0].entries;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].before.as_ref().map(|entry| entry.kind),
+            Some(0x08)
+        );
+        assert_eq!(
+            entries[0].after.as_ref().map(|entry| entry.kind),
+            Some(0x08)
+        );
+    }
+}
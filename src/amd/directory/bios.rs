@@ -1,11 +1,19 @@
-use alloc::{boxed::Box, string::String, vec::Vec};
+use alloc::{borrow::Cow, vec::Vec};
 use core::mem;
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
 use zerocopy::{AsBytes, FromBytes, LayoutVerified as LV};
 
-use super::{ComboDirectoryEntry, ComboDirectoryHeader, DirectoryHeader};
+use super::{
+    check_signature, AddressMode, Checksum, ComboDirectoryEntry, ComboDirectoryHeader,
+    DirectoryHeader, PspBinaryHeader,
+};
+use crate::amd::address::{AddrTranslator, FlashAddress};
+use crate::amd::Rom;
+use crate::Error;
 
-#[derive(AsBytes, FromBytes, Clone, Copy, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug, Deserialize)]
 #[repr(C)]
 pub struct BiosDirectoryEntry {
     /// 0x00: type of entry
@@ -25,23 +33,53 @@ pub struct BiosDirectoryEntry {
 }
 
 impl BiosDirectoryEntry {
-    pub fn data(&self, data: &[u8]) -> Result<Box<[u8]>, String> {
-        let start = (self.source & 0x1FFFFFF) as usize;
-        let end = start + self.size as usize;
-        if end <= data.len() {
-            Ok(data[start..end].to_vec().into_boxed_slice())
-        } else {
-            Err(format!(
-                "BIOS directory entry invalid: {:08X}:{:08X}",
-                start, end
-            ))
-        }
+    /// Flash offset of the data of the entry, unless its address is
+    /// relative to the directory
+    pub fn addr(&self, translator: &AddrTranslator) -> FlashAddress {
+        translator.offset(self.source & AddressMode::ADDRESS_MASK)
+    }
+
+    /// Data of the entry in the image of `rom`, borrowed from it.
+    /// `directory` is the offset of the directory containing the entry.
+    pub fn data<'d>(&self, rom: &Rom<'d>, directory: usize) -> Result<&'d [u8], Error> {
+        let data = rom.data();
+        let range = FlashAddress(rom.resolve_address(directory, self.source)?)
+            .range(self.size as usize, data.len())?;
+        Ok(&data[range])
     }
 
     pub fn instance(&self) -> u8 {
         (self.flags >> 4) & 0xF
     }
 
+    /// Whether the entry is flagged as compressed, which the BIOS binary
+    /// (0x62) usually is
+    pub fn is_compressed(&self) -> bool {
+        self.flags & 0x08 != 0
+    }
+
+    /// Data of the entry in the image of `rom`, decompressed. None if the
+    /// data holds no zlib stream.
+    #[cfg(feature = "zlib")]
+    pub fn decompress(&self, rom: &Rom, directory: usize) -> Option<Result<Vec<u8>, Error>> {
+        match self.data(rom, directory) {
+            Ok(data) => zlib_stream(data).map(inflate),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    /// Describes the memory region security attributes of `region_kind`,
+    /// which tell how the PSP protects the memory the entry is copied to
+    pub fn region_description(&self) -> &'static str {
+        match self.region_kind {
+            0x00 => "Normal memory",
+            0x01 => "TA1 secure memory",
+            0x02 => "TA2 secure memory",
+            0x03 => "SMM code memory",
+            _ => "Reserved",
+        }
+    }
+
     pub fn description(&self) -> &'static str {
         match self.kind {
             0x05 => "BIOS Signing Key",
@@ -76,74 +114,193 @@ impl BiosDirectoryEntry {
     }
 }
 
+/// Largest size data is decompressed to, against corrupt streams
+#[cfg(feature = "zlib")]
+pub const MAX_DECOMPRESSED_SIZE: usize = 0x400_0000;
+
+/// Zlib stream in the data of an entry: the body following the header of a
+/// compressed PSP binary, such as the BIOS binary (0x62), or the data itself
+/// if it starts with a zlib header
+pub fn zlib_stream(data: &[u8]) -> Option<&[u8]> {
+    if let Some(header) = PspBinaryHeader::new(data) {
+        if header.magic == u32::from_le_bytes(*b"$PS1") && header.compressed == 1 {
+            let start = mem::size_of::<PspBinaryHeader>();
+            let end = match header.compressed_size as usize {
+                0 => data.len(),
+                size => data.len().min(start + size),
+            };
+            return data.get(start..end);
+        }
+    }
+    match data {
+        [cmf, flg, ..] if cmf & 0x0F == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 => {
+            Some(data)
+        }
+        _ => None,
+    }
+}
+
+/// Payload of entry data, decompressed if it holds a zlib stream. None if
+/// the stream does not decompress, or cannot without the `zlib` feature.
+pub fn payload(data: &[u8]) -> Option<Cow<'_, [u8]>> {
+    match zlib_stream(data) {
+        #[cfg(feature = "zlib")]
+        Some(stream) => inflate(stream).ok().map(Cow::Owned),
+        #[cfg(not(feature = "zlib"))]
+        Some(_) => None,
+        None => Some(Cow::Borrowed(data)),
+    }
+}
+
+/// Decompresses a zlib stream
+#[cfg(feature = "zlib")]
+pub fn inflate(stream: &[u8]) -> Result<Vec<u8>, Error> {
+    miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(stream, MAX_DECOMPRESSED_SIZE)
+        .map_err(|err| Error::Invalid(format!("zlib stream invalid: {:?}", err)))
+}
+
+/// Serialized with `region`, the description of `region_kind`, following
+/// the fields. It is ignored when deserializing.
+impl Serialize for BiosDirectoryEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entry = serializer.serialize_struct("BiosDirectoryEntry", 8)?;
+        entry.serialize_field("kind", &self.kind)?;
+        entry.serialize_field("region_kind", &self.region_kind)?;
+        entry.serialize_field("flags", &self.flags)?;
+        entry.serialize_field("sub_program", &self.sub_program)?;
+        entry.serialize_field("size", &self.size)?;
+        entry.serialize_field("source", &self.source)?;
+        entry.serialize_field("destination", &self.destination)?;
+        entry.serialize_field("region", self.region_description())?;
+        entry.end()
+    }
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct BiosDirectory {
+pub struct BiosDirectory<'a> {
     header: DirectoryHeader,
-    entries: Vec<BiosDirectoryEntry>,
+    entries: Cow<'a, [BiosDirectoryEntry]>,
 }
 
-impl<'a> BiosDirectory {
-    pub fn new(data: &'a [u8]) -> Result<Self, String> {
-        if &data[..4] == b"$BHD" || &data[..4] == b"$BL2" {
-            let header =
-                DirectoryHeader::read_from_prefix(data).ok_or("BIOS directory header invalid")?;
-
-            let hs = mem::size_of::<DirectoryHeader>();
-            let (entries, _) = LV::<_, [BiosDirectoryEntry]>::new_slice_from_prefix(
-                &data[hs..],
-                header.entries as usize,
-            )
-            .ok_or("BIOS directory entries invalid")?;
-
-            return Ok(Self {
-                header,
-                entries: entries.to_vec(),
-            });
-        }
+impl<'a> BiosDirectory<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        check_signature(data, &[b"$BHD", b"$BL2"])?;
+        let hs = mem::size_of::<DirectoryHeader>();
+        let header = DirectoryHeader::read_from_prefix(data).ok_or(Error::Truncated {
+            need: hs,
+            have: data.len(),
+        })?;
+        let count = header.entries as usize;
+        let (entries, _) = LV::<_, [BiosDirectoryEntry]>::new_slice_from_prefix(&data[hs..], count)
+            .ok_or(Error::Truncated {
+                need: hs + count * mem::size_of::<BiosDirectoryEntry>(),
+                have: data.len(),
+            })?;
 
-        Err(format!("BIOS directory header not found"))
+        Ok(Self {
+            header,
+            entries: Cow::Borrowed(entries.into_slice()),
+        })
     }
 
     pub fn header(&self) -> DirectoryHeader {
         self.header
     }
 
-    pub fn entries(&self) -> Vec<BiosDirectoryEntry> {
-        self.entries.clone() // so much for zero copy
+    /// Size of the header and the entries
+    pub fn size(&self) -> usize {
+        mem::size_of::<DirectoryHeader>() + self.entries.len() * mem::size_of::<BiosDirectoryEntry>()
+    }
+
+    pub fn entries(&self) -> &[BiosDirectoryEntry] {
+        &self.entries
+    }
+
+    /// Header and entries as stored in the image
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.as_bytes().to_vec();
+        bytes.extend_from_slice(self.entries.as_bytes());
+        bytes
+    }
+
+    /// Recomputes the Fletcher-32 checksum over the directory after the
+    /// checksum field
+    pub fn verify_checksum(&self) -> Checksum {
+        Checksum::of(&self.to_bytes())
+    }
+
+    /// Copies the entries out of the image the directory was parsed from
+    pub fn into_owned(self) -> BiosDirectory<'static> {
+        BiosDirectory {
+            header: self.header,
+            entries: Cow::Owned(self.entries.into_owned()),
+        }
     }
 }
 
-pub struct BiosComboDirectory {
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BiosComboDirectory<'a> {
     header: ComboDirectoryHeader,
-    entries: Vec<ComboDirectoryEntry>,
+    entries: Cow<'a, [ComboDirectoryEntry]>,
 }
 
-impl<'a> BiosComboDirectory {
-    pub fn new(data: &'a [u8]) -> Result<Self, String> {
-        if &data[..4] == b"2BHD" {
-            let header =
-                ComboDirectoryHeader::read_from_prefix(data).ok_or("BIOS combo header invalid")?;
-            let hs = mem::size_of::<ComboDirectoryHeader>();
-            let (entries, _) = LV::<_, [ComboDirectoryEntry]>::new_slice_from_prefix(
-                &data[hs..],
-                header.entries as usize,
-            )
-            .ok_or("BIOS combo entries invalid")?;
-
-            return Ok(Self {
-                header,
-                entries: entries.to_vec(),
-            });
-        }
+impl<'a> BiosComboDirectory<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        check_signature(data, &[b"2BHD"])?;
+        let hs = mem::size_of::<ComboDirectoryHeader>();
+        let header = ComboDirectoryHeader::read_from_prefix(data).ok_or(Error::Truncated {
+            need: hs,
+            have: data.len(),
+        })?;
+        let count = header.entries as usize;
+        let (entries, _) =
+            LV::<_, [ComboDirectoryEntry]>::new_slice_from_prefix(&data[hs..], count).ok_or(
+                Error::Truncated {
+                    need: hs + count * mem::size_of::<ComboDirectoryEntry>(),
+                    have: data.len(),
+                },
+            )?;
 
-        Err(format!("BIOS combo header not found"))
+        Ok(Self {
+            header,
+            entries: Cow::Borrowed(entries.into_slice()),
+        })
     }
 
     pub fn header(&self) -> ComboDirectoryHeader {
         self.header
     }
 
-    pub fn entries(&self) -> Vec<ComboDirectoryEntry> {
-        self.entries.clone()
+    /// Size of the header and the entries
+    pub fn size(&self) -> usize {
+        mem::size_of::<ComboDirectoryHeader>()
+            + self.entries.len() * mem::size_of::<ComboDirectoryEntry>()
+    }
+
+    pub fn entries(&self) -> &[ComboDirectoryEntry] {
+        &self.entries
+    }
+
+    /// Header and entries as stored in the image
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.as_bytes().to_vec();
+        bytes.extend_from_slice(self.entries.as_bytes());
+        bytes
+    }
+
+    /// Recomputes the Fletcher-32 checksum over the directory after the
+    /// checksum field
+    pub fn verify_checksum(&self) -> Checksum {
+        Checksum::of(&self.to_bytes())
+    }
+
+    /// Copies the entries out of the image the directory was parsed from
+    pub fn into_owned(self) -> BiosComboDirectory<'static> {
+        BiosComboDirectory {
+            header: self.header,
+            entries: Cow::Owned(self.entries.into_owned()),
+        }
     }
 }
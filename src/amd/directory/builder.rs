@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::vec::Vec;
+use core::{convert::TryFrom, mem};
+use zerocopy::AsBytes;
+
+use super::{fletcher32, AddressMode, BiosDirectoryEntry, DirectoryHeader, PspDirectoryEntry};
+use crate::amd::platform::Platform;
+use crate::Error;
+
+/// An entry of a PSP or BIOS directory
+pub trait DirectoryEntry: AsBytes + Copy {
+    /// Points the entry to `size` bytes of data at `address`
+    fn set_data(&mut self, size: u32, address: u64);
+}
+
+impl DirectoryEntry for PspDirectoryEntry {
+    fn set_data(&mut self, size: u32, address: u64) {
+        self.size = size;
+        self.value = address;
+    }
+}
+
+impl DirectoryEntry for BiosDirectoryEntry {
+    fn set_data(&mut self, size: u32, address: u64) {
+        self.size = size;
+        self.source = address;
+    }
+}
+
+/// Builds a directory from entries and the payloads of entries, serializing
+/// it followed by the payloads
+#[derive(Clone, Debug)]
+pub struct DirectoryBuilder<E> {
+    magic: [u8; 4],
+    additional_info: u32,
+    alignment: usize,
+    mode: AddressMode,
+    entries: Vec<(E, Option<Vec<u8>>)>,
+}
+
+pub type PspDirectoryBuilder = DirectoryBuilder<PspDirectoryEntry>;
+pub type BiosDirectoryBuilder = DirectoryBuilder<BiosDirectoryEntry>;
+
+impl PspDirectoryBuilder {
+    pub fn new() -> Self {
+        Self::with_magic(*b"$PSP")
+    }
+
+    pub fn level2() -> Self {
+        Self::with_magic(*b"$PL2")
+    }
+}
+
+impl BiosDirectoryBuilder {
+    pub fn new() -> Self {
+        Self::with_magic(*b"$BHD")
+    }
+
+    pub fn level2() -> Self {
+        Self::with_magic(*b"$BL2")
+    }
+}
+
+impl Default for PspDirectoryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for BiosDirectoryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: DirectoryEntry> DirectoryBuilder<E> {
+    /// Builder of a directory with the given signature, placing payloads at
+    /// 4K boundaries and addressing them by memory mapped address
+    pub fn with_magic(magic: [u8; 4]) -> Self {
+        Self {
+            magic,
+            additional_info: 0,
+            alignment: 0x1000,
+            mode: AddressMode::PhysAddr,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn additional_info(mut self, additional_info: u32) -> Self {
+        self.additional_info = additional_info;
+        self
+    }
+
+    /// Alignment of the payloads in the image, a power of two
+    pub fn alignment(mut self, alignment: usize) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Address mode of the addresses of the payloads
+    pub fn address_mode(mut self, mode: AddressMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Adds an entry as is, any address it holds is kept
+    pub fn entry(mut self, entry: E) -> Self {
+        self.entries.push((entry, None));
+        self
+    }
+
+    /// Adds an entry whose size and address are set to those of `payload`
+    /// once it is placed
+    pub fn payload(mut self, entry: E, payload: Vec<u8>) -> Self {
+        self.entries.push((entry, Some(payload)));
+        self
+    }
+
+    /// Size of the header and the entries
+    pub fn size(&self) -> usize {
+        mem::size_of::<DirectoryHeader>() + self.entries.len() * mem::size_of::<E>()
+    }
+
+    /// Serializes the directory at `offset` of an image for `platform`,
+    /// followed by the payloads. Space between the payloads is erased.
+    pub fn build(&self, offset: usize, platform: &Platform) -> Result<Vec<u8>, Error> {
+        if !self.alignment.is_power_of_two() {
+            return Err(Error::InvalidArgument(format!(
+                "payload alignment {:#X} is not a power of two",
+                self.alignment
+            )));
+        }
+
+        let mut bytes = vec![0; self.size()];
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for (entry, payload) in self.entries.iter() {
+            let mut entry = *entry;
+            if let Some(payload) = payload {
+                let mask = self.alignment - 1;
+                let start = ((offset + bytes.len() + mask) & !mask) - offset;
+                let size = u32::try_from(payload.len()).map_err(|_| {
+                    Error::InvalidArgument(format!("payload of {:#X} bytes", payload.len()))
+                })?;
+                entry.set_data(
+                    size,
+                    platform.encode_address(self.mode, offset, offset + start)?,
+                );
+                bytes.resize(start, 0xFF);
+                bytes.extend_from_slice(payload);
+            }
+            entries.push(entry);
+        }
+
+        let header = DirectoryHeader {
+            magic: u32::from_le_bytes(self.magic),
+            checksum: 0,
+            entries: entries.len() as u32,
+            additional_info: self.additional_info,
+        };
+        let mut directory = header.as_bytes().to_vec();
+        for entry in entries.iter() {
+            directory.extend_from_slice(entry.as_bytes());
+        }
+        let checksum = fletcher32(&directory[8..]);
+        directory[4..8].copy_from_slice(&checksum.to_le_bytes());
+        bytes[..directory.len()].copy_from_slice(&directory);
+        Ok(bytes)
+    }
+}
@@ -1,36 +1,228 @@
-use alloc::string::String;
+use alloc::vec::Vec;
 use serde::{Deserialize, Serialize};
 use zerocopy::{AsBytes, FromBytes};
 
+use crate::Error;
+
 pub use self::bios::*;
+pub use self::builder::*;
 pub use self::psp::*;
 
 mod bios;
+mod builder;
 mod psp;
 
-pub enum Directory {
-    Bios(BiosDirectory),
-    BiosCombo(BiosComboDirectory),
-    BiosLevel2(BiosDirectory),
-    Psp(PspDirectory),
-    PspCombo(PspComboDirectory),
-    PspLevel2(PspDirectory),
+/// Fletcher-32 checksum used by directory headers, computed over the
+/// directory starting after the checksum field
+pub fn fletcher32(data: &[u8]) -> u32 {
+    let mut c0: u32 = 0xFFFF;
+    let mut c1: u32 = 0xFFFF;
+    for block in data.chunks(2 * 359) {
+        for word in block.chunks(2) {
+            c0 += word[0] as u32 | (*word.get(1).unwrap_or(&0) as u32) << 8;
+            c1 += c0;
+        }
+        c0 = (c0 & 0xFFFF) + (c0 >> 16);
+        c1 = (c1 & 0xFFFF) + (c1 >> 16);
+    }
+    c0 = (c0 & 0xFFFF) + (c0 >> 16);
+    c1 = (c1 & 0xFFFF) + (c1 >> 16);
+    (c1 << 16) | c0
+}
+
+/// Checksum of a directory as stored in its header and as computed over the
+/// directory
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Checksum {
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl Checksum {
+    /// Checksum of `directory`, the bytes of a directory header and its
+    /// entries
+    fn of(directory: &[u8]) -> Self {
+        let mut stored = [0; 4];
+        stored.copy_from_slice(&directory[4..8]);
+        Checksum {
+            stored: u32::from_le_bytes(stored),
+            computed: fletcher32(&directory[8..]),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.stored == self.computed
+    }
+
+    /// Fails with `Error::ChecksumMismatch` if the checksum of the directory
+    /// at `addr` is not valid
+    pub fn check(&self, addr: usize) -> Result<(), Error> {
+        if self.is_valid() {
+            Ok(())
+        } else {
+            Err(Error::ChecksumMismatch {
+                addr,
+                stored: self.stored,
+                computed: self.computed,
+            })
+        }
+    }
+}
+
+/// How an entry refers to a location in the flash, selected by bits 63:62 of
+/// entry addresses
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AddressMode {
+    /// Memory mapped address, the flash is mapped at the top of the 4G
+    /// address space
+    PhysAddr,
+    /// Offset from the start of the flash
+    FlashOffset,
+    /// Offset from the header of the directory containing the entry
+    DirHeaderOffset,
+    /// Offset from the start of the slot containing the directory
+    SlotOffset,
+}
+
+impl AddressMode {
+    /// Mask of the address bits of an entry address
+    pub const ADDRESS_MASK: u64 = (1 << 62) - 1;
+
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0 => Self::PhysAddr,
+            1 => Self::FlashOffset,
+            2 => Self::DirHeaderOffset,
+            _ => Self::SlotOffset,
+        }
+    }
+
+    /// Address mode of an entry address
+    pub fn of(address: u64) -> Self {
+        Self::from_bits(address >> 62)
+    }
+
+    pub fn bits(&self) -> u64 {
+        match self {
+            Self::PhysAddr => 0,
+            Self::FlashOffset => 1,
+            Self::DirHeaderOffset => 2,
+            Self::SlotOffset => 3,
+        }
+    }
+}
+
+/// Signatures of every kind of directory
+pub(crate) const SIGNATURES: [&[u8; 4]; 6] = [b"$PSP", b"$PL2", b"2PSP", b"$BHD", b"$BL2", b"2BHD"];
+
+/// Error for `data` not starting with an expected directory signature, at
+/// offset 0 of `data`
+fn signature_error(data: &[u8]) -> Error {
+    match data.get(..4) {
+        Some(signature) => {
+            let mut found = [0; 4];
+            found.copy_from_slice(signature);
+            Error::DirectoryMagicMismatch {
+                found: u32::from_le_bytes(found),
+                addr: 0,
+            }
+        }
+        None => Error::Truncated {
+            need: 4,
+            have: data.len(),
+        },
+    }
+}
+
+/// Checks that `data` starts with one of the given directory signatures
+pub(crate) fn check_signature(data: &[u8], signatures: &[&[u8; 4]]) -> Result<(), Error> {
+    match data.get(..4) {
+        Some(signature) if signatures.iter().any(|s| &s[..] == signature) => Ok(()),
+        _ => Err(signature_error(data)),
+    }
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Directory<'a> {
+    Bios(BiosDirectory<'a>),
+    BiosCombo(BiosComboDirectory<'a>),
+    BiosLevel2(BiosDirectory<'a>),
+    Psp(PspDirectory<'a>),
+    PspCombo(PspComboDirectory<'a>),
+    PspLevel2(PspDirectory<'a>),
 }
 
-impl<'a> Directory {
-    pub fn new(data: &'a [u8]) -> Result<Self, String> {
-        match &data[..4] {
+impl<'a> Directory<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        match data.get(..4).unwrap_or_default() {
             b"$BHD" => BiosDirectory::new(data).map(Self::Bios),
             b"2BHD" => BiosComboDirectory::new(data).map(Self::BiosCombo),
             b"$BL2" => BiosDirectory::new(data).map(Self::BiosLevel2),
             b"$PSP" => PspDirectory::new(data).map(Self::Psp),
             b"2PSP" => PspComboDirectory::new(data).map(Self::PspCombo),
             b"$PL2" => PspDirectory::new(data).map(Self::PspLevel2),
-            unknown => Err(format!("unknown directory signature {:X?}", unknown)),
+            _ => Err(signature_error(data)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bios(_) => "BIOS Directory",
+            Self::BiosCombo(_) => "BIOS Combo Directory",
+            Self::BiosLevel2(_) => "BIOS Level 2 Directory",
+            Self::Psp(_) => "PSP Directory",
+            Self::PspCombo(_) => "PSP Combo Directory",
+            Self::PspLevel2(_) => "PSP Level 2 Directory",
+        }
+    }
+
+    /// Size of the directory header and its entries
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Bios(dir) | Self::BiosLevel2(dir) => dir.size(),
+            Self::BiosCombo(combo) => combo.size(),
+            Self::Psp(dir) | Self::PspLevel2(dir) => dir.size(),
+            Self::PspCombo(combo) => combo.size(),
+        }
+    }
+
+    /// Header and entries as stored in the image
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Bios(dir) | Self::BiosLevel2(dir) => dir.to_bytes(),
+            Self::BiosCombo(combo) => combo.to_bytes(),
+            Self::Psp(dir) | Self::PspLevel2(dir) => dir.to_bytes(),
+            Self::PspCombo(combo) => combo.to_bytes(),
+        }
+    }
+
+    /// Recomputes the checksum of the directory
+    pub fn verify_checksum(&self) -> Checksum {
+        match self {
+            Self::Bios(dir) | Self::BiosLevel2(dir) => dir.verify_checksum(),
+            Self::BiosCombo(combo) => combo.verify_checksum(),
+            Self::Psp(dir) | Self::PspLevel2(dir) => dir.verify_checksum(),
+            Self::PspCombo(combo) => combo.verify_checksum(),
+        }
+    }
+
+    /// Copies the entries out of the image the directory was parsed from
+    pub fn into_owned(self) -> Directory<'static> {
+        match self {
+            Self::Bios(dir) => Directory::Bios(dir.into_owned()),
+            Self::BiosCombo(combo) => Directory::BiosCombo(combo.into_owned()),
+            Self::BiosLevel2(dir) => Directory::BiosLevel2(dir.into_owned()),
+            Self::Psp(dir) => Directory::Psp(dir.into_owned()),
+            Self::PspCombo(combo) => Directory::PspCombo(combo.into_owned()),
+            Self::PspLevel2(dir) => Directory::PspLevel2(dir.into_owned()),
         }
     }
 }
 
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(AsBytes, FromBytes, Clone, Copy, Debug, Deserialize, Serialize)]
 #[repr(C)]
 pub struct DirectoryHeader {
@@ -40,9 +232,26 @@ pub struct DirectoryHeader {
     pub checksum: u32,
     /// 0x08: number of entries
     pub entries: u32,
-    pub rsvd_0c: u32,
+    /// 0x0C: additional info, bits 30:29 hold the address mode of second
+    /// generation directories
+    pub additional_info: u32,
+}
+
+impl DirectoryHeader {
+    /// Address mode of the entries of the directory
+    pub fn address_mode(&self) -> AddressMode {
+        AddressMode::from_bits(self.additional_info as u64 >> 29)
+    }
+
+    /// Size of the directory and the data of its entries declared by bits
+    /// 9:0 of the additional info in 4K units, 0 if none is declared
+    pub fn max_size(&self) -> usize {
+        (self.additional_info & 0x3FF) as usize * 0x1000
+    }
 }
 
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(AsBytes, FromBytes, Clone, Copy, Debug, Deserialize, Serialize)]
 #[repr(C)]
 pub struct ComboDirectoryHeader {
@@ -61,6 +270,8 @@ pub struct ComboDirectoryHeader {
     pub rsvd_1c: u32,
 }
 
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(AsBytes, FromBytes, Clone, Copy, Debug, Serialize, Deserialize)]
 #[repr(C)]
 pub struct ComboDirectoryEntry {
@@ -1,10 +1,18 @@
-use alloc::{boxed::Box, string::String, vec::Vec};
-use core::mem;
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::{fmt, mem};
 use serde::{Deserialize, Serialize};
 use zerocopy::{AsBytes, FromBytes, LayoutVerified as LV};
 
-use super::{ComboDirectoryEntry, ComboDirectoryHeader, DirectoryHeader};
+use super::{
+    check_signature, AddressMode, Checksum, ComboDirectoryEntry, ComboDirectoryHeader,
+    DirectoryHeader,
+};
+use crate::amd::address::{AddrTranslator, FlashAddress};
+use crate::amd::Rom;
+use crate::Error;
 
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(AsBytes, FromBytes, Clone, Copy, Debug, Deserialize, Serialize)]
 #[repr(C)]
 pub struct PspDirectoryEntry {
@@ -22,31 +30,43 @@ pub struct PspDirectoryEntry {
 }
 
 impl PspDirectoryEntry {
-    pub fn data(&self, data: &[u8]) -> Result<Box<[u8]>, String> {
+    /// Data of the entry in the image of `rom`, borrowed from it.
+    /// `directory` is the offset of the directory containing the entry.
+    /// Entries holding a value instead of data own the bytes of the value.
+    pub fn data<'d>(&self, rom: &Rom<'d>, directory: usize) -> Result<Cow<'d, [u8]>, Error> {
         if self.size == 0xFFFFFFFF {
-            return Ok(vec![
-                (self.value) as u8,
-                (self.value >> 8) as u8,
-                (self.value >> (2 * 8)) as u8,
-                (self.value >> (3 * 8)) as u8,
-                (self.value >> (4 * 8)) as u8,
-                (self.value >> (5 * 8)) as u8,
-                (self.value >> (6 * 8)) as u8,
-                (self.value >> (7 * 8)) as u8,
-            ]
-            .into_boxed_slice());
+            return Ok(Cow::Owned(self.value.to_le_bytes().to_vec()));
         }
 
-        let start = (self.value & 0x1FFFFFF) as usize;
-        let end = start + self.size as usize;
-        if end <= data.len() {
-            Ok(data[start..end].to_vec().into_boxed_slice())
-        } else {
-            Err(format!(
-                "PSP directory entry invalid: {:08X}:{:08X}",
-                start, end
-            ))
-        }
+        let data = rom.data();
+        let range = FlashAddress(rom.resolve_address(directory, self.value)?)
+            .range(self.size as usize, data.len())?;
+        Ok(Cow::Borrowed(&data[range]))
+    }
+
+    /// Flash offset of the data of the entry, unless it holds a value or its
+    /// address is relative to the directory
+    pub fn addr(&self, translator: &AddrTranslator) -> FlashAddress {
+        translator.offset(self.value & AddressMode::ADDRESS_MASK)
+    }
+
+    /// True if the entry points to a level 2 directory
+    pub fn is_directory(&self) -> bool {
+        matches!(self.kind, 0x40 | 0x48 | 0x49 | 0x4A)
+    }
+
+    /// True if the data of the entry is a key token
+    pub fn is_key(&self) -> bool {
+        matches!(
+            self.kind,
+            0x00 | 0x09 | 0x0A | 0x0D | 0x43 | 0x4E | 0x53 | 0x81
+        )
+    }
+
+    /// Tells apart entries of the same type and sub program, kept in bits 3
+    /// to 6 of the ROM ID byte, below which are the ROM and writable bits
+    pub fn instance(&self) -> u8 {
+        (self.rom_id >> 3) & 0xF
     }
 
     pub fn description(&self) -> &'static str {
@@ -130,75 +150,306 @@ impl PspDirectoryEntry {
     }
 }
 
+/// Header of PSP binaries, preceding the body and followed by the signature
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct PspBinaryHeader {
+    /// 0x00: initialization vector of encrypted binaries
+    pub iv: [u8; 16],
+    /// 0x10: magic of the header ("$PS1"), not set by every binary
+    pub magic: u32,
+    /// 0x14: size of the signed body following the header
+    pub size_signed: u32,
+    /// 0x18: 1 if the body is encrypted
+    pub encrypted: u32,
+    pub rsvd_1c: u32,
+    /// 0x20: wrapped key of encrypted binaries
+    pub encryption_key: [u8; 16],
+    /// 0x30: 1 if the binary is signed
+    pub signed: u32,
+    pub rsvd_34: u32,
+    /// 0x38: ID of the key that signed the binary
+    pub signature_key_id: [u8; 16],
+    /// 0x48: 1 if the body is compressed
+    pub compressed: u32,
+    pub rsvd_4c: u32,
+    /// 0x50: size of the uncompressed body
+    pub uncompressed_size: u32,
+    /// 0x54: size of the compressed body
+    pub compressed_size: u32,
+    pub rsvd_58: [u8; 8],
+    /// 0x60: version of the binary
+    pub version: u32,
+    pub rsvd_64: [u8; 8],
+    /// 0x6C: total size of the binary
+    pub size_total: u32,
+    pub rsvd_70: [u8; 12],
+    /// 0x7C: type of the binary
+    pub kind: u8,
+    /// 0x7D: sub type of the binary
+    pub sub_kind: u8,
+    /// 0x7E: used to filter binaries by model
+    pub sub_program: u8,
+    pub rsvd_7f: u8,
+    pub rsvd_80: [u8; 128],
+}
+
+impl PspBinaryHeader {
+    pub fn new(data: &[u8]) -> Option<Self> {
+        Self::read_from_prefix(data)
+    }
+
+    /// Size of the header and the signed body
+    pub fn signed_len(&self) -> usize {
+        mem::size_of::<Self>() + self.size_signed as usize
+    }
+}
+
+/// Header of a key token, followed by the exponent, the modulus and, unless
+/// the key certifies itself, the signature of the certifying key. Numbers
+/// after the header are little endian.
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct PspKeyTokenHeader {
+    /// 0x00: version of the token (1)
+    pub version: u32,
+    /// 0x04: ID of the key, which PSP binary headers name their signing key by
+    pub key_id: [u8; 16],
+    /// 0x14: ID of the key that signed the token
+    pub certifying_key_id: [u8; 16],
+    /// 0x24: what the key may sign
+    pub key_usage: u32,
+    /// 0x28: lowest security patch level of the binaries the key accepts
+    pub security_patch_level: u32,
+    pub rsvd_2c: [u8; 12],
+    /// 0x38: size of the exponent in bits
+    pub exponent_size: u32,
+    /// 0x3C: size of the modulus in bits
+    pub modulus_size: u32,
+}
+
+impl PspKeyTokenHeader {
+    /// Reads a header of version 1 with exponent and modulus sizes of 2048
+    /// or 4096 bits
+    pub fn new(data: &[u8]) -> Option<Self> {
+        let header = Self::read_from_prefix(data)?;
+        let size_valid = |bits: u32| matches!(bits, 2048 | 4096);
+        if header.version == 1
+            && size_valid(header.exponent_size)
+            && size_valid(header.modulus_size)
+        {
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// Size of the header, the exponent and the modulus, the part of the
+    /// token the certifying key signs
+    pub fn signed_len(&self) -> usize {
+        mem::size_of::<Self>() + (self.exponent_size / 8 + self.modulus_size / 8) as usize
+    }
+
+    /// True if the key certifies itself, as the AMD root key does
+    pub fn is_self_signed(&self) -> bool {
+        self.key_id == self.certifying_key_id
+    }
+}
+
+/// Key ID in hex, in the order of its bytes
+pub fn format_key_id(id: &[u8; 16]) -> String {
+    id.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Decoded header of a key token
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PspKeyToken {
+    pub version: u32,
+    /// Key ID in hex
+    pub key_id: String,
+    /// ID of the key that signed the token in hex
+    pub certifying_key_id: String,
+    pub key_usage: u32,
+    pub security_patch_level: u32,
+    /// Size of the exponent in bits
+    pub exponent_size: u32,
+    /// Size of the modulus in bits
+    pub modulus_size: u32,
+}
+
+impl PspKeyToken {
+    /// Decodes the key token at the start of `data`, if its exponent and
+    /// modulus fit
+    pub fn new(data: &[u8]) -> Option<Self> {
+        let header = PspKeyTokenHeader::new(data)?;
+        if header.signed_len() > data.len() {
+            return None;
+        }
+        Some(PspKeyToken {
+            version: header.version,
+            key_id: format_key_id(&header.key_id),
+            certifying_key_id: format_key_id(&header.certifying_key_id),
+            key_usage: header.key_usage,
+            security_patch_level: header.security_patch_level,
+            exponent_size: header.exponent_size,
+            modulus_size: header.modulus_size,
+        })
+    }
+}
+
+impl fmt::Display for PspKeyToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key {}", self.key_id)?;
+        if self.certifying_key_id == self.key_id {
+            write!(f, ", self-signed")?;
+        } else {
+            write!(f, ", certified by {}", self.certifying_key_id)?;
+        }
+        write!(
+            f,
+            ", usage {:#X}, {} bit, SPL {:#X}",
+            self.key_usage, self.modulus_size, self.security_patch_level
+        )
+    }
+}
+
+/// Describes a bit of the PSP Soft Fuse Chain (entry type 0x0B)
+pub fn soft_fuse_bit_name(bit: u8) -> &'static str {
+    match bit {
+        0 => "Secure Debug Unlock",
+        7 => "Disable PSP Post Codes",
+        15 => "PSP Post Code Destination (0 = LPC, 1 = eSPI)",
+        29 => "Disable MP2 Firmware Loading",
+        _ => "Unknown",
+    }
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct PspDirectory {
+pub struct PspDirectory<'a> {
     header: DirectoryHeader,
-    entries: Vec<PspDirectoryEntry>,
+    entries: Cow<'a, [PspDirectoryEntry]>,
 }
 
-impl<'a> PspDirectory {
-    pub fn new(data: &'a [u8]) -> Result<Self, String> {
-        if &data[..4] == b"$PSP" || &data[..4] == b"$PL2" {
-            let header =
-                DirectoryHeader::read_from_prefix(data).ok_or("PSP directory header invalid")?;
-
-            let hs = mem::size_of::<DirectoryHeader>();
-            let (entries, _) = LV::<_, [PspDirectoryEntry]>::new_slice_from_prefix(
-                &data[hs..],
-                header.entries as usize,
-            )
-            .ok_or("PSP directory entries invalid")?;
-
-            return Ok(Self {
-                header,
-                entries: entries.to_vec(),
-            });
-        }
+impl<'a> PspDirectory<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        check_signature(data, &[b"$PSP", b"$PL2"])?;
+        let hs = mem::size_of::<DirectoryHeader>();
+        let header = DirectoryHeader::read_from_prefix(data).ok_or(Error::Truncated {
+            need: hs,
+            have: data.len(),
+        })?;
+        let count = header.entries as usize;
+        let (entries, _) = LV::<_, [PspDirectoryEntry]>::new_slice_from_prefix(&data[hs..], count)
+            .ok_or(Error::Truncated {
+                need: hs + count * mem::size_of::<PspDirectoryEntry>(),
+                have: data.len(),
+            })?;
 
-        Err(format!("PSP directory header not found"))
+        Ok(Self {
+            header,
+            entries: Cow::Borrowed(entries.into_slice()),
+        })
     }
 
     pub fn header(&self) -> DirectoryHeader {
         self.header
     }
 
-    pub fn entries(&self) -> Vec<PspDirectoryEntry> {
-        self.entries.clone()
+    /// Size of the header and the entries
+    pub fn size(&self) -> usize {
+        mem::size_of::<DirectoryHeader>() + self.entries.len() * mem::size_of::<PspDirectoryEntry>()
+    }
+
+    pub fn entries(&self) -> &[PspDirectoryEntry] {
+        &self.entries
+    }
+
+    /// Header and entries as stored in the image
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.as_bytes().to_vec();
+        bytes.extend_from_slice(self.entries.as_bytes());
+        bytes
+    }
+
+    /// Recomputes the Fletcher-32 checksum over the directory after the
+    /// checksum field
+    pub fn verify_checksum(&self) -> Checksum {
+        Checksum::of(&self.to_bytes())
+    }
+
+    /// Copies the entries out of the image the directory was parsed from
+    pub fn into_owned(self) -> PspDirectory<'static> {
+        PspDirectory {
+            header: self.header,
+            entries: Cow::Owned(self.entries.into_owned()),
+        }
     }
 }
 
-pub struct PspComboDirectory {
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PspComboDirectory<'a> {
     header: ComboDirectoryHeader,
-    entries: Vec<ComboDirectoryEntry>,
+    entries: Cow<'a, [ComboDirectoryEntry]>,
 }
 
-impl<'a> PspComboDirectory {
-    pub fn new(data: &'a [u8]) -> Result<Self, String> {
-        if &data[..4] == b"2PSP" {
-            let header =
-                ComboDirectoryHeader::read_from_prefix(data).ok_or("PSP combo header invalid")?;
-
-            let hs = mem::size_of::<ComboDirectoryHeader>();
-            let (entries, _) = LV::<_, [ComboDirectoryEntry]>::new_slice_from_prefix(
-                &data[hs..],
-                header.entries as usize,
-            )
-            .ok_or("PSP combo entries invalid")?;
-
-            return Ok(Self {
-                header,
-                entries: entries.to_vec(),
-            });
-        }
+impl<'a> PspComboDirectory<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        check_signature(data, &[b"2PSP"])?;
+        let hs = mem::size_of::<ComboDirectoryHeader>();
+        let header = ComboDirectoryHeader::read_from_prefix(data).ok_or(Error::Truncated {
+            need: hs,
+            have: data.len(),
+        })?;
+        let count = header.entries as usize;
+        let (entries, _) =
+            LV::<_, [ComboDirectoryEntry]>::new_slice_from_prefix(&data[hs..], count).ok_or(
+                Error::Truncated {
+                    need: hs + count * mem::size_of::<ComboDirectoryEntry>(),
+                    have: data.len(),
+                },
+            )?;
 
-        Err(format!("PSP combo header not found"))
+        Ok(Self {
+            header,
+            entries: Cow::Borrowed(entries.into_slice()),
+        })
     }
 
     pub fn header(&self) -> ComboDirectoryHeader {
         self.header
     }
 
-    pub fn entries(&self) -> Vec<ComboDirectoryEntry> {
-        self.entries.clone()
+    /// Size of the header and the entries
+    pub fn size(&self) -> usize {
+        mem::size_of::<ComboDirectoryHeader>()
+            + self.entries.len() * mem::size_of::<ComboDirectoryEntry>()
+    }
+
+    pub fn entries(&self) -> &[ComboDirectoryEntry] {
+        &self.entries
+    }
+
+    /// Header and entries as stored in the image
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.as_bytes().to_vec();
+        bytes.extend_from_slice(self.entries.as_bytes());
+        bytes
+    }
+
+    /// Recomputes the Fletcher-32 checksum over the directory after the
+    /// checksum field
+    pub fn verify_checksum(&self) -> Checksum {
+        Checksum::of(&self.to_bytes())
+    }
+
+    /// Copies the entries out of the image the directory was parsed from
+    pub fn into_owned(self) -> PspComboDirectory<'static> {
+        PspComboDirectory {
+            header: self.header,
+            entries: Cow::Owned(self.entries.into_owned()),
+        }
     }
 }
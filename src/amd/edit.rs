@@ -0,0 +1,1100 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use zerocopy::AsBytes;
+
+use super::directory::{
+    fletcher32, AddressMode, BiosDirectoryEntry, ComboDirectoryEntry, ComboDirectoryHeader,
+    Directory, DirectoryHeader, PspDirectoryEntry,
+};
+use super::{flash, Rom};
+use crate::layout::Layout;
+use crate::Error;
+
+/// Recomputes the checksum of the directory at `offset` spanning `size` bytes
+pub fn fix_checksum(image: &mut [u8], offset: usize, size: usize) {
+    let checksum = fletcher32(&image[offset + 8..offset + size]);
+    image[offset + 4..offset + 8].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Offset of entry `index` in the directory at `offset`
+pub(super) fn psp_entry_offset(offset: usize, index: usize) -> usize {
+    offset + mem::size_of::<DirectoryHeader>() + index * mem::size_of::<PspDirectoryEntry>()
+}
+
+/// Offset of entry `index` in the BIOS directory at `offset`
+fn bios_entry_offset(offset: usize, index: usize) -> usize {
+    offset + mem::size_of::<DirectoryHeader>() + index * mem::size_of::<BiosDirectoryEntry>()
+}
+
+/// Offset of entry `index` in the combo directory at `offset`
+fn combo_entry_offset(offset: usize, index: usize) -> usize {
+    offset + mem::size_of::<ComboDirectoryHeader>() + index * mem::size_of::<ComboDirectoryEntry>()
+}
+
+/// Where a directory entry and its data are in the image
+struct EntryLocation {
+    /// Offset of the entry
+    entry: usize,
+    /// Size of the entries of the directory
+    entry_size: usize,
+    /// Number of entries of the directory
+    count: usize,
+    dir_size: usize,
+    kind: u8,
+    /// Address mode new addresses of the entry are written in
+    mode: AddressMode,
+    /// Offset and size of the data, if the entry has data
+    data: Option<(usize, usize)>,
+}
+
+/// Offsets of the EFS directory pointers, relative to the EFS
+const EFS_POINTERS: [usize; 6] = [0x10, 0x14, 0x18, 0x1C, 0x20, 0x28];
+
+impl<'a> Rom<'a> {
+    /// Encodes `offset` in the given address mode, or as a flash offset if
+    /// that mode can not express it
+    pub(super) fn reencode_address(
+        &self,
+        mode: AddressMode,
+        directory: usize,
+        offset: usize,
+    ) -> Result<u64, Error> {
+        self.encode_address(mode, directory, offset)
+            .or_else(|_| self.encode_address(AddressMode::FlashOffset, directory, offset))
+    }
+
+    /// Checks that the range from `start` to `end` lies within the image and
+    /// is not claimed by any area of `layout`
+    fn check_unclaimed(&self, layout: &Layout, start: usize, end: usize) -> Result<(), Error> {
+        if end > self.data.len() {
+            return Err(Error::InvalidArgument(format!(
+                "{:#X} bytes at {:#X} out of bounds",
+                end - start,
+                start
+            )));
+        }
+        match layout
+            .areas()
+            .iter()
+            .find(|area| area.offset < end && start < area.end())
+        {
+            Some(area) => Err(Error::Conflict(format!(
+                "{:#X} overlaps {} at {:#X}",
+                start, area.name, area.offset
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Creates a BIOS level 2 directory at `offset` holding the entries of
+    /// the BIOS directory at `parent`, and appends an entry of type 0x70
+    /// pointing to it to the parent, returning the modified image. The parent
+    /// must be followed by erased space for the new entry.
+    pub fn create_bios_level2(&self, parent: usize, offset: usize) -> Result<Vec<u8>, Error> {
+        let directories = self.directories();
+        let dir = match directories.iter().find(|(o, _)| *o == parent) {
+            Some((_, Directory::Bios(dir))) => dir,
+            Some((_, directory)) => {
+                return Err(Error::InvalidArgument(format!(
+                    "{} at {:#X} is not a BIOS directory",
+                    directory.name(),
+                    parent
+                )))
+            }
+            None => return Err(Error::DirectoryNotFound { addr: parent }),
+        };
+        let entries = dir.entries();
+        if entries.iter().any(|entry| entry.kind == 0x70) {
+            return Err(Error::Conflict(format!(
+                "BIOS directory at {:#X} already has a level 2 directory",
+                parent
+            )));
+        }
+
+        // Space for the new directory and the new entry of the parent
+        let entry_size = mem::size_of::<BiosDirectoryEntry>();
+        let parent_end = parent + dir.size();
+        let size = dir.size();
+        let ranges = [(parent_end, parent_end + entry_size), (offset, offset + size)];
+        if ranges[0].0 < ranges[1].1 && ranges[1].0 < ranges[0].1 {
+            return Err(Error::Conflict(format!(
+                "{:#X} overlaps the new entry of the parent",
+                offset
+            )));
+        }
+        let layout = self.layout();
+        for (start, end) in ranges.iter() {
+            self.check_unclaimed(&layout, *start, *end)?;
+        }
+        if self.data[parent_end..parent_end + entry_size]
+            .iter()
+            .any(|b| *b != 0xFF)
+        {
+            return Err(Error::Conflict(format!(
+                "no free space after BIOS directory at {:#X}",
+                parent
+            )));
+        }
+
+        let mut image = self.data.to_vec();
+        let mut header = dir.header();
+        header.magic = u32::from_le_bytes(*b"$BL2");
+        image[offset..offset + mem::size_of::<DirectoryHeader>()].copy_from_slice(header.as_bytes());
+        for (i, entry) in entries.iter().enumerate() {
+            let mut entry = *entry;
+            if entry.size != 0 && AddressMode::of(entry.source) == AddressMode::DirHeaderOffset {
+                let target = self.resolve_address(parent, entry.source)?;
+                entry.source = self.reencode_address(AddressMode::DirHeaderOffset, offset, target)?;
+            }
+            let start = bios_entry_offset(offset, i);
+            image[start..start + entry_size].copy_from_slice(entry.as_bytes());
+        }
+        fix_checksum(&mut image, offset, size);
+
+        let mode = match header.address_mode() {
+            AddressMode::DirHeaderOffset | AddressMode::SlotOffset => AddressMode::PhysAddr,
+            mode => mode,
+        };
+        let level2 = BiosDirectoryEntry {
+            kind: 0x70,
+            region_kind: 0,
+            flags: 0,
+            sub_program: 0,
+            size: size as u32,
+            source: self.encode_address(mode, parent, offset)?,
+            destination: !0,
+        };
+        image[parent_end..parent_end + entry_size].copy_from_slice(level2.as_bytes());
+        let count = entries.len() as u32 + 1;
+        image[parent + 8..parent + 12].copy_from_slice(&count.to_le_bytes());
+        fix_checksum(&mut image, parent, dir.size() + entry_size);
+        Ok(image)
+    }
+
+    /// Fills the ranges of the image not claimed by the EFS, a directory or
+    /// an entry with the erase value, returning the modified image. Firmware
+    /// pointed to by the legacy EFS fields (IMC, GbE and xHCI) and the
+    /// promontory fields has no known size, so the range from such a pointer
+    /// to the next area is kept.
+    pub fn scrub(&self) -> Result<Vec<u8>, Error> {
+        let (efs, platform) = (&self.efs, &self.platform);
+        let legacy: Vec<usize> = [
+            efs.imc_firmware(platform),
+            efs.gbe_firmware(platform),
+            efs.xhci_firmware(platform),
+            efs.promontory_firmware(platform),
+            efs.lp_promontory_firmware(platform),
+            efs.promontory2_firmware(platform),
+        ]
+        .iter()
+        .flatten()
+        .map(|address| address.0)
+        .collect();
+
+        let mut image = self.data.to_vec();
+        for (offset, size) in self.layout().gaps() {
+            let end = legacy
+                .iter()
+                .filter(|pointer| **pointer >= offset && **pointer < offset + size)
+                .min()
+                .copied()
+                .unwrap_or(offset + size);
+            for byte in image[offset..end].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+        Ok(image)
+    }
+
+    /// First erased, 4K aligned range of `size` bytes not claimed by any
+    /// area
+    fn free_space(&self, size: usize) -> Option<usize> {
+        self.layout()
+            .gaps()
+            .into_iter()
+            .filter_map(|(start, gap)| {
+                let end = start + gap;
+                let mut offset = (start + 0xFFF) & !0xFFF;
+                while offset + size <= end {
+                    let range = &self.data[offset..offset + size];
+                    match range.iter().rposition(|b| *b != 0xFF) {
+                        Some(i) => offset = (offset + i + 1 + 0xFFF) & !0xFFF,
+                        None => return Some(offset),
+                    }
+                }
+                None
+            })
+            .next()
+    }
+
+    /// Places chipset firmware in the first erased, 4K aligned range not
+    /// claimed by any area and points the promontory field of the EFS, or
+    /// the low power promontory field, at it. The pointer is written in the
+    /// form of the directory pointers of the EFS. Firmware previously pointed
+    /// to is left in place. Returns the modified image and the offset of the
+    /// firmware.
+    pub fn set_promontory(
+        &self,
+        low_power: bool,
+        firmware: &[u8],
+    ) -> Result<(Vec<u8>, usize), Error> {
+        if firmware.is_empty() {
+            return Err(Error::InvalidArgument(String::from(
+                "promontory firmware is empty",
+            )));
+        }
+        let offset = self.free_space(firmware.len()).ok_or_else(|| {
+            Error::Conflict(format!(
+                "no free space for {:#X} bytes of promontory firmware",
+                firmware.len()
+            ))
+        })?;
+
+        let pointer = self.efs_pointer(self.efs_directories().first().copied(), offset)?;
+
+        let mut image = self.data.to_vec();
+        image[offset..offset + firmware.len()].copy_from_slice(firmware);
+        let field = self.efs_offset + if low_power { 0x34 } else { 0x30 };
+        image[field..field + 4].copy_from_slice(&pointer.to_le_bytes());
+        Ok((image, offset))
+    }
+
+    /// Moves the directory at `from` to `to`, returning the modified image.
+    /// The EFS pointers, combo entries and level 2 entries referring to the
+    /// directory are rewritten in their address mode, as are the directory
+    /// relative addresses of its own entries, and the checksums fixed.
+    pub fn move_directory(&self, from: usize, to: usize) -> Result<Vec<u8>, Error> {
+        let directories = self.directories();
+        let moved = directories
+            .iter()
+            .find(|(o, _)| *o == from)
+            .map(|(_, directory)| directory)
+            .ok_or(Error::DirectoryNotFound { addr: from })?;
+        let size = moved.size();
+        let end = to + size;
+        if end > self.data.len() {
+            return Err(Error::InvalidArgument(format!(
+                "{:#X} bytes at {:#X} out of bounds",
+                size, to
+            )));
+        }
+        for area in self.layout().areas() {
+            if area.offset != from && area.offset < end && to < area.end() {
+                return Err(Error::Conflict(format!(
+                    "{:#X} overlaps {} at {:#X}",
+                    to, area.name, area.offset
+                )));
+            }
+        }
+
+        let mut image = self.data.to_vec();
+        for byte in image[from..from + size].iter_mut() {
+            *byte = 0xFF;
+        }
+        image[to..end].copy_from_slice(&self.data[from..from + size]);
+
+        // Entries of the moved directory with addresses relative to it
+        let addresses: Vec<(usize, u64)> = match moved {
+            Directory::Psp(dir) | Directory::PspLevel2(dir) => dir
+                .entries()
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.size != 0xFFFF_FFFF)
+                .map(|(i, entry)| (psp_entry_offset(0, i) + 8, entry.value))
+                .collect(),
+            Directory::Bios(dir) | Directory::BiosLevel2(dir) => dir
+                .entries()
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (bios_entry_offset(0, i) + 8, entry.source))
+                .collect(),
+            Directory::PspCombo(combo) => combo
+                .entries()
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (combo_entry_offset(0, i) + 8, entry.directory))
+                .collect(),
+            Directory::BiosCombo(combo) => combo
+                .entries()
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (combo_entry_offset(0, i) + 8, entry.directory))
+                .collect(),
+        };
+        for (field, address) in addresses {
+            if AddressMode::of(address) == AddressMode::DirHeaderOffset {
+                let target = self.resolve_address(from, address)?;
+                let new_address =
+                    self.reencode_address(AddressMode::DirHeaderOffset, to, target)?;
+                image[to + field..to + field + 8].copy_from_slice(&new_address.to_le_bytes());
+            }
+        }
+        fix_checksum(&mut image, to, size);
+
+        // Directories referring to the moved one
+        for (offset, directory) in directories.iter().filter(|(o, _)| *o != from) {
+            let pointers: Vec<(usize, u64)> = match directory {
+                Directory::Psp(dir) | Directory::PspLevel2(dir) => dir
+                    .entries()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| entry.is_directory())
+                    .map(|(i, entry)| (psp_entry_offset(*offset, i) + 8, entry.value))
+                    .collect(),
+                Directory::Bios(dir) | Directory::BiosLevel2(dir) => dir
+                    .entries()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| entry.kind == 0x70)
+                    .map(|(i, entry)| (bios_entry_offset(*offset, i) + 8, entry.source))
+                    .collect(),
+                Directory::PspCombo(combo) => combo
+                    .entries()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, entry)| (combo_entry_offset(*offset, i) + 8, entry.directory))
+                    .collect(),
+                Directory::BiosCombo(combo) => combo
+                    .entries()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, entry)| (combo_entry_offset(*offset, i) + 8, entry.directory))
+                    .collect(),
+            };
+            let mut changed = false;
+            for (field, address) in pointers {
+                if self.resolve_address(*offset, address) == Ok(from) {
+                    let new_address =
+                        self.reencode_address(AddressMode::of(address), *offset, to)?;
+                    image[field..field + 8].copy_from_slice(&new_address.to_le_bytes());
+                    changed = true;
+                }
+            }
+            if changed {
+                fix_checksum(&mut image, *offset, directory.size());
+            }
+        }
+
+        // EFS pointers are 32-bit memory mapped addresses or flash offsets
+        for field in EFS_POINTERS.iter().map(|field| self.efs_offset + field) {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&self.data[field..field + 4]);
+            let pointer = u32::from_le_bytes(bytes);
+            if !flash::pointer_set(pointer) || self.offset(pointer as u64) != from {
+                continue;
+            }
+            let new_pointer = self.efs_pointer(Some(pointer), to)?;
+            image[field..field + 4].copy_from_slice(&new_pointer.to_le_bytes());
+        }
+        Ok(image)
+    }
+
+    /// EFS pointer to `offset`, in the address mode of the pointer `like` or
+    /// memory mapped
+    fn efs_pointer(&self, like: Option<u32>, offset: usize) -> Result<u32, Error> {
+        let mode = like.map_or(AddressMode::PhysAddr, |pointer| {
+            self.platform.pointer_mode(pointer)
+        });
+        let address = self.platform.encode_address(mode, 0, offset)?;
+        Ok((address & AddressMode::ADDRESS_MASK) as u32)
+    }
+
+    /// Offsets of the data of every entry with data, once per entry
+    /// referring to it
+    fn entry_data_offsets(&self, directories: &[(usize, Directory)]) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        for (offset, directory) in directories.iter() {
+            match directory {
+                Directory::Psp(dir) | Directory::PspLevel2(dir) => {
+                    for entry in dir.entries() {
+                        if entry.size != 0xFFFF_FFFF && !entry.is_directory() {
+                            offsets.extend(self.resolve_address(*offset, entry.value));
+                        }
+                    }
+                }
+                Directory::Bios(dir) | Directory::BiosLevel2(dir) => {
+                    for entry in dir.entries() {
+                        if entry.kind != 0x70 {
+                            offsets.extend(self.resolve_address(*offset, entry.source));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        offsets
+    }
+
+    /// Moves the data of entry `index` of the directory at `directory` to
+    /// `offset` and fixes the directory checksum, returning the modified
+    /// image. The old location is erased unless another entry refers to it.
+    ///
+    /// The new address uses the address mode of the directory header if it
+    /// sets one, or else the mode of the old address. If that mode can not
+    /// express the new location, a flash offset is written instead.
+    pub fn move_entry(
+        &self,
+        directory: usize,
+        index: usize,
+        offset: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let directories = self.directories();
+        let (header, address, size, address_offset, dir_size) =
+            match directories.iter().find(|(o, _)| *o == directory) {
+                Some((_, Directory::Psp(dir))) | Some((_, Directory::PspLevel2(dir))) => {
+                    let entry = dir
+                        .entries()
+                        .get(index)
+                        .copied()
+                        .ok_or(Error::EntryNotFound { directory, index })?;
+                    if entry.size == 0xFFFF_FFFF || entry.is_directory() {
+                        return Err(Error::InvalidArgument(format!(
+                            "PSP entry {:02X} has no data to move",
+                            entry.kind
+                        )));
+                    }
+                    let address_offset = psp_entry_offset(directory, index) + 8;
+                    (dir.header(), entry.value, entry.size, address_offset, dir.size())
+                }
+                Some((_, Directory::Bios(dir))) | Some((_, Directory::BiosLevel2(dir))) => {
+                    let entry = dir
+                        .entries()
+                        .get(index)
+                        .copied()
+                        .ok_or(Error::EntryNotFound { directory, index })?;
+                    if entry.size == 0 || entry.kind == 0x70 {
+                        return Err(Error::InvalidArgument(format!(
+                            "BIOS entry {:02X} has no data to move",
+                            entry.kind
+                        )));
+                    }
+                    let address_offset = bios_entry_offset(directory, index) + 8;
+                    (dir.header(), entry.source, entry.size, address_offset, dir.size())
+                }
+                Some(_) => {
+                    return Err(Error::InvalidArgument(String::from(
+                        "entries of combo directories can not be moved",
+                    )))
+                }
+                None => return Err(Error::DirectoryNotFound { addr: directory }),
+            };
+
+        let start = self.resolve_address(directory, address)?;
+        let size = size as usize;
+        let end = offset + size;
+        if start + size > self.data.len() || end > self.data.len() {
+            return Err(Error::InvalidArgument(format!(
+                "entry of {:#X} bytes can not be moved from {:#X} to {:#X}",
+                size, start, offset
+            )));
+        }
+        for area in self.layout().areas() {
+            if area.offset != start && area.offset < end && offset < area.end() {
+                return Err(Error::Conflict(format!(
+                    "{:#X} overlaps {} at {:#X}",
+                    offset, area.name, area.offset
+                )));
+            }
+        }
+
+        let mode = match header.address_mode() {
+            AddressMode::PhysAddr => AddressMode::of(address),
+            mode => mode,
+        };
+        let new_address = self.reencode_address(mode, directory, offset)?;
+
+        let mut image = self.data.to_vec();
+        let references = self
+            .entry_data_offsets(directories)
+            .iter()
+            .filter(|o| **o == start)
+            .count();
+        if references == 1 {
+            for byte in image[start..start + size].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+        image[offset..end].copy_from_slice(&self.data[start..start + size]);
+        image[address_offset..address_offset + 8].copy_from_slice(&new_address.to_le_bytes());
+        fix_checksum(&mut image, directory, dir_size);
+        Ok(image)
+    }
+
+    /// Entry `index` of the directory at `directory`, where it is and where
+    /// its data is
+    fn locate_entry(
+        &self,
+        directories: &[(usize, Directory)],
+        directory: usize,
+        index: usize,
+    ) -> Result<EntryLocation, Error> {
+        let location = match directories.iter().find(|(o, _)| *o == directory) {
+            Some((_, Directory::Psp(dir))) | Some((_, Directory::PspLevel2(dir))) => {
+                let entry = dir
+                    .entries()
+                    .get(index)
+                    .copied()
+                    .ok_or(Error::EntryNotFound { directory, index })?;
+                let has_data =
+                    entry.size != 0xFFFF_FFFF && entry.size != 0 && !entry.is_directory();
+                EntryLocation {
+                    entry: psp_entry_offset(directory, index),
+                    entry_size: mem::size_of::<PspDirectoryEntry>(),
+                    count: dir.entries().len(),
+                    dir_size: dir.size(),
+                    kind: entry.kind,
+                    mode: match dir.header().address_mode() {
+                        AddressMode::PhysAddr => AddressMode::of(entry.value),
+                        mode => mode,
+                    },
+                    data: if has_data {
+                        Some((
+                            self.resolve_address(directory, entry.value)?,
+                            entry.size as usize,
+                        ))
+                    } else {
+                        None
+                    },
+                }
+            }
+            Some((_, Directory::Bios(dir))) | Some((_, Directory::BiosLevel2(dir))) => {
+                let entry = dir
+                    .entries()
+                    .get(index)
+                    .copied()
+                    .ok_or(Error::EntryNotFound { directory, index })?;
+                let has_data = entry.size != 0 && entry.kind != 0x70;
+                EntryLocation {
+                    entry: bios_entry_offset(directory, index),
+                    entry_size: mem::size_of::<BiosDirectoryEntry>(),
+                    count: dir.entries().len(),
+                    dir_size: dir.size(),
+                    kind: entry.kind,
+                    mode: match dir.header().address_mode() {
+                        AddressMode::PhysAddr => AddressMode::of(entry.source),
+                        mode => mode,
+                    },
+                    data: if has_data {
+                        Some((
+                            self.resolve_address(directory, entry.source)?,
+                            entry.size as usize,
+                        ))
+                    } else {
+                        None
+                    },
+                }
+            }
+            Some(_) => {
+                return Err(Error::InvalidArgument(String::from(
+                    "entries of combo directories can not be edited",
+                )))
+            }
+            None => return Err(Error::DirectoryNotFound { addr: directory }),
+        };
+        if let Some((start, size)) = location.data {
+            if start + size > self.data.len() {
+                return Err(Error::Truncated {
+                    need: start + size,
+                    have: self.data.len(),
+                });
+            }
+        }
+        Ok(location)
+    }
+
+    /// Replaces the data of entry `index` of the directory at `directory`,
+    /// sets the size of the entry and fixes the directory checksum, returning
+    /// the modified image. Data no larger than the old data is written in its
+    /// place, larger data to the first erased, 4K aligned range not claimed
+    /// by any area, the entry then pointing there like `move_entry` does.
+    /// Data shared with other entries, such as those of A/B copies, is left
+    /// to them and the new data always written elsewhere.
+    pub fn replace_entry(
+        &self,
+        directory: usize,
+        index: usize,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        if data.is_empty() {
+            return Err(Error::InvalidArgument(String::from("entry data is empty")));
+        }
+        let directories = self.directories();
+        let location = self.locate_entry(directories, directory, index)?;
+        let (start, size) = location.data.ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "entry {:02X} has no data to replace",
+                location.kind
+            ))
+        })?;
+        let shared = self
+            .entry_data_offsets(directories)
+            .iter()
+            .filter(|o| **o == start)
+            .count()
+            > 1;
+
+        let mut image = self.data.to_vec();
+        if !shared {
+            for byte in image[start..start + size].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+        let offset = if !shared && data.len() <= size {
+            start
+        } else {
+            let offset = self.free_space(data.len()).ok_or_else(|| {
+                Error::Conflict(format!(
+                    "no free space for {:#X} bytes of entry {:02X}",
+                    data.len(),
+                    location.kind
+                ))
+            })?;
+            let address = self.reencode_address(location.mode, directory, offset)?;
+            image[location.entry + 8..location.entry + 16].copy_from_slice(&address.to_le_bytes());
+            offset
+        };
+        image[offset..offset + data.len()].copy_from_slice(data);
+        image[location.entry + 4..location.entry + 8]
+            .copy_from_slice(&(data.len() as u32).to_le_bytes());
+        fix_checksum(&mut image, directory, location.dir_size);
+        Ok(image)
+    }
+
+    /// Removes entry `index` from the directory at `directory`, moving the
+    /// entries after it up, and fixes the entry count and checksum of the
+    /// directory, returning the modified image. The data of the entry is
+    /// erased unless another entry refers to it.
+    pub fn delete_entry(&self, directory: usize, index: usize) -> Result<Vec<u8>, Error> {
+        let directories = self.directories();
+        let location = self.locate_entry(directories, directory, index)?;
+
+        let mut image = self.data.to_vec();
+        if let Some((start, size)) = location.data {
+            let references = self
+                .entry_data_offsets(directories)
+                .iter()
+                .filter(|o| **o == start)
+                .count();
+            if references == 1 {
+                for byte in image[start..start + size].iter_mut() {
+                    *byte = 0xFF;
+                }
+            }
+        }
+        let end = directory + location.dir_size;
+        image.copy_within(location.entry + location.entry_size..end, location.entry);
+        for byte in image[end - location.entry_size..end].iter_mut() {
+            *byte = 0xFF;
+        }
+        let count = location.count as u32 - 1;
+        image[directory + 8..directory + 12].copy_from_slice(&count.to_le_bytes());
+        fix_checksum(
+            &mut image,
+            directory,
+            location.dir_size - location.entry_size,
+        );
+        Ok(image)
+    }
+
+    /// Repacks the data of the entries of the directory at `directory`
+    /// towards the lowest offset used by any of them, closing gaps left by
+    /// removed or shrunk entries. Entries keep the alignment of their current
+    /// offset, up to 4K, and entries shared with other directories stay in
+    /// place. Returns the modified image and the number of bytes reclaimed
+    /// at the end of the packed data.
+    pub fn compact_directory(&self, directory: usize) -> Result<(Vec<u8>, usize), Error> {
+        let directories = self.directories();
+        let dir = directories
+            .iter()
+            .find(|(o, _)| *o == directory)
+            .map(|(_, dir)| dir)
+            .ok_or(Error::DirectoryNotFound { addr: directory })?;
+        // Index, offset and size of each entry with data
+        let mut entries: Vec<(usize, usize, usize)> = Vec::new();
+        match dir {
+            Directory::Psp(dir) | Directory::PspLevel2(dir) => {
+                for (i, entry) in dir.entries().iter().enumerate() {
+                    if entry.size != 0xFFFF_FFFF && entry.size != 0 && !entry.is_directory() {
+                        let start = self.resolve_address(directory, entry.value)?;
+                        entries.push((i, start, entry.size as usize));
+                    }
+                }
+            }
+            Directory::Bios(dir) | Directory::BiosLevel2(dir) => {
+                for (i, entry) in dir.entries().iter().enumerate() {
+                    if entry.size != 0 && entry.kind != 0x70 {
+                        let start = self.resolve_address(directory, entry.source)?;
+                        entries.push((i, start, entry.size as usize));
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::InvalidArgument(String::from(
+                    "combo directories have no entry data",
+                )))
+            }
+        }
+        let references = self.entry_data_offsets(directories);
+        entries.retain(|(_, start, _)| references.iter().filter(|o| *o == start).count() == 1);
+        entries.sort_by_key(|(_, start, _)| *start);
+
+        let base = match entries.first() {
+            Some((_, start, _)) => *start,
+            None => return Ok((self.data.to_vec(), 0)),
+        };
+        let old_end = entries.iter().map(|(_, start, size)| start + size).max().unwrap_or(base);
+        let mut occupied: Vec<(usize, usize)> = self
+            .layout()
+            .areas()
+            .iter()
+            .filter(|area| !entries.iter().any(|(_, start, _)| *start == area.offset))
+            .map(|area| (area.offset, area.end()))
+            .collect();
+
+        let mut image = self.data.to_vec();
+        let mut new_end = base;
+        for (index, start, size) in entries.iter() {
+            let alignment = (1 << start.trailing_zeros().min(12)) as usize;
+            let mut offset = (base + alignment - 1) & !(alignment - 1);
+            while let Some((_, end)) = occupied
+                .iter()
+                .find(|(o, end)| *o < offset + size && offset < *end)
+            {
+                offset = (end + alignment - 1) & !(alignment - 1);
+            }
+            if offset < *start {
+                image = Rom::with_efs_and_platform(&image, self.efs_offset, self.platform)?
+                    .move_entry(directory, *index, offset)?;
+            } else {
+                offset = *start;
+            }
+            occupied.push((offset, offset + size));
+            new_end = new_end.max(offset + size);
+        }
+        Ok((image, old_end - new_end))
+    }
+
+    /// Appends a Soft Fuse Chain entry holding `value` to the PSP directory
+    /// at `directory` and fixes its checksum, returning the modified image.
+    /// The directory must be followed by erased space for the new entry.
+    pub fn insert_soft_fuse_chain(&self, directory: usize, value: u64) -> Result<Vec<u8>, Error> {
+        let directories = self.directories();
+        let dir = match directories.iter().find(|(o, _)| *o == directory) {
+            Some((_, Directory::Psp(dir))) | Some((_, Directory::PspLevel2(dir))) => dir,
+            Some((_, other)) => {
+                return Err(Error::InvalidArgument(format!(
+                    "{} at {:#X} is not a PSP directory",
+                    other.name(),
+                    directory
+                )))
+            }
+            None => return Err(Error::DirectoryNotFound { addr: directory }),
+        };
+        let entries = dir.entries();
+        if entries.iter().any(|entry| entry.kind == 0x0B) {
+            return Err(Error::Conflict(format!(
+                "PSP directory at {:#X} already has a Soft Fuse Chain entry",
+                directory
+            )));
+        }
+
+        let entry_size = mem::size_of::<PspDirectoryEntry>();
+        let start = directory + dir.size();
+        self.check_unclaimed(&self.layout(), start, start + entry_size)?;
+        if self.data[start..start + entry_size]
+            .iter()
+            .any(|b| *b != 0xFF)
+        {
+            return Err(Error::Conflict(format!(
+                "no free space after PSP directory at {:#X}",
+                directory
+            )));
+        }
+
+        let mut image = self.data.to_vec();
+        let entry = PspDirectoryEntry {
+            kind: 0x0B,
+            sub_program: 0,
+            rom_id: 0,
+            rsvd_03: 0,
+            size: 0xFFFF_FFFF,
+            value,
+        };
+        image[start..start + entry_size].copy_from_slice(entry.as_bytes());
+        let count = entries.len() as u32 + 1;
+        image[directory + 8..directory + 12].copy_from_slice(&count.to_le_bytes());
+        fix_checksum(&mut image, directory, dir.size() + entry_size);
+        Ok(image)
+    }
+
+    /// Sets or clears a bit of every PSP Soft Fuse Chain entry and fixes the
+    /// directory checksums, returning the modified image
+    pub fn set_soft_fuse_bit(&self, bit: u8, value: bool) -> Result<Vec<u8>, Error> {
+        if bit >= 64 {
+            return Err(Error::InvalidArgument(format!(
+                "Soft Fuse Chain bit {} out of range",
+                bit
+            )));
+        }
+
+        let mut image = self.data.to_vec();
+        let mut count = 0;
+        for &(offset, ref directory) in self.directories() {
+            let dir = match directory {
+                Directory::Psp(dir) | Directory::PspLevel2(dir) => dir,
+                _ => continue,
+            };
+            let mut changed = false;
+            for (i, entry) in dir.entries().iter().enumerate() {
+                if entry.kind != 0x0B {
+                    continue;
+                }
+                if entry.size != 0xFFFF_FFFF {
+                    return Err(Error::Unsupported(format!(
+                        "Soft Fuse Chain entry in directory {:#X} is not a value entry",
+                        offset
+                    )));
+                }
+                let fuses = if value {
+                    entry.value | 1 << bit
+                } else {
+                    entry.value & !(1 << bit)
+                };
+                let value_offset = psp_entry_offset(offset, i) + 8;
+                image[value_offset..value_offset + 8].copy_from_slice(&fuses.to_le_bytes());
+                changed = true;
+                count += 1;
+            }
+            if changed {
+                fix_checksum(&mut image, offset, dir.size());
+            }
+        }
+
+        if count == 0 {
+            return Err(Error::EntryKindNotFound { kind: 0x0B });
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{AmdFixture, BIOS_OFFSET, EFS_OFFSET, PSP_OFFSET};
+
+    fn checksums_valid(image: &[u8]) -> bool {
+        Rom::new(image)
+            .unwrap()
+            .verify_checksums()
+            .iter()
+            .all(|directory| directory.checksum.is_valid())
+    }
+
+    fn psp_entries(image: &[u8], offset: usize) -> Vec<PspDirectoryEntry> {
+        match Rom::new(image).unwrap().directory(offset).unwrap() {
+            Directory::Psp(dir) | Directory::PspLevel2(dir) => dir.entries().to_vec(),
+            directory => panic!("{} at {:#X}", directory.name(), offset),
+        }
+    }
+
+    fn bios_entries(image: &[u8], offset: usize) -> Vec<BiosDirectoryEntry> {
+        match Rom::new(image).unwrap().directory(offset).unwrap() {
+            Directory::Bios(dir) | Directory::BiosLevel2(dir) => dir.entries().to_vec(),
+            directory => panic!("{} at {:#X}", directory.name(), offset),
+        }
+    }
+
+    #[test]
+    fn move_entry_keeps_data_and_address_mode() {
+        let image = AmdFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        let entry = psp_entries(&image, PSP_OFFSET)[1];
+        let start = rom.resolve_address(PSP_OFFSET, entry.value).unwrap();
+        let size = entry.size as usize;
+
+        let moved = rom.move_entry(PSP_OFFSET, 1, 0x20_0000).unwrap();
+        let new_entry = psp_entries(&moved, PSP_OFFSET)[1];
+        assert_eq!(AddressMode::of(new_entry.value), AddressMode::of(entry.value));
+        assert_eq!(
+            Rom::new(&moved)
+                .unwrap()
+                .resolve_address(PSP_OFFSET, new_entry.value),
+            Ok(0x20_0000)
+        );
+        assert_eq!(moved[0x20_0000..0x20_0000 + size], image[start..start + size]);
+        assert!(moved[start..start + size].iter().all(|b| *b == 0xFF));
+        assert!(checksums_valid(&moved));
+    }
+
+    #[test]
+    fn move_entry_rejects_overlaps() {
+        let image = AmdFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        assert!(matches!(
+            rom.move_entry(PSP_OFFSET, 1, BIOS_OFFSET),
+            Err(Error::Conflict(_))
+        ));
+    }
+
+    #[test]
+    fn compact_directory_closes_gaps() {
+        let image = AmdFixture::default().build();
+        // Deleting the boot loader leaves a 4K gap before the SMU firmware
+        let image = Rom::new(&image).unwrap().delete_entry(PSP_OFFSET, 1).unwrap();
+        let smu = psp_entries(&image, PSP_OFFSET)[2];
+        let rom = Rom::new(&image).unwrap();
+        let start = rom.resolve_address(PSP_OFFSET, smu.value).unwrap();
+
+        let (compacted, reclaimed) = rom.compact_directory(PSP_OFFSET).unwrap();
+        assert_eq!(reclaimed, 0x1000);
+        let smu = psp_entries(&compacted, PSP_OFFSET)[2];
+        assert_eq!(smu.kind, 0x08);
+        assert_eq!(
+            Rom::new(&compacted)
+                .unwrap()
+                .resolve_address(PSP_OFFSET, smu.value),
+            Ok(start - 0x1000)
+        );
+        assert!(checksums_valid(&compacted));
+    }
+
+    #[test]
+    fn move_directory_rewrites_efs_pointer() {
+        let image = AmdFixture::default().build();
+        let moved = Rom::new(&image)
+            .unwrap()
+            .move_directory(BIOS_OFFSET, 0x3_0000)
+            .unwrap();
+        let rom = Rom::new(&moved).unwrap();
+        assert_eq!(rom.offset(rom.efs().bios as u64), 0x3_0000);
+        assert!(rom.directories().iter().any(|(o, _)| *o == 0x3_0000));
+        assert!(!rom.directories().iter().any(|(o, _)| *o == BIOS_OFFSET));
+        assert_eq!(
+            bios_entries(&moved, 0x3_0000).as_bytes(),
+            bios_entries(&image, BIOS_OFFSET).as_bytes()
+        );
+        assert!(checksums_valid(&moved));
+    }
+
+    #[test]
+    fn move_directory_keeps_flash_offsets_of_regions() {
+        let image_offset = 0x1_0000;
+        let mut image = AmdFixture {
+            image_offset,
+            ..AmdFixture::default()
+        }
+        .build();
+        let efs = EFS_OFFSET - image_offset;
+        let pointer = BIOS_OFFSET as u32;
+        image[efs + 0x28..efs + 0x2C].copy_from_slice(&pointer.to_le_bytes());
+        let rom = Rom::new(&image).unwrap();
+        let bios = rom.offset(pointer as u64);
+        assert_eq!(bios, BIOS_OFFSET - image_offset);
+
+        let moved = rom.move_directory(bios, 0x3_0000).unwrap();
+        let rom = Rom::new(&moved).unwrap();
+        let pointer = rom.efs().bios;
+        assert_eq!(pointer, (image_offset + 0x3_0000) as u32);
+        assert_eq!(rom.offset(pointer as u64), 0x3_0000);
+        assert!(checksums_valid(&moved));
+    }
+
+    #[test]
+    fn move_directory_rewrites_combo_entries() {
+        let image = AmdFixture {
+            combo: true,
+            ..AmdFixture::default()
+        }
+        .build();
+        let moved = Rom::new(&image)
+            .unwrap()
+            .move_directory(PSP_OFFSET, 0x3_0000)
+            .unwrap();
+        let rom = Rom::new(&moved).unwrap();
+        assert!(matches!(
+            rom.directories().iter().find(|(o, _)| *o == 0x3_0000),
+            Some((_, Directory::Psp(_)))
+        ));
+        assert_eq!(
+            psp_entries(&moved, 0x3_0000).as_bytes(),
+            psp_entries(&image, PSP_OFFSET).as_bytes()
+        );
+        assert!(checksums_valid(&moved));
+    }
+
+    #[test]
+    fn insert_soft_fuse_chain_appends_an_entry() {
+        let image = AmdFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        assert!(matches!(
+            rom.insert_soft_fuse_chain(PSP_OFFSET, 1),
+            Err(Error::Conflict(_))
+        ));
+
+        let image = rom.delete_entry(PSP_OFFSET, 2).unwrap();
+        let inserted = Rom::new(&image)
+            .unwrap()
+            .insert_soft_fuse_chain(PSP_OFFSET, 0x1234)
+            .unwrap();
+        let entries = psp_entries(&inserted, PSP_OFFSET);
+        assert_eq!(entries.len(), 4);
+        let entry = entries[3];
+        assert_eq!((entry.kind, entry.size, entry.value), (0x0B, 0xFFFF_FFFF, 0x1234));
+        assert!(checksums_valid(&inserted));
+    }
+
+    #[test]
+    fn set_promontory_points_the_efs_at_the_firmware() {
+        let image = AmdFixture::default().build();
+        let firmware = [0x5A; 0x100];
+        let (image, offset) = Rom::new(&image)
+            .unwrap()
+            .set_promontory(false, &firmware)
+            .unwrap();
+        assert_eq!(image[offset..offset + firmware.len()], firmware);
+
+        let rom = Rom::new(&image).unwrap();
+        let pointer = rom.efs().promontory;
+        assert_eq!(AddressMode::of(pointer as u64), AddressMode::PhysAddr);
+        assert!(pointer >= 0xFF00_0000);
+        assert_eq!(rom.offset(pointer as u64), offset);
+        assert_eq!(rom.efs().lp_promontory_firmware(&rom.platform()), None);
+    }
+
+    #[test]
+    fn create_bios_level2_moves_entries() {
+        let image = AmdFixture::default().build();
+        let created = Rom::new(&image)
+            .unwrap()
+            .create_bios_level2(BIOS_OFFSET, 0x6_0000)
+            .unwrap();
+        let parent = bios_entries(&created, BIOS_OFFSET);
+        let level2 = bios_entries(&created, 0x6_0000);
+        assert_eq!(level2.as_bytes(), bios_entries(&image, BIOS_OFFSET).as_bytes());
+        assert_eq!(parent.len(), level2.len() + 1);
+        let entry = parent[level2.len()];
+        assert_eq!(entry.kind, 0x70);
+
+        let rom = Rom::new(&created).unwrap();
+        assert_eq!(rom.resolve_address(BIOS_OFFSET, entry.source), Ok(0x6_0000));
+        assert!(matches!(
+            rom.directories().iter().find(|(o, _)| *o == 0x6_0000),
+            Some((_, Directory::BiosLevel2(_)))
+        ));
+        assert!(checksums_valid(&created));
+
+        let image = AmdFixture {
+            level2: true,
+            ..AmdFixture::default()
+        }
+        .build();
+        assert!(matches!(
+            Rom::new(&image)
+                .unwrap()
+                .create_bios_level2(BIOS_OFFSET, 0x6_0000),
+            Err(Error::Conflict(_))
+        ));
+    }
+}
@@ -0,0 +1,315 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use zerocopy::AsBytes;
+
+#[cfg(feature = "zlib")]
+use super::directory::{inflate, zlib_stream};
+use super::directory::{BiosDirectoryEntry, Directory, PspDirectoryEntry};
+use super::walk::RomVisitor;
+use super::{flash, Rom};
+use crate::extract::{camel_case, decode, join, Extraction, Metadata, Naming};
+use crate::handler::{Registry, Target};
+
+impl<'a> Rom<'a> {
+    /// Extracts the EFS, every directory reachable from it and the data of
+    /// their entries. Directories are named by offset and kind, entries by
+    /// the offset of their data, their kind and its description, a level 2
+    /// directory being found under the directory pointing to it.
+    pub fn extract_all(&self) -> Extraction {
+        self.extract_all_with(&Registry::new())
+    }
+
+    /// Like `extract_all`, entry data being decoded by the handlers of
+    /// `registry`
+    pub fn extract_all_with(&self, registry: &Registry) -> Extraction {
+        self.extract_named(registry, Naming::Tree)
+    }
+
+    /// Like `extract_all_with`, components being named by `naming`. With
+    /// `Naming::Coreboot`, only entry data is extracted. Copies of a blob,
+    /// such as those of A/B recovery directories, are extracted once, and
+    /// different blobs of the same name are told apart by their offset, as
+    /// are entries of the same name in a directory with `Naming::Entry`.
+    pub fn extract_named(&self, registry: &Registry, naming: Naming) -> Extraction {
+        let mut extractor = Extractor {
+            registry,
+            naming,
+            paths: Vec::new(),
+            extraction: Extraction::new(),
+        };
+        self.walk(&mut extractor);
+        extractor.extraction
+    }
+}
+
+/// Entry found by `Rom::find_entries`
+#[derive(Clone, Debug)]
+pub struct FoundEntry<'a> {
+    /// Offset of the entry data in the image
+    pub offset: usize,
+    pub sub_program: u8,
+    pub instance: u8,
+    pub description: &'static str,
+    /// Entry data, borrowed from the image
+    pub data: &'a [u8],
+}
+
+impl<'a> Rom<'a> {
+    /// Entries of the kind of a `Target::PspEntry` or `Target::BiosEntry`,
+    /// of a sub program and instance if given, in the order `walk` visits
+    /// them. Entries without data are left out, and copies of the same data,
+    /// such as those of A/B recovery directories, are found once.
+    pub fn find_entries(
+        &self,
+        target: Target,
+        sub_program: Option<u8>,
+        instance: Option<u8>,
+    ) -> Vec<FoundEntry<'a>> {
+        let mut finder = Finder {
+            data: self.data,
+            target,
+            sub_program,
+            instance,
+            found: Vec::new(),
+        };
+        self.walk(&mut finder);
+        finder.found
+    }
+}
+
+struct Finder<'a> {
+    data: &'a [u8],
+    target: Target,
+    sub_program: Option<u8>,
+    instance: Option<u8>,
+    found: Vec<FoundEntry<'a>>,
+}
+
+impl<'a> Finder<'a> {
+    fn matches(&self, target: Target, sub_program: u8, instance: u8) -> bool {
+        target == self.target
+            && self.sub_program.unwrap_or(sub_program) == sub_program
+            && self.instance.unwrap_or(instance) == instance
+    }
+
+    /// Entry data the walk found at `offset`, borrowed from the image
+    fn borrow(&self, offset: usize, data: &[u8]) -> &'a [u8] {
+        self.data
+            .get(offset..offset + data.len())
+            .unwrap_or_default()
+    }
+
+    fn insert(&mut self, entry: FoundEntry<'a>) {
+        if !entry.data.is_empty() && self.found.iter().all(|found| found.data != entry.data) {
+            self.found.push(entry);
+        }
+    }
+}
+
+impl<'a> RomVisitor for Finder<'a> {
+    fn visit_psp_entry(
+        &mut self,
+        _directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if !self.matches(Target::psp(entry), entry.sub_program, entry.instance()) {
+            return;
+        }
+        if let (false, Some(offset), Some(data)) = (entry.is_directory(), offset, data) {
+            self.insert(FoundEntry {
+                offset,
+                sub_program: entry.sub_program,
+                instance: entry.instance(),
+                description: entry.description(),
+                data: self.borrow(offset, data),
+            });
+        }
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        _directory: usize,
+        entry: &BiosDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if !self.matches(Target::bios(entry), entry.sub_program, entry.instance()) {
+            return;
+        }
+        if let (false, Some(offset), Some(data)) = (entry.kind == 0x70, offset, data) {
+            self.insert(FoundEntry {
+                offset,
+                sub_program: entry.sub_program,
+                instance: entry.instance(),
+                description: entry.description(),
+                data: self.borrow(offset, data),
+            });
+        }
+    }
+}
+
+/// Name of entry data in coreboot's amd_blobs, without extension
+fn coreboot_name(kind: u8, description: &str) -> String {
+    format!("TypeId0x{:02X}_{}", kind, camel_case(description))
+}
+
+struct Extractor<'r> {
+    registry: &'r Registry,
+    naming: Naming,
+    /// Paths of the directories being visited, innermost last
+    paths: Vec<String>,
+    extraction: Extraction,
+}
+
+impl<'r> Extractor<'r> {
+    fn path(&self) -> &str {
+        self.paths.last().map(String::as_str).unwrap_or("")
+    }
+
+    fn insert_entry(
+        &mut self,
+        target: Target,
+        (kind, sub_program, instance): (u8, u8, u8),
+        description: &str,
+        offset: usize,
+        data: &[u8],
+    ) {
+        // Entries such as the APOB have a destination but no data
+        if data.is_empty() {
+            return;
+        }
+        let (bytes, decoded) = decode(self.registry, target, data);
+        let metadata = Metadata {
+            offset: Some(offset),
+            size: data.len(),
+            description: String::from(description),
+            decoded,
+        };
+        let path = match self.naming {
+            Naming::Tree => join(
+                self.path(),
+                &format!("{:08X} {:02X} {}", offset, kind, description),
+            ),
+            Naming::Entry => {
+                let name = format!(
+                    "{:02X}.{:02X}.{:X} {}",
+                    kind, sub_program, instance, description
+                );
+                let path = join(self.path(), &name);
+                if self.extraction.contains_key(&path) {
+                    join(self.path(), &format!("{} {:08X}", name, offset))
+                } else {
+                    path
+                }
+            }
+            Naming::Coreboot => {
+                let extension = match target {
+                    Target::PspEntry(0x08) | Target::PspEntry(0x12) => "csbin",
+                    Target::PspEntry(_) if description.ends_with("Key") => "tkn",
+                    Target::PspEntry(_) => "sbin",
+                    Target::BiosEntry(0x64) | Target::BiosEntry(0x65) => "csbin",
+                    _ => "bin",
+                };
+                let name = coreboot_name(kind, description);
+                let path = format!("{}.{}", name, extension);
+                match self.extraction.get(&path) {
+                    None => path,
+                    Some((_, existing)) if *existing == bytes => return,
+                    Some(_) => format!("{}_{:08X}.{}", name, offset, extension),
+                }
+            }
+        };
+        // Compressed data is extracted decompressed as well, except into
+        // coreboot's amd_blobs
+        #[cfg(feature = "zlib")]
+        if self.naming != Naming::Coreboot {
+            if let Some(Ok(decompressed)) = zlib_stream(data).map(inflate) {
+                let metadata = Metadata {
+                    description: format!("{} (decompressed)", description),
+                    decoded: true,
+                    ..metadata.clone()
+                };
+                self.extraction
+                    .insert(format!("{} decompressed", path), (metadata, decompressed));
+            }
+        }
+        self.extraction.insert(path, (metadata, bytes));
+    }
+}
+
+impl<'r> RomVisitor for Extractor<'r> {
+    fn visit_efs(&mut self, offset: usize, efs: &flash::EFS) {
+        if self.naming == Naming::Coreboot {
+            return;
+        }
+        let metadata = Metadata {
+            offset: Some(offset),
+            size: mem::size_of::<flash::EFS>(),
+            description: String::from("Embedded Firmware Structure"),
+            decoded: false,
+        };
+        self.extraction.insert(
+            format!("{:08X} EFS", offset),
+            (metadata, efs.as_bytes().to_vec()),
+        );
+    }
+
+    fn visit_directory(&mut self, offset: usize, _depth: usize, directory: &Directory) {
+        let path = join(self.path(), &format!("{:08X} {}", offset, directory.name()));
+        if self.naming != Naming::Coreboot {
+            let metadata = Metadata {
+                offset: Some(offset),
+                size: directory.size(),
+                description: String::from(directory.name()),
+                decoded: false,
+            };
+            self.extraction
+                .insert(path.clone(), (metadata, directory.to_bytes()));
+        }
+        self.paths.push(path);
+    }
+
+    fn leave_directory(&mut self, _offset: usize, _depth: usize, _directory: &Directory) {
+        self.paths.pop();
+    }
+
+    fn visit_psp_entry(
+        &mut self,
+        _directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if let (false, Some(offset), Some(data)) = (entry.is_directory(), offset, data) {
+            self.insert_entry(
+                Target::psp(entry),
+                (entry.kind, entry.sub_program, entry.instance()),
+                entry.description(),
+                offset,
+                data,
+            );
+        }
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        _directory: usize,
+        entry: &BiosDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if let (false, Some(offset), Some(data)) = (entry.kind == 0x70, offset, data) {
+            self.insert_entry(
+                Target::bios(entry),
+                (entry.kind, entry.sub_program, entry.instance()),
+                entry.description(),
+                offset,
+                data,
+            );
+        }
+    }
+}
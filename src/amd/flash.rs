@@ -1,8 +1,23 @@
 // SPDX-License-Identifier: MIT
+use alloc::{string::String, vec::Vec};
+use core::fmt;
 use serde::{Deserialize, Serialize};
 use zerocopy::{AsBytes, FromBytes, Unaligned};
 
+use super::address::FlashAddress;
+use super::platform::Platform;
+use super::DIR_UNSET;
+
 /// Embedded Firmware Structure
+///
+/// Offsets 0x00 to 0x49 follow `struct embedded_firmware` of coreboot's
+/// amdfwtool (`util/amdfwtool/amdfwtool.h`). amdfwtool still lists 0x38,
+/// 0x3C and 0x4A to 0x4F as reserved; the uses of those fields, and of
+/// 0x2C, are the ones found in second generation EFS of vendor images, and
+/// they are only decoded for a second generation EFS. Their JSON names are
+/// kept from when they were left reserved.
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[derive(AsBytes, Unaligned, FromBytes, Clone, Copy, Debug, Serialize, Deserialize)]
 #[repr(packed)]
 pub struct EFS {
@@ -24,13 +39,20 @@ pub struct EFS {
     pub second_gen: u32,
     /// 0x28: BIOS directory for family 17 model 60 and later
     pub bios: u32,
-    pub rsvd_2c: u32,
+    /// 0x2C: second promontory firmware of boards with two chipsets
+    #[serde(rename = "rsvd_2c")]
+    pub promontory2: u32,
     /// 0x30: promontory firmware
     pub promontory: u32,
     /// 0x34: low power promontory firmware
     pub lp_promontory: u32,
-    pub rsvd_38: u32,
-    pub rsvd_3c: u32,
+    /// 0x38: PSP directory the PSP falls back to if the one of `psp` fails
+    /// to verify
+    #[serde(rename = "rsvd_38")]
+    pub psp_backup: u32,
+    /// 0x3C: backup PSP directory of family 19h
+    #[serde(rename = "rsvd_3c")]
+    pub psp_backup_19: u32,
     /// 0x40: SPI mode for family 15 models 60 to 6f
     pub spi_mode_15_60_6f: u8,
     /// 0x41: SPI speed for family 15 models 60 to 6f
@@ -49,5 +71,285 @@ pub struct EFS {
     pub spi_speed: u8,
     /// 0x49: Micron flag (0xAA for Micron, 0x55 for automatic) for family 17 model 30 and later
     pub micron: u8,
-    pub rsvd_4a: u8,
+    /// 0x4A: SPI mode for family 19h
+    #[serde(rename = "rsvd_4a")]
+    pub spi_mode_19: u8,
+    /// 0x4B: SPI speed for family 19h
+    #[serde(rename = "rsvd_4b")]
+    pub spi_speed_19: u8,
+    /// 0x4C: second EFS of family 19h and later
+    #[serde(rename = "rsvd_4c")]
+    pub efs2: u32,
+}
+
+/// True unless an EFS pointer is 0 or `DIR_UNSET`
+pub fn pointer_set(pointer: u32) -> bool {
+    pointer != 0 && pointer != DIR_UNSET
+}
+
+/// Image offset an EFS pointer refers to on `platform`, if it is set
+pub fn pointer_address(pointer: u32, platform: &Platform) -> Option<FlashAddress> {
+    if pointer_set(pointer) {
+        Some(FlashAddress(platform.offset(pointer as u64)))
+    } else {
+        None
+    }
+}
+
+/// SPI settings the SoC reads from the EFS, 0xFF being unset for the mode
+/// and speed
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SpiConfig {
+    pub mode: u8,
+    pub mode_description: String,
+    pub speed: u8,
+    pub speed_description: String,
+    pub micron: u8,
+    pub micron_description: String,
+}
+
+impl SpiConfig {
+    /// Decodes the settings of a first or second generation EFS, whose
+    /// Micron flags differ. The first generation flags any part other than
+    /// a Micron one with 0xFF.
+    pub fn new(mode: u8, speed: u8, micron: u8, second_gen: bool) -> Self {
+        SpiConfig {
+            mode,
+            mode_description: String::from(spi_mode_description(mode)),
+            speed,
+            speed_description: String::from(spi_speed_description(speed)),
+            micron,
+            micron_description: String::from(match (micron, second_gen) {
+                (0x0A, false) | (0xAA, true) => "Micron",
+                (0xFF, false) => "Not Micron",
+                (0xFF, true) => "Unset",
+                (0x55, true) => "Automatic",
+                _ => "Unknown",
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SpiConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mode {:02X} ({}), speed {:02X} ({}), Micron {:02X} ({})",
+            self.mode,
+            self.mode_description,
+            self.speed,
+            self.speed_description,
+            self.micron,
+            self.micron_description
+        )
+    }
+}
+
+/// Describes an SPI read mode of the EFS
+pub fn spi_mode_description(mode: u8) -> &'static str {
+    match mode {
+        0 => "Normal read, up to 33 MHz",
+        1 => "Reserved",
+        2 => "Dual I/O 1-1-2",
+        3 => "Quad I/O 1-1-4",
+        4 => "Dual I/O 1-2-2",
+        5 => "Quad I/O 1-4-4",
+        6 => "Normal read, up to 66 MHz",
+        7 => "Fast read",
+        0xFF => "Unset",
+        _ => "Unknown",
+    }
+}
+
+/// Describes an SPI speed of the EFS
+pub fn spi_speed_description(speed: u8) -> &'static str {
+    match speed {
+        0 => "66.66 MHz",
+        1 => "33.33 MHz",
+        2 => "22.22 MHz",
+        3 => "16.66 MHz",
+        4 => "100 MHz",
+        5 => "800 kHz",
+        0xFF => "Unset",
+        _ => "Unknown",
+    }
+}
+
+impl EFS {
+    /// True if bit 0 of `second_gen` is clear, as in the EFS of family 17h
+    /// models 30h and later
+    pub fn is_second_gen(&self) -> bool {
+        self.second_gen & 1 == 0
+    }
+
+    /// Legacy PSP directory of family 15h
+    pub fn psp_legacy_directory(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.psp_legacy, platform)
+    }
+
+    pub fn psp_directory(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.psp, platform)
+    }
+
+    pub fn bios_directory_17_00_0f(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.bios_17_00_0f, platform)
+    }
+
+    pub fn bios_directory_17_10_1f(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.bios_17_10_1f, platform)
+    }
+
+    pub fn bios_directory_17_30_3f_19_00_0f(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.bios_17_30_3f_19_00_0f, platform)
+    }
+
+    pub fn bios_directory(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.bios, platform)
+    }
+
+    /// IMC firmware of family 15h, in `rsvd_04`
+    pub fn imc_firmware(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.rsvd_04, platform)
+    }
+
+    /// GbE firmware of family 15h, in `rsvd_08`
+    pub fn gbe_firmware(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.rsvd_08, platform)
+    }
+
+    /// xHCI firmware of family 15h, in `rsvd_0c`
+    pub fn xhci_firmware(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.rsvd_0c, platform)
+    }
+
+    pub fn promontory_firmware(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.promontory, platform)
+    }
+
+    pub fn lp_promontory_firmware(&self, platform: &Platform) -> Option<FlashAddress> {
+        pointer_address(self.lp_promontory, platform)
+    }
+
+    /// Firmware of the second promontory of boards with two chipsets, in a
+    /// second generation EFS
+    pub fn promontory2_firmware(&self, platform: &Platform) -> Option<FlashAddress> {
+        self.second_gen_pointer(self.promontory2, platform)
+    }
+
+    /// PSP directory the PSP falls back to if the one of `psp` fails to
+    /// verify, in a second generation EFS
+    pub fn psp_backup_directory(&self, platform: &Platform) -> Option<FlashAddress> {
+        self.second_gen_pointer(self.psp_backup, platform)
+    }
+
+    /// Backup PSP directory of family 19h, in a second generation EFS
+    pub fn psp_backup_directory_19(&self, platform: &Platform) -> Option<FlashAddress> {
+        self.second_gen_pointer(self.psp_backup_19, platform)
+    }
+
+    /// Second EFS of family 19h and later, in a second generation EFS
+    pub fn efs2(&self, platform: &Platform) -> Option<FlashAddress> {
+        self.second_gen_pointer(self.efs2, platform)
+    }
+
+    /// SPI mode of family 19h, in a second generation EFS
+    pub fn spi_mode_19(&self) -> Option<u8> {
+        Some(self.spi_mode_19).filter(|_| self.is_second_gen())
+    }
+
+    /// SPI speed of family 19h, in a second generation EFS
+    pub fn spi_speed_19(&self) -> Option<u8> {
+        Some(self.spi_speed_19).filter(|_| self.is_second_gen())
+    }
+
+    /// Pointers of fields that are reserved in a first generation EFS
+    fn second_gen_pointer(&self, pointer: u32, platform: &Platform) -> Option<FlashAddress> {
+        if self.is_second_gen() {
+            pointer_address(pointer, platform)
+        } else {
+            None
+        }
+    }
+
+    /// Directory and firmware pointers and the generation field, by field
+    /// name. The fields reserved in a first generation EFS follow the
+    /// others, and are left out of it.
+    pub fn pointer_fields(&self) -> Vec<(&'static str, u32)> {
+        let mut fields = vec![
+            ("psp_legacy", self.psp_legacy),
+            ("psp", self.psp),
+            ("bios_17_00_0f", self.bios_17_00_0f),
+            ("bios_17_10_1f", self.bios_17_10_1f),
+            ("bios_17_30_3f_19_00_0f", self.bios_17_30_3f_19_00_0f),
+            ("second_gen", self.second_gen),
+            ("bios", self.bios),
+            ("promontory", self.promontory),
+            ("lp_promontory", self.lp_promontory),
+        ];
+        if self.is_second_gen() {
+            fields.extend_from_slice(&[
+                ("promontory2", self.promontory2),
+                ("psp_backup", self.psp_backup),
+                ("psp_backup_19", self.psp_backup_19),
+                ("efs2", self.efs2),
+            ]);
+        }
+        fields
+    }
+
+    /// Directory pointers that are set, each once, in the order of the
+    /// fields
+    pub fn directory_pointers(&self) -> Vec<u32> {
+        let mut pointers = Vec::new();
+        let backups = if self.is_second_gen() {
+            [self.psp_backup, self.psp_backup_19]
+        } else {
+            [DIR_UNSET; 2]
+        };
+        for pointer in [
+            self.psp_legacy,
+            self.psp,
+            self.bios_17_00_0f,
+            self.bios_17_10_1f,
+            self.bios_17_30_3f_19_00_0f,
+            self.bios,
+        ]
+        .iter()
+        .chain(backups.iter())
+        {
+            if pointer_set(*pointer) && !pointers.contains(pointer) {
+                pointers.push(*pointer);
+            }
+        }
+        pointers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Rom;
+    use crate::testutil::AmdFixture;
+
+    #[test]
+    fn erased_second_gen_fields_add_no_directories() {
+        let image = AmdFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        let platform = rom.platform();
+        let mut efs = rom.efs();
+        assert!(efs.is_second_gen());
+        let directories = efs.directory_pointers();
+        assert_eq!(directories.len(), 2);
+        for erased in [0xFFFF_FFFF, 0] {
+            efs.promontory2 = erased;
+            efs.psp_backup = erased;
+            efs.psp_backup_19 = erased;
+            efs.efs2 = erased;
+            assert_eq!(efs.directory_pointers(), directories);
+            assert_eq!(efs.psp_backup_directory(&platform), None);
+            assert_eq!(efs.psp_backup_directory_19(&platform), None);
+            assert_eq!(efs.promontory2_firmware(&platform), None);
+            assert_eq!(efs.efs2(&platform), None);
+        }
+    }
 }
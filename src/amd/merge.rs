@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::vec::Vec;
+
+use super::directory::{AddressMode, Directory};
+use super::edit::{fix_checksum, psp_entry_offset};
+use super::Rom;
+use crate::layout::Area;
+use crate::Error;
+
+/// Offsets of the PSP directory pointers, relative to the EFS
+const EFS_PSP_POINTERS: [usize; 2] = [0x10, 0x14];
+
+/// True if the area is a PSP directory or the data of a PSP entry
+fn is_psp(area: &Area) -> bool {
+    area.name.starts_with("PSP")
+}
+
+impl<'a> Rom<'a> {
+    /// Directory and index of a PSP entry whose data starts at `offset`
+    fn psp_entry_at(&self, offset: usize) -> Option<(usize, usize)> {
+        self.directories()
+            .iter()
+            .find_map(|(directory, dir)| match dir {
+                Directory::Psp(dir) | Directory::PspLevel2(dir) => dir
+                    .entries()
+                    .iter()
+                    .position(|entry| {
+                        entry.size != 0xFFFF_FFFF
+                            && !entry.is_directory()
+                            && self.resolve_address(*directory, entry.value) == Ok(offset)
+                    })
+                    .map(|index| (*directory, index)),
+                _ => None,
+            })
+    }
+
+    /// PSP entries pointing to BIOS level 2 directories, as the offset of
+    /// their directory, their index and their address
+    fn psp_bios_entries(&self) -> Vec<(usize, usize, u64)> {
+        let mut entries = Vec::new();
+        for &(offset, ref directory) in self.directories() {
+            if let Directory::Psp(dir) | Directory::PspLevel2(dir) = directory {
+                for (i, entry) in dir.entries().iter().enumerate() {
+                    if entry.kind == 0x49 {
+                        entries.push((offset, i, entry.value));
+                    }
+                }
+            }
+        }
+        entries
+    }
+
+    /// Combines the BIOS tree of this image with the PSP tree of `psp`, an
+    /// image of the same size, returning the merged image. Directories and
+    /// entries of `psp` that would overwrite the EFS, the BIOS tree or other
+    /// data of this image are first moved to space free in both images. The
+    /// EFS of this image is kept, with the PSP directory pointers of `psp`.
+    /// PSP entries pointing to BIOS level 2 directories are pointed at those
+    /// of this image, in order.
+    pub fn merge_psp(&self, psp: &Rom) -> Result<Vec<u8>, Error> {
+        if psp.data.len() != self.data.len() {
+            return Err(Error::InvalidArgument(format!(
+                "image sizes differ: {:#X} and {:#X}",
+                self.data.len(),
+                psp.data.len()
+            )));
+        }
+
+        // Bytes of this image that the PSP tree of `psp` may use
+        let areas = self.layout().areas().to_vec();
+        let mut usable: Vec<bool> = self.data.iter().map(|b| *b == 0xFF).collect();
+        for area in areas.iter() {
+            for byte in usable[area.offset..area.end()].iter_mut() {
+                *byte = is_psp(area);
+            }
+        }
+
+        let mut source = psp.data.to_vec();
+        loop {
+            let rom = Rom::new(&source)?;
+            let layout = rom.layout();
+            let conflict = layout
+                .areas()
+                .iter()
+                .filter(|area| is_psp(area))
+                .find(|area| !usable[area.offset..area.end()].iter().all(|byte| *byte));
+            let area = match conflict {
+                Some(area) => area,
+                None => break,
+            };
+
+            let mut free: Vec<bool> = usable
+                .iter()
+                .zip(source.iter())
+                .map(|(usable, byte)| *usable && *byte == 0xFF)
+                .collect();
+            for area in layout.areas() {
+                for byte in free[area.offset..area.end()].iter_mut() {
+                    *byte = false;
+                }
+            }
+            let to = (0..source.len())
+                .step_by(0x1000)
+                .find(|offset| {
+                    match free.get(*offset..*offset + area.size) {
+                        Some(range) => range.iter().all(|byte| *byte),
+                        None => false,
+                    }
+                })
+                .ok_or_else(|| {
+                    Error::Conflict(format!(
+                        "no free space for {} at {:#X}",
+                        area.name, area.offset
+                    ))
+                })?;
+            let moved = if rom
+                .directories()
+                .iter()
+                .any(|(offset, _)| *offset == area.offset)
+            {
+                rom.move_directory(area.offset, to)?
+            } else {
+                let (directory, index) = rom.psp_entry_at(area.offset).ok_or_else(|| {
+                    Error::NotFound(format!("no PSP entry at {:#X}", area.offset))
+                })?;
+                rom.move_entry(directory, index, to)?
+            };
+            source = moved;
+        }
+
+        let rom = Rom::new(&source)?;
+        let mut image = self.data.to_vec();
+        for area in areas.iter().filter(|area| is_psp(area)) {
+            for byte in image[area.offset..area.end()].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+        for area in rom.layout().areas().iter().filter(|area| is_psp(area)) {
+            image[area.offset..area.end()].copy_from_slice(&source[area.offset..area.end()]);
+        }
+        for field in EFS_PSP_POINTERS.iter() {
+            let from = rom.efs_offset + field;
+            let to = self.efs_offset + field;
+            image[to..to + 4].copy_from_slice(&source[from..from + 4]);
+        }
+
+        let targets: Vec<usize> = self
+            .psp_bios_entries()
+            .into_iter()
+            .filter_map(|(directory, _, address)| self.resolve_address(directory, address).ok())
+            .collect();
+        let merged = image.clone();
+        let rom = Rom::new(&merged)?;
+        let entries = rom.psp_bios_entries();
+        if entries.len() != targets.len() {
+            return Err(Error::Conflict(format!(
+                "{} PSP entries point to BIOS directories, {} expected",
+                entries.len(),
+                targets.len()
+            )));
+        }
+        let mut changed = Vec::new();
+        for ((directory, index, address), target) in entries.into_iter().zip(targets) {
+            let address = rom.reencode_address(AddressMode::of(address), directory, target)?;
+            let offset = psp_entry_offset(directory, index) + 8;
+            image[offset..offset + 8].copy_from_slice(&address.to_le_bytes());
+            if !changed.contains(&directory) {
+                changed.push(directory);
+            }
+        }
+        for &(offset, ref directory) in rom.directories() {
+            if changed.contains(&offset) {
+                fix_checksum(&mut image, offset, directory.size());
+            }
+        }
+        Ok(image)
+    }
+}
@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: MIT
+
+//! AMD microcode patches, as BIOS entries of type 0x66 hold them, or in the
+//! containers Linux loads them from, whose equivalence table maps the CPUID
+//! signatures of processors to the equivalence IDs patches are for
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, mem};
+use serde::{Deserialize, Serialize};
+use zerocopy::{AsBytes, FromBytes};
+
+/// Magic of a container ("DMA\0")
+pub const CONTAINER_MAGIC: u32 = 0x0041_4D44;
+
+/// Section type of the equivalence table of a container
+const SECTION_EQUIVALENCE_TABLE: u32 = 0;
+
+/// Section type of a patch of a container
+const SECTION_PATCH: u32 = 1;
+
+/// Header of a microcode patch, followed by the encrypted patch
+#[derive(AsBytes, FromBytes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct PatchHeader {
+    /// 0x00: date the patch was built, as hex digits MMDDYYYY
+    pub date: u32,
+    /// 0x04: patch level the processor reports once the patch is loaded
+    pub patch_level: u32,
+    /// 0x08: format of the patch data
+    pub data_id: u16,
+    pub data_len: u8,
+    pub init_flag: u8,
+    pub data_checksum: u32,
+    pub nb_dev_id: u32,
+    pub sb_dev_id: u32,
+    /// 0x18: equivalence ID of the processors the patch is for
+    pub equivalent_id: u16,
+    pub nb_rev_id: u8,
+    pub sb_rev_id: u8,
+    pub bios_api_rev: u8,
+    pub rsvd_1d: [u8; 3],
+    pub match_reg: [u32; 8],
+}
+
+impl PatchHeader {
+    /// Reads a header whose date is valid, which tells patches from other
+    /// data
+    pub fn new(data: &[u8]) -> Option<Self> {
+        let header = Self::read_from_prefix(data)?;
+        header.date().map(|_| header)
+    }
+
+    /// Date as YYYY-MM-DD, none if the digits are no date
+    pub fn date(&self) -> Option<String> {
+        let digits = format!("{:08X}", self.date);
+        let number = |range: core::ops::Range<usize>| digits[range].parse::<u32>().ok();
+        let (month, day, year) = (number(0..2)?, number(2..4)?, number(4..8)?);
+        if (1..=12).contains(&month) && (1..=31).contains(&day) && (1990..2100).contains(&year) {
+            Some(format!("{:04}-{:02}-{:02}", year, month, day))
+        } else {
+            None
+        }
+    }
+
+    /// Size of the patches of the family in the top byte of the patch level,
+    /// as Linux sizes them, none for unknown families
+    pub fn patch_size(&self) -> Option<usize> {
+        match self.patch_level >> 24 {
+            // Families 10h to 12h
+            0x01..=0x03 => Some(2048),
+            // Family 14h
+            0x05 => Some(1824),
+            // Family 15h
+            0x06 => Some(4096),
+            // Family 16h
+            0x07 => Some(3458),
+            // Family 17h
+            0x08 => Some(3200),
+            // Family 19h
+            0x0A => Some(5568),
+            // Family 1Ah, whose patches Linux sizes by their container
+            // section only
+            0x0B => None,
+            _ => None,
+        }
+    }
+}
+
+/// Microcode patch found in an entry or a container
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MicrocodePatch {
+    /// Offset of the patch in the data it was found in
+    pub offset: usize,
+    pub size: usize,
+    pub patch_level: u32,
+    /// Date the patch was built, as YYYY-MM-DD
+    pub date: String,
+    /// Equivalence ID of the processors the patch is for
+    pub equivalent_id: u16,
+    /// CPUID signatures of the processors with the equivalence ID, known
+    /// from the equivalence table of a container only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<u32>,
+}
+
+impl MicrocodePatch {
+    fn new(offset: usize, size: usize, header: &PatchHeader) -> Self {
+        MicrocodePatch {
+            offset,
+            size,
+            patch_level: header.patch_level,
+            date: header.date().unwrap_or_default(),
+            equivalent_id: header.equivalent_id,
+            signatures: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for MicrocodePatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "patch level {:#010X}, {}, equivalent ID {:#06X}",
+            self.patch_level, self.date, self.equivalent_id
+        )?;
+        for signature in self.signatures.iter() {
+            write!(f, ", CPUID {:08X}", signature)?;
+        }
+        Ok(())
+    }
+}
+
+/// Patches of a container, or of the data of a BIOS entry, which holds
+/// patches of the size of their family one after the other
+pub fn patches(data: &[u8]) -> Vec<MicrocodePatch> {
+    if dword(data, 0) == Some(CONTAINER_MAGIC) {
+        return container(data);
+    }
+    let mut patches = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = PatchHeader::new(&data[offset..]) {
+        let size = header
+            .patch_size()
+            .unwrap_or(data.len() - offset)
+            .min(data.len() - offset);
+        patches.push(MicrocodePatch::new(offset, size, &header));
+        offset += size;
+    }
+    patches
+}
+
+/// Patches of a container, with the signatures of the equivalence table
+fn container(data: &[u8]) -> Vec<MicrocodePatch> {
+    // Installed CPUID signatures and the equivalence IDs they map to
+    let mut table: Vec<(u32, u16)> = Vec::new();
+    let mut patches = Vec::new();
+    let mut offset = 4;
+    while let (Some(kind), Some(size)) = (dword(data, offset), dword(data, offset + 4)) {
+        let start = offset + 8;
+        let section = match data.get(start..start.saturating_add(size as usize)) {
+            Some(section) => section,
+            None => break,
+        };
+        match kind {
+            SECTION_EQUIVALENCE_TABLE => {
+                for entry in section.chunks_exact(16) {
+                    let signature = dword(entry, 0).unwrap_or_default();
+                    if signature == 0 {
+                        break;
+                    }
+                    table.push((signature, u16::from_le_bytes([entry[12], entry[13]])));
+                }
+            }
+            SECTION_PATCH => {
+                if let Some(header) = PatchHeader::new(section) {
+                    let mut patch = MicrocodePatch::new(start, section.len(), &header);
+                    patch.signatures = table
+                        .iter()
+                        .filter(|(_, id)| *id == header.equivalent_id)
+                        .map(|(signature, _)| *signature)
+                        .collect();
+                    patches.push(patch);
+                }
+            }
+            _ => (),
+        }
+        offset = start + section.len();
+    }
+    patches
+}
+
+fn dword(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + mem::size_of::<u32>())?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Patch of the patch level, for the equivalence ID 0x8310, padded to
+    /// `size` bytes
+    fn patch(patch_level: u32, size: usize) -> Vec<u8> {
+        let mut header = PatchHeader::read_from_prefix(&[0; 64][..]).unwrap();
+        header.date = 0x0115_2024;
+        header.patch_level = patch_level;
+        header.equivalent_id = 0x8310;
+        let mut patch = header.as_bytes().to_vec();
+        patch.resize(size, 0xCC);
+        patch
+    }
+
+    #[test]
+    fn patches_are_sized_by_family() {
+        let mut data = patch(0x0830_107A, 3200);
+        data.extend(patch(0x0830_107B, 3200));
+        data.extend(vec![0xFF; 0x100]);
+        let patches = patches(&data);
+        let found: Vec<(usize, usize, u32)> = patches
+            .iter()
+            .map(|patch| (patch.offset, patch.size, patch.patch_level))
+            .collect();
+        assert_eq!(found, [(0, 3200, 0x0830_107A), (3200, 3200, 0x0830_107B)]);
+        assert_eq!(patches[0].date, "2024-01-15");
+
+        // Family 1Ah patches are as large as the data holding them
+        let data = patch(0x0B00_4010, 0x3800);
+        let patches = super::patches(&data);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].size, 0x3800);
+
+        // A family 19h patch cut short
+        let data = patch(0x0A20_1210, 0x1000);
+        assert_eq!(super::patches(&data)[0].size, 0x1000);
+    }
+
+    #[test]
+    fn containers_map_signatures_to_patches() {
+        let mut table = Vec::new();
+        for (signature, id) in [(0x0083_0F10u32, 0x8310u16), (0x0083_0F11, 0x8311), (0, 0)] {
+            table.extend_from_slice(&signature.to_le_bytes());
+            table.extend_from_slice(&[0; 8]);
+            table.extend_from_slice(&id.to_le_bytes());
+            table.extend_from_slice(&[0; 2]);
+        }
+        let patch = patch(0x0830_107A, 3200);
+        let mut data = CONTAINER_MAGIC.to_le_bytes().to_vec();
+        for (kind, section) in [(SECTION_EQUIVALENCE_TABLE, &table), (SECTION_PATCH, &patch)] {
+            data.extend_from_slice(&kind.to_le_bytes());
+            data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            data.extend_from_slice(section);
+        }
+        let patches = patches(&data);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].offset, 12 + table.len() + 8);
+        assert_eq!(patches[0].size, 3200);
+        assert_eq!(patches[0].signatures, [0x0083_0F10]);
+    }
+}
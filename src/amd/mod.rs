@@ -1,52 +1,600 @@
 // SPDX-License-Identifier: MIT
 
-use alloc::string::String;
-use core::mem;
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::{
+    cell::{OnceCell, RefCell},
+    mem,
+};
 use serde::{Deserialize, Serialize};
-use zerocopy::LayoutVerified;
+use zerocopy::FromBytes;
 
+use self::directory::{AddressMode, Directory, DirectoryHeader};
+use self::platform::Platform;
+use crate::layout::{Area, Layout};
+use crate::source::Source;
+use crate::Error;
+
+pub mod address;
+pub mod builder;
 pub mod directory;
+pub mod diff;
+pub(crate) mod edit;
+pub mod extract;
 pub mod flash;
+mod merge;
+pub mod microcode;
+pub mod platform;
+pub(crate) mod sign;
+pub mod stream;
+pub mod usage;
+pub mod verify;
+pub mod walk;
+
+/// First bytes of the EFS, 0x55AA55AA in little endian
+pub const EFS_MAGIC: [u8; 4] = [0xaa, 0x55, 0xaa, 0x55];
+
+/// Offsets of the EFS in a 16M flash, as placed by AMD's tools
+const EFS_OFFSETS: [usize; 6] = [
+    0x02_0000, 0x82_0000, 0xC2_0000, 0xE2_0000, 0xF2_0000, 0xFA_0000,
+];
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+/// Size of the flash the offsets of `EFS_OFFSETS` are in
+const EFS_WINDOW: usize = 0x100_0000;
+
+/// Value of unset EFS directory pointers, along with 0
+pub const DIR_UNSET: u32 = 0xffff_ffff;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Rom<'a> {
     data: &'a [u8],
+    efs_offset: usize,
     efs: flash::EFS,
+    platform: Platform,
+    /// Directories reachable from the EFS, parsed on first use
+    #[serde(skip)]
+    directories: OnceCell<Vec<(usize, Directory<'a>)>>,
+    /// Other directories parsed by offset, so each is parsed once
+    #[serde(skip)]
+    parsed: RefCell<Vec<(usize, Directory<'a>)>>,
 }
 
 impl<'a> Rom<'a> {
-    pub fn new(data: &'a [u8]) -> Result<Rom, String> {
-        let mut i = 0;
-        // TODO: Can we just iterate over chunks? The last one may be too short.
-        /*
-        for block in data.chunks(0x1000) {
+    pub fn new(data: &'a [u8]) -> Result<Rom<'a>, Error> {
+        let min = mem::size_of::<flash::EFS>();
+        if data.len() < min {
+            return Err(Error::ImageTooSmall {
+                size: data.len(),
+                min,
+            });
         }
-        */
-        // TODO: Handle errors?
-        while i + mem::size_of::<flash::EFS>() <= data.len() {
-            if data[i..i + 4] == [0xaa, 0x55, 0xaa, 0x55] {
-                let lv: LayoutVerified<_, flash::EFS> =
-                    LayoutVerified::new_unaligned_from_prefix(&data[i..])
-                        .unwrap()
-                        .0;
-                return Ok(Rom {
-                    data: &data[i..],
-                    efs: *lv,
-                    // .map_err(|err| format!("EFS invalid: {:?}", err))?,
-                });
+        Self::with_efs(data, Self::find_efs(data)?)
+    }
+
+    /// Parses the image from the EFS at `offset`, such as one of the copies
+    /// `scan_all` finds, instead of the first EFS
+    pub fn with_efs(data: &'a [u8], offset: usize) -> Result<Rom<'a>, Error> {
+        let efs = data
+            .get(offset..)
+            .filter(|efs| efs.starts_with(&EFS_MAGIC))
+            .ok_or(Error::EfsNotFound {
+                offset: Some(offset),
+            })?;
+        let efs = flash::EFS::read_from_prefix(efs).ok_or(Error::EfsTruncated {
+            offset,
+            have: data.len() - offset,
+        })?;
+        let platform = Platform::detect_mapping(&efs, offset, data.len(), |offset| {
+            let mut magic = [0; 4];
+            data.read_at(offset, &mut magic).ok().map(|_| magic)
+        });
+        Ok(Rom {
+            data,
+            efs_offset: offset,
+            efs,
+            platform,
+            directories: OnceCell::new(),
+            parsed: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Offset of the EFS: the first of the offsets AMD places it at in every
+    /// 16M of the image that holds one, then the first 4K aligned one, then
+    /// the first at any offset for images carved out of a flash. A magic too
+    /// close to the end to hold an EFS is only returned if no other is found.
+    fn find_efs(data: &[u8]) -> Result<usize, Error> {
+        let size = mem::size_of::<flash::EFS>();
+        let fits = |offset: usize| data.len() - offset >= size;
+        let magic_at = |offset: &usize| data[*offset..].starts_with(&EFS_MAGIC);
+
+        let candidates = (0..data.len())
+            .step_by(EFS_WINDOW)
+            .flat_map(|window| EFS_OFFSETS.iter().map(move |offset| window + offset))
+            .filter(|offset| *offset < data.len());
+        let aligned = (0..data.len()).step_by(0x1000);
+        let unaligned = Magics { data, offset: 0 };
+
+        let mut truncated = None;
+        for offset in candidates.chain(aligned).filter(magic_at).chain(unaligned) {
+            if fits(offset) {
+                return Ok(offset);
             }
+            truncated.get_or_insert(offset);
+        }
+        match truncated {
+            Some(offset) => Err(Error::EfsTruncated {
+                offset,
+                have: data.len() - offset,
+            }),
+            None => Err(Error::EfsNotFound { offset: None }),
+        }
+    }
 
-            i += 0x1000;
+    /// Every EFS of the image with its offset, in the order of the offsets,
+    /// such as the copies of A/B recovery layouts. EFS are searched at 4K
+    /// aligned offsets, which those AMD places are, then at any offset if
+    /// none is found there. A magic too close to the end to hold an EFS is
+    /// skipped.
+    pub fn scan_all(data: &[u8]) -> Vec<(usize, flash::EFS)> {
+        let read =
+            |offset: usize| flash::EFS::read_from_prefix(&data[offset..]).map(|efs| (offset, efs));
+        let found: Vec<(usize, flash::EFS)> = (0..data.len())
+            .step_by(0x1000)
+            .filter(|offset| data[*offset..].starts_with(&EFS_MAGIC))
+            .filter_map(read)
+            .collect();
+        if !found.is_empty() {
+            return found;
         }
+        Magics { data, offset: 0 }.filter_map(read).collect()
+    }
 
-        Err(format!("Embedded Firmware Structure not found"))
+    /// Parses the image for the given platform instead of the detected one
+    pub fn with_platform(data: &'a [u8], platform: Platform) -> Result<Rom<'a>, Error> {
+        let mut rom = Self::new(data)?;
+        rom.platform = platform;
+        Ok(rom)
     }
 
+    /// Parses the image from the EFS at `offset` for the given platform,
+    /// combining `with_efs` and `with_platform`
+    pub fn with_efs_and_platform(
+        data: &'a [u8],
+        offset: usize,
+        platform: Platform,
+    ) -> Result<Rom<'a>, Error> {
+        let mut rom = Self::with_efs(data, offset)?;
+        rom.platform = platform;
+        Ok(rom)
+    }
+
+    /// The whole image. Before the EFS offset was tracked this was the
+    /// image from the EFS on; slice it at `efs_offset` for that.
     pub fn data(&self) -> &'a [u8] {
         self.data
     }
 
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
     pub fn efs(&self) -> flash::EFS {
         self.efs
     }
+
+    /// Offset of the EFS in the image
+    pub fn efs_offset(&self) -> usize {
+        self.efs_offset
+    }
+
+    /// Converts a directory or entry address into an offset in the image.
+    /// Addresses may be memory mapped, see `Platform::offset`.
+    pub fn offset(&self, address: u64) -> usize {
+        self.platform.offset(address)
+    }
+
+    /// Converts an entry address into an offset in the image, honoring its
+    /// address mode. `directory` is the offset of the directory containing
+    /// the entry.
+    pub fn resolve_address(&self, directory: usize, address: u64) -> Result<usize, Error> {
+        self.platform.resolve_address(directory, address)
+    }
+
+    /// Converts an offset in the image into an entry address of the given
+    /// mode. `directory` is the offset of the directory containing the entry.
+    pub fn encode_address(
+        &self,
+        mode: AddressMode,
+        directory: usize,
+        offset: usize,
+    ) -> Result<u64, Error> {
+        self.platform.encode_address(mode, directory, offset)
+    }
+
+    /// Parses the directory at `offset` in the image. Every directory is
+    /// parsed once, later calls return the directory parsed first.
+    pub fn directory(&self, offset: usize) -> Result<Directory<'a>, Error> {
+        let cached = |directories: &[(usize, Directory<'a>)]| {
+            directories
+                .iter()
+                .find(|(o, _)| *o == offset)
+                .map(|(_, directory)| directory.clone())
+        };
+        if let Some(directory) = self.directories.get().and_then(|d| cached(d)) {
+            return Ok(directory);
+        }
+        if let Some(directory) = cached(&self.parsed.borrow()) {
+            return Ok(directory);
+        }
+        let data = self.data.get(offset..).ok_or(Error::OutOfBounds {
+            offset,
+            size: mem::size_of::<DirectoryHeader>(),
+            have: self.data.len(),
+        })?;
+        let directory = parse_directory(data, offset)?;
+        self.parsed.borrow_mut().push((offset, directory.clone()));
+        Ok(directory)
+    }
+
+    /// Directory pointers of the EFS that are set
+    pub fn efs_directories(&self) -> Vec<u32> {
+        self.efs.directory_pointers()
+    }
+
+    /// Every directory reachable from the EFS, including combo and level 2
+    /// directories, with its offset in the image. Directories that fail to
+    /// parse are skipped. They are parsed on the first call only.
+    pub fn directories(&self) -> &[(usize, Directory<'a>)] {
+        self.directories.get_or_init(|| self.parse_directories())
+    }
+
+    fn parse_directories(&self) -> Vec<(usize, Directory<'a>)> {
+        reachable(&self.platform, &self.efs, |offset| self.directory(offset))
+    }
+
+    /// Areas of the image claimed by the EFS, the directories and the data
+    /// of their entries. Entries shared between directories are listed once.
+    pub fn layout(&self) -> Layout {
+        layout(
+            &self.platform,
+            self.data.len(),
+            self.efs_offset,
+            self.directories(),
+        )
+    }
+
+    /// Areas claimed by the EFS, the directories and the data of their
+    /// entries, in that order. Unlike those of `layout`, areas may overlap
+    /// and reach past the end of the image.
+    pub fn areas(&self) -> Vec<Area> {
+        areas(&self.platform, self.efs_offset, self.directories())
+    }
+}
+
+/// Parses the directory at the start of `data`, found at `offset` in the
+/// image
+fn parse_directory(data: &[u8], offset: usize) -> Result<Directory<'_>, Error> {
+    Directory::new(data).map_err(|err| match err {
+        Error::DirectoryMagicMismatch { found, .. } => Error::DirectoryMagicMismatch {
+            found,
+            addr: offset,
+        },
+        err => err,
+    })
+}
+
+/// Every directory reachable from the EFS, parsed by `parse` from its offset
+/// in the image. Directories that fail to parse are skipped.
+fn reachable<'d>(
+    platform: &Platform,
+    efs: &flash::EFS,
+    mut parse: impl FnMut(usize) -> Result<Directory<'d>, Error>,
+) -> Vec<(usize, Directory<'d>)> {
+    let mut queue: VecDeque<usize> = efs
+        .directory_pointers()
+        .into_iter()
+        .map(|pointer| platform.offset(pointer as u64))
+        .collect();
+    let mut directories: Vec<(usize, Directory<'d>)> = Vec::new();
+    while let Some(offset) = queue.pop_front() {
+        if directories.iter().any(|(o, _)| *o == offset) {
+            continue;
+        }
+        let directory = match parse(offset) {
+            Ok(directory) => directory,
+            Err(_) => continue,
+        };
+        match &directory {
+            Directory::Bios(dir) | Directory::BiosLevel2(dir) => {
+                for entry in dir.entries() {
+                    if entry.kind == 0x70 {
+                        queue.extend(platform.resolve_address(offset, entry.source));
+                    }
+                }
+            }
+            Directory::Psp(dir) | Directory::PspLevel2(dir) => {
+                for entry in dir.entries() {
+                    if entry.is_directory() {
+                        queue.extend(platform.resolve_address(offset, entry.value));
+                    }
+                }
+            }
+            Directory::BiosCombo(combo) => {
+                for entry in combo.entries() {
+                    queue.extend(platform.resolve_address(offset, entry.directory));
+                }
+            }
+            Directory::PspCombo(combo) => {
+                for entry in combo.entries() {
+                    queue.extend(platform.resolve_address(offset, entry.directory));
+                }
+            }
+        }
+        directories.push((offset, directory));
+    }
+    directories
+}
+
+/// Areas of an image of `size` bytes claimed by the EFS, the directories and
+/// the data of their entries
+fn layout(
+    platform: &Platform,
+    size: usize,
+    efs_offset: usize,
+    directories: &[(usize, Directory)],
+) -> Layout {
+    let mut layout = Layout::new(size);
+    for area in areas(platform, efs_offset, directories) {
+        layout.insert(area.name, area.offset, area.size);
+    }
+    layout
+}
+
+/// Areas claimed by the EFS, the directories and the data of their entries
+fn areas(platform: &Platform, efs_offset: usize, directories: &[(usize, Directory)]) -> Vec<Area> {
+    let mut areas = vec![Area {
+        name: String::from("EFS"),
+        offset: efs_offset,
+        size: mem::size_of::<flash::EFS>(),
+    }];
+    for (offset, directory) in directories.iter() {
+        areas.push(Area {
+            name: String::from(directory.name()),
+            offset: *offset,
+            size: directory.size(),
+        });
+    }
+    for (offset, directory) in directories.iter() {
+        match directory {
+            Directory::Bios(dir) | Directory::BiosLevel2(dir) => {
+                for entry in dir.entries() {
+                    if entry.kind == 0x70 {
+                        continue;
+                    }
+                    if let Ok(start) = platform.resolve_address(*offset, entry.source) {
+                        areas.push(Area {
+                            name: format!("BIOS {:02X} {}", entry.kind, entry.description()),
+                            offset: start,
+                            size: entry.size as usize,
+                        });
+                    }
+                }
+            }
+            Directory::Psp(dir) | Directory::PspLevel2(dir) => {
+                for entry in dir.entries() {
+                    if entry.is_directory() || entry.size == 0xFFFF_FFFF {
+                        continue;
+                    }
+                    if let Ok(start) = platform.resolve_address(*offset, entry.value) {
+                        areas.push(Area {
+                            name: format!("PSP {:02X} {}", entry.kind, entry.description()),
+                            offset: start,
+                            size: entry.size as usize,
+                        });
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    areas
+}
+
+/// Offsets of every EFS magic in the data, in order
+struct Magics<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Magics<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.offset < self.data.len() {
+            // Skip to the next candidate first byte, which is much faster than
+            // comparing every window of four bytes
+            let skip = self.data[self.offset..]
+                .iter()
+                .position(|byte| *byte == EFS_MAGIC[0])?;
+            let offset = self.offset + skip;
+            self.offset = offset + 1;
+            if self.data[offset..].starts_with(&EFS_MAGIC) {
+                return Some(offset);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::address::FlashAddress;
+    use super::platform::Generation;
+    use super::*;
+    use crate::testutil::{
+        AmdFixture, BIOS_OFFSET, EFS_OFFSET, PSP_LEVEL2B_OFFSET, PSP_LEVEL2_OFFSET, PSP_OFFSET,
+    };
+
+    #[test]
+    fn verify_checksum_reports_mismatch() {
+        let mut image = AmdFixture::default().build();
+        assert_eq!(Rom::new(&image).unwrap().verify_checksum(), Ok(()));
+
+        // Size of the first PSP entry, covered by the checksum
+        image[PSP_OFFSET + 0x14] ^= 1;
+        let rom = Rom::new(&image).unwrap();
+        assert!(matches!(
+            rom.verify_checksum(),
+            Err(Error::ChecksumMismatch {
+                addr: PSP_OFFSET,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn second_gen_efs_decodes_extended_fields() {
+        let image = AmdFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        let mut efs = rom.efs();
+        efs.psp_backup = PSP_OFFSET as u32;
+        efs.spi_mode_19 = 5;
+        efs.spi_speed_19 = 4;
+        assert_eq!(
+            efs.psp_backup_directory(&rom.platform()),
+            Some(FlashAddress(PSP_OFFSET))
+        );
+        assert!(efs.directory_pointers().contains(&(PSP_OFFSET as u32)));
+        let spi = rom.platform().spi_config_19(&efs).unwrap();
+        assert_eq!(spi.mode_description, "Quad I/O 1-4-4");
+        assert_eq!(spi.speed_description, "100 MHz");
+
+        efs.second_gen = 0xFFFF_FFFF;
+        assert_eq!(efs.psp_backup_directory(&rom.platform()), None);
+        assert_eq!(efs.spi_mode_19(), None);
+        assert!(!efs
+            .pointer_fields()
+            .iter()
+            .any(|(field, _)| *field == "psp_backup"));
+        assert_eq!(
+            flash::SpiConfig::new(0, 0, 0xFF, false).micron_description,
+            "Not Micron"
+        );
+    }
+
+    #[test]
+    fn platform_is_detected_for_each_flash_size() {
+        let layouts = [
+            (0x100_0000, 0),
+            (0x200_0000, 0),
+            (0x200_0000, 0x100_0000),
+            (0x400_0000, 0x300_0000),
+        ];
+        for (size, window_offset) in layouts {
+            let image = AmdFixture {
+                size,
+                window_offset,
+                ..AmdFixture::default()
+            }
+            .build();
+            let rom = Rom::new(&image).unwrap();
+            let platform = rom.platform();
+            assert_eq!(
+                platform,
+                Platform {
+                    generation: Generation::Second,
+                    flash_size: size,
+                    window_offset,
+                    image_offset: 0,
+                }
+            );
+            let efs = rom.efs();
+            assert_eq!(
+                efs.psp_directory(&platform),
+                Some(FlashAddress(window_offset + PSP_OFFSET))
+            );
+            assert_eq!(
+                efs.bios_directory(&platform),
+                Some(FlashAddress(window_offset + BIOS_OFFSET))
+            );
+        }
+    }
+
+    #[test]
+    fn platform_is_detected_for_regions() {
+        let image_offset = 0x1_0000;
+        let image = AmdFixture {
+            image_offset,
+            ..AmdFixture::default()
+        }
+        .build();
+        let rom = Rom::new(&image).unwrap();
+        let platform = rom.platform();
+        assert_eq!(platform.flash_size, 0x100_0000);
+        assert_eq!(platform.image_offset, image_offset);
+        let efs = rom.efs();
+        assert_eq!(
+            efs.psp_directory(&platform),
+            Some(FlashAddress(PSP_OFFSET - image_offset))
+        );
+        assert_eq!(
+            efs.bios_directory(&platform),
+            Some(FlashAddress(BIOS_OFFSET - image_offset))
+        );
+    }
+
+    #[test]
+    fn each_efs_of_ab_layouts_resolves_its_copy() {
+        let mut image = AmdFixture {
+            size: 0x200_0000,
+            ab_recovery: true,
+            ..AmdFixture::default()
+        }
+        .build();
+        let (a, b) = image.split_at_mut(0x100_0000);
+        b.copy_from_slice(a);
+        let offsets: Vec<usize> = Rom::scan_all(&image).iter().map(|(o, _)| *o).collect();
+        assert_eq!(offsets, [EFS_OFFSET, 0x100_0000 + EFS_OFFSET]);
+
+        let first = Rom::with_efs(&image, offsets[0]).unwrap();
+        let second = Rom::with_efs(&image, offsets[1]).unwrap();
+        assert_eq!(first.platform().window_offset, 0);
+        assert_eq!(second.platform().window_offset, 0x100_0000);
+        assert_eq!(second.data().len(), image.len());
+        assert_eq!(second.efs_offset(), offsets[1]);
+        let directories =
+            |rom: &Rom| -> Vec<usize> { rom.directories().iter().map(|(o, _)| *o).collect() };
+        for offset in [
+            PSP_OFFSET,
+            PSP_LEVEL2_OFFSET,
+            PSP_LEVEL2B_OFFSET,
+            BIOS_OFFSET,
+        ] {
+            assert!(directories(&first).contains(&offset));
+            assert!(directories(&second).contains(&(0x100_0000 + offset)));
+        }
+        assert!(directories(&second).iter().all(|o| *o >= 0x100_0000));
+    }
+
+    #[test]
+    fn missing_efs_is_not_found() {
+        let image = AmdFixture::default().build();
+        assert!(matches!(
+            Rom::with_efs(&image, 0),
+            Err(Error::EfsNotFound { offset: Some(0) })
+        ));
+        assert!(matches!(
+            Rom::new(&vec![0xFF; 0x1000]),
+            Err(Error::EfsNotFound { offset: None })
+        ));
+    }
+
+    #[test]
+    fn directory_past_the_end_is_out_of_bounds() {
+        let image = AmdFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        assert!(matches!(
+            rom.directory(image.len() + 1),
+            Err(Error::OutOfBounds { .. })
+        ));
+    }
 }
@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+use super::address::{flash_mask, AddrTranslator, FlashAddress};
+use super::directory::{check_signature, AddressMode, SIGNATURES};
+use super::{flash, EFS_OFFSETS, EFS_WINDOW};
+use crate::Error;
+
+/// Generation of the EFS, telling which of its fields the SoC reads
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Generation {
+    /// Family 15h and family 17h models 00h to 2Fh
+    First,
+    /// Family 17h models 30h and later and family 19h, bit 0 of
+    /// `EFS::second_gen` is clear
+    Second,
+}
+
+/// SoC generation, flash size and mapping an image is parsed for, which
+/// select the address masks and the EFS fields in use
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Platform {
+    pub generation: Generation,
+    /// Size of the flash, a power of two. The flash is mapped at the top of
+    /// the 4G address space.
+    pub flash_size: usize,
+    /// Offset in the flash of the 16M mapped below 4G, which is not the
+    /// first 16M of larger flashes booting from an EFS further in
+    #[serde(default)]
+    pub window_offset: usize,
+    /// Offset in the flash the image starts at, for images of a region of
+    /// the flash
+    #[serde(default)]
+    pub image_offset: usize,
+}
+
+impl Platform {
+    /// Detects the platform of an image of `size` bytes from its EFS
+    pub fn detect(efs: &flash::EFS, size: usize) -> Self {
+        let generation = if efs.is_second_gen() {
+            Generation::Second
+        } else {
+            Generation::First
+        };
+        Platform {
+            generation,
+            flash_size: size.next_power_of_two(),
+            window_offset: 0,
+            image_offset: 0,
+        }
+    }
+
+    /// Detects the platform of an image of `size` bytes from its EFS at
+    /// `efs_offset`, along with how the image is mapped. Larger flashes may
+    /// map the 16M holding the EFS below 4G, and images of a region of the
+    /// flash are placed from where AMD places the EFS or at the end of the
+    /// flash. Of these mappings, the one resolving the most EFS directory
+    /// pointers to a directory is kept, the image being the whole flash
+    /// with the 16M holding the EFS mapped on a tie, so each EFS of A/B
+    /// layouts repeating the same pointers resolves them to its own copy.
+    /// `magic` reads the first 4 bytes at an offset in the image.
+    pub fn detect_mapping(
+        efs: &flash::EFS,
+        efs_offset: usize,
+        size: usize,
+        magic: impl Fn(usize) -> Option<[u8; 4]>,
+    ) -> Self {
+        let whole = Self::detect(efs, size);
+        let mut candidates = vec![
+            Platform {
+                window_offset: efs_offset / EFS_WINDOW * EFS_WINDOW,
+                ..whole
+            },
+            whole,
+        ];
+        let regions = EFS_OFFSETS
+            .iter()
+            .filter_map(|offset| offset.checked_sub(efs_offset))
+            .chain(Some(whole.flash_size - size));
+        for image_offset in regions {
+            candidates.push(Platform {
+                flash_size: (image_offset + size).next_power_of_two(),
+                image_offset,
+                ..whole
+            });
+        }
+
+        let pointers = efs.directory_pointers();
+        let resolved = |platform: &Platform| {
+            pointers
+                .iter()
+                .filter_map(|pointer| magic(platform.offset(*pointer as u64)))
+                .filter(|signature| check_signature(signature, &SIGNATURES).is_ok())
+                .count()
+        };
+        // The last of the best is returned, so the first candidate is last
+        candidates
+            .into_iter()
+            .rev()
+            .max_by_key(resolved)
+            .unwrap_or(whole)
+    }
+
+    /// Mask of the address bits selecting a byte of the flash
+    pub fn address_mask(&self) -> u64 {
+        flash_mask(self.flash_size)
+    }
+
+    /// Translator of the addresses of the flash. Both generations map at
+    /// most 16M of the flash below 4G, second generation SoCs map all of it
+    /// above 4G too.
+    pub fn translator(&self) -> AddrTranslator {
+        AddrTranslator::with_mapping(self.flash_size, self.window_offset, self.image_offset)
+    }
+
+    /// Converts a memory mapped address or a flash offset into an image
+    /// offset
+    pub fn offset(&self, address: u64) -> usize {
+        self.translator().offset(address).0
+    }
+
+    /// Memory mapped address of an image offset
+    pub fn mmio_address(&self, offset: usize) -> Result<u64, Error> {
+        Ok(self.translator().mmio(FlashAddress(offset))?.0)
+    }
+
+    /// Address mode of an EFS pointer, which is a flash offset if it falls
+    /// within the flash and memory mapped otherwise
+    pub fn pointer_mode(&self, pointer: u32) -> AddressMode {
+        if (pointer as usize) < self.flash_size {
+            AddressMode::FlashOffset
+        } else {
+            AddressMode::PhysAddr
+        }
+    }
+
+    /// Converts an entry address into an image offset, honoring its address
+    /// mode. `directory` is the offset of the directory containing the entry.
+    pub fn resolve_address(&self, directory: usize, address: u64) -> Result<usize, Error> {
+        let bits = address & AddressMode::ADDRESS_MASK;
+        match AddressMode::of(address) {
+            AddressMode::PhysAddr | AddressMode::FlashOffset => Ok(self.offset(bits)),
+            AddressMode::DirHeaderOffset => Ok(directory + bits as usize),
+            AddressMode::SlotOffset => Err(Error::Unsupported(format!(
+                "slot relative address {:#X} not supported",
+                address
+            ))),
+        }
+    }
+
+    /// Converts an image offset into an entry address of the given mode.
+    /// `directory` is the offset of the directory containing the entry.
+    pub fn encode_address(
+        &self,
+        mode: AddressMode,
+        directory: usize,
+        offset: usize,
+    ) -> Result<u64, Error> {
+        let address = match mode {
+            AddressMode::PhysAddr => self.mmio_address(offset)?,
+            AddressMode::FlashOffset => (offset + self.image_offset) as u64,
+            AddressMode::DirHeaderOffset => offset.checked_sub(directory).ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "offset {:#X} precedes directory {:#X}",
+                    offset, directory
+                ))
+            })? as u64,
+            AddressMode::SlotOffset => {
+                return Err(Error::Unsupported(String::from(
+                    "slot relative addresses not supported",
+                )))
+            }
+        };
+        Ok(address | mode.bits() << 62)
+    }
+
+    /// SPI mode set in the EFS for this generation
+    pub fn spi_mode(&self, efs: &flash::EFS) -> u8 {
+        match self.generation {
+            Generation::First => efs.spi_mode_17_00_1f,
+            Generation::Second => efs.spi_mode,
+        }
+    }
+
+    /// SPI speed set in the EFS for this generation
+    pub fn spi_speed(&self, efs: &flash::EFS) -> u8 {
+        match self.generation {
+            Generation::First => efs.spi_speed_17_00_1f,
+            Generation::Second => efs.spi_speed,
+        }
+    }
+
+    /// Micron flag set in the EFS for this generation
+    pub fn micron(&self, efs: &flash::EFS) -> u8 {
+        match self.generation {
+            Generation::First => efs.micron_17_00_1f,
+            Generation::Second => efs.micron,
+        }
+    }
+
+    /// SPI settings of the EFS for this generation, decoded
+    pub fn spi_config(&self, efs: &flash::EFS) -> flash::SpiConfig {
+        flash::SpiConfig::new(
+            self.spi_mode(efs),
+            self.spi_speed(efs),
+            self.micron(efs),
+            self.generation == Generation::Second,
+        )
+    }
+
+    /// SPI settings of family 19h, decoded, if the EFS is of the second
+    /// generation and sets them. The Micron flag is the one of family 17h
+    /// model 30h and later.
+    pub fn spi_config_19(&self, efs: &flash::EFS) -> Option<flash::SpiConfig> {
+        if self.generation != Generation::Second {
+            return None;
+        }
+        match (efs.spi_mode_19()?, efs.spi_speed_19()?) {
+            (0xFF, 0xFF) => None,
+            (mode, speed) => Some(flash::SpiConfig::new(mode, speed, efs.micron, true)),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: MIT
+
+//! AMD images parsed from a `Source`, reading only the EFS and the
+//! directories, so images of any size are parsed without holding them in
+//! memory. Entry data is read when asked for.
+
+use alloc::vec::Vec;
+use core::mem;
+use zerocopy::FromBytes;
+
+use super::directory::{
+    BiosDirectoryEntry, ComboDirectoryEntry, ComboDirectoryHeader, Directory, PspDirectoryEntry,
+};
+use super::platform::Platform;
+use super::{
+    flash, layout, parse_directory, reachable, Magics, EFS_MAGIC, EFS_OFFSETS, EFS_WINDOW,
+};
+use crate::layout::Layout;
+use crate::source::Source;
+use crate::Error;
+
+/// Bytes read at once while searching the image for an unaligned EFS
+const CHUNK: usize = 0x10_0000;
+
+/// AMD image read on demand, the counterpart of `Rom` for images not in
+/// memory. Offsets are in the source.
+pub struct StreamedRom<S> {
+    source: S,
+    efs_offset: usize,
+    efs: flash::EFS,
+    platform: Platform,
+    directories: Vec<(usize, Directory<'static>)>,
+}
+
+impl<S: Source> StreamedRom<S> {
+    /// Finds the EFS like `Rom::new` and parses every directory reachable
+    /// from it
+    pub fn new(source: S) -> Result<Self, Error> {
+        let min = mem::size_of::<flash::EFS>();
+        if source.len() < min {
+            return Err(Error::ImageTooSmall {
+                size: source.len(),
+                min,
+            });
+        }
+        let efs_offset = find_efs(&source)?;
+        let efs = flash::EFS::read_from(source.read(efs_offset, min)?.as_slice()).ok_or(
+            Error::EfsTruncated {
+                offset: efs_offset,
+                have: source.len() - efs_offset,
+            },
+        )?;
+        let platform = Platform::detect_mapping(&efs, efs_offset, source.len(), |offset| {
+            let mut magic = [0; 4];
+            source.read_at(offset, &mut magic).ok().map(|_| magic)
+        });
+        let directories = reachable(&platform, &efs, |offset| read_directory(&source, offset));
+        Ok(StreamedRom {
+            source,
+            efs_offset,
+            efs,
+            platform,
+            directories,
+        })
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn efs(&self) -> flash::EFS {
+        self.efs
+    }
+
+    pub fn efs_offset(&self) -> usize {
+        self.efs_offset
+    }
+
+    /// Every directory reachable from the EFS, like `Rom::directories`
+    pub fn directories(&self) -> &[(usize, Directory<'static>)] {
+        &self.directories
+    }
+
+    /// Data of a PSP directory entry of the directory at `directory`, read
+    /// from the source. Entries holding a value instead of data have none.
+    pub fn psp_entry_data(
+        &self,
+        directory: usize,
+        entry: &PspDirectoryEntry,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        if entry.size == 0xFFFF_FFFF {
+            return Ok(None);
+        }
+        let offset = self.platform.resolve_address(directory, entry.value)?;
+        self.source.read(offset, entry.size as usize).map(Some)
+    }
+
+    /// Data of a BIOS directory entry of the directory at `directory`, read
+    /// from the source
+    pub fn bios_entry_data(
+        &self,
+        directory: usize,
+        entry: &BiosDirectoryEntry,
+    ) -> Result<Vec<u8>, Error> {
+        let offset = self.platform.resolve_address(directory, entry.source)?;
+        self.source.read(offset, entry.size as usize)
+    }
+
+    /// Areas of the image, like `Rom::layout`
+    pub fn layout(&self) -> Layout {
+        layout(
+            &self.platform,
+            self.source.len(),
+            self.efs_offset,
+            &self.directories,
+        )
+    }
+}
+
+/// Offset of the EFS, searched in the order of `Rom::new`
+fn find_efs(source: &impl Source) -> Result<usize, Error> {
+    let len = source.len();
+    let size = mem::size_of::<flash::EFS>();
+    let magic_at = |offset: &usize| {
+        let mut magic = [0; 4];
+        source.read_at(*offset, &mut magic).is_ok() && magic == EFS_MAGIC
+    };
+
+    let candidates = (0..len)
+        .step_by(EFS_WINDOW)
+        .flat_map(|window| EFS_OFFSETS.iter().map(move |offset| window + offset))
+        .filter(|offset| *offset < len);
+    let aligned = (0..len).step_by(0x1000);
+    let unaligned = (0..len).step_by(CHUNK).flat_map(|start| {
+        // Chunks overlap by the size of the magic less one, so magics across
+        // chunks are found
+        let end = len.min(start + CHUNK + EFS_MAGIC.len() - 1);
+        let data = source.read(start, end - start).unwrap_or_default();
+        Magics {
+            data: &data,
+            offset: 0,
+        }
+        .map(|offset| start + offset)
+        .collect::<Vec<usize>>()
+    });
+
+    let mut truncated = None;
+    for offset in candidates.chain(aligned).filter(magic_at).chain(unaligned) {
+        if len - offset >= size {
+            return Ok(offset);
+        }
+        truncated.get_or_insert(offset);
+    }
+    match truncated {
+        Some(offset) => Err(Error::EfsTruncated {
+            offset,
+            have: len - offset,
+        }),
+        None => Err(Error::EfsNotFound { offset: None }),
+    }
+}
+
+/// Reads and parses the directory at `offset`. The header tells the number
+/// of entries, enough bytes for the largest header and entries are read.
+fn read_directory(source: &impl Source, offset: usize) -> Result<Directory<'static>, Error> {
+    let available = source.len().checked_sub(offset).ok_or(Error::Truncated {
+        need: offset,
+        have: source.len(),
+    })?;
+    let mut header = [0; 12];
+    source.read_at(offset, &mut header[..available.min(12)])?;
+    let count = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    let entry = mem::size_of::<PspDirectoryEntry>()
+        .max(mem::size_of::<BiosDirectoryEntry>())
+        .max(mem::size_of::<ComboDirectoryEntry>());
+    let size = count
+        .saturating_mul(entry)
+        .saturating_add(mem::size_of::<ComboDirectoryHeader>())
+        .min(available);
+    let data = source.read(offset, size)?;
+    parse_directory(&data, offset).map(Directory::into_owned)
+}
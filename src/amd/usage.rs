@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: MIT
+
+//! Space taken by the entries of every directory, against the size the
+//! directory declares for itself and the data of its entries
+
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use super::directory::{BiosDirectoryEntry, Directory, PspDirectoryEntry};
+use super::walk::RomVisitor;
+use super::Rom;
+
+/// Space of a PSP or BIOS directory and the data of its entries, in bytes
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DirectoryUsage {
+    /// Bytes of entry data, level 2 directories and value entries left out
+    pub entries: usize,
+    /// Offset of the first byte of the directory or of its entry data
+    pub start: usize,
+    /// Offset following the last byte of the directory or of its entry data
+    pub end: usize,
+    /// Size declared by bits 9:0 of the additional info of the header, 0 if
+    /// the directory declares none
+    pub max_size: usize,
+    /// Bytes of the declared size left after the span from `start` to
+    /// `end`, 0 if the span exceeds it
+    pub headroom: Option<usize>,
+}
+
+struct Usages {
+    usages: Vec<(usize, DirectoryUsage)>,
+}
+
+impl Usages {
+    fn add(&mut self, directory: usize, offset: Option<usize>, data: Option<&[u8]>) {
+        let (offset, len) = match (offset, data) {
+            (Some(offset), Some(data)) if !data.is_empty() => (offset, data.len()),
+            _ => return,
+        };
+        if let Some((_, usage)) = self.usages.iter_mut().find(|(o, _)| *o == directory) {
+            usage.entries += len;
+            usage.start = usage.start.min(offset);
+            usage.end = usage.end.max(offset + len);
+        }
+    }
+}
+
+impl RomVisitor for Usages {
+    fn visit_directory(&mut self, offset: usize, _depth: usize, directory: &Directory) {
+        let header = match directory {
+            Directory::Bios(dir) | Directory::BiosLevel2(dir) => dir.header(),
+            Directory::Psp(dir) | Directory::PspLevel2(dir) => dir.header(),
+            _ => return,
+        };
+        self.usages.push((
+            offset,
+            DirectoryUsage {
+                entries: 0,
+                start: offset,
+                end: offset + directory.size(),
+                max_size: header.max_size(),
+                headroom: None,
+            },
+        ));
+    }
+
+    fn visit_psp_entry(
+        &mut self,
+        directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if !entry.is_directory() {
+            self.add(directory, offset, data);
+        }
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        directory: usize,
+        entry: &BiosDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if entry.kind != 0x70 {
+            self.add(directory, offset, data);
+        }
+    }
+}
+
+impl<'a> Rom<'a> {
+    /// Usage of every PSP and BIOS directory reachable from the EFS, by
+    /// offset of the directory
+    pub fn directory_usage(&self) -> Vec<(usize, DirectoryUsage)> {
+        let mut usages = Usages { usages: Vec::new() };
+        self.walk(&mut usages);
+        for (_, usage) in usages.usages.iter_mut() {
+            if usage.max_size > 0 {
+                usage.headroom = Some(usage.max_size.saturating_sub(usage.end - usage.start));
+            }
+        }
+        usages.usages
+    }
+}
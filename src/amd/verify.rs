@@ -0,0 +1,338 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, mem};
+use rsa::{BigUint, Pss, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+
+use super::directory::{format_key_id, Checksum, Directory, PspBinaryHeader, PspKeyTokenHeader};
+use super::Rom;
+use crate::Error;
+
+/// Checksum of a directory of an image
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DirectoryChecksum {
+    pub offset: usize,
+    /// Name of the kind of directory, such as "PSP Level 2 Directory"
+    pub directory: String,
+    pub checksum: Checksum,
+}
+
+/// Outcome of checking the signature of a PSP binary or key token
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SignatureStatus {
+    Valid,
+    Invalid,
+    /// No key token of the image has the ID of the signing key
+    KeyNotFound,
+    /// The signature does not fit in the entry
+    Truncated,
+    /// The key is of a size the PSP does not sign with
+    Unsupported,
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Valid => write!(f, "valid"),
+            SignatureStatus::Invalid => write!(f, "invalid"),
+            SignatureStatus::KeyNotFound => write!(f, "key not found"),
+            SignatureStatus::Truncated => write!(f, "truncated"),
+            SignatureStatus::Unsupported => write!(f, "unsupported key"),
+        }
+    }
+}
+
+/// Signature of a PSP entry checked against the key tokens of the image
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SignatureCheck {
+    /// Offset of the directory of the entry
+    pub directory: usize,
+    pub kind: u8,
+    /// Offset of the entry data
+    pub offset: usize,
+    /// ID of the signing key in hex
+    pub key_id: String,
+    pub status: SignatureStatus,
+}
+
+/// Public key of a key token, with the part of the token signed by the
+/// certifying key and the signature following it
+struct Key<'a> {
+    header: PspKeyTokenHeader,
+    /// Little endian, as are the modulus and the signature
+    exponent: &'a [u8],
+    modulus: &'a [u8],
+    signed: &'a [u8],
+    signature: &'a [u8],
+}
+
+impl<'a> Key<'a> {
+    /// Parses the key token at the start of `data`
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let header = PspKeyTokenHeader::new(data)?;
+        let signed = data.get(..header.signed_len())?;
+        let exponent_start = mem::size_of::<PspKeyTokenHeader>();
+        let modulus_start = exponent_start + header.exponent_size as usize / 8;
+        Some(Key {
+            header,
+            exponent: &signed[exponent_start..modulus_start],
+            modulus: &signed[modulus_start..],
+            signed,
+            signature: &data[signed.len()..],
+        })
+    }
+
+    /// Every key token in a key table (types 0x50 and 0x51), found at any
+    /// dword after the PSP binary header of the table
+    fn table(data: &'a [u8]) -> Vec<Self> {
+        let mut keys = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            match Key::new(&data[offset..]) {
+                Some(key) => {
+                    offset += key.signed.len();
+                    keys.push(key);
+                }
+                None => offset += 4,
+            }
+        }
+        keys
+    }
+
+    /// Checks an RSASSA-PSS signature over `data`, made the way `sign`
+    /// makes it, trailing bytes of `signature` being ignored
+    fn verify(&self, data: &[u8], signature: &[u8]) -> SignatureStatus {
+        let size = self.modulus.len();
+        let mut signature = match signature.get(..size) {
+            Some(signature) => signature.to_vec(),
+            None => return SignatureStatus::Truncated,
+        };
+        signature.reverse();
+        let key = match RsaPublicKey::new(
+            BigUint::from_bytes_le(self.modulus),
+            BigUint::from_bytes_le(self.exponent),
+        ) {
+            Ok(key) => key,
+            Err(_) => return SignatureStatus::Unsupported,
+        };
+        let result = match size {
+            256 => key.verify(Pss::new::<Sha256>(), &Sha256::digest(data), &signature),
+            512 => key.verify(Pss::new::<Sha384>(), &Sha384::digest(data), &signature),
+            _ => return SignatureStatus::Unsupported,
+        };
+        match result {
+            Ok(()) => SignatureStatus::Valid,
+            Err(_) => SignatureStatus::Invalid,
+        }
+    }
+}
+
+impl<'a> Rom<'a> {
+    /// Checks that the EFS pointers lead to directories, that directory
+    /// checksums are correct and that entries lie within the image, returning
+    /// a description of every problem found
+    pub fn verify(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for pointer in self.efs_directories() {
+            let offset = self.offset(pointer as u64);
+            if offset >= self.data.len() || Directory::new(&self.data[offset..]).is_err() {
+                problems.push(format!("EFS pointer {:#X} invalid", pointer));
+            }
+        }
+
+        for &(offset, ref directory) in self.directories() {
+            let end = offset + directory.size();
+            if end > self.data.len() {
+                problems.push(format!("{} at {:#X} truncated", directory.name(), offset));
+                continue;
+            }
+            if !directory.verify_checksum().is_valid() {
+                problems.push(format!("{} at {:#X} checksum invalid", directory.name(), offset));
+            }
+
+            match directory {
+                Directory::Psp(dir) | Directory::PspLevel2(dir) => {
+                    for entry in dir.entries() {
+                        if entry.size == 0xFFFF_FFFF {
+                            continue;
+                        }
+                        if entry.is_directory() {
+                            if !self.directory_at(offset, entry.value) {
+                                problems.push(format!(
+                                    "PSP entry {:02X} in {:#X} does not point to a directory",
+                                    entry.kind, offset
+                                ));
+                            }
+                            continue;
+                        }
+                        let data = match self.entry_data(offset, entry.value, entry.size) {
+                            Some(data) => data,
+                            None => {
+                                problems.push(format!(
+                                    "PSP entry {:02X} in {:#X} out of bounds",
+                                    entry.kind, offset
+                                ));
+                                continue;
+                            }
+                        };
+                        // Signatures are checked by `verify_signatures`, but
+                        // they must fit in the entry
+                        if let Some(header) = PspBinaryHeader::new(data) {
+                            if header.magic == u32::from_le_bytes(*b"$PS1")
+                                && header.signed == 1
+                                && header.signed_len() + 0x100 > data.len()
+                            {
+                                problems.push(format!(
+                                    "PSP entry {:02X} in {:#X} too small for its signature",
+                                    entry.kind, offset
+                                ));
+                            }
+                        }
+                    }
+                }
+                Directory::Bios(dir) | Directory::BiosLevel2(dir) => {
+                    for entry in dir.entries() {
+                        if entry.kind == 0x70 {
+                            if !self.directory_at(offset, entry.source) {
+                                problems.push(format!(
+                                    "BIOS entry 70 in {:#X} does not point to a directory",
+                                    offset
+                                ));
+                            }
+                        } else if entry.size != 0
+                            && self.entry_data(offset, entry.source, entry.size).is_none()
+                        {
+                            problems.push(format!(
+                                "BIOS entry {:02X} in {:#X} out of bounds",
+                                entry.kind, offset
+                            ));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        for check in self.verify_signatures() {
+            if check.status == SignatureStatus::Invalid {
+                problems.push(format!(
+                    "PSP entry {:02X} in {:#X} signature invalid",
+                    check.kind, check.directory
+                ));
+            }
+        }
+        problems
+    }
+
+    /// Checks the signature of every signed PSP binary, and of every key
+    /// token certified by another key, against the key tokens of the key
+    /// entries, such as the AMD public key (type 0x00) and the OEM public key
+    /// (0x0A), and of the key tables (0x50 and 0x51). Entries shared between
+    /// directories are checked once.
+    pub fn verify_signatures(&self) -> Vec<SignatureCheck> {
+        let mut entries = Vec::new();
+        for &(offset, ref directory) in self.directories() {
+            if let Directory::Psp(dir) | Directory::PspLevel2(dir) = directory {
+                for entry in dir.entries() {
+                    if entry.is_directory() || entry.size == 0xFFFF_FFFF {
+                        continue;
+                    }
+                    let start = match self.resolve_address(offset, entry.value) {
+                        Ok(start) => start,
+                        Err(_) => continue,
+                    };
+                    if entries.iter().any(|&(_, _, other, _)| other == start) {
+                        continue;
+                    }
+                    if let Some(data) = self.entry_data(offset, entry.value, entry.size) {
+                        entries.push((offset, *entry, start, data));
+                    }
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
+        for &(_, entry, _, data) in entries.iter() {
+            match entry.kind {
+                0x50 | 0x51 => keys.extend(Key::table(data)),
+                _ if entry.is_key() => keys.extend(Key::new(data)),
+                _ => (),
+            }
+        }
+
+        let mut checks = Vec::new();
+        for &(directory, entry, offset, data) in entries.iter() {
+            let token = if entry.is_key() { Key::new(data) } else { None };
+            let (key_id, signed, signature) = match (token, PspBinaryHeader::new(data)) {
+                // The root key certifies itself
+                (Some(token), _) if token.header.is_self_signed() => continue,
+                (Some(token), _) => (
+                    token.header.certifying_key_id,
+                    token.signed,
+                    token.signature,
+                ),
+                (None, Some(header))
+                    if header.magic == u32::from_le_bytes(*b"$PS1") && header.signed == 1 =>
+                {
+                    let end = header.signed_len().min(data.len());
+                    (header.signature_key_id, &data[..end], &data[end..])
+                }
+                _ => continue,
+            };
+            let status = match keys.iter().find(|key| key.header.key_id == key_id) {
+                Some(key) => key.verify(signed, signature),
+                None => SignatureStatus::KeyNotFound,
+            };
+            checks.push(SignatureCheck {
+                directory,
+                kind: entry.kind,
+                offset,
+                key_id: format_key_id(&key_id),
+                status,
+            });
+        }
+        checks
+    }
+
+    /// Fails with the `Error::ChecksumMismatch` of the first directory, in
+    /// the order of `directories`, whose checksum is invalid
+    pub fn verify_checksum(&self) -> Result<(), Error> {
+        self.directories()
+            .iter()
+            .try_for_each(|(offset, directory)| directory.verify_checksum().check(*offset))
+    }
+
+    /// Recomputes the checksum of every directory, in the order of
+    /// `directories`
+    pub fn verify_checksums(&self) -> Vec<DirectoryChecksum> {
+        self.directories()
+            .iter()
+            .map(|(offset, directory)| DirectoryChecksum {
+                offset: *offset,
+                directory: String::from(directory.name()),
+                checksum: directory.verify_checksum(),
+            })
+            .collect()
+    }
+
+    /// Data of an entry of the directory at `directory`, if it lies within
+    /// the image
+    fn entry_data(&self, directory: usize, address: u64, size: u32) -> Option<&'a [u8]> {
+        let start = self.resolve_address(directory, address).ok()?;
+        self.data.get(start..start.checked_add(size as usize)?)
+    }
+
+    /// True if the address of an entry of the directory at `directory`
+    /// points to a directory
+    fn directory_at(&self, directory: usize, address: u64) -> bool {
+        match self.resolve_address(directory, address) {
+            Ok(offset) if offset < self.data.len() => Directory::new(&self.data[offset..]).is_ok(),
+            _ => false,
+        }
+    }
+}
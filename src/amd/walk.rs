@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: MIT
+
+//! Depth first traversal of the EFS, the directories reachable from it and
+//! their entries
+
+use alloc::vec::Vec;
+
+use super::directory::{BiosDirectoryEntry, ComboDirectoryEntry, Directory, PspDirectoryEntry};
+use super::{flash, Rom};
+use crate::Error;
+
+/// Callbacks of `Rom::walk`, all doing nothing by default. `depth` is 0 for
+/// directories the EFS points to and grows by one for every directory
+/// followed to reach a directory.
+pub trait RomVisitor {
+    /// Called first, with the offset of the EFS in the image
+    fn visit_efs(&mut self, _offset: usize, _efs: &flash::EFS) {}
+
+    /// Called before the entries of a directory
+    fn visit_directory(&mut self, _offset: usize, _depth: usize, _directory: &Directory) {}
+
+    /// Called after the entries of a directory and everything below them
+    fn leave_directory(&mut self, _offset: usize, _depth: usize, _directory: &Directory) {}
+
+    /// Called for a directory that fails to parse
+    fn visit_error(&mut self, _offset: usize, _depth: usize, _err: &Error) {}
+
+    /// Called for every entry of a PSP directory at `directory`, with the
+    /// offset of the entry data in the image if its address resolves and
+    /// the data if it lies within the image. Entries holding a value
+    /// instead of data have neither.
+    fn visit_psp_entry(
+        &mut self,
+        _directory: usize,
+        _entry: &PspDirectoryEntry,
+        _offset: Option<usize>,
+        _data: Option<&[u8]>,
+    ) {
+    }
+
+    /// Called for every entry of a BIOS directory at `directory`, like
+    /// `visit_psp_entry`
+    fn visit_bios_entry(
+        &mut self,
+        _directory: usize,
+        _entry: &BiosDirectoryEntry,
+        _offset: Option<usize>,
+        _data: Option<&[u8]>,
+    ) {
+    }
+
+    /// Called for every entry of a combo directory at `directory`, with the
+    /// offset of the directory it points to if its address resolves
+    fn visit_combo_entry(
+        &mut self,
+        _directory: usize,
+        _entry: &ComboDirectoryEntry,
+        _offset: Option<usize>,
+    ) {
+    }
+}
+
+impl<'a> Rom<'a> {
+    /// Visits the EFS and every directory reachable from it. The entries of
+    /// a directory are visited in order, a directory an entry points to
+    /// right after that entry. Directories reached more than once are only
+    /// visited the first time.
+    pub fn walk(&self, visitor: &mut impl RomVisitor) {
+        visitor.visit_efs(self.efs_offset, &self.efs);
+        let mut visited = Vec::new();
+        for pointer in self.efs_directories() {
+            self.walk_directory(self.offset(pointer as u64), 0, &mut visited, visitor);
+        }
+    }
+
+    fn walk_directory(
+        &self,
+        offset: usize,
+        depth: usize,
+        visited: &mut Vec<usize>,
+        visitor: &mut impl RomVisitor,
+    ) {
+        if visited.contains(&offset) {
+            return;
+        }
+        visited.push(offset);
+        let directory = match self.directory(offset) {
+            Ok(directory) => directory,
+            Err(err) => {
+                visitor.visit_error(offset, depth, &err);
+                return;
+            }
+        };
+
+        visitor.visit_directory(offset, depth, &directory);
+        match &directory {
+            Directory::Bios(dir) | Directory::BiosLevel2(dir) => {
+                for entry in dir.entries() {
+                    let start = self.resolve_address(offset, entry.source).ok();
+                    let data = start.and_then(|start| self.data_at(start, entry.size));
+                    visitor.visit_bios_entry(offset, entry, start, data);
+                    if let (0x70, Some(start)) = (entry.kind, start) {
+                        self.walk_directory(start, depth + 1, visited, visitor);
+                    }
+                }
+            }
+            Directory::Psp(dir) | Directory::PspLevel2(dir) => {
+                for entry in dir.entries() {
+                    let start = match entry.size {
+                        0xFFFF_FFFF => None,
+                        _ => self.resolve_address(offset, entry.value).ok(),
+                    };
+                    let data = start.and_then(|start| self.data_at(start, entry.size));
+                    visitor.visit_psp_entry(offset, entry, start, data);
+                    if let (true, Some(start)) = (entry.is_directory(), start) {
+                        self.walk_directory(start, depth + 1, visited, visitor);
+                    }
+                }
+            }
+            Directory::BiosCombo(combo) => {
+                for entry in combo.entries() {
+                    self.walk_combo_entry(offset, entry, depth, visited, visitor);
+                }
+            }
+            Directory::PspCombo(combo) => {
+                for entry in combo.entries() {
+                    self.walk_combo_entry(offset, entry, depth, visited, visitor);
+                }
+            }
+        }
+        visitor.leave_directory(offset, depth, &directory);
+    }
+
+    fn walk_combo_entry(
+        &self,
+        offset: usize,
+        entry: &ComboDirectoryEntry,
+        depth: usize,
+        visited: &mut Vec<usize>,
+        visitor: &mut impl RomVisitor,
+    ) {
+        let start = self.resolve_address(offset, entry.directory).ok();
+        visitor.visit_combo_entry(offset, entry, start);
+        if let Some(start) = start {
+            self.walk_directory(start, depth + 1, visited, visitor);
+        }
+    }
+
+    /// Data of an entry at `start` of the image, if it lies within it
+    fn data_at(&self, start: usize, size: u32) -> Option<&'a [u8]> {
+        self.data.get(start..start.checked_add(size as usize)?)
+    }
+}
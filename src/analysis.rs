@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: MIT
+
+//! Entropy of an image in sliding windows, split into spans of high entropy,
+//! likely compressed or encrypted, and of low entropy, likely erased space or
+//! NVRAM. High entropy spans that no structure of the image claims are often
+//! blobs that no directory or region describes.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::entropy::Table;
+use crate::layout::Layout;
+
+/// Window size of `Analysis::new` callers without a preference
+pub const DEFAULT_WINDOW: usize = 0x1000;
+
+/// Entropy in bits per byte below which a window is low entropy
+pub const LOW: f32 = 2.0;
+
+/// Entropy in bits per byte above which a window is high entropy. Random
+/// data has close to 7.95 bits per byte in a window of 0x1000 bytes.
+pub const HIGH: f32 = 7.5;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Class {
+    /// Erased space, padding and sparse data such as NVRAM
+    Low,
+    /// Code and tables
+    Medium,
+    /// Compressed or encrypted data
+    High,
+}
+
+impl Class {
+    pub fn of(entropy: f32) -> Self {
+        if entropy < LOW {
+            Class::Low
+        } else if entropy > HIGH {
+            Class::High
+        } else {
+            Class::Medium
+        }
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Class::Low => "low",
+            Class::Medium => "medium",
+            Class::High => "high",
+        };
+        f.pad(name)
+    }
+}
+
+/// Consecutive bytes whose windows are of the same class
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Span {
+    pub offset: usize,
+    pub size: usize,
+    pub class: Class,
+    /// Mean entropy of the windows of the span
+    pub entropy: f32,
+    /// Names of the areas of the layout the span overlaps, none if the
+    /// layout is unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub areas: Option<Vec<String>>,
+}
+
+impl Span {
+    pub fn end(&self) -> usize {
+        self.offset + self.size
+    }
+
+    /// Whether the span is high entropy and outside of every area of the
+    /// layout
+    pub fn unreferenced(&self) -> bool {
+        self.class == Class::High && self.areas.as_ref().is_some_and(Vec::is_empty)
+    }
+}
+
+/// Entropy of windows of `window` bytes every `step` bytes of an image, in
+/// bits per byte. The bytes from the offset of a window to the next one are
+/// classified by the entropy of the window.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Analysis {
+    pub window: usize,
+    pub step: usize,
+    pub windows: Vec<f32>,
+    pub spans: Vec<Span>,
+}
+
+impl Analysis {
+    /// Computes the entropy of every window, which must not be 0 bytes, as
+    /// must the step. Windows at the end of the image are shorter.
+    pub fn new(data: &[u8], window: usize, step: usize) -> Self {
+        assert!(window > 0, "entropy window is 0");
+        assert!(step > 0, "entropy step is 0");
+        let windows = windows(data, window, step);
+
+        let mut spans: Vec<Span> = Vec::new();
+        let mut sum = 0.0;
+        for (i, entropy) in windows.iter().enumerate() {
+            let offset = i * step;
+            let size = step.min(data.len() - offset);
+            let class = Class::of(*entropy);
+            match spans.last_mut() {
+                Some(span) if span.class == class => {
+                    span.size += size;
+                    sum += *entropy;
+                }
+                _ => {
+                    close(spans.last_mut(), sum, step);
+                    spans.push(Span {
+                        offset,
+                        size,
+                        class,
+                        entropy: 0.0,
+                        areas: None,
+                    });
+                    sum = *entropy;
+                }
+            }
+        }
+        close(spans.last_mut(), sum, step);
+
+        Analysis {
+            window,
+            step,
+            windows,
+            spans,
+        }
+    }
+
+    /// Names the areas of the layout every span overlaps
+    pub fn locate(&mut self, layout: &Layout) {
+        for span in self.spans.iter_mut() {
+            span.areas = Some(
+                layout
+                    .areas()
+                    .iter()
+                    .filter(|area| area.offset < span.end() && area.end() > span.offset)
+                    .map(|area| area.name.clone())
+                    .collect(),
+            );
+        }
+    }
+
+    /// High entropy spans outside of every area of the layout
+    pub fn unreferenced(&self) -> impl Iterator<Item = &Span> {
+        self.spans.iter().filter(|span| span.unreferenced())
+    }
+}
+
+/// Sets the entropy of a span to the mean of its windows, which are one per
+/// step but for the last one
+fn close(span: Option<&mut Span>, sum: f32, step: usize) {
+    if let Some(span) = span {
+        span.entropy = sum / span.size.div_ceil(step) as f32;
+    }
+}
+
+/// Entropy of every window, the byte counts being updated by the bytes that
+/// leave and enter the window instead of counted again
+fn windows(data: &[u8], window: usize, step: usize) -> Vec<f32> {
+    let table = Table::new(window.min(data.len()));
+    let mut counts = [0u32; 256];
+    let (mut start, mut end) = (0, 0);
+    let mut windows = Vec::new();
+    for offset in (0..data.len()).step_by(step) {
+        let next_end = data.len().min(offset.saturating_add(window));
+        for byte in data[start..offset.min(end)].iter() {
+            counts[*byte as usize] -= 1;
+        }
+        for byte in data[end.max(offset)..next_end].iter() {
+            counts[*byte as usize] += 1;
+        }
+        start = offset;
+        end = next_end;
+        windows.push(table.entropy(&counts, end - start));
+    }
+    windows
+}
@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: MIT
+
+//! Security assessment of an image in the manner of chipsec: how the flash
+//! descriptor protects the ME region, whether Boot Guard is provisioned and
+//! how the PSP verifies what it boots. Only the image is read, so fused
+//! settings of the platform are out of reach.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    vec::Vec,
+};
+use core::{cmp::Reverse, fmt};
+use serde::{Deserialize, Serialize};
+
+use crate::amd::{
+    self,
+    directory::{BiosDirectoryEntry, PspBinaryHeader, PspDirectoryEntry},
+    walk::RomVisitor,
+};
+use crate::intel::{self, descriptor, me, RegionKind, HAP};
+
+/// How much a finding weakens the platform, from least to most
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Severity {
+    /// A protection is in place, or a setting worth knowing
+    Info,
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Info => "info",
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Finding {
+    /// Stable identifier of the check, such as `intel-me-writable`
+    pub id: String,
+    pub severity: Severity,
+    pub title: String,
+    /// What in the image the finding is based on
+    pub evidence: String,
+}
+
+impl Finding {
+    fn new(id: &str, severity: Severity, title: &str, evidence: String) -> Self {
+        Finding {
+            id: String::from(id),
+            severity,
+            title: String::from(title),
+            evidence,
+        }
+    }
+}
+
+/// Findings of the Intel and AMD checks, most severe first
+pub fn audit(data: &[u8]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if let Ok(rom) = intel::Rom::new(data) {
+        audit_intel(&rom, &mut findings);
+    }
+    if let Ok(rom) = amd::Rom::new(data) {
+        audit_amd(&rom, &mut findings);
+    }
+    findings.sort_by_key(|finding| Reverse(finding.severity));
+    findings
+}
+
+/// Write access of the host CPU to a region, from the first flash master
+/// entry
+fn host_writable(mstr1: u32, region: RegionKind) -> bool {
+    let (_, write) = descriptor::Version::of(mstr1).access_shifts();
+    mstr1 & 1 << (write + region as u32) != 0
+}
+
+fn audit_intel(rom: &intel::Rom, findings: &mut Vec<Finding>) {
+    if let Ok(master) = rom.flash_master() {
+        let mstr1 = master.mstr1;
+        let evidence = format!("FLMSTR1 = {:08X}", mstr1);
+        if host_writable(mstr1, RegionKind::Descriptor) {
+            findings.push(Finding::new(
+                "intel-descriptor-writable",
+                Severity::High,
+                "Flash descriptor writable by the host",
+                evidence.clone(),
+            ));
+        }
+        if host_writable(mstr1, RegionKind::ManagementEngine) {
+            findings.push(Finding::new(
+                "intel-me-writable",
+                Severity::High,
+                "ME region writable by the host",
+                evidence,
+            ));
+        }
+    }
+
+    if let Ok(Some(me)) = rom.me() {
+        if let Some(version) = me.version() {
+            for advisory in me::advisories(&version) {
+                findings.push(Finding::new(
+                    "intel-me-advisory",
+                    Severity::High,
+                    advisory.title,
+                    format!("ME version {} affected by {}", version, advisory.id),
+                ));
+            }
+        }
+    }
+
+    if let Ok(pchstrap) = rom.flash_pchstrap() {
+        let strap = pchstrap.data[0];
+        if strap & HAP == HAP {
+            findings.push(Finding::new(
+                "intel-hap",
+                Severity::Info,
+                "ME disabled by the High Assurance Platform bit",
+                format!("PCHSTRAP0 = {:08X}", strap),
+            ));
+        }
+    }
+
+    // The boot policy manifest holds the Boot Guard profile, which the ACM
+    // only enforces if the key manifest hash is fused
+    match find(rom.data(), b"__ACBP__") {
+        Some(offset) => findings.push(Finding::new(
+            "intel-boot-guard",
+            Severity::Info,
+            "Boot Guard boot policy manifest present",
+            format!("__ACBP__ at {:#X}", offset),
+        )),
+        None => findings.push(Finding::new(
+            "intel-boot-guard",
+            Severity::Medium,
+            "Boot Guard not provisioned",
+            String::from("no __ACBP__ boot policy manifest"),
+        )),
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Entry types and security relevant data seen while walking the directories
+#[derive(Default)]
+struct Survey {
+    psp_kinds: BTreeSet<u8>,
+    bios_kinds: BTreeSet<u8>,
+    /// Soft Fuse Chain values and the directories holding them
+    fuses: Vec<(usize, u64)>,
+    /// Header of every PSP boot loader, by offset
+    boot_loaders: BTreeMap<usize, Option<PspBinaryHeader>>,
+}
+
+impl RomVisitor for Survey {
+    fn visit_psp_entry(
+        &mut self,
+        directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        self.psp_kinds.insert(entry.kind);
+        match entry.kind {
+            0x0B if entry.size == 0xFFFF_FFFF => self.fuses.push((directory, entry.value)),
+            0x01 | 0x73 => {
+                if let Some(offset) = offset {
+                    self.boot_loaders
+                        .insert(offset, data.and_then(PspBinaryHeader::new));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        _directory: usize,
+        entry: &BiosDirectoryEntry,
+        _offset: Option<usize>,
+        _data: Option<&[u8]>,
+    ) {
+        self.bios_kinds.insert(entry.kind);
+    }
+}
+
+fn audit_amd(rom: &amd::Rom, findings: &mut Vec<Finding>) {
+    let mut survey = Survey::default();
+    rom.walk(&mut survey);
+
+    if !survey.psp_kinds.contains(&0x00) {
+        findings.push(Finding::new(
+            "amd-psp-key",
+            Severity::High,
+            "No AMD public key, the PSP cannot verify its boot loader",
+            String::from("no PSP entry of type 0x00"),
+        ));
+    }
+    for (offset, header) in survey.boot_loaders.iter() {
+        match header {
+            Some(header) if header.signed == 1 => findings.push(Finding::new(
+                "amd-psp-secure-boot",
+                Severity::Info,
+                "PSP boot loader signed",
+                format!(
+                    "boot loader at {:#X} signed by key {}",
+                    offset,
+                    key_id(&header.signature_key_id)
+                ),
+            )),
+            _ => findings.push(Finding::new(
+                "amd-psp-secure-boot",
+                Severity::High,
+                "PSP boot loader not signed",
+                format!("boot loader at {:#X} has no signed header", offset),
+            )),
+        }
+    }
+
+    if !survey.bios_kinds.is_empty() {
+        if !survey.bios_kinds.contains(&0x07) {
+            findings.push(Finding::new(
+                "amd-bios-signature",
+                Severity::Medium,
+                "BIOS not signed, Platform Secure Boot cannot verify it",
+                String::from("no BIOS entry of type 0x07"),
+            ));
+        } else if !survey.bios_kinds.contains(&0x05) {
+            findings.push(Finding::new(
+                "amd-bios-signature",
+                Severity::Medium,
+                "BIOS signature without a BIOS signing key",
+                String::from("BIOS entry of type 0x07 but none of type 0x05"),
+            ));
+        }
+    }
+
+    let rollback: Vec<String> = [0x55u8, 0x56]
+        .iter()
+        .filter(|kind| survey.psp_kinds.contains(kind))
+        .map(|kind| format!("{:#04X}", kind))
+        .collect();
+    if rollback.is_empty() {
+        findings.push(Finding::new(
+            "amd-rollback",
+            Severity::Low,
+            "No anti-rollback entries, older PSP firmware can be flashed",
+            String::from("no PSP entry of type 0x55 or 0x56"),
+        ));
+    } else {
+        findings.push(Finding::new(
+            "amd-rollback",
+            Severity::Info,
+            "PSP anti-rollback entries present",
+            format!("PSP entries of type {}", rollback.join(", ")),
+        ));
+    }
+
+    for (directory, fuses) in survey.fuses.iter() {
+        if fuses & 1 != 0 {
+            findings.push(Finding::new(
+                "amd-debug-unlock",
+                Severity::High,
+                "Secure Debug Unlock set in the Soft Fuse Chain",
+                format!(
+                    "Soft Fuse Chain {:#018X} in directory {:#X}",
+                    fuses, directory
+                ),
+            ));
+        }
+    }
+}
+
+fn key_id(id: &[u8; 16]) -> String {
+    id.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{AmdFixture, IntelFixture};
+
+    /// Identifiers and severities of the findings
+    fn summary(findings: &[Finding]) -> Vec<(&str, Severity)> {
+        findings
+            .iter()
+            .map(|finding| (finding.id.as_str(), finding.severity))
+            .collect()
+    }
+
+    #[test]
+    fn locked_intel_image_lacks_boot_guard_only() {
+        let image = IntelFixture::default().build();
+        assert_eq!(
+            summary(&audit(&image)),
+            [("intel-boot-guard", Severity::Medium)]
+        );
+    }
+
+    #[test]
+    fn unlocked_intel_image_is_flagged() {
+        let image = IntelFixture {
+            // Host reads and writes the descriptor, BIOS and ME regions
+            mstr1: 0x0070_0700,
+            hap: true,
+            me_version: Some([11, 8, 50, 3425]),
+            boot_guard: true,
+        }
+        .build();
+        let findings = audit(&image);
+        assert_eq!(
+            summary(&findings),
+            [
+                ("intel-descriptor-writable", Severity::High),
+                ("intel-me-writable", Severity::High),
+                ("intel-me-advisory", Severity::High),
+                ("intel-me-advisory", Severity::High),
+                ("intel-me-advisory", Severity::High),
+                ("intel-hap", Severity::Info),
+                ("intel-boot-guard", Severity::Info),
+            ]
+        );
+        assert_eq!(findings[0].evidence, "FLMSTR1 = 00700700");
+        assert_eq!(
+            findings[2].evidence,
+            "ME version 11.8.50.3425 affected by INTEL-SA-00213"
+        );
+    }
+
+    #[test]
+    fn amd_image_is_flagged() {
+        let image = AmdFixture::default().build();
+        let findings = audit(&image);
+        assert_eq!(
+            summary(&findings),
+            [
+                ("amd-debug-unlock", Severity::High),
+                ("amd-bios-signature", Severity::Medium),
+                ("amd-rollback", Severity::Low),
+                ("amd-psp-secure-boot", Severity::Info),
+            ]
+        );
+        assert!(findings[3].evidence.ends_with(&"00".repeat(16)));
+    }
+}
@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: MIT
+
+//! Analysis of every image of a directory, the images being analyzed in
+//! parallel
+
+use rayon::prelude::*;
+use romulan::identify::Database;
+use romulan::report::Report;
+use romulan::wrapper;
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Line of the NDJSON stream, the report of an image or why it has none
+#[derive(Serialize)]
+struct Line<'a> {
+    path: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<Report>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Whether `name` matches `pattern`, in which `*` matches any characters
+/// and `?` any one character
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and of the name where it started matching
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Files of `dir` whose name matches `glob`, and those of its
+/// subdirectories if `recursive`, sorted. Hidden files and directories are
+/// skipped.
+pub fn files(dir: &Path, recursive: bool, glob: Option<&str>) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("failed to read {}: {}", dir.display(), err))?;
+    let mut found = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|err| format!("failed to read {}: {}", dir.display(), err))?
+            .path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            if recursive {
+                found.extend(files(&path, recursive, glob)?);
+            }
+        } else if glob.is_none_or(|glob| matches(glob, &name)) {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+fn analyze(path: &Path, database: &Database) -> Result<Report, String> {
+    let data = super::map(path)?;
+    Ok(Report::unwrapped(wrapper::unwrap(&data)?.1, database)?)
+}
+
+/// Analyzes the images found below `dir`, writing the JSON report of each to
+/// `output` under its path relative to `dir` with `.json` appended, or else
+/// a JSON line per image to stdout in the order of the paths. Returns the
+/// number of images that could not be analyzed, which are listed on stderr
+/// or have an `error` instead of a `report` in their line.
+pub fn run(
+    dir: &Path,
+    paths: &[PathBuf],
+    database: &Database,
+    output: Option<&Path>,
+) -> Result<usize, String> {
+    let relative = |path: &Path| path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+    if let Some(output) = output {
+        let failed = paths
+            .par_iter()
+            .filter(|path| {
+                let result = analyze(path, database).and_then(|report| {
+                    let mut target = output.join(relative(path)).into_os_string();
+                    target.push(".json");
+                    let target = PathBuf::from(target);
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent).map_err(|err| {
+                            format!("failed to create {}: {}", parent.display(), err)
+                        })?;
+                    }
+                    let text = serde_json::to_string_pretty(&report)
+                        .map_err(|err| format!("failed to serialize: {}", err))?;
+                    fs::write(&target, text + "\n")
+                        .map_err(|err| format!("failed to write {}: {}", target.display(), err))
+                });
+                if let Err(err) = &result {
+                    eprintln!("romulan: {}: {}", path.display(), err);
+                }
+                result.is_err()
+            })
+            .count();
+        return Ok(failed);
+    }
+
+    // Lines are serialized as they are analyzed, so only their text is kept
+    let lines: Vec<(Result<String, String>, bool)> = paths
+        .par_iter()
+        .map(|path| {
+            let (report, error) = match analyze(path, database) {
+                Ok(report) => (Some(report), None),
+                Err(err) => (None, Some(err)),
+            };
+            let failed = error.is_some();
+            let line = Line {
+                path,
+                report,
+                error,
+            };
+            let text =
+                serde_json::to_string(&line).map_err(|err| format!("failed to serialize: {}", err));
+            (text, failed)
+        })
+        .collect();
+    let mut stdout = io::stdout().lock();
+    for (text, _) in lines.iter() {
+        writeln!(stdout, "{}", text.as_ref()?)
+            .map_err(|err| format!("failed to write output: {}", err))?;
+    }
+    Ok(lines.iter().filter(|(_, failed)| *failed).count())
+}
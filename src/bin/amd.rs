@@ -3,6 +3,7 @@
 use std::{env, fmt::Write, fs, path::PathBuf, process};
 
 use romulan::amd::{
+    address::FlashAddress,
     directory::{BiosDirectory, BiosDirectoryEntry, Directory, PspDirectoryEntry},
     Rom,
 };
@@ -32,7 +33,8 @@ fn print_bios_dir_entry(entry: &BiosDirectoryEntry, padding: &str) {
         destination,
     } = entry;
     let desc = entry.description();
-    println!("{padding}  * Type {kind:02X} Region {region_kind:02X} Flags {flags:02X} SubProg {sub_program:02X} Size {size:08X} Source {source:016X} Dest {destination:016X}: {desc}");
+    let region = entry.region_description();
+    println!("{padding}  * Type {kind:02X} Region {region_kind:02X} ({region}) Flags {flags:02X} SubProg {sub_program:02X} Size {size:08X} Source {source:016X} Dest {destination:016X}: {desc}");
 }
 
 fn print_psp_dir_entry(entry: &PspDirectoryEntry, padding: &str) {
@@ -48,21 +50,17 @@ fn print_psp_dir_entry(entry: &PspDirectoryEntry, padding: &str) {
     println!("{padding}  * Type {kind:02X} SubProg {sub_program:02X} Rom {rom_id:02X} Size {size:08X} Value {value:016X}: {desc}");
 }
 
-// FIXME: DO NOT HARDCODE THIS!!!
-// this needs to be per flash part size; define enum etc
-const ADDR_MASK: u64 = 0x00FF_FFFF;
-
-fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<&PathBuf>) {
+fn print_directory(rom: &Rom, address: u64, indent: usize, export_opt: Option<&PathBuf>) {
     let mut padding = String::with_capacity(indent);
     for i in 0..indent {
         padding.push(' ');
     }
-    let offset = (address & ADDR_MASK) as usize;
-    match Directory::new(&data[offset..]) {
+    let offset = rom.offset(address);
+    match rom.directory(offset) {
         Ok(Directory::Bios(directory)) => {
             println!("{padding}* {address:#X}: BIOS Directory");
             for entry in directory.entries() {
-                print_bios_dir_entry(&entry, &padding);
+                print_bios_dir_entry(entry, &padding);
                 if let Some(export) = export_opt {
                     let name = format!(
                         "BIOS/Level1/Type{:02X}_Region{:02X}_Flags{:02X}_SubProg{:02X}_{}",
@@ -78,21 +76,21 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
                     }
                     fs::create_dir_all(&dir)
                         .expect(&format!("failed to create directory '{}'", name));
-                    match entry.data(data) {
+                    match entry.data(rom, offset) {
                         Ok(ok) => {
-                            fs::write(dir.join("raw"), &ok)
+                            fs::write(dir.join("raw"), ok)
                                 .expect(&format!("failed to write '{}/raw'", name));
-                            fs::write(dir.join("hex"), hexdump(&ok))
+                            fs::write(dir.join("hex"), hexdump(ok))
                                 .expect(&format!("failed to write '{}/hex'", name));
                         }
                         Err(err) => {
-                            fs::write(dir.join("error"), err)
+                            fs::write(dir.join("error"), err.to_string())
                                 .expect(&format!("failed to write '{}/error'", name));
                         }
                     };
                 }
                 if entry.kind == 0x70 {
-                    print_directory(data, entry.source, indent + 4, export_opt);
+                    print_directory(rom, entry.source, indent + 4, export_opt);
                 }
             }
         }
@@ -100,13 +98,13 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
             println!("{}* {:#X}: BIOS Combo Directory", padding, address);
             for entry in combo.entries() {
                 println!("{}  * {:X?}", padding, entry);
-                print_directory(data, entry.directory, indent + 4, export_opt);
+                print_directory(rom, entry.directory, indent + 4, export_opt);
             }
         }
         Ok(Directory::BiosLevel2(directory)) => {
             println!("{}* {:#X}: BIOS Level 2 Directory", padding, address);
             for entry in directory.entries() {
-                print_bios_dir_entry(&entry, &padding);
+                print_bios_dir_entry(entry, &padding);
                 if let Some(export) = export_opt {
                     let name = format!(
                         "BIOS/Level2/Type{:02X}_Region{:02X}_Flags{:02X}_SubProg{:02X}_{}",
@@ -122,15 +120,15 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
                     }
                     fs::create_dir_all(&dir)
                         .expect(&format!("failed to create directory '{}'", name));
-                    match entry.data(data) {
+                    match entry.data(rom, offset) {
                         Ok(ok) => {
-                            fs::write(dir.join("raw"), &ok)
+                            fs::write(dir.join("raw"), ok)
                                 .expect(&format!("failed to write '{}/raw'", name));
-                            fs::write(dir.join("hex"), hexdump(&ok))
+                            fs::write(dir.join("hex"), hexdump(ok))
                                 .expect(&format!("failed to write '{}/hex'", name));
                         }
                         Err(err) => {
-                            fs::write(dir.join("error"), err)
+                            fs::write(dir.join("error"), err.to_string())
                                 .expect(&format!("failed to write '{}/error'", name));
                         }
                     };
@@ -140,7 +138,7 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
         Ok(Directory::Psp(directory)) => {
             println!("{}* {:#X}: PSP Directory", padding, address);
             for entry in directory.entries() {
-                print_psp_dir_entry(&entry, &padding);
+                print_psp_dir_entry(entry, &padding);
                 if let Some(export) = export_opt {
                     let name = format!(
                         "PSP/Level1/Type{:02X}_SubProg{:02X}_Rom{:02X}_{}",
@@ -155,7 +153,7 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
                     }
                     fs::create_dir_all(&dir)
                         .expect(&format!("failed to create directory '{}'", name));
-                    match entry.data(data) {
+                    match entry.data(rom, offset) {
                         Ok(ok) => {
                             fs::write(dir.join("raw"), &ok)
                                 .expect(&format!("failed to write '{}/raw'", name));
@@ -163,13 +161,13 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
                                 .expect(&format!("failed to write '{}/hex'", name));
                         }
                         Err(err) => {
-                            fs::write(dir.join("error"), err)
+                            fs::write(dir.join("error"), err.to_string())
                                 .expect(&format!("failed to write '{}/error'", name));
                         }
                     };
                 }
                 if entry.kind == 0x40 {
-                    print_directory(data, entry.value, indent + 4, export_opt);
+                    print_directory(rom, entry.value, indent + 4, export_opt);
                 }
             }
         }
@@ -177,13 +175,13 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
             println!("{}* {:#X}: PSP Combo Directory", padding, address);
             for entry in combo.entries() {
                 println!("{}  * {:X?}", padding, entry);
-                print_directory(data, entry.directory, indent + 4, export_opt);
+                print_directory(rom, entry.directory, indent + 4, export_opt);
             }
         }
         Ok(Directory::PspLevel2(directory)) => {
             println!("{}* {:#X}: PSP Level 2 Directory", padding, address);
             for entry in directory.entries() {
-                print_psp_dir_entry(&entry, &padding);
+                print_psp_dir_entry(entry, &padding);
                 if let Some(export) = export_opt {
                     let name = format!(
                         "PSP/Level2/Type{:02X}_SubProg{:02X}_Rom{:02X}_{}",
@@ -198,7 +196,7 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
                     }
                     fs::create_dir_all(&dir)
                         .expect(&format!("failed to create directory '{}'", name));
-                    match entry.data(data) {
+                    match entry.data(rom, offset) {
                         Ok(ok) => {
                             fs::write(dir.join("raw"), &ok)
                                 .expect(&format!("failed to write '{}/raw'", name));
@@ -206,7 +204,7 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
                                 .expect(&format!("failed to write '{}/hex'", name));
                         }
                         Err(err) => {
-                            fs::write(dir.join("error"), err)
+                            fs::write(dir.join("error"), err.to_string())
                                 .expect(&format!("failed to write '{}/error'", name));
                         }
                     };
@@ -222,7 +220,19 @@ fn print_directory(data: &[u8], address: u64, indent: usize, export_opt: Option<
     }
 }
 
-const DIR_UNSET: u32 = 0xffff_ffff;
+/// Image mapped instead of read if built with the mmap feature
+#[cfg(feature = "mmap")]
+fn map(path: &str) -> std::io::Result<memmap2::Mmap> {
+    let file = fs::File::open(path)?;
+    // The image may change under the mapping, the parsers do not rely on
+    // data read twice being the same
+    unsafe { memmap2::Mmap::map(&file) }
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map(path: &str) -> std::io::Result<Vec<u8>> {
+    fs::read(path)
+}
 
 fn main() {
     let file = if let Some(file) = env::args().nth(1) {
@@ -243,30 +253,31 @@ fn main() {
         None
     };
 
-    let data = fs::read(file).unwrap();
-    let rom = Rom::new(&data).unwrap();
+    let data = map(&file).unwrap_or_else(|err| {
+        eprintln!("failed to read {}: {}", file, err);
+        process::exit(1);
+    });
+    let rom = Rom::new(&data).unwrap_or_else(|err| {
+        eprintln!("{}: {}", file, err);
+        process::exit(1);
+    });
     let efs = rom.efs();
     println!("{efs:#X?}");
 
-    let dirs = [
-        efs.psp_legacy,
-        efs.psp,
-        efs.bios,
-        efs.bios_17_00_0f,
-        efs.bios_17_10_1f,
-        efs.bios_17_30_3f_19_00_0f,
-    ];
-    let bios_offset = (efs.bios_17_00_0f as u64 & ADDR_MASK) as usize;
-    println!("BIOS@{bios_offset:X}");
-    let d = BiosDirectory::new(&data[bios_offset..]).unwrap();
-    println!("{d:#?}");
-    d.entries().iter().for_each(|e| {
-        let ed = e.description();
-        println!("{ed}: {e:#?}");
-    });
-    dirs.iter().for_each(|d| {
-        if *d != DIR_UNSET {
-            print_directory(&data, *d as u64, 0, export_opt.as_ref())
+    if let Some(FlashAddress(bios_offset)) = efs.bios_directory_17_00_0f(&rom.platform()) {
+        println!("BIOS@{:X}", bios_offset);
+        match BiosDirectory::new(data.get(bios_offset..).unwrap_or_default()) {
+            Ok(d) => {
+                println!("{d:#?}");
+                d.entries().iter().for_each(|e| {
+                    let ed = e.description();
+                    println!("{ed}: {e:#?}");
+                });
+            }
+            Err(err) => println!("failed to load BIOS directory: {}", err),
         }
-    });
+    }
+    for pointer in efs.directory_pointers() {
+        print_directory(&rom, pointer as u64, 0, export_opt.as_ref())
+    }
 }
@@ -4,6 +4,20 @@ use std::{env, fs, process};
 
 use romulan::intel::{RegionKind, Rom};
 
+/// Image mapped instead of read if built with the mmap feature
+#[cfg(feature = "mmap")]
+fn map(path: &str) -> std::io::Result<memmap2::Mmap> {
+    let file = fs::File::open(path)?;
+    // The image may change under the mapping, the parsers do not rely on
+    // data read twice being the same
+    unsafe { memmap2::Mmap::map(&file) }
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map(path: &str) -> std::io::Result<Vec<u8>> {
+    fs::read(path)
+}
+
 fn main() {
     let file = if let Some(file) = env::args().nth(1) {
         file
@@ -12,10 +26,18 @@ fn main() {
         process::exit(1);
     };
 
-    let data = fs::read(file).unwrap();
+    let data = map(&file).unwrap_or_else(|err| {
+        eprintln!("failed to read {}: {}", file, err);
+        process::exit(1);
+    });
 
     // Get the Flash Descriptor Region Section
-    let flash_region = Rom::new(&data).unwrap().flash_region().unwrap();
+    let flash_region = Rom::new(&data)
+        .and_then(|rom| rom.flash_region())
+        .unwrap_or_else(|err| {
+            eprintln!("{}: {}", file, err);
+            process::exit(1);
+        });
 
     // Determine a regions base and limit addresses from the Flash
     // Region Record that corresponds to it.
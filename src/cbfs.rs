@@ -0,0 +1,566 @@
+// SPDX-License-Identifier: MIT
+
+//! Coreboot file systems, the archives of the stages, payloads and data of
+//! coreboot images in areas of the flash map such as `COREBOOT`, `FW_MAIN_A`
+//! and `FW_MAIN_B`. Files follow each other at 64 byte alignment, each with a
+//! header of big endian fields, its name and its attributes, such as the
+//! compression of its data.
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use plain::Plain;
+use serde::{Deserialize, Serialize};
+
+use crate::extract::{join, Extraction, Metadata};
+use crate::fmap::Fmap;
+use crate::identify::sha256;
+use crate::{lz4, lzma, Error};
+
+pub const FILE_MAGIC: [u8; 8] = *b"LARCHIVE";
+
+/// Alignment of the file headers
+pub const ALIGNMENT: usize = 64;
+
+/// Types of files
+pub const TYPE_DELETED: u32 = 0x0000_0000;
+pub const TYPE_BOOTBLOCK: u32 = 0x01;
+pub const TYPE_CBFS_HEADER: u32 = 0x02;
+pub const TYPE_LEGACY_STAGE: u32 = 0x10;
+pub const TYPE_STAGE: u32 = 0x11;
+pub const TYPE_SELF: u32 = 0x20;
+/// Free space
+pub const TYPE_NULL: u32 = 0xFFFF_FFFF;
+
+pub const COMPRESSION_NONE: u32 = 0;
+pub const COMPRESSION_LZMA: u32 = 1;
+pub const COMPRESSION_LZ4: u32 = 2;
+
+/// Tag of the attribute with the compression of the file data
+pub const ATTRIBUTE_COMPRESSION: u32 = 0x4243_5A4C;
+
+/// Segment types of SELF payloads
+pub const SEGMENT_CODE: u32 = 0x434F_4445;
+pub const SEGMENT_DATA: u32 = 0x4441_5441;
+pub const SEGMENT_BSS: u32 = 0x4253_5320;
+pub const SEGMENT_PARAMS: u32 = 0x5041_5241;
+pub const SEGMENT_ENTRY: u32 = 0x454E_5452;
+
+/// Name of a file type, as cbfstool prints it
+pub fn type_name(kind: u32) -> &'static str {
+    match kind {
+        TYPE_DELETED => "deleted",
+        TYPE_BOOTBLOCK => "bootblock",
+        TYPE_CBFS_HEADER => "cbfs header",
+        TYPE_LEGACY_STAGE | TYPE_STAGE => "stage",
+        TYPE_SELF => "simple elf",
+        0x21 => "fit_payload",
+        0x30 => "optionrom",
+        0x40 => "bootsplash",
+        0x50 => "raw",
+        0x51 => "vsa",
+        0x52 => "mbi",
+        0x53 => "microcode",
+        0x60 => "fsp",
+        0x61 => "mrc",
+        0x62 => "mma",
+        0x63 => "efi",
+        0x70 => "struct",
+        0xAA => "cmos_default",
+        0xAB => "spd",
+        0xAC => "mrc_cache",
+        0x1AA => "cmos_layout",
+        TYPE_NULL => "null",
+        _ => "unknown",
+    }
+}
+
+pub fn compression_name(compression: u32) -> &'static str {
+    match compression {
+        COMPRESSION_NONE => "none",
+        COMPRESSION_LZMA => "LZMA",
+        COMPRESSION_LZ4 => "LZ4",
+        _ => "unknown",
+    }
+}
+
+/// Decompresses data of a compression, none if it is not compressed
+pub fn decompress(compression: u32, data: &[u8]) -> Option<Result<Vec<u8>, Error>> {
+    match compression {
+        COMPRESSION_NONE => None,
+        COMPRESSION_LZMA => Some(lzma::decompress(data)),
+        COMPRESSION_LZ4 => Some(lz4::decompress(data)),
+        _ => Some(Err(Error::Unsupported(format!(
+            "CBFS compression {} not supported",
+            compression
+        )))),
+    }
+}
+
+/// Header of a file, followed by its name, its attributes and its data.
+/// Fields are big endian.
+#[repr(C, packed)]
+pub struct FileHeader {
+    pub magic: [u8; 8],
+    /// Size of the data
+    pub len: u32,
+    pub kind: u32,
+    /// Offset of the attributes from the header, 0 if there are none
+    pub attributes_offset: u32,
+    /// Offset of the data from the header
+    pub offset: u32,
+}
+
+unsafe impl Plain for FileHeader {}
+
+impl FileHeader {
+    pub fn data_len(&self) -> usize {
+        u32::from_be(self.len) as usize
+    }
+
+    pub fn kind(&self) -> u32 {
+        u32::from_be(self.kind)
+    }
+
+    pub fn attributes_offset(&self) -> usize {
+        u32::from_be(self.attributes_offset) as usize
+    }
+
+    pub fn data_offset(&self) -> usize {
+        u32::from_be(self.offset) as usize
+    }
+}
+
+/// Header of stages of the legacy format, which is little endian unlike
+/// the rest of CBFS, followed by the stage
+#[repr(C, packed)]
+pub struct LegacyStageHeader {
+    pub compression: u32,
+    pub entry: u64,
+    pub load: u64,
+    /// Size of the stage in the file
+    pub len: u32,
+    /// Size of the stage in memory
+    pub mem_len: u32,
+}
+
+unsafe impl Plain for LegacyStageHeader {}
+
+/// Segment of a SELF payload, the segments being at the start of the file
+/// data up to the entry segment. Fields are big endian.
+#[repr(C, packed)]
+pub struct SegmentHeader {
+    pub kind: u32,
+    pub compression: u32,
+    /// Offset of the segment data in the file data
+    pub offset: u32,
+    pub load: u64,
+    /// Size of the segment data
+    pub len: u32,
+    /// Size of the segment in memory
+    pub mem_len: u32,
+}
+
+unsafe impl Plain for SegmentHeader {}
+
+impl SegmentHeader {
+    pub fn kind(&self) -> u32 {
+        u32::from_be(self.kind)
+    }
+
+    pub fn compression(&self) -> u32 {
+        u32::from_be(self.compression)
+    }
+
+    pub fn data_offset(&self) -> usize {
+        u32::from_be(self.offset) as usize
+    }
+
+    pub fn data_len(&self) -> usize {
+        u32::from_be(self.len) as usize
+    }
+}
+
+/// File of a CBFS
+pub struct CbfsFile<'a> {
+    /// Offset of the header in the CBFS
+    offset: usize,
+    header: &'a FileHeader,
+    name: String,
+    attributes: &'a [u8],
+    data: &'a [u8],
+}
+
+impl<'a> CbfsFile<'a> {
+    /// Offset of the header in the CBFS
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Offset of the data in the CBFS
+    pub fn data_offset(&self) -> usize {
+        self.offset + self.header.data_offset()
+    }
+
+    pub fn header(&self) -> &'a FileHeader {
+        self.header
+    }
+
+    /// Name, such as `fallback/romstage`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Attribute of a tag, with its tag and size
+    pub fn attribute(&self, tag: u32) -> Option<&'a [u8]> {
+        let mut i = 0;
+        while let (Some(other), Some(len)) =
+            (be32(self.attributes, i), be32(self.attributes, i + 4))
+        {
+            let len = len as usize;
+            if other == 0 || other == 0xFFFF_FFFF || len < 8 {
+                break;
+            }
+            if other == tag {
+                return self.attributes.get(i..i + len);
+            }
+            i += len;
+        }
+        None
+    }
+
+    /// Compression of the data, or of the stage of legacy stages, and the
+    /// size of the data decompressed if the file tells it
+    pub fn compression(&self) -> (u32, Option<usize>) {
+        if let Some(attribute) = self.attribute(ATTRIBUTE_COMPRESSION) {
+            let compression = be32(attribute, 8).unwrap_or_default();
+            return (compression, be32(attribute, 12).map(|size| size as usize));
+        }
+        match self.legacy_stage() {
+            Some(stage) => (stage.compression, None),
+            None => (COMPRESSION_NONE, None),
+        }
+    }
+
+    /// Header of a legacy stage
+    pub fn legacy_stage(&self) -> Option<&'a LegacyStageHeader> {
+        if self.header.kind() != TYPE_LEGACY_STAGE {
+            return None;
+        }
+        plain::from_bytes(self.data).ok()
+    }
+
+    /// Decompresses the data, or the stage of a legacy stage, none if it is
+    /// not compressed
+    pub fn decompress(&self) -> Option<Result<Vec<u8>, Error>> {
+        let (compression, _) = self.compression();
+        match self.legacy_stage() {
+            Some(stage) => {
+                let start = mem::size_of::<LegacyStageHeader>();
+                let data = self
+                    .data
+                    .get(start..start + stage.len as usize)
+                    .unwrap_or_default();
+                decompress(compression, data)
+            }
+            None => decompress(compression, self.data),
+        }
+    }
+
+    /// Segments of a SELF payload, up to the entry segment
+    pub fn segments(&self) -> Vec<&'a SegmentHeader> {
+        let mut segments = Vec::new();
+        if self.header.kind() != TYPE_SELF || self.compression().0 != COMPRESSION_NONE {
+            return segments;
+        }
+        for chunk in self.data.chunks_exact(mem::size_of::<SegmentHeader>()) {
+            let segment: &SegmentHeader = match plain::from_bytes(chunk) {
+                Ok(segment) => segment,
+                Err(_) => break,
+            };
+            segments.push(segment);
+            if segment.kind() == SEGMENT_ENTRY {
+                break;
+            }
+        }
+        segments
+    }
+
+    /// Data of a segment of a SELF payload, none if it is outside the file
+    pub fn segment_data(&self, segment: &SegmentHeader) -> Option<&'a [u8]> {
+        let start = segment.data_offset();
+        self.data.get(start..start.checked_add(segment.data_len())?)
+    }
+}
+
+/// Coreboot file system at the start of an area
+pub struct Cbfs<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cbfs<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Cbfs<'a>, Error> {
+        if !data.starts_with(&FILE_MAGIC) {
+            return Err(Error::SignatureMismatch {
+                structure: "CBFS",
+                offset: 0,
+            });
+        }
+        Ok(Cbfs { data })
+    }
+
+    /// Files up to the first offset without a valid file
+    pub fn files(&self) -> Vec<CbfsFile<'a>> {
+        let mut files = Vec::new();
+        let mut offset = 0;
+        while let Some(file) = self.file(offset) {
+            offset = (file.data_offset() + file.data.len()).div_ceil(ALIGNMENT) * ALIGNMENT;
+            files.push(file);
+        }
+        files
+    }
+
+    fn file(&self, offset: usize) -> Option<CbfsFile<'a>> {
+        let data = self.data.get(offset..)?;
+        let header: &FileHeader = plain::from_bytes(data).ok()?;
+        if header.magic != FILE_MAGIC || header.data_offset() < mem::size_of::<FileHeader>() {
+            return None;
+        }
+        let attributes_offset = match header.attributes_offset() {
+            0 => header.data_offset(),
+            attributes_offset => attributes_offset.min(header.data_offset()),
+        };
+        let name = data
+            .get(mem::size_of::<FileHeader>()..attributes_offset)?
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as char)
+            .collect();
+        Some(CbfsFile {
+            offset,
+            header,
+            name,
+            attributes: data.get(attributes_offset..header.data_offset())?,
+            data: data.get(header.data_offset()..header.data_offset() + header.data_len())?,
+        })
+    }
+}
+
+/// Areas of the flash map holding a CBFS, with their name and offset in the
+/// image
+pub fn areas<'a>(map: &Fmap<'a>) -> Vec<(String, usize, Cbfs<'a>)> {
+    map.entries()
+        .into_iter()
+        .filter_map(|entry| {
+            let cbfs = Cbfs::new(map.area_data(entry)?).ok()?;
+            Some((entry.name(), entry.offset as usize, cbfs))
+        })
+        .collect()
+}
+
+/// Extracts the files of every CBFS of the flash map as `FMAP/<area>/<name>`,
+/// compressed files being extracted decompressed as well, and the segments
+/// of SELF payloads as `<name> segment <index>`
+pub fn extract_all(map: &Fmap) -> Extraction {
+    let mut extraction = Extraction::new();
+    let mut insert = |path: String, offset: Option<usize>, description: String, data: Vec<u8>| {
+        let metadata = Metadata {
+            offset,
+            size: data.len(),
+            description,
+            decoded: offset.is_none(),
+        };
+        extraction.insert(path, (metadata, data));
+    };
+    for (area, area_offset, cbfs) in areas(map) {
+        let area_path = join("FMAP", &area);
+        for file in cbfs.files() {
+            let kind = file.header().kind();
+            if kind == TYPE_NULL || kind == TYPE_DELETED {
+                continue;
+            }
+            let path = join(&area_path, file.name());
+            let description = String::from(type_name(kind));
+            if let Some(Ok(decompressed)) = file.decompress() {
+                insert(
+                    format!("{} decompressed", path),
+                    None,
+                    format!("{} (decompressed)", description),
+                    decompressed,
+                );
+            }
+            for (i, segment) in file.segments().iter().enumerate() {
+                let data = match file.segment_data(segment) {
+                    Some(data) if !data.is_empty() => data,
+                    _ => continue,
+                };
+                let data = match decompress(segment.compression(), data) {
+                    Some(Ok(decompressed)) => decompressed,
+                    Some(Err(_)) => continue,
+                    None => data.to_vec(),
+                };
+                insert(
+                    format!("{} segment {}", path, i),
+                    None,
+                    format!("{} segment", description),
+                    data,
+                );
+            }
+            insert(
+                path,
+                Some(area_offset + file.data_offset()),
+                description,
+                file.data().to_vec(),
+            );
+        }
+    }
+    extraction
+}
+
+/// File of a CBFS, empty space and deleted files left out
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileEntry {
+    pub area: String,
+    pub name: String,
+    pub kind: String,
+    /// Offset of the file data in the image
+    pub offset: usize,
+    pub size: usize,
+    /// Lowercase hex SHA-256 digest of the file data
+    pub sha256: String,
+}
+
+/// A file added, changed or removed between the images. Added files are
+/// not before, removed ones not after.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileDiff {
+    pub before: Option<FileEntry>,
+    pub after: Option<FileEntry>,
+}
+
+/// Files of every CBFS of the flash map of an image
+pub fn files(data: &[u8]) -> Vec<FileEntry> {
+    let map = match Fmap::find(data) {
+        Some(map) => map,
+        None => return Vec::new(),
+    };
+    let mut files = Vec::new();
+    for (area, area_offset, cbfs) in areas(&map) {
+        for file in cbfs.files() {
+            let kind = file.header().kind();
+            if kind == TYPE_NULL || kind == TYPE_DELETED {
+                continue;
+            }
+            files.push(FileEntry {
+                area: area.clone(),
+                name: String::from(file.name()),
+                kind: String::from(type_name(kind)),
+                offset: area_offset + file.data_offset(),
+                size: file.data().len(),
+                sha256: sha256(file.data()),
+            });
+        }
+    }
+    files
+}
+
+/// Compares the CBFS files of two images, matched by area and name, in the
+/// order of the image before, those only in the image after last
+pub fn diff(before: &[u8], after: &[u8]) -> Vec<FileDiff> {
+    let mut after = files(after);
+    let mut diffs = Vec::new();
+    for old in files(before) {
+        let new = after
+            .iter()
+            .position(|new| new.area == old.area && new.name == old.name)
+            .map(|i| after.remove(i));
+        match new {
+            Some(new) if new.sha256 == old.sha256 && new.kind == old.kind => (),
+            new => diffs.push(FileDiff {
+                before: Some(old),
+                after: new,
+            }),
+        }
+    }
+    for new in after {
+        diffs.push(FileDiff {
+            before: None,
+            after: Some(new),
+        });
+    }
+    diffs
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{coreboot_image, CBFS_OFFSET};
+
+    /// LZ4 frame of "abcabcabcabc!"
+    const LZ4_FRAME: [u8; 23] = [
+        0x04, 0x22, 0x4D, 0x18, 0x60, 0x40, 0x82, 0x08, 0x00, 0x00, 0x00, 0x35, 0x61, 0x62, 0x63,
+        0x03, 0x00, 0x10, 0x21, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn files_are_enumerated_and_decompressed() {
+        let image = coreboot_image(&[
+            (
+                "cbfs master header",
+                TYPE_CBFS_HEADER,
+                COMPRESSION_NONE,
+                &[0; 32],
+            ),
+            (
+                "config",
+                0x50,
+                COMPRESSION_NONE,
+                b"CONFIG_VENDOR_SYSTEM76=y\n",
+            ),
+            ("fallback/payload", TYPE_SELF, COMPRESSION_LZ4, &LZ4_FRAME),
+        ]);
+        let map = Fmap::find(&image).unwrap();
+        let areas = areas(&map);
+        assert_eq!(areas.len(), 1);
+        let (name, offset, cbfs) = &areas[0];
+        assert_eq!((name.as_str(), *offset), ("COREBOOT", CBFS_OFFSET));
+
+        let files = cbfs.files();
+        let names: Vec<&str> = files.iter().map(|file| file.name()).collect();
+        assert_eq!(names, ["cbfs master header", "config", "fallback/payload"]);
+        assert_eq!(files[1].data(), b"CONFIG_VENDOR_SYSTEM76=y\n");
+        assert!(files[1].decompress().is_none());
+        assert_eq!(files[2].compression().0, COMPRESSION_LZ4);
+        assert_eq!(files[2].decompress().unwrap().unwrap(), b"abcabcabcabc!");
+        assert!(files.iter().all(|file| file.offset() % ALIGNMENT == 0));
+
+        let entries = super::files(&image);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].kind, "raw");
+        assert_eq!(entries[1].offset, CBFS_OFFSET + files[1].data_offset());
+    }
+
+    #[test]
+    fn diff_reports_changed_files() {
+        let before = coreboot_image(&[("config", 0x50, COMPRESSION_NONE, b"A=y\n")]);
+        let after = coreboot_image(&[
+            ("config", 0x50, COMPRESSION_NONE, b"A=n\n"),
+            ("logo.bmp", 0x50, COMPRESSION_NONE, b"BM"),
+        ]);
+        let diffs = diff(&before, &after);
+        assert_eq!(diffs.len(), 2);
+        assert!(diffs[0].before.is_some() && diffs[0].after.is_some());
+        assert_eq!(diffs[1].after.as_ref().unwrap().name, "logo.bmp");
+        assert!(diff(&before, &before).is_empty());
+    }
+}
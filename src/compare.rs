@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: MIT
+
+//! Cheap comparison of blobs: by size, then by a fast non-cryptographic
+//! hash, comparing bytes only when both match. Fingerprints are computed
+//! once per blob, so comparing a blob against many others reads it once.
+//! Blobs with different fingerprints differ, blobs with equal fingerprints
+//! are most likely equal.
+
+use core::cmp::Ordering;
+use serde::{Deserialize, Serialize};
+
+/// Bytes compared at once by `first_difference` before looking for the
+/// exact differing byte
+pub const BLOCK: usize = 0x1000;
+
+/// Size and `fnv1a` hash of a blob
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Fingerprint {
+    pub size: usize,
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    pub fn of(data: &[u8]) -> Self {
+        Fingerprint {
+            size: data.len(),
+            hash: fnv1a(data),
+        }
+    }
+}
+
+/// 64 bit hash of the data in the manner of FNV-1a, mixing in eight bytes
+/// per step instead of one
+pub fn fnv1a(data: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = OFFSET;
+    let mut words = data.chunks_exact(8);
+    for word in words.by_ref() {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(word);
+        hash = (hash ^ u64::from_le_bytes(bytes)).wrapping_mul(PRIME);
+    }
+    for byte in words.remainder() {
+        hash = (hash ^ u64::from(*byte)).wrapping_mul(PRIME);
+    }
+    hash
+}
+
+/// Offset of the first byte that differs between the blobs at or after
+/// `start`, skipping equal blocks with one comparison each. The shorter blob
+/// ending first counts as a difference.
+pub fn first_difference(a: &[u8], b: &[u8], start: usize) -> Option<usize> {
+    let len = a.len().min(b.len());
+    let mut offset = start;
+    while offset < len {
+        let end = len.min(offset + BLOCK);
+        if a[offset..end] != b[offset..end] {
+            return (offset..end).find(|i| a[*i] != b[*i]);
+        }
+        offset = end;
+    }
+    match a.len().cmp(&b.len()) {
+        Ordering::Equal => None,
+        _ => Some(len.max(start)),
+    }
+}
@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: MIT
+
+//! Bytes of an image claimed by its structures: the EFS, directories and
+//! entries of AMD images, the flash regions, UEFI volumes and FIT entries of
+//! Intel images. Holes no structure claims may hide payloads.
+
+use alloc::{string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::entropy::entropy;
+use crate::intel::{self, fit, RegionKind};
+use crate::layout::Area;
+use crate::{amd, Error};
+
+/// Number of bytes from the start of a hole kept for a hexdump
+pub const HEAD_SIZE: usize = 16;
+
+/// Length from which a run of 0xFF or 0x00 bytes in a gap is a hole of its
+/// own, shorter runs being part of the data around them
+pub const MIN_FILL: usize = 0x100;
+
+/// Areas claimed by the structures of an image, which may overlap
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CoverageMap {
+    size: usize,
+    /// Sorted by offset
+    areas: Vec<Area>,
+}
+
+impl CoverageMap {
+    /// Map of an image of `size` bytes that claims no bytes
+    pub fn new(size: usize) -> Self {
+        CoverageMap {
+            size,
+            areas: Vec::new(),
+        }
+    }
+
+    /// Maps the structures of an Intel image, or of an AMD image if it has
+    /// no flash descriptor. The BIOS regions of Intel images are claimed by
+    /// their volumes instead of as a whole.
+    pub fn of(data: &[u8]) -> Result<Self, Error> {
+        let mut map = CoverageMap::new(data.len());
+        if let Ok(rom) = intel::Rom::new(data) {
+            map.claim_intel(&rom)?;
+        } else {
+            let rom = amd::Rom::new(data)?;
+            for area in rom.areas() {
+                map.claim(area.name, area.offset, area.size);
+            }
+        }
+        Ok(map)
+    }
+
+    fn claim_intel(&mut self, rom: &intel::Rom) -> Result<(), Error> {
+        for region in rom.regions()? {
+            let kind = match region.index {
+                Some(index) => RegionKind::ALL[index],
+                None => continue,
+            };
+            let data = match kind {
+                RegionKind::Bios | RegionKind::SecondaryBios => rom.get_region(kind)?,
+                _ => None,
+            };
+            let data = match data {
+                Some(data) => data,
+                None => {
+                    self.claim(region.name, region.offset, region.size);
+                    continue;
+                }
+            };
+            for volume in intel::BiosVolumes::new(data) {
+                let header = volume.header();
+                self.claim(
+                    format!("{} volume {}", region.name, { header.guid }),
+                    region.offset + volume.offset(),
+                    header.length as usize,
+                );
+            }
+        }
+
+        if let Ok(table) = rom.fit() {
+            let size = (table.header().size() * 16).max(16);
+            self.claim(String::from("FIT"), table.offset(), size);
+            for entry in table.entries() {
+                let offset = match table.entry_offset(entry) {
+                    Some(offset) => offset,
+                    None => continue,
+                };
+                let size = match length(entry.kind(), &rom.data()[offset..]) {
+                    Some(size) => size,
+                    None => continue,
+                };
+                self.claim(String::from(entry.description()), offset, size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Size of the image
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Claims the bytes of an area, as far as they are in the image
+    pub fn claim(&mut self, name: String, offset: usize, size: usize) {
+        if offset >= self.size || size == 0 {
+            return;
+        }
+        let size = size.min(self.size - offset);
+        let i = self.areas.partition_point(|area| area.offset <= offset);
+        self.areas.insert(i, Area { name, offset, size });
+    }
+
+    /// Claimed areas, sorted by offset
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+
+    /// Whether any area claims the byte at `offset`
+    pub fn is_claimed(&self, offset: usize) -> bool {
+        self.areas
+            .iter()
+            .take_while(|area| area.offset <= offset)
+            .any(|area| area.end() > offset)
+    }
+
+    /// Number of bytes claimed by any area
+    pub fn covered(&self) -> usize {
+        self.size - self.gaps().iter().map(|(_, size)| size).sum::<usize>()
+    }
+
+    /// Offsets and sizes of the ranges no area claims
+    pub fn gaps(&self) -> Vec<(usize, usize)> {
+        let mut gaps = Vec::new();
+        let mut offset = 0;
+        for area in self.areas.iter() {
+            if area.offset > offset {
+                gaps.push((offset, area.offset - offset));
+            }
+            offset = offset.max(area.end());
+        }
+        if self.size > offset {
+            gaps.push((offset, self.size - offset));
+        }
+        gaps
+    }
+
+    /// Ranges no area claims, with a summary of their data. Gaps are split
+    /// into runs of erased space and the data between them, so that data in
+    /// a large erased gap stands out.
+    pub fn holes(&self, data: &[u8]) -> Vec<Hole> {
+        let mut holes = Vec::new();
+        for (offset, size) in self.gaps() {
+            let gap = match data.get(offset..offset + size) {
+                Some(gap) => gap,
+                None => continue,
+            };
+            for (start, end) in runs(gap) {
+                holes.push(Hole::new(offset + start, &gap[start..end]));
+            }
+        }
+        holes
+    }
+
+    /// Coverage of the image and its holes
+    pub fn report(&self, data: &[u8]) -> CoverageReport {
+        CoverageReport {
+            size: self.size,
+            covered: self.covered(),
+            holes: self.holes(data),
+        }
+    }
+}
+
+/// Range of an image no structure claims
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Hole {
+    pub offset: usize,
+    pub size: usize,
+    /// The value of every byte, if they are all the same, such as 0xFF for
+    /// erased space
+    pub fill: Option<u8>,
+    /// Entropy in bits per byte
+    pub entropy: f32,
+    /// First bytes of the hole, at most `HEAD_SIZE`
+    pub head: Vec<u8>,
+}
+
+impl Hole {
+    pub fn new(offset: usize, data: &[u8]) -> Self {
+        let fill = match data.split_first() {
+            Some((first, rest)) if rest.iter().all(|byte| byte == first) => Some(*first),
+            _ => None,
+        };
+        Hole {
+            offset,
+            size: data.len(),
+            fill,
+            entropy: entropy(data),
+            head: data[..data.len().min(HEAD_SIZE)].to_vec(),
+        }
+    }
+
+    /// Whether every byte is 0xFF or 0x00
+    pub fn is_erased(&self) -> bool {
+        matches!(self.fill, Some(0xFF) | Some(0x00))
+    }
+}
+
+/// Number of bytes of an image claimed, and the holes in between
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CoverageReport {
+    pub size: usize,
+    pub covered: usize,
+    pub holes: Vec<Hole>,
+}
+
+/// Length of a microcode update or an ACM from its header, the size field of
+/// FIT entries of these types not being used. Other types are not claimed.
+fn length(kind: u8, data: &[u8]) -> Option<usize> {
+    let dword = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    };
+    match kind {
+        // Total size at 0x20, 2048 bytes if 0
+        fit::MICROCODE => match dword(0x20)? {
+            0 => Some(2048),
+            size => Some(size),
+        },
+        // Size in dwords at 0x18
+        fit::STARTUP_ACM => Some(dword(0x18)? * 4),
+        _ => None,
+    }
+}
+
+/// Ranges of runs of at least `MIN_FILL` erased bytes in a gap, and of the
+/// data between them
+fn runs(gap: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < gap.len() {
+        let byte = gap[i];
+        let end = gap[i..]
+            .iter()
+            .position(|other| *other != byte)
+            .map_or(gap.len(), |len| i + len);
+        if (byte == 0xFF || byte == 0x00) && (end - i >= MIN_FILL || end - i == gap.len()) {
+            if i > start {
+                runs.push((start, i));
+            }
+            runs.push((i, end));
+            start = end;
+        }
+        i = end;
+    }
+    if gap.len() > start {
+        runs.push((start, gap.len()));
+    }
+    runs
+}
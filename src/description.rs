@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: MIT
+
+//! Serializable description of an image from which it can be rebuilt byte
+//! for byte. AMD structures are described field by field, everything else
+//! is embedded as base64.
+
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use zerocopy::AsBytes;
+
+use crate::amd::{self, directory::Directory, flash};
+use crate::{patch, Error};
+
+/// A run of bytes of the image not covered by a described structure
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Blob {
+    pub offset: usize,
+    #[serde(with = "base64")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DirectoryDescription {
+    pub offset: usize,
+    pub directory: Directory<'static>,
+}
+
+/// The EFS and the directories reachable from it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AmdDescription {
+    pub efs_offset: usize,
+    pub efs: flash::EFS,
+    pub directories: Vec<DirectoryDescription>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Description {
+    pub size: usize,
+    /// Value of the bytes not covered by a structure or blob
+    pub fill: u8,
+    pub amd: Option<AmdDescription>,
+    /// Payloads and any other data, written after the structures
+    pub blobs: Vec<Blob>,
+}
+
+impl Description {
+    /// Describes the image, using its most frequent byte as fill
+    pub fn new(data: &[u8]) -> Self {
+        let mut counts = [0usize; 256];
+        for byte in data {
+            counts[*byte as usize] += 1;
+        }
+        let fill = (0..=255u8)
+            .max_by_key(|byte| counts[*byte as usize])
+            .unwrap_or(0xFF);
+
+        let amd = amd::Rom::new(data).ok().map(|rom| AmdDescription {
+            efs_offset: rom.efs_offset(),
+            efs: rom.efs(),
+            directories: rom
+                .directories()
+                .iter()
+                .map(|(offset, directory)| DirectoryDescription {
+                    offset: *offset,
+                    directory: directory.clone().into_owned(),
+                })
+                .collect(),
+        });
+
+        let mut description = Description {
+            size: data.len(),
+            fill,
+            amd,
+            blobs: Vec::new(),
+        };
+        // The structures alone always fit, as they were read from the image
+        let structures = description.build().unwrap_or_default();
+        if let Ok(patches) = patch::diff(&structures, data) {
+            description.blobs = patches
+                .into_iter()
+                .map(|patch| Blob {
+                    offset: patch.offset,
+                    data: patch.data,
+                })
+                .collect();
+        }
+        description
+    }
+
+    /// Rebuilds the image
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let mut image = vec![self.fill; self.size];
+        if let Some(amd) = &self.amd {
+            write(&mut image, amd.efs_offset, amd.efs.as_bytes(), "EFS")?;
+            for description in amd.directories.iter() {
+                let bytes = description.directory.to_bytes();
+                write(
+                    &mut image,
+                    description.offset,
+                    &bytes,
+                    description.directory.name(),
+                )?;
+            }
+        }
+        for blob in self.blobs.iter() {
+            write(&mut image, blob.offset, &blob.data, "blob")?;
+        }
+        Ok(image)
+    }
+}
+
+fn write(image: &mut [u8], offset: usize, bytes: &[u8], name: &str) -> Result<(), Error> {
+    let size = image.len();
+    let target = offset
+        .checked_add(bytes.len())
+        .and_then(|end| image.get_mut(offset..end))
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "{} at {:#X} ends past the image at {:#X}",
+                name, offset, size
+            ))
+        })?;
+    target.copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Serializes bytes as a standard base64 string with padding
+mod base64 {
+    use alloc::{string::String, vec::Vec};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    pub fn encode(data: &[u8]) -> String {
+        let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+        for chunk in data.chunks(3) {
+            let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+                bits | (*byte as u32) << (16 - 8 * i)
+            });
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    text.push(ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3F] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    pub fn decode(text: &str) -> Option<Vec<u8>> {
+        let text = text.as_bytes();
+        if !text.len().is_multiple_of(4) {
+            return None;
+        }
+        let mut data = Vec::with_capacity(text.len() / 4 * 3);
+        for chunk in text.chunks(4) {
+            let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+            if padding > 2 {
+                return None;
+            }
+            let mut bits = 0u32;
+            for c in chunk[..4 - padding].iter() {
+                let value = ALPHABET.iter().position(|a| a == c)? as u32;
+                bits = bits << 6 | value;
+            }
+            bits <<= 6 * padding;
+            for i in 0..3 - padding {
+                data.push((bits >> (16 - 8 * i)) as u8);
+            }
+        }
+        Some(data)
+    }
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        decode(&text).ok_or_else(|| D::Error::custom("invalid base64"))
+    }
+}
@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: MIT
+
+//! Shannon entropy of an image by block, telling compressed or encrypted
+//! data (close to 8 bits per byte) from code, tables and erased space
+
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// Block size of `EntropyMap::new` callers without a preference
+pub const DEFAULT_BLOCK: usize = 0x1000;
+
+/// Entropy of every block of an image, in bits per byte from 0 to 8. The
+/// last block may be shorter than the others.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EntropyMap {
+    pub block_size: usize,
+    pub entropy: Vec<f32>,
+}
+
+impl EntropyMap {
+    /// Computes the entropy of every block of `block_size` bytes, which must
+    /// not be 0. With the std feature, blocks are computed by one thread per
+    /// CPU.
+    pub fn new(data: &[u8], block_size: usize) -> Self {
+        assert!(block_size > 0, "entropy block size is 0");
+        let table = Table::new(block_size);
+        EntropyMap {
+            block_size,
+            entropy: map(data, block_size, &table),
+        }
+    }
+
+    /// Entropy of the block holding the byte at `offset`
+    pub fn at(&self, offset: usize) -> Option<f32> {
+        self.entropy.get(offset / self.block_size).copied()
+    }
+
+    /// Offsets and entropies of the blocks, in order
+    pub fn blocks(&self) -> impl Iterator<Item = (usize, f32)> + '_ {
+        let size = self.block_size;
+        self.entropy
+            .iter()
+            .enumerate()
+            .map(move |(i, entropy)| (i * size, *entropy))
+    }
+}
+
+/// Entropy of the data in bits per byte
+pub fn entropy(data: &[u8]) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = histogram(data)
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| *c as f64 * libm::log2(*c as f64))
+        .sum();
+    let n = data.len() as f64;
+    (libm::log2(n) - sum / n).max(0.0) as f32
+}
+
+/// `c * log2(c)` for every count a byte can have in a block, so a block
+/// costs a histogram and 256 lookups instead of 256 logarithms
+pub(crate) struct Table {
+    c_log_c: Vec<f64>,
+}
+
+impl Table {
+    pub(crate) fn new(block_size: usize) -> Self {
+        Table {
+            c_log_c: (0..=block_size)
+                .map(|c| match c {
+                    0 => 0.0,
+                    c => c as f64 * libm::log2(c as f64),
+                })
+                .collect(),
+        }
+    }
+
+    /// Entropy of `len` bytes with the given byte counts, `len` being at
+    /// most the block size of the table
+    pub(crate) fn entropy(&self, counts: &[u32; 256], len: usize) -> f32 {
+        if len == 0 {
+            return 0.0;
+        }
+        let sum: f64 = counts.iter().map(|c| self.c_log_c[*c as usize]).sum();
+        let n = len as f64;
+        (libm::log2(n) - sum / n).max(0.0) as f32
+    }
+}
+
+/// Counts of every byte value. Consecutive bytes are counted in separate
+/// histograms, which are summed at the end, so increments of the same
+/// counter do not wait for each other.
+fn histogram(data: &[u8]) -> [u32; 256] {
+    let mut partial = [[0u32; 256]; 4];
+    let mut words = data.chunks_exact(4);
+    for word in words.by_ref() {
+        partial[0][word[0] as usize] += 1;
+        partial[1][word[1] as usize] += 1;
+        partial[2][word[2] as usize] += 1;
+        partial[3][word[3] as usize] += 1;
+    }
+    for byte in words.remainder() {
+        partial[0][*byte as usize] += 1;
+    }
+    let mut counts = [0u32; 256];
+    for (i, count) in counts.iter_mut().enumerate() {
+        *count = partial[0][i] + partial[1][i] + partial[2][i] + partial[3][i];
+    }
+    counts
+}
+
+fn blocks(data: &[u8], block_size: usize, table: &Table) -> Vec<f32> {
+    data.chunks(block_size)
+        .map(|block| table.entropy(&histogram(block), block.len()))
+        .collect()
+}
+
+#[cfg(not(feature = "std"))]
+fn map(data: &[u8], block_size: usize, table: &Table) -> Vec<f32> {
+    blocks(data, block_size, table)
+}
+
+/// Splits the image into one run of whole blocks per CPU
+#[cfg(feature = "std")]
+fn map(data: &[u8], block_size: usize, table: &Table) -> Vec<f32> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let count = data.len().div_ceil(block_size);
+    let per_thread = count.div_ceil(threads);
+    if threads == 1 || per_thread < 2 {
+        return blocks(data, block_size, table);
+    }
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = data
+            .chunks(per_thread * block_size)
+            .map(|run| scope.spawn(move || blocks(run, block_size, table)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
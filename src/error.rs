@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::{String, ToString};
+use core::mem;
+use plain::Plain;
+
+use crate::intel::RegionKind;
+
+/// Errors of parsing and editing images. Failures of parsing have a variant
+/// each, with the offsets and sizes involved. Failures of edits, which
+/// depend on their arguments, carry a message.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Error {
+    /// No Embedded Firmware Structure in an AMD image, or none at `offset`
+    #[error("Embedded Firmware Structure not found{}", at(.offset))]
+    EfsNotFound { offset: Option<usize> },
+    /// The EFS magic was found at `offset`, but only `have` bytes of the
+    /// image follow it
+    #[error("Embedded Firmware Structure at {offset:#X} truncated: {have:#X} bytes available")]
+    EfsTruncated { offset: usize, have: usize },
+    /// The image of `size` bytes is smaller than the `min` bytes of an EFS
+    #[error("image of {size:#X} bytes smaller than the minimum of {min:#X}")]
+    ImageTooSmall { size: usize, min: usize },
+    /// No flash descriptor in an Intel image
+    #[error("Flash descriptor not found")]
+    DescriptorNotFound,
+    /// The data at `addr` does not start with a known directory signature,
+    /// `found` being its first four bytes in little endian
+    #[error("unknown directory signature {found:08X} at {addr:#X}")]
+    DirectoryMagicMismatch { found: u32, addr: usize },
+    /// The data at `offset` does not start with the signature of `structure`
+    #[error("{structure} signature not found at {offset:#X}")]
+    SignatureMismatch {
+        structure: &'static str,
+        offset: usize,
+    },
+    /// A structure needs `need` bytes but only `have` are available
+    #[error("truncated: {need:#X} bytes needed, {have:#X} available")]
+    Truncated { need: usize, have: usize },
+    /// Data of `size` bytes at `offset` extends past the `have` bytes of the
+    /// image
+    #[error("{size:#X} bytes at {offset:#X} out of bounds of {have:#X} bytes")]
+    OutOfBounds {
+        offset: usize,
+        size: usize,
+        have: usize,
+    },
+    /// The structure at `addr` stores the checksum `stored` instead of the
+    /// `computed` checksum of its contents
+    #[error("checksum {stored:08X} at {addr:#X} invalid, computed {computed:08X}")]
+    ChecksumMismatch {
+        addr: usize,
+        stored: u32,
+        computed: u32,
+    },
+    /// Compressed data of `structure` is corrupt at `offset`
+    #[error("{structure} data corrupt at {offset:#X}")]
+    Corrupt {
+        structure: &'static str,
+        offset: usize,
+    },
+    /// No directory at `addr`
+    #[error("no directory at {addr:#X}")]
+    DirectoryNotFound { addr: usize },
+    /// The directory at `directory` has no entry `index`
+    #[error("directory at {directory:#X} has no entry {index}")]
+    EntryNotFound { directory: usize, index: usize },
+    /// No directory has an entry of type `kind`
+    #[error("entry {kind:02X} not found")]
+    EntryKindNotFound { kind: u8 },
+    /// The flash descriptor has no `region`
+    #[error("{region} region not found")]
+    RegionNotFound { region: RegionKind },
+    /// The BIOS region has no volume `index`
+    #[error("Volume {index} not found")]
+    VolumeNotFound { index: usize },
+    /// No variable store has a variable `name`
+    #[error("Variable {name} not found")]
+    VariableNotFound { name: String },
+    /// The partition table of the ME region has no partition `name`
+    #[error("ME partition {name} not found")]
+    PartitionNotFound { name: String },
+    /// No code partition directory of the ME region has a module `name`
+    #[error("ME module {name} not found")]
+    ModuleNotFound { name: String },
+    /// Version `version` of `structure` is not supported
+    #[error("{structure} version {version} not supported")]
+    UnsupportedVersion {
+        structure: &'static str,
+        version: u32,
+    },
+    /// A structure has invalid contents
+    #[error("{0}")]
+    Invalid(String),
+    /// Any other structure, such as a file or a wrapped image, does not exist
+    #[error("{0}")]
+    NotFound(String),
+    /// An address mode or a feature of a structure is not supported
+    #[error("{0}")]
+    Unsupported(String),
+    /// An argument of an edit is out of range
+    #[error("{0}")]
+    InvalidArgument(String),
+    /// An edit does not fit or would overwrite other data
+    #[error("{0}")]
+    Conflict(String),
+    /// Signing failed
+    #[error("{0}")]
+    Sign(String),
+}
+
+/// Where an EFS was looked for, if at a given offset
+fn at(offset: &Option<usize>) -> String {
+    offset
+        .map(|offset| format!(" at {:#X}", offset))
+        .unwrap_or_default()
+}
+
+/// Reads a `T` at `offset` in `data`, which is truncated if it is too short
+/// to hold it
+pub(crate) fn from_bytes<T: Plain>(data: &[u8], offset: usize) -> Result<&T, Error> {
+    plain::from_bytes(data.get(offset..).unwrap_or_default()).map_err(|_| Error::Truncated {
+        need: offset + mem::size_of::<T>(),
+        have: data.len(),
+    })
+}
+
+/// Lets callers reporting errors as text use `?` on library results
+impl From<Error> for String {
+    fn from(err: Error) -> String {
+        err.to_string()
+    }
+}
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MIT
+
+//! Components of an image extracted into memory by logical path, a path
+//! being the names of the components containing a component and its own
+//! name, separated by slashes
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::handler::{Registry, Target};
+use crate::identify::sha256;
+
+/// Where an extracted component was found
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Metadata {
+    /// Offset of the component in the image, none for components found in
+    /// data decoded by a handler
+    pub offset: Option<usize>,
+    /// Size of the component where it was found
+    pub size: usize,
+    pub description: String,
+    /// Whether the bytes are the component decoded by a handler instead of
+    /// the component itself
+    pub decoded: bool,
+}
+
+/// Metadata and bytes of every component, by path
+pub type Extraction = BTreeMap<String, (Metadata, Vec<u8>)>;
+
+/// Component of an extraction as listed in a manifest written along with
+/// the extracted files, telling where each came from and what it hashes to
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    /// Path of the component, which is also the path of its file
+    pub path: String,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+    /// Lowercase hex SHA-256 digest of the extracted bytes
+    pub sha256: String,
+}
+
+/// Manifest of the extraction, in the order of the paths
+pub fn manifest(extraction: &Extraction) -> Vec<ManifestEntry> {
+    extraction
+        .iter()
+        .map(|(path, (metadata, data))| ManifestEntry {
+            path: path.clone(),
+            metadata: metadata.clone(),
+            sha256: sha256(data),
+        })
+        .collect()
+}
+
+/// How extracted components are named
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Naming {
+    /// Paths following the structure of the image, telling where every
+    /// component was found
+    Tree,
+    /// Paths following the structure of the image like `Tree`, entry data
+    /// being named by its type, sub program and instance and its
+    /// description, such as `08.01.0 SMU Firmware`, so that the blobs of
+    /// two images line up when their layouts differ
+    Entry,
+    /// Blobs only, in one directory, named like the blobs of coreboot's
+    /// amd_blobs so they can be used by a coreboot build: the type of the
+    /// entry and its description in camel case, such as
+    /// `TypeId0x08_SmuFirmware.csbin`
+    Coreboot,
+}
+
+/// Words of a description in camel case, everything but letters and digits
+/// dropped: "PMU Firmware Code (DDR4 2D)" becomes "PmuFirmwareCodeDdr42d"
+pub(crate) fn camel_case(description: &str) -> String {
+    let mut name = String::new();
+    for word in description.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.extend(chars.map(|c| c.to_ascii_lowercase()));
+        }
+    }
+    name
+}
+
+/// Joins a path and the name of a component in it, slashes in the name,
+/// such as those of CBFS file names, being replaced by underscores so that
+/// the name stays one component of the path
+pub(crate) fn join(path: &str, name: &str) -> String {
+    let name = name.replace('/', "_");
+    if path.is_empty() {
+        name
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+/// Bytes of a component, decoded by the handler of its target if one is
+/// registered and succeeds
+pub(crate) fn decode(registry: &Registry, target: Target, data: &[u8]) -> (Vec<u8>, bool) {
+    match registry.extract(target, data) {
+        Some(Ok(decoded)) => (decoded, true),
+        _ => (data.to_vec(), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_keeps_names_one_component() {
+        assert_eq!(join("", "FMAP"), "FMAP");
+        assert_eq!(
+            join("FMAP/COREBOOT", "fallback/romstage"),
+            "FMAP/COREBOOT/fallback_romstage"
+        );
+        assert_eq!(
+            join("FMAP/COREBOOT", "../../../revpwn/escaped"),
+            "FMAP/COREBOOT/.._.._.._revpwn_escaped"
+        );
+    }
+}
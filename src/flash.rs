@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: MIT
+
+//! Reading images straight from a flash chip with flashrom, or from an EM100
+//! emulator with its em100 tool
+
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::{env, fs};
+
+/// Reads the flash through a flashrom programmer such as `internal` or
+/// `ch341a_spi`, with any parameters flashrom accepts after a colon, or
+/// through `em100`, into `path`
+pub fn read(programmer: &str, path: &Path) -> Result<(), String> {
+    let (tool, status) = if programmer == "em100" {
+        (
+            "em100",
+            Command::new("em100").arg("--upload").arg(path).status(),
+        )
+    } else {
+        (
+            "flashrom",
+            Command::new("flashrom")
+                .arg("--programmer")
+                .arg(programmer)
+                .arg("--read")
+                .arg(path)
+                .status(),
+        )
+    };
+    let status = status.map_err(|err| format!("failed to run {}: {}", tool, err))?;
+    if !status.success() {
+        return Err(format!("{} failed: {}", tool, status));
+    }
+    Ok(())
+}
+
+/// File the image is read into when it is not saved, removed when dropped
+pub struct Dump {
+    path: PathBuf,
+}
+
+impl Dump {
+    pub fn new() -> Self {
+        let name = format!("romulan-{}.bin", process::id());
+        Dump {
+            path: env::temp_dir().join(name),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Dump {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: MIT
+
+//! Flash map of coreboot and ChromeOS images, naming the areas of the flash,
+//! such as the CBFS of the `COREBOOT` area or the `SMMSTORE` variable store.
+//! The map is found by its signature anywhere in the image, and the offsets
+//! of the areas are in the image.
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use plain::Plain;
+
+use crate::error::{from_bytes, Error};
+
+pub const SIGNATURE: [u8; 8] = *b"__FMAP__";
+
+/// Major version of the maps that are parsed
+pub const VERSION_MAJOR: u8 = 1;
+
+/// Area flags
+pub const AREA_STATIC: u16 = 1 << 0;
+pub const AREA_COMPRESSED: u16 = 1 << 1;
+pub const AREA_RO: u16 = 1 << 2;
+pub const AREA_PRESERVE: u16 = 1 << 3;
+
+#[repr(C, packed)]
+pub struct Header {
+    pub signature: [u8; 8],
+    pub version_major: u8,
+    pub version_minor: u8,
+    /// Address the flash is mapped at
+    pub base: u64,
+    /// Size of the flash
+    pub size: u32,
+    pub name: [u8; 32],
+    /// Number of areas following the header
+    pub areas: u16,
+}
+
+unsafe impl Plain for Header {}
+
+#[repr(C, packed)]
+pub struct AreaEntry {
+    /// Offset of the area in the image
+    pub offset: u32,
+    pub size: u32,
+    pub name: [u8; 32],
+    pub flags: u16,
+}
+
+unsafe impl Plain for AreaEntry {}
+
+impl AreaEntry {
+    pub fn name(&self) -> String {
+        name(&self.name)
+    }
+}
+
+/// Names of the flags that are set
+pub fn flag_names(flags: u16) -> Vec<&'static str> {
+    [
+        (AREA_STATIC, "static"),
+        (AREA_COMPRESSED, "compressed"),
+        (AREA_RO, "read-only"),
+        (AREA_PRESERVE, "preserve"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .map(|(_, name)| *name)
+    .collect()
+}
+
+/// Name of the map or an area, up to the first NUL byte
+fn name(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as char)
+        .collect()
+}
+
+/// Flash map of an image
+pub struct Fmap<'a> {
+    data: &'a [u8],
+    /// Offset of the map in the image
+    offset: usize,
+    header: &'a Header,
+}
+
+impl<'a> Fmap<'a> {
+    /// Searches the image for a map of a version that is parsed, whose
+    /// areas follow it
+    pub fn find(data: &'a [u8]) -> Option<Fmap<'a>> {
+        (0..data.len().saturating_sub(mem::size_of::<Header>()))
+            .filter(|offset| data[*offset..].starts_with(&SIGNATURE))
+            .find_map(|offset| Fmap::new(data, offset).ok())
+    }
+
+    /// Parses the map at `offset` in the image
+    pub fn new(data: &'a [u8], offset: usize) -> Result<Fmap<'a>, Error> {
+        let header: &Header = from_bytes(data, offset)?;
+        if header.signature != SIGNATURE {
+            return Err(Error::SignatureMismatch {
+                structure: "FMAP",
+                offset,
+            });
+        }
+        if header.version_major != VERSION_MAJOR {
+            return Err(Error::UnsupportedVersion {
+                structure: "FMAP",
+                version: header.version_major as u32,
+            });
+        }
+        let need =
+            offset + mem::size_of::<Header>() + header.areas as usize * mem::size_of::<AreaEntry>();
+        if need > data.len() {
+            return Err(Error::Truncated {
+                need,
+                have: data.len(),
+            });
+        }
+        Ok(Fmap {
+            data,
+            offset,
+            header,
+        })
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn header(&self) -> &'a Header {
+        self.header
+    }
+
+    pub fn name(&self) -> String {
+        name(&self.header.name)
+    }
+
+    pub fn entries(&self) -> Vec<&'a AreaEntry> {
+        let start = self.offset + mem::size_of::<Header>();
+        let end = start + self.header.areas as usize * mem::size_of::<AreaEntry>();
+        self.data[start..end]
+            .chunks_exact(mem::size_of::<AreaEntry>())
+            .filter_map(|chunk| plain::from_bytes(chunk).ok())
+            .collect()
+    }
+
+    /// The first area of a name, such as `COREBOOT` or `SMMSTORE`
+    pub fn area(&self, name: &str) -> Option<&'a AreaEntry> {
+        self.entries()
+            .into_iter()
+            .find(|entry| entry.name() == name)
+    }
+
+    /// Data of an area, none if it is outside the image
+    pub fn area_data(&self, entry: &AreaEntry) -> Option<&'a [u8]> {
+        let start = entry.offset as usize;
+        self.data
+            .get(start..start.checked_add(entry.size as usize)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{coreboot_image, CBFS_OFFSET, COREBOOT_SIZE, FMAP_OFFSET};
+
+    #[test]
+    fn areas_are_found_by_name() {
+        let image = coreboot_image(&[]);
+        let map = Fmap::find(&image).unwrap();
+        assert_eq!(map.offset(), FMAP_OFFSET);
+        assert_eq!(map.name(), "FLASH");
+        let names: Vec<String> = map.entries().iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["FMAP", "COREBOOT"]);
+        assert_eq!(flag_names(map.entries()[0].flags), ["static"]);
+
+        let coreboot = map.area("COREBOOT").unwrap();
+        let data = map.area_data(coreboot).unwrap();
+        assert_eq!(data.len(), COREBOOT_SIZE - CBFS_OFFSET);
+        assert_eq!(data.as_ptr(), image[CBFS_OFFSET..].as_ptr());
+        assert!(map.area("SMMSTORE").is_none());
+        assert!(Fmap::find(&image[..FMAP_OFFSET]).is_none());
+    }
+
+    #[test]
+    fn invalid_maps_are_rejected() {
+        let mut image = coreboot_image(&[]);
+        assert!(matches!(
+            Fmap::new(&image, 0),
+            Err(Error::SignatureMismatch { offset: 0, .. })
+        ));
+        // Areas past the end of the image
+        let end = FMAP_OFFSET + mem::size_of::<Header>() + mem::size_of::<AreaEntry>();
+        assert!(matches!(
+            Fmap::new(&image[..end], FMAP_OFFSET),
+            Err(Error::Truncated { .. })
+        ));
+        image[FMAP_OFFSET + 8] = VERSION_MAJOR + 1;
+        assert!(matches!(
+            Fmap::new(&image, FMAP_OFFSET),
+            Err(Error::UnsupportedVersion { version: 2, .. })
+        ));
+        assert!(Fmap::find(&image).is_none());
+    }
+}
@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: MIT
+
+//! Handlers for the data of specific entry kinds and sections, registered by
+//! callers to support blobs this crate knows nothing about
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+use uefi::guid::Guid;
+
+use crate::amd::directory::{BiosDirectoryEntry, PspDirectoryEntry};
+use crate::Error;
+
+/// Data a handler is registered for
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Target {
+    /// Data of PSP directory entries of a kind
+    PspEntry(u8),
+    /// Data of BIOS directory entries of a kind
+    BiosEntry(u8),
+    /// Data of GUID defined sections with a tool GUID, following their
+    /// `section::GuidDefined` header
+    Section(Guid),
+}
+
+impl Target {
+    pub fn psp(entry: &PspDirectoryEntry) -> Self {
+        Target::PspEntry(entry.kind)
+    }
+
+    pub fn bios(entry: &BiosDirectoryEntry) -> Self {
+        Target::BiosEntry(entry.kind)
+    }
+}
+
+/// A named value parsed from data
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Field {
+    pub name: String,
+    pub value: String,
+}
+
+/// Understands the data of a `Target`. Every method has a default, so a
+/// handler only implements what it supports.
+pub trait Handler {
+    /// One line description of the data
+    fn describe(&self, _data: &[u8]) -> Option<String> {
+        None
+    }
+
+    /// Fields of a header or other structure in the data
+    fn parse(&self, _data: &[u8]) -> Result<Vec<Field>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Payload of the data, decompressed or stripped of headers. The data
+    /// itself by default.
+    fn extract(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(data.to_vec())
+    }
+}
+
+/// Handlers by target. A target registered more than once is handled by the
+/// last handler registered for it.
+#[derive(Default)]
+pub struct Registry {
+    handlers: Vec<(Target, Box<dyn Handler>)>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, target: Target, handler: Box<dyn Handler>) {
+        self.handlers
+            .retain(|(registered, _)| *registered != target);
+        self.handlers.push((target, handler));
+    }
+
+    /// Removes the handler of a target, returning it
+    pub fn unregister(&mut self, target: Target) -> Option<Box<dyn Handler>> {
+        let i = self
+            .handlers
+            .iter()
+            .position(|(registered, _)| *registered == target)?;
+        Some(self.handlers.remove(i).1)
+    }
+
+    pub fn get(&self, target: Target) -> Option<&dyn Handler> {
+        self.handlers
+            .iter()
+            .find(|(registered, _)| *registered == target)
+            .map(|(_, handler)| handler.as_ref())
+    }
+
+    /// Describes the data, if a handler is registered and knows it
+    pub fn describe(&self, target: Target, data: &[u8]) -> Option<String> {
+        self.get(target)?.describe(data)
+    }
+
+    /// Parses the data, if a handler is registered for its target
+    pub fn parse(&self, target: Target, data: &[u8]) -> Option<Result<Vec<Field>, Error>> {
+        Some(self.get(target)?.parse(data))
+    }
+
+    /// Extracts the payload of the data, if a handler is registered for its
+    /// target
+    pub fn extract(&self, target: Target, data: &[u8]) -> Option<Result<Vec<u8>, Error>> {
+        Some(self.get(target)?.extract(data))
+    }
+}
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: MIT
+
+//! Identification of blobs by their SHA-256 digest, against a database built
+//! from known releases such as AMD's firmware_binaries repository or a
+//! team's golden images
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt::Write;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zerocopy::FromBytes;
+
+use crate::amd::directory::{PspBinaryHeader, PspDirectoryEntry};
+
+/// Name and version of a known blob, and the GUIDs fwupd updates it by
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Identity {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub guids: Vec<String>,
+}
+
+/// Known blobs by the lowercase hex SHA-256 digest of their data. Serializes
+/// as a map from digest to identity, so a database is loaded from JSON with
+/// `serde_json::from_str`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Database {
+    identities: BTreeMap<String, Identity>,
+}
+
+/// Lowercase hex SHA-256 digest of `data`
+pub fn sha256(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in Sha256::digest(data).iter() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.identities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+
+    /// Adds the identity of blobs with the given digest, replacing any
+    /// identity it had. The digest is case insensitive.
+    pub fn insert(&mut self, sha256: &str, identity: Identity) -> Option<Identity> {
+        self.identities
+            .insert(sha256.to_ascii_lowercase(), identity)
+    }
+
+    /// Adds the identity of `data`, such as a blob of a release
+    pub fn add(&mut self, data: &[u8], identity: Identity) -> Option<Identity> {
+        self.identities.insert(sha256(data), identity)
+    }
+
+    /// Identity of blobs with the given digest
+    pub fn get(&self, sha256: &str) -> Option<&Identity> {
+        self.identities.get(&sha256.to_ascii_lowercase())
+    }
+
+    /// Identity of `data`, if it is a known blob
+    pub fn identify(&self, data: &[u8]) -> Option<&Identity> {
+        self.identities.get(&sha256(data))
+    }
+
+    /// Adds every identity of `other`, replacing those of the same digests
+    pub fn merge(&mut self, other: Database) {
+        self.identities.extend(other.identities);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Identity)> {
+        self.identities
+            .iter()
+            .map(|(sha256, identity)| (sha256.as_str(), identity))
+    }
+}
+
+/// Identity of a blob of a reference tree such as AMD's firmware_binaries
+/// or coreboot's amd_blobs. Files named after their PSP entry type, like
+/// `TypeId0x08_SmuFirmware_Cezanne.csbin`, are named by the description of
+/// the type, other files by their name. `program` is the directory of the
+/// file, naming the processors it is for. The version is that of the PSP
+/// binary header, if the blob has one.
+pub fn reference_identity(program: Option<&str>, file_name: &str, data: &[u8]) -> Identity {
+    let kind = file_name
+        .strip_prefix("TypeId0x")
+        .and_then(|rest| rest.get(..2))
+        .and_then(|kind| u8::from_str_radix(kind, 16).ok());
+    let base = match kind {
+        Some(kind) => {
+            let mut entry = PspDirectoryEntry::new_zeroed();
+            entry.kind = kind;
+            String::from(entry.description())
+        }
+        None => String::from(file_name),
+    };
+    let name = match program {
+        Some(program) => format!("{} for {}", base, program),
+        None => base,
+    };
+    Identity {
+        name,
+        version: psp_version(data),
+        guids: Vec::new(),
+    }
+}
+
+/// Version of the PSP binary header of the blob, as dotted bytes from the
+/// most significant one, leading zero bytes left out
+pub(crate) fn psp_version(data: &[u8]) -> Option<String> {
+    let header = PspBinaryHeader::new(data)?;
+    if header.magic != u32::from_le_bytes(*b"$PS1") || matches!(header.version, 0 | 0xFFFF_FFFF) {
+        return None;
+    }
+    let bytes = header.version.to_be_bytes();
+    let first = bytes.iter().position(|byte| *byte != 0)?;
+    let mut version = String::new();
+    for (i, byte) in bytes[first..].iter().enumerate() {
+        if i > 0 {
+            version.push('.');
+        }
+        let _ = write!(version, "{}", byte);
+    }
+    Some(version)
+}
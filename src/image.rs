@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: MIT
+
+//! Vendor independent view of an image, for callers that only need its
+//! layout, its components and a verification pass
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::{Area, Layout};
+use crate::report::{self, VolumeReport};
+use crate::{amd, intel, Error};
+
+/// Vendor whose structures an image is parsed by
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Vendor {
+    Intel,
+    Amd,
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Vendor::Intel => write!(f, "Intel"),
+            Vendor::Amd => write!(f, "AMD"),
+        }
+    }
+}
+
+/// An image parsed by one vendor's structures. Offsets are in `data`, which
+/// starts where the vendor's structures place the start of the flash.
+pub trait FirmwareImage {
+    fn vendor(&self) -> Vendor;
+
+    fn data(&self) -> &[u8];
+
+    /// Top level areas of the image: the flash regions of Intel images, the
+    /// EFS, directories and entry data of AMD images
+    fn regions(&self) -> Result<Layout, Error>;
+
+    /// Every part of the image found, outer parts before the parts they
+    /// contain
+    fn components(&self) -> Vec<Area>;
+
+    /// Description of every problem found in the image
+    fn verify(&self) -> Vec<String>;
+
+    /// One line description of the image
+    fn summary(&self) -> String;
+}
+
+/// Parses the image as an Intel image, or as an AMD image if it has no flash
+/// descriptor. The image is borrowed from any source of bytes, such as a
+/// `Vec<u8>` or a memory mapped file, and is not copied.
+pub fn open<S: AsRef<[u8]> + ?Sized>(source: &S) -> Result<Box<dyn FirmwareImage + '_>, Error> {
+    let data = source.as_ref();
+    if let Ok(rom) = intel::Rom::new(data) {
+        Ok(Box::new(rom))
+    } else if let Ok(rom) = amd::Rom::new(data) {
+        Ok(Box::new(rom))
+    } else {
+        Err(Error::NotFound(String::from(
+            "no Intel flash descriptor or AMD EFS found",
+        )))
+    }
+}
+
+impl<'a> FirmwareImage for intel::Rom<'a> {
+    fn vendor(&self) -> Vendor {
+        Vendor::Intel
+    }
+
+    fn data(&self) -> &[u8] {
+        intel::Rom::data(self)
+    }
+
+    fn regions(&self) -> Result<Layout, Error> {
+        self.layout()
+    }
+
+    fn components(&self) -> Vec<Area> {
+        let mut components = self
+            .layout()
+            .map(|layout| layout.areas().to_vec())
+            .unwrap_or_default();
+        if let (Ok(Some(bios)), Ok(Some((base, _)))) = (
+            self.bios(),
+            self.get_region_base_limit(intel::RegionKind::Bios),
+        ) {
+            for volume in report::volumes(bios.data(), base) {
+                push_volume(&mut components, &volume);
+            }
+        }
+        components
+    }
+
+    fn verify(&self) -> Vec<String> {
+        intel::Rom::verify(self)
+    }
+
+    fn summary(&self) -> String {
+        let regions = self
+            .layout()
+            .map(|layout| layout.areas().len())
+            .unwrap_or(0);
+        let version = self.me().ok().flatten().and_then(|me| me.version());
+        match version {
+            Some(version) => format!("Intel image, {} regions, ME {}", regions, version),
+            None => format!("Intel image, {} regions", regions),
+        }
+    }
+}
+
+/// Adds a volume, its files and the volumes in their sections
+fn push_volume(components: &mut Vec<Area>, volume: &VolumeReport) {
+    components.push(Area {
+        name: format!("Volume {}", volume.guid),
+        offset: volume.offset,
+        size: volume.header_length as usize + volume.size,
+    });
+    for file in volume.files.iter() {
+        components.push(Area {
+            name: format!("File {}", file.guid),
+            offset: file.offset,
+            size: file.size,
+        });
+        for section in file.sections.iter() {
+            for volume in section.volumes.iter() {
+                push_volume(components, volume);
+            }
+        }
+    }
+}
+
+impl<'a> FirmwareImage for amd::Rom<'a> {
+    fn vendor(&self) -> Vendor {
+        Vendor::Amd
+    }
+
+    fn data(&self) -> &[u8] {
+        amd::Rom::data(self)
+    }
+
+    fn regions(&self) -> Result<Layout, Error> {
+        Ok(self.layout())
+    }
+
+    fn components(&self) -> Vec<Area> {
+        self.layout().areas().to_vec()
+    }
+
+    fn verify(&self) -> Vec<String> {
+        amd::Rom::verify(self)
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "AMD image, EFS at {:#X}, {} directories",
+            self.efs_offset(),
+            self.directories().len()
+        )
+    }
+}
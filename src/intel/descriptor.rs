@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: MIT
+
+//! Contents of the flash descriptor: the flash components, the regions and
+//! the gaps between them, the access the flash masters have to the regions
+//! and the straps read by the chipset and the processor
+
+use alloc::{format, string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+use super::{RegionKind, Rom, ALT_ME_DISABLE, HAP};
+use crate::Error;
+
+/// Layout of the descriptor, which changed with the 100 series chipsets
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Version {
+    /// ICH8 to 9 series chipsets: 5 regions, 3 masters, component densities
+    /// in 3 bits
+    One,
+    /// 100 series and later chipsets: up to 12 regions, 5 masters,
+    /// component densities in 4 bits
+    Two,
+}
+
+impl Version {
+    /// Version of a descriptor with the first flash master entry. Older
+    /// descriptors have the requester ID 0 of the host CPU in bits 15:0 of
+    /// it, newer ones access bits for regions 0 to 7 there.
+    pub fn of(mstr1: u32) -> Self {
+        if mstr1 & 0xFFFF == 0 {
+            Version::One
+        } else {
+            Version::Two
+        }
+    }
+
+    /// Bit of the first region in the read and write access fields of a
+    /// flash master entry
+    pub fn access_shifts(&self) -> (u32, u32) {
+        match self {
+            Version::One => (16, 24),
+            Version::Two => (8, 20),
+        }
+    }
+
+    /// Number of regions the access fields of a flash master entry cover
+    pub fn access_regions(&self) -> usize {
+        match self {
+            Version::One => 8,
+            Version::Two => 12,
+        }
+    }
+
+    /// Index of the PCH strap and the bit in it that disables the ME after
+    /// bring up: the HAP bit of PCHSTRAP0, or the AltMeDisable bit of
+    /// PCHSTRAP10 of chipsets before the 100 series
+    pub fn hap_strap(&self) -> (usize, u32) {
+        match self {
+            Version::One => (10, ALT_ME_DISABLE),
+            Version::Two => (0, HAP),
+        }
+    }
+
+    fn regions(&self) -> usize {
+        match self {
+            Version::One => 5,
+            Version::Two => RegionKind::ALL.len(),
+        }
+    }
+
+    fn masters(&self) -> &'static [&'static str] {
+        match self {
+            Version::One => &MASTERS[..3],
+            Version::Two => &MASTERS[..],
+        }
+    }
+}
+
+/// Names of the flash masters, in the order of their entries
+pub const MASTERS: [&str; 5] = ["Host CPU/BIOS", "ME", "GbE", "Reserved", "EC"];
+
+/// Name of the region with the index, which is past the kinds known for
+/// regions 9 to 11 of the access fields
+pub fn region_name(index: usize) -> String {
+    match RegionKind::ALL.get(index) {
+        Some(kind) => format!("{}", kind),
+        None => format!("Region {}", index),
+    }
+}
+
+/// Flash chip described by the descriptor
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Component {
+    pub index: usize,
+    /// Size of the chip in bytes, unknown for reserved density values
+    pub density: Option<usize>,
+}
+
+/// Density of a component as a size in bytes
+pub fn density(version: Version, flcomp: u32, index: usize) -> Option<usize> {
+    let value = match version {
+        Version::One => (flcomp >> (index * 3)) & 0x7,
+        Version::Two => (flcomp >> (index * 4)) & 0xF,
+    };
+    match (version, value) {
+        (Version::One, 0..=5) | (Version::Two, 0..=7) => Some(0x8_0000 << value),
+        _ => None,
+    }
+}
+
+/// Region of the descriptor, or gap between regions named "Padding"
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RegionEntry {
+    pub name: String,
+    /// Index of the region in the region table, none for padding
+    pub index: Option<usize>,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Access of a flash master to the regions
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MasterAccess {
+    pub name: String,
+    /// The flash master entry
+    pub value: u32,
+    /// Names of the regions the master may read
+    pub read: Vec<String>,
+    /// Names of the regions the master may write
+    pub write: Vec<String>,
+}
+
+impl MasterAccess {
+    pub fn new(version: Version, name: &str, value: u32) -> Self {
+        let (read, write) = version.access_shifts();
+        let regions = |shift: u32| {
+            (0..version.access_regions())
+                .filter(|i| value & 1 << (shift + *i as u32) != 0)
+                .map(region_name)
+                .collect()
+        };
+        MasterAccess {
+            name: String::from(name),
+            value,
+            read: regions(read),
+            write: regions(write),
+        }
+    }
+}
+
+impl<'a> Rom<'a> {
+    /// Layout version of the descriptor
+    pub fn descriptor_version(&self) -> Result<Version, Error> {
+        Ok(Version::of(self.flash_master()?.mstr1))
+    }
+
+    /// Flash chips, with their densities from the FLCOMP register
+    pub fn components(&self) -> Result<Vec<Component>, Error> {
+        let version = self.descriptor_version()?;
+        let offset = ((self.descriptor.map0 & 0xFF) << 4) as usize;
+        let flcomp = self.dwords(offset, 1)?[0];
+        let count = ((self.descriptor.map0 >> 8) & 0x3) as usize + 1;
+        Ok((0..count)
+            .map(|index| Component {
+                index,
+                density: density(version, flcomp, index),
+            })
+            .collect())
+    }
+
+    /// Regions the descriptor claims space for, and the gaps between them,
+    /// sorted by offset. Regions other than the descriptor starting at 0 are
+    /// left out, being the zero entries of unused regions.
+    pub fn regions(&self) -> Result<Vec<RegionEntry>, Error> {
+        let version = self.descriptor_version()?;
+        let mut regions = Vec::new();
+        for (index, kind) in RegionKind::ALL.iter().enumerate().take(version.regions()) {
+            match self.get_region_base_limit(*kind)? {
+                Some((0, _)) if *kind != RegionKind::Descriptor => (),
+                Some((base, limit)) => regions.push(RegionEntry {
+                    name: format!("{}", kind),
+                    index: Some(index),
+                    offset: base,
+                    size: limit + 1 - base,
+                }),
+                None => (),
+            }
+        }
+        regions.sort_by_key(|region| region.offset);
+
+        let mut offset = 0;
+        let mut entries = Vec::new();
+        for region in regions {
+            if region.offset > offset {
+                entries.push(padding(offset, region.offset));
+            }
+            offset = offset.max(region.offset + region.size);
+            entries.push(region);
+        }
+        if self.data.len() > offset {
+            entries.push(padding(offset, self.data.len()));
+        }
+        Ok(entries)
+    }
+
+    /// Read and write access of the flash masters
+    pub fn masters(&self) -> Result<Vec<MasterAccess>, Error> {
+        let version = self.descriptor_version()?;
+        let master = self.flash_master()?;
+        let values = [
+            master.mstr1,
+            master.mstr2,
+            master.mstr3,
+            master.mstr4,
+            master.mstr5,
+        ];
+        Ok(version
+            .masters()
+            .iter()
+            .zip(values.iter())
+            .map(|(name, value)| MasterAccess::new(version, name, *value))
+            .collect())
+    }
+
+    /// Straps read by the chipset, as many as the descriptor holds
+    pub fn pch_straps(&self) -> Result<Vec<u32>, Error> {
+        let offset = (((self.descriptor.map1 >> 16) & 0xFF) << 4) as usize;
+        let count = (self.descriptor.map1 >> 24) as usize;
+        self.dwords(offset, count)
+    }
+
+    /// Straps read by the processor, as many as the descriptor holds
+    pub fn processor_straps(&self) -> Result<Vec<u32>, Error> {
+        let offset = ((self.descriptor.map2 & 0xFF) << 4) as usize;
+        let count = ((self.descriptor.map2 >> 8) & 0xFF) as usize;
+        self.dwords(offset, count)
+    }
+
+    fn dwords(&self, offset: usize, count: usize) -> Result<Vec<u32>, Error> {
+        let end = offset + count * 4;
+        let data = self.data.get(offset..end).ok_or(Error::Truncated {
+            need: end,
+            have: self.data.len(),
+        })?;
+        Ok(data
+            .chunks_exact(4)
+            .map(|dword| u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]))
+            .collect())
+    }
+}
+
+fn padding(offset: usize, end: usize) -> RegionEntry {
+    RegionEntry {
+        name: String::from("Padding"),
+        index: None,
+        offset,
+        size: end - offset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{IntelFixture, BIOS_REGION_OFFSET, INTEL_SIZE, ME_REGION_OFFSET};
+
+    #[test]
+    fn regions_and_gaps_are_listed() {
+        let image = IntelFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        assert_eq!(rom.descriptor_version().unwrap(), Version::Two);
+        assert_eq!(
+            rom.components().unwrap(),
+            [Component {
+                index: 0,
+                density: Some(0x8_0000),
+            }]
+        );
+        let regions = rom.regions().unwrap();
+        let layout: Vec<(&str, Option<usize>, usize, usize)> = regions
+            .iter()
+            .map(|region| {
+                (
+                    region.name.as_str(),
+                    region.index,
+                    region.offset,
+                    region.size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                ("Flash Descriptor", Some(0), 0, ME_REGION_OFFSET),
+                ("Intel ME", Some(2), ME_REGION_OFFSET, 0x3000),
+                ("Padding", None, 0x4000, BIOS_REGION_OFFSET - 0x4000),
+                (
+                    "BIOS",
+                    Some(1),
+                    BIOS_REGION_OFFSET,
+                    INTEL_SIZE - BIOS_REGION_OFFSET
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn master_access_and_straps_are_decoded() {
+        let image = IntelFixture {
+            mstr1: 0x0060_0700,
+            hap: true,
+            ..IntelFixture::default()
+        }
+        .build();
+        let rom = Rom::new(&image).unwrap();
+        let masters = rom.masters().unwrap();
+        let names: Vec<&str> = masters.iter().map(|master| master.name.as_str()).collect();
+        assert_eq!(names, MASTERS);
+        assert_eq!(masters[0].read, ["Flash Descriptor", "BIOS", "Intel ME"]);
+        assert_eq!(masters[0].write, ["BIOS", "Intel ME"]);
+        assert_eq!(masters[1].write, ["Intel ME"]);
+
+        assert_eq!(rom.pch_straps().unwrap(), [HAP, 0]);
+        assert_eq!(rom.processor_straps().unwrap(), [0xAB]);
+        assert!(rom.high_assurance_platform().unwrap());
+    }
+
+    #[test]
+    fn first_layout_is_told_apart() {
+        assert_eq!(Version::of(0x0A0B_0000), Version::One);
+        let access = MasterAccess::new(Version::One, "Host CPU/BIOS", 0x0A0B_0000);
+        assert_eq!(access.read, ["Flash Descriptor", "BIOS", "GbE"]);
+        assert_eq!(access.write, ["BIOS", "GbE"]);
+        assert_eq!(density(Version::One, 0x2A, 0), Some(0x20_0000));
+        assert_eq!(density(Version::One, 0x2A, 1), Some(0x100_0000));
+        assert_eq!(density(Version::One, 0x7, 0), None);
+        assert_eq!(Version::One.hap_strap(), (10, ALT_ME_DISABLE));
+        assert_eq!(region_name(10), "Region 10");
+    }
+}
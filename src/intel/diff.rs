@@ -0,0 +1,481 @@
+// SPDX-License-Identifier: MIT
+
+//! Differences between the regions, the ME version and the BIOS volumes of
+//! two images. Regions are matched by name, volumes and files by GUID and
+//! how many of the same GUID come before them, so that files that only moved
+//! are not reported.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use serde::{Deserialize, Serialize};
+
+use super::descriptor::RegionEntry;
+use super::{file, me, microcode, Rom};
+use crate::cbfs;
+use crate::identify::sha256;
+
+/// A region whose range or data differs between the images, or which is
+/// only in one of them
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RegionDiff {
+    pub name: String,
+    pub before: Option<RegionEntry>,
+    pub after: Option<RegionEntry>,
+    /// Whether the data of a region in both images differs
+    pub data_changed: bool,
+}
+
+/// ME versions that differ between the images, none if the image has no
+/// ME region or its version is unknown
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MeDiff {
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Microcode revision for a CPUID signature that differs between the
+/// images, the highest of the updates for it, none if the image has no
+/// update for it
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MicrocodeDiff {
+    pub cpuid: u32,
+    pub before: Option<u32>,
+    pub after: Option<u32>,
+}
+
+/// Partition of the ME region or module of a code partition directory
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MeEntry {
+    pub name: String,
+    /// Offset in the ME region of partitions, in the partition of modules
+    pub offset: usize,
+    pub length: usize,
+    /// Lowercase hex SHA-256 digest of the data
+    pub sha256: String,
+}
+
+/// A module added, changed or removed between the images
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ModuleDiff {
+    pub before: Option<MeEntry>,
+    pub after: Option<MeEntry>,
+}
+
+/// An ME partition whose data differs between the images, with the modules
+/// that differ, or which is only in one of them
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PartitionDiff {
+    pub name: String,
+    pub before: Option<MeEntry>,
+    pub after: Option<MeEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<ModuleDiff>,
+}
+
+/// File of a BIOS volume
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileEntry {
+    pub guid: String,
+    /// Offset of the file header in the BIOS region
+    pub offset: usize,
+    /// Size of the file data
+    pub size: usize,
+    /// Lowercase hex SHA-256 digest of the file data
+    pub sha256: String,
+}
+
+/// A file added, changed or removed between the images. Added files are
+/// not before, removed ones not after.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileDiff {
+    pub before: Option<FileEntry>,
+    pub after: Option<FileEntry>,
+}
+
+/// A BIOS volume of either image whose files differ from the other image,
+/// or which is only in one of them
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VolumeDiff {
+    pub guid: String,
+    /// Offset of the volume in the BIOS region before
+    pub before: Option<usize>,
+    /// Offset of the volume in the BIOS region after
+    pub after: Option<usize>,
+    pub files: Vec<FileDiff>,
+}
+
+/// Differences between two images
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RomDiff {
+    pub regions: Vec<RegionDiff>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub me: Option<MeDiff>,
+    /// ME partitions that differ, in the order they are in the image before,
+    /// those only in the image after last
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub me_partitions: Vec<PartitionDiff>,
+    /// Sorted by CPUID
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub microcode: Vec<MicrocodeDiff>,
+    /// Volumes that differ, in the order they are in the image before, those
+    /// only in the image after last
+    pub volumes: Vec<VolumeDiff>,
+    /// CBFS files that differ, for images with a flash map
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cbfs: Vec<cbfs::FileDiff>,
+}
+
+impl RomDiff {
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+            && self.me.is_none()
+            && self.me_partitions.is_empty()
+            && self.microcode.is_empty()
+            && self.volumes.is_empty()
+            && self.cbfs.is_empty()
+    }
+}
+
+/// Compares the regions, the ME version and partitions, the microcode
+/// revisions, the files of the BIOS volumes and the CBFS files of the images
+pub fn diff(before: &Rom, after: &Rom) -> RomDiff {
+    let cbfs = cbfs::diff(before.data(), after.data());
+    let regions = diff_regions(before, after);
+    let me_partitions = diff_partitions(before, after);
+    let microcode = diff_microcode(before, after);
+
+    let before_me = before.me().ok().flatten().and_then(|me| me.version());
+    let after_me = after.me().ok().flatten().and_then(|me| me.version());
+    let me = if before_me == after_me {
+        None
+    } else {
+        Some(MeDiff {
+            before: before_me,
+            after: after_me,
+        })
+    };
+
+    let before = volumes(before);
+    let mut after = volumes(after);
+    let mut volumes = Vec::new();
+    for old in before {
+        let new = after
+            .iter()
+            .position(|new| new.key == old.key)
+            .map(|i| after.remove(i));
+        volumes.push(diff_volume(Some(old), new));
+    }
+    for new in after {
+        volumes.push(diff_volume(None, Some(new)));
+    }
+    volumes.retain(|volume| {
+        volume.before.is_none() || volume.after.is_none() || !volume.files.is_empty()
+    });
+
+    RomDiff {
+        regions,
+        me,
+        me_partitions,
+        microcode,
+        volumes,
+        cbfs,
+    }
+}
+
+fn diff_partitions(before: &Rom, after: &Rom) -> Vec<PartitionDiff> {
+    let mut after = partitions(after);
+    let mut diffs = Vec::new();
+    for old in partitions(before) {
+        let new = after
+            .iter()
+            .position(|new| new.entry.name == old.entry.name)
+            .map(|i| after.remove(i));
+        match new {
+            Some(new) if new.entry == old.entry => (),
+            Some(new) => diffs.push(PartitionDiff {
+                name: old.entry.name.clone(),
+                modules: diff_modules(old.modules, new.modules),
+                before: Some(old.entry),
+                after: Some(new.entry),
+            }),
+            None => diffs.push(PartitionDiff {
+                name: old.entry.name.clone(),
+                before: Some(old.entry),
+                after: None,
+                modules: Vec::new(),
+            }),
+        }
+    }
+    for new in after {
+        diffs.push(PartitionDiff {
+            name: new.entry.name.clone(),
+            before: None,
+            after: Some(new.entry),
+            modules: Vec::new(),
+        });
+    }
+    diffs
+}
+
+fn diff_modules(before: Vec<MeEntry>, mut after: Vec<MeEntry>) -> Vec<ModuleDiff> {
+    let mut modules = Vec::new();
+    for old in before {
+        let new = after
+            .iter()
+            .position(|new| new.name == old.name)
+            .map(|i| after.remove(i));
+        match new {
+            Some(new) if new.sha256 == old.sha256 => (),
+            new => modules.push(ModuleDiff {
+                before: Some(old),
+                after: new,
+            }),
+        }
+    }
+    for new in after {
+        modules.push(ModuleDiff {
+            before: None,
+            after: Some(new),
+        });
+    }
+    modules
+}
+
+fn diff_microcode(before: &Rom, after: &Rom) -> Vec<MicrocodeDiff> {
+    // Highest revision for each CPUID
+    let revisions = |rom: &Rom| -> BTreeMap<u32, u32> {
+        let mut revisions = BTreeMap::new();
+        for update in microcode::updates(rom) {
+            for cpuid in update.cpuids() {
+                let revision = revisions.entry(cpuid).or_insert(update.revision);
+                *revision = (*revision).max(update.revision);
+            }
+        }
+        revisions
+    };
+    let before = revisions(before);
+    let mut after = revisions(after);
+    let mut diffs = Vec::new();
+    for (cpuid, revision) in before {
+        let new = after.remove(&cpuid);
+        if new != Some(revision) {
+            diffs.push(MicrocodeDiff {
+                cpuid,
+                before: Some(revision),
+                after: new,
+            });
+        }
+    }
+    for (cpuid, revision) in after {
+        diffs.push(MicrocodeDiff {
+            cpuid,
+            before: None,
+            after: Some(revision),
+        });
+    }
+    diffs.sort_by_key(|diff| diff.cpuid);
+    diffs
+}
+
+fn diff_regions<'a>(before: &Rom<'a>, after: &Rom<'a>) -> Vec<RegionDiff> {
+    let regions = |rom: &Rom| -> Vec<RegionEntry> {
+        rom.regions()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|region| region.index.is_some())
+            .collect()
+    };
+    let data = |rom: &Rom<'a>, region: &RegionEntry| -> &'a [u8] {
+        rom.data()
+            .get(region.offset..region.offset + region.size)
+            .unwrap_or_default()
+    };
+
+    let mut after_regions = regions(after);
+    let mut diffs = Vec::new();
+    for old in regions(before) {
+        let new = after_regions
+            .iter()
+            .position(|new| new.name == old.name)
+            .map(|i| after_regions.remove(i));
+        let data_changed = new
+            .as_ref()
+            .is_some_and(|new| data(before, &old) != data(after, new));
+        if new.as_ref() == Some(&old) && !data_changed {
+            continue;
+        }
+        diffs.push(RegionDiff {
+            name: old.name.clone(),
+            before: Some(old),
+            after: new,
+            data_changed,
+        });
+    }
+    for new in after_regions {
+        diffs.push(RegionDiff {
+            name: new.name.clone(),
+            before: None,
+            after: Some(new),
+            data_changed: false,
+        });
+    }
+    diffs
+}
+
+fn diff_volume(before: Option<Volume>, after: Option<Volume>) -> VolumeDiff {
+    let guid = before
+        .as_ref()
+        .or(after.as_ref())
+        .map(|volume| volume.key.0.clone())
+        .unwrap_or_default();
+    let (before_offset, before) = match before {
+        Some(volume) => (Some(volume.offset), volume.files),
+        None => (None, Vec::new()),
+    };
+    let (after_offset, mut after) = match after {
+        Some(volume) => (Some(volume.offset), volume.files),
+        None => (None, Vec::new()),
+    };
+
+    let mut files = Vec::new();
+    for old in before {
+        let new = after
+            .iter()
+            .position(|new| new.key == old.key)
+            .map(|i| after.remove(i));
+        match new {
+            Some(new) if new.entry.sha256 == old.entry.sha256 => (),
+            new => files.push(FileDiff {
+                before: Some(old.entry),
+                after: new.map(|new| new.entry),
+            }),
+        }
+    }
+    for new in after {
+        files.push(FileDiff {
+            before: None,
+            after: Some(new.entry),
+        });
+    }
+    VolumeDiff {
+        guid,
+        before: before_offset,
+        after: after_offset,
+        files,
+    }
+}
+
+/// File of a volume, keyed by its GUID and how many files of the GUID come
+/// before it
+struct File {
+    key: (String, usize),
+    entry: FileEntry,
+}
+
+struct Volume {
+    key: (String, usize),
+    offset: usize,
+    files: Vec<File>,
+}
+
+/// Volumes of the BIOS region with their files, pad files left out
+fn volumes(rom: &Rom) -> Vec<Volume> {
+    let bios = match rom.bios() {
+        Ok(Some(bios)) => bios,
+        _ => return Vec::new(),
+    };
+    let mut volumes: Vec<Volume> = Vec::new();
+    for volume in bios.volumes() {
+        let header = volume.header();
+        let guid = { header.guid }.to_string();
+        let data_offset = volume.offset() + header.header_length as usize;
+        let mut files: Vec<File> = Vec::new();
+        for file in volume.files() {
+            let header = file.header();
+            if header.kind() == file::HeaderKind::Ffs(file::KIND_PAD) {
+                continue;
+            }
+            let guid = { header.guid }.to_string();
+            let count = occurrences(files.iter().map(|file| &file.key.0), &guid);
+            files.push(File {
+                key: (guid.clone(), count),
+                entry: FileEntry {
+                    guid,
+                    offset: data_offset + file.offset(),
+                    size: file.data().len(),
+                    sha256: sha256(file.data()),
+                },
+            });
+        }
+        let count = occurrences(volumes.iter().map(|volume| &volume.key.0), &guid);
+        volumes.push(Volume {
+            key: (guid, count),
+            offset: volume.offset(),
+            files,
+        });
+    }
+    volumes
+}
+
+/// Partition of the ME region with the modules of its code partition
+/// directory
+struct Partition {
+    entry: MeEntry,
+    modules: Vec<MeEntry>,
+}
+
+/// Partitions of the flash partition table of the ME region
+fn partitions(rom: &Rom) -> Vec<Partition> {
+    let fpt = match rom.me() {
+        Ok(Some(me)) => match me::Fpt::new(me.data()) {
+            Ok(fpt) => fpt,
+            Err(_) => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+    fpt.entries()
+        .into_iter()
+        .map(|entry| {
+            let data = fpt.partition(entry).unwrap_or_default();
+            let modules = match me::Cpd::new(data) {
+                Ok(cpd) => cpd
+                    .entries()
+                    .into_iter()
+                    .map(|module| MeEntry {
+                        name: me::entry_name(&module.name),
+                        offset: module.offset(),
+                        length: module.length as usize,
+                        sha256: sha256(cpd.module(module).unwrap_or_default()),
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            Partition {
+                entry: MeEntry {
+                    name: me::entry_name(&entry.name),
+                    offset: entry.offset as usize,
+                    length: entry.length as usize,
+                    sha256: sha256(data),
+                },
+                modules,
+            }
+        })
+        .collect()
+}
+
+fn occurrences<'a>(guids: impl Iterator<Item = &'a String>, guid: &str) -> usize {
+    guids.filter(|other| *other == guid).count()
+}
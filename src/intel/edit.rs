@@ -0,0 +1,350 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use uefi::guid::Guid;
+
+use super::{file, section, volume, BiosVolume, RegionKind, Rom};
+use crate::error::{from_bytes, Error};
+
+fn align(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+fn polarity(volume: &BiosVolume) -> bool {
+    volume
+        .header()
+        .attributes()
+        .contains(volume::Attributes::ERASE_POLARITY)
+}
+
+impl<'a> Rom<'a> {
+    fn bios_base(&self) -> Result<usize, Error> {
+        self.get_region_base_limit(RegionKind::Bios)?
+            .map(|(base, _limit)| base)
+            .ok_or(Error::RegionNotFound {
+                region: RegionKind::Bios,
+            })
+    }
+
+    /// Sets or clears the bit of the PCH straps that disables the ME after
+    /// bring up, as `Version::hap_strap` tells, returning the modified image
+    pub fn set_hap(&self, enable: bool) -> Result<Vec<u8>, Error> {
+        let (index, bit) = self.descriptor_version()?.hap_strap();
+        let strap = self.flash_pchstrap()?.data[index];
+        let value = if enable { strap | bit } else { strap & !bit };
+        let offset = (((self.descriptor.map1 >> 16) & 0xFF) << 4) as usize + index * 4;
+        let mut image = self.data.to_vec();
+        image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        Ok(image)
+    }
+
+    /// Fills the ranges of the image outside of any flash region with the
+    /// erase value, returning the modified image
+    pub fn scrub(&self) -> Result<Vec<u8>, Error> {
+        let mut image = self.data.to_vec();
+        for (offset, size) in self.layout()?.gaps() {
+            for byte in image[offset..offset + size].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+        Ok(image)
+    }
+
+    /// Resizes a flash region to `size` bytes and updates its base and limit
+    /// in the region table, returning the modified image. The BIOS region
+    /// keeps its end, as it must stay at the top of the flash, and grows or
+    /// shrinks at its start; other regions keep their base. Its contents are
+    /// kept at the same end, and bytes that no longer fit must be erased.
+    pub fn resize_region(&self, kind: RegionKind, size: usize) -> Result<Vec<u8>, Error> {
+        if kind == RegionKind::Descriptor {
+            return Err(Error::InvalidArgument(String::from(
+                "Flash descriptor region can not be resized",
+            )));
+        }
+        if size == 0 || size & 0xFFF != 0 {
+            return Err(Error::InvalidArgument(format!(
+                "region size {:#X} is not a multiple of 4K",
+                size
+            )));
+        }
+        let (base, limit) = self
+            .get_region_base_limit(kind)?
+            .ok_or(Error::RegionNotFound { region: kind })?;
+        let old = self
+            .get_region(kind)?
+            .ok_or(Error::RegionNotFound { region: kind })?;
+        let (new_base, new_limit) = if kind == RegionKind::Bios {
+            let new_base = (limit + 1).checked_sub(size).ok_or_else(|| {
+                Error::Conflict(format!("{} region of {:#X} bytes does not fit", kind, size))
+            })?;
+            (new_base, limit)
+        } else {
+            (base, base + size - 1)
+        };
+        if new_limit >= self.data.len() {
+            return Err(Error::Conflict(format!(
+                "{} region of {:#X} bytes does not fit",
+                kind, size
+            )));
+        }
+        for other in RegionKind::ALL.iter().filter(|other| **other != kind) {
+            if let Some((other_base, other_limit)) = self.get_region_base_limit(*other)? {
+                if other_base <= new_limit && new_base <= other_limit {
+                    return Err(Error::Conflict(format!(
+                        "{} region would overlap {} region",
+                        kind, other
+                    )));
+                }
+            }
+        }
+
+        // Contents are kept at the end for BIOS, at the start otherwise
+        let kept = old.len().min(size);
+        let (kept_data, dropped) = if kind == RegionKind::Bios {
+            let (dropped, kept_data) = old.split_at(old.len() - kept);
+            (kept_data, dropped)
+        } else {
+            let (kept_data, dropped) = old.split_at(kept);
+            (kept_data, dropped)
+        };
+        if dropped.iter().any(|b| *b != 0xFF) {
+            return Err(Error::Conflict(format!(
+                "shrinking {} region to {:#X} bytes would discard data",
+                kind, size
+            )));
+        }
+
+        let mut image = self.data.to_vec();
+        for byte in image[base..=limit].iter_mut() {
+            *byte = 0xFF;
+        }
+        let start = if kind == RegionKind::Bios {
+            new_limit + 1 - kept
+        } else {
+            new_base
+        };
+        image[start..start + kept].copy_from_slice(kept_data);
+
+        let offset = self.flash_region_offset() + kind as usize * 4;
+        let reg = (new_base >> 12) as u32 | ((new_limit >> 12) as u32) << 16;
+        image[offset..offset + 4].copy_from_slice(&reg.to_le_bytes());
+        Ok(image)
+    }
+
+    /// Replaces the data of the first section of type `kind` in the file
+    /// `guid`, returning the modified image. The file is rewritten in place if
+    /// it still fits, otherwise it is moved to the free space of its volume.
+    pub fn replace_section(
+        &self,
+        guid: Guid,
+        kind: section::HeaderKind,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let bios = self.bios()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::Bios,
+        })?;
+        for (index, volume) in bios.volumes().enumerate() {
+            for file in volume.files() {
+                let header = file.header();
+                let file_guid = header.guid;
+                if file_guid != guid {
+                    continue;
+                }
+                if !header.sectioned() {
+                    return Err(Error::InvalidArgument(format!(
+                        "File {} does not contain sections",
+                        guid
+                    )));
+                }
+
+                let hs = mem::size_of::<file::Header>();
+                let raw = &volume.data()[file.offset()..file.offset() + header.size()];
+                let mut new_file = raw[..hs].to_vec();
+                let mut found = false;
+                for section in file.sections() {
+                    while new_file.len() % 4 != 0 {
+                        new_file.push(0);
+                    }
+
+                    let start = hs + section.offset();
+                    let end = start + section.header().size();
+                    if !found && section.header().kind() == kind {
+                        let ss = mem::size_of::<section::Header>();
+                        let mut new_section = raw[start..start + ss].to_vec();
+                        new_section.extend_from_slice(data);
+                        if new_section.len() > 0xFFFFFF {
+                            return Err(Error::Conflict(format!(
+                                "Section data too large: {} bytes",
+                                data.len()
+                            )));
+                        }
+                        let size = new_section.len();
+                        section::set_size(&mut new_section, size);
+                        new_file.extend_from_slice(&new_section);
+                        found = true;
+                    } else {
+                        new_file.extend_from_slice(&raw[start..end]);
+                    }
+                }
+                if !found {
+                    return Err(Error::NotFound(format!(
+                        "File {} has no {:?} section",
+                        guid, kind
+                    )));
+                }
+                if new_file.len() > 0xFFFFFF {
+                    return Err(Error::Conflict(format!(
+                        "File {} too large: {} bytes",
+                        guid,
+                        new_file.len()
+                    )));
+                }
+
+                let size = new_file.len();
+                file::set_size(&mut new_file, size);
+                file::fix_checksums(&mut new_file, polarity(&volume));
+                return self.place_file(index, Some(file.offset()), &new_file);
+            }
+        }
+
+        Err(Error::NotFound(format!(
+            "File {} not found in an uncompressed volume",
+            guid
+        )))
+    }
+
+    /// Inserts a complete FFS file into the free space of the volume with the
+    /// given index, returning the modified image. The size, state and
+    /// checksums of the file are recomputed for the polarity of the volume.
+    pub fn insert_file(&self, volume_index: usize, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let hs = mem::size_of::<file::Header>();
+        if data.len() < hs || data.len() > 0xFFFFFF {
+            return Err(Error::InvalidArgument(format!(
+                "File size invalid: {} bytes",
+                data.len()
+            )));
+        }
+
+        let bios = self.bios()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::Bios,
+        })?;
+        let volume = bios
+            .volumes()
+            .nth(volume_index)
+            .ok_or(Error::VolumeNotFound {
+                index: volume_index,
+            })?;
+
+        let header = from_bytes::<file::Header>(data, 0)?;
+        let guid = header.guid;
+        for file in volume.files() {
+            let file_guid = file.header().guid;
+            if file_guid == guid {
+                return Err(Error::Conflict(format!(
+                    "File {} already exists in volume {}",
+                    guid, volume_index
+                )));
+            }
+        }
+
+        let mut new_file = data.to_vec();
+        let size = new_file.len();
+        file::set_size(&mut new_file, size);
+        file::fix_checksums(&mut new_file, polarity(&volume));
+        self.place_file(volume_index, None, &new_file)
+    }
+
+    /// Writes `new_file` into the volume with the given index. If `old` is the
+    /// offset of an existing file, it is replaced in place when possible and
+    /// turned into a pad file otherwise.
+    fn place_file(
+        &self,
+        volume_index: usize,
+        old: Option<usize>,
+        new_file: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let bios = self.bios()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::Bios,
+        })?;
+        let volume = bios
+            .volumes()
+            .nth(volume_index)
+            .ok_or(Error::VolumeNotFound {
+                index: volume_index,
+            })?;
+        let polarity = polarity(&volume);
+        let erase = if polarity { 0xFF } else { 0x00 };
+        let hs = mem::size_of::<file::Header>();
+
+        // Offsets of the volume and its files in the image
+        let volume_start = self.bios_base()? + volume.offset();
+        let header_length = volume.header().header_length as usize;
+        let files_start = volume_start + header_length;
+        let files = volume.data();
+
+        let mut image = self.data.to_vec();
+
+        if let Some(offset) = old {
+            let old_size = from_bytes::<file::Header>(files, offset)?.size();
+            let slot = align(old_size, 8);
+            let gap = slot.saturating_sub(align(new_file.len(), 8));
+            if new_file.len() <= slot && (gap == 0 || gap >= hs) {
+                let start = files_start + offset;
+                for b in image[start..start + slot].iter_mut() {
+                    *b = erase;
+                }
+                image[start..start + new_file.len()].copy_from_slice(new_file);
+                if gap > 0 {
+                    let pad_start = start + slot - gap;
+                    image[pad_start..pad_start + gap].copy_from_slice(&file::pad(gap, polarity));
+                }
+                volume::fix_checksum(&mut image[volume_start..volume_start + header_length]);
+                return Ok(image);
+            }
+
+            // Release the old slot, the file is moved to free space below
+            let start = files_start + offset;
+            image[start..start + slot].copy_from_slice(&file::pad(slot, polarity));
+        }
+
+        // Free space starts after the last file and spans the erased bytes
+        let mut free_start = 0;
+        for file in volume.files() {
+            free_start = align(file.offset() + file.header().size(), 8);
+        }
+        let free_end = free_start
+            + files
+                .get(free_start..)
+                .unwrap_or_default()
+                .iter()
+                .take_while(|b| **b == erase)
+                .count();
+
+        // Insert a pad file if the file data requires a larger alignment
+        let header = from_bytes::<file::Header>(new_file, 0)?;
+        let alignment = header.data_alignment().max(8);
+        let mut start = free_start;
+        if !(header_length + start + hs).is_multiple_of(alignment) {
+            start = align(header_length + start + 2 * hs, alignment) - header_length - hs;
+        }
+        if start + new_file.len() > free_end {
+            return Err(Error::Conflict(format!(
+                "Not enough free space in volume {}: {} bytes needed, {} bytes free",
+                volume_index,
+                start + new_file.len() - free_start,
+                free_end - free_start
+            )));
+        }
+
+        if start > free_start {
+            let pad = file::pad(start - free_start, polarity);
+            let pad_start = files_start + free_start;
+            image[pad_start..pad_start + pad.len()].copy_from_slice(&pad);
+        }
+        let file_start = files_start + start;
+        image[file_start..file_start + new_file.len()].copy_from_slice(new_file);
+        volume::fix_checksum(&mut image[volume_start..volume_start + header_length]);
+        Ok(image)
+    }
+}
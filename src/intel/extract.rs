@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::string::{String, ToString};
+use core::mem;
+
+use super::{file, section, BiosSections, BiosVolume, BiosVolumes, RegionKind, Rom};
+use crate::extract::{decode, join, Extraction, Metadata};
+use crate::handler::{Registry, Target};
+
+impl<'a> Rom<'a> {
+    /// Extracts the flash regions and the volumes, files and sections of
+    /// the BIOS region. Regions are named as in `layout`, volumes, files and
+    /// sections by their index and GUID or kind, each being found under the
+    /// component containing it.
+    pub fn extract_all(&self) -> Extraction {
+        self.extract_all_with(&Registry::new())
+    }
+
+    /// Like `extract_all`, GUID defined sections being decoded by the
+    /// handlers of `registry` and the sections they decode to extracted too
+    pub fn extract_all_with(&self, registry: &Registry) -> Extraction {
+        let mut extractor = Extractor {
+            registry,
+            extraction: Extraction::new(),
+        };
+        if let Ok(layout) = self.layout() {
+            for area in layout.areas() {
+                let metadata = Metadata {
+                    offset: Some(area.offset),
+                    size: area.size,
+                    description: area.name.clone(),
+                    decoded: false,
+                };
+                let data = self.data[area.offset..area.end()].to_vec();
+                extractor
+                    .extraction
+                    .insert(area.name.clone(), (metadata, data));
+            }
+        }
+        if let (Ok(Some(bios)), Ok(Some((base, _)))) =
+            (self.bios(), self.get_region_base_limit(RegionKind::Bios))
+        {
+            let path = RegionKind::Bios.to_string();
+            for (i, volume) in bios.volumes().enumerate() {
+                extractor.volume(&path, i, &volume, Some(base + volume.offset()));
+            }
+        }
+        extractor.extraction
+    }
+}
+
+struct Extractor<'r> {
+    registry: &'r Registry,
+    extraction: Extraction,
+}
+
+impl<'r> Extractor<'r> {
+    fn insert(&mut self, path: &str, offset: Option<usize>, description: String, data: &[u8]) {
+        let metadata = Metadata {
+            offset,
+            size: data.len(),
+            description,
+            decoded: false,
+        };
+        self.extraction
+            .insert(String::from(path), (metadata, data.to_vec()));
+    }
+
+    /// Extracts a volume with its header at `offset` in the image, if known
+    fn volume(&mut self, path: &str, i: usize, volume: &BiosVolume, offset: Option<usize>) {
+        let header = volume.header();
+        let path = join(path, &format!("{:02} {}", i, { header.guid }));
+        let data_offset = offset.map(|offset| offset + header.header_length as usize);
+        self.insert(&path, data_offset, String::from("Volume"), volume.data());
+
+        for (i, file) in volume.files().enumerate() {
+            let header = file.header();
+            let file_path = join(&path, &format!("{:02} {}", i, { header.guid }));
+            let file_offset =
+                data_offset.map(|offset| offset + file.offset() + mem::size_of::<file::Header>());
+            self.insert(
+                &file_path,
+                file_offset,
+                format!("{:?}", header.kind()),
+                file.data(),
+            );
+            if header.sectioned() {
+                self.sections(&file_path, file.data(), file_offset);
+            }
+        }
+    }
+
+    /// Extracts the sections in `data`, found at `offset` in the image if
+    /// known
+    fn sections(&mut self, path: &str, data: &[u8], offset: Option<usize>) {
+        for (i, section) in BiosSections::new(data).enumerate() {
+            let kind = section.header().kind();
+            let path = join(path, &format!("{:02} {:?}", i, kind));
+            let section_offset =
+                offset.map(|offset| offset + section.offset() + mem::size_of::<section::Header>());
+            self.insert(&path, section_offset, format!("{:?}", kind), section.data());
+
+            match kind {
+                section::HeaderKind::GuidDefined => {
+                    self.guid_defined(&path, section.data());
+                }
+                section::HeaderKind::VolumeImage => {
+                    for (i, volume) in BiosVolumes::new(section.data()).enumerate() {
+                        let volume_offset = section_offset.map(|offset| offset + volume.offset());
+                        self.volume(&path, i, &volume, volume_offset);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Decodes the data of a GUID defined section with the handler of its
+    /// tool GUID and extracts the sections it holds
+    fn guid_defined(&mut self, path: &str, data: &[u8]) {
+        let header = match plain::from_bytes::<section::GuidDefined>(data) {
+            Ok(header) => header,
+            Err(_) => return,
+        };
+        // The data offset counts from the start of the common header
+        let start = (header.data_offset as usize)
+            .saturating_sub(mem::size_of::<section::Header>())
+            .max(mem::size_of::<section::GuidDefined>());
+        let encoded = match data.get(start..) {
+            Some(encoded) => encoded,
+            None => return,
+        };
+        let decoded = match decode(self.registry, Target::Section(header.guid), encoded) {
+            (decoded, true) => decoded,
+            (_, false) => return,
+        };
+
+        let path = join(path, "Decoded");
+        let metadata = Metadata {
+            offset: None,
+            size: encoded.len(),
+            description: format!("{}", { header.guid }),
+            decoded: true,
+        };
+        self.extraction
+            .insert(path.clone(), (metadata, decoded.clone()));
+        self.sections(&path, &decoded, None);
+    }
+}
@@ -1,10 +1,23 @@
 // SPDX-License-Identifier: MIT
 
+use alloc::vec::Vec;
 use bitflags::bitflags;
+use core::mem;
 use plain::Plain;
+use serde::{Deserialize, Serialize};
 use uefi::guid::Guid;
 
-#[derive(Debug)]
+/// File type of pad files, used to fill gaps between files
+pub const KIND_PAD: u8 = 0xF0;
+
+/// File checksum used when `ATTRIB_CHECKSUM` is not set
+pub const FIXED_CHECKSUM: u8 = 0xAA;
+
+/// Data alignment in bytes, indexed by `Header::alignment`
+pub const ALIGNMENTS: [usize; 8] = [1, 16, 128, 512, 1024, 4096, 32768, 65536];
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum HeaderKind {
     Raw,
     Freeform,
@@ -94,6 +107,11 @@ impl Header {
         (self.attributes & 0x38) >> 3
     }
 
+    /// Required alignment of the file data in bytes
+    pub fn data_alignment(&self) -> usize {
+        ALIGNMENTS[self.alignment() as usize]
+    }
+
     pub fn state(&self, polarity: bool) -> State {
         State::from_bits_truncate(if polarity {
             ! self.state
@@ -128,3 +146,68 @@ impl Header {
 }
 
 unsafe impl Plain for Header {}
+
+/// Sets the 24-bit size field of a raw file header
+pub fn set_size(file: &mut [u8], size: usize) {
+    file[20] = size as u8;
+    file[21] = (size >> 8) as u8;
+    file[22] = (size >> 16) as u8;
+}
+
+/// Recomputes the header and data checksums of a raw file and marks it valid
+/// using the given erase polarity
+pub fn fix_checksums(file: &mut [u8], polarity: bool) {
+    let hs = mem::size_of::<Header>();
+    let attributes = Attributes::from_bits_truncate(file[19]);
+
+    file[16] = 0;
+    file[17] = 0;
+    file[23] = 0;
+    let sum = file[..hs].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    file[16] = 0u8.wrapping_sub(sum);
+
+    file[17] = if attributes.contains(Attributes::ATTRIB_CHECKSUM) {
+        let sum = file[hs..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        0u8.wrapping_sub(sum)
+    } else {
+        FIXED_CHECKSUM
+    };
+
+    let state = State::STATE_HEADER_CONSTRUCTION | State::STATE_HEADER_VALID | State::STATE_DATA_VALID;
+    file[23] = if polarity {
+        !state.bits()
+    } else {
+        state.bits()
+    };
+}
+
+/// True if the header and data checksums of a raw file are correct. The
+/// state and file checksum bytes are not part of the header checksum.
+pub fn checksums_valid(file: &[u8]) -> bool {
+    let hs = mem::size_of::<Header>();
+    let sum = file[..hs]
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 17 && *i != 23)
+        .fold(0u8, |sum, (_, b)| sum.wrapping_add(*b));
+    if sum != 0 {
+        return false;
+    }
+    let attributes = Attributes::from_bits_truncate(file[19]);
+    if attributes.contains(Attributes::ATTRIB_CHECKSUM) {
+        file[hs..].iter().fold(file[17], |sum, b| sum.wrapping_add(*b)) == 0
+    } else {
+        file[17] == FIXED_CHECKSUM
+    }
+}
+
+/// Creates a pad file of `size` bytes filled with the erase value
+pub fn pad(size: usize, polarity: bool) -> Vec<u8> {
+    let erase = if polarity { 0xFF } else { 0x00 };
+    let mut file = vec![erase; size];
+    file[18] = KIND_PAD;
+    file[19] = 0;
+    set_size(&mut file, size);
+    fix_checksums(&mut file, polarity);
+    file
+}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: MIT
+
+//! Firmware Interface Table, which tells the processor where the microcode
+//! updates, the startup ACM and the Boot Guard manifests are before any
+//! firmware runs. The table is found through a pointer 0x40 bytes below 4G,
+//! the end of the image being mapped there.
+
+use alloc::vec::Vec;
+use core::mem;
+use plain::Plain;
+
+use crate::error::{from_bytes, Error};
+
+/// Address of the pointer to the table
+pub const POINTER_ADDRESS: u64 = 0xFFFF_FFC0;
+
+/// Address the image ends at
+pub const TOP: u64 = 0x1_0000_0000;
+
+/// Address field of the header entry
+pub const SIGNATURE: [u8; 8] = *b"_FIT_   ";
+
+pub const HEADER: u8 = 0x00;
+pub const MICROCODE: u8 = 0x01;
+pub const STARTUP_ACM: u8 = 0x02;
+pub const KEY_MANIFEST: u8 = 0x0B;
+pub const BOOT_POLICY_MANIFEST: u8 = 0x0C;
+pub const UNUSED: u8 = 0x7F;
+
+#[repr(C, packed)]
+pub struct Entry {
+    pub address: u64,
+    /// Size in 16 byte units, or the number of entries for the header
+    pub size: [u8; 3],
+    pub reserved: u8,
+    pub version: u16,
+    /// Type in bits 6:0, whether the checksum is valid in bit 7
+    pub kind: u8,
+    pub checksum: u8,
+}
+
+unsafe impl Plain for Entry {}
+
+impl Entry {
+    pub fn kind(&self) -> u8 {
+        self.kind & 0x7F
+    }
+
+    /// True if the checksum of the entry is to be checked
+    pub fn checksum_valid(&self) -> bool {
+        self.kind & 0x80 != 0
+    }
+
+    /// The size field, which is in 16 byte units for most types
+    pub fn size(&self) -> usize {
+        self.size[0] as usize | (self.size[1] as usize) << 8 | (self.size[2] as usize) << 16
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self.kind() {
+            HEADER => "FIT Header",
+            MICROCODE => "Microcode Update",
+            STARTUP_ACM => "Startup ACM",
+            0x03 => "Diagnostic ACM",
+            0x07 => "BIOS Startup Module",
+            0x08 => "TPM Policy",
+            0x09 => "BIOS Policy",
+            0x0A => "TXT Policy",
+            KEY_MANIFEST => "Key Manifest",
+            BOOT_POLICY_MANIFEST => "Boot Policy Manifest",
+            0x10 => "CSE Secure Boot",
+            0x2D => "TXTSX Policy",
+            0x2F => "JMP Debug Policy",
+            UNUSED => "Unused",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Firmware Interface Table of an image
+pub struct Fit<'a> {
+    /// The image, its end mapped at 4G
+    data: &'a [u8],
+    /// Offset of the table in the image
+    offset: usize,
+    header: &'a Entry,
+}
+
+impl<'a> Fit<'a> {
+    /// Follows the pointer at `POINTER_ADDRESS` to the table
+    pub fn find(data: &'a [u8]) -> Result<Fit<'a>, Error> {
+        let pointer = offset(data, POINTER_ADDRESS)
+            .and_then(|offset| data.get(offset..offset + 8))
+            .ok_or(Error::Truncated {
+                need: (TOP - POINTER_ADDRESS) as usize,
+                have: data.len(),
+            })?;
+        let mut address = [0; 8];
+        address.copy_from_slice(pointer);
+        let address = u64::from_le_bytes(address);
+        match offset(data, address) {
+            Some(offset) => Fit::new(data, offset),
+            None => Err(Error::NotFound(format!(
+                "FIT pointer {:#X} outside of the image",
+                address
+            ))),
+        }
+    }
+
+    /// Parses the table at `offset` in the image
+    pub fn new(data: &'a [u8], offset: usize) -> Result<Fit<'a>, Error> {
+        let header: &Entry = from_bytes(data, offset)?;
+        if header.address.to_le_bytes() != SIGNATURE || header.kind() != HEADER {
+            return Err(Error::SignatureMismatch {
+                structure: "FIT",
+                offset,
+            });
+        }
+        let need = offset + header.size() * mem::size_of::<Entry>();
+        if header.size() == 0 || need > data.len() {
+            return Err(Error::Truncated {
+                need,
+                have: data.len(),
+            });
+        }
+        Ok(Fit {
+            data,
+            offset,
+            header,
+        })
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn header(&self) -> &'a Entry {
+        self.header
+    }
+
+    /// Entries following the header
+    pub fn entries(&self) -> Vec<&'a Entry> {
+        self.table()
+            .chunks_exact(mem::size_of::<Entry>())
+            .skip(1)
+            .filter_map(|chunk| plain::from_bytes(chunk).ok())
+            .collect()
+    }
+
+    /// Whether the bytes of the table sum to zero, none if the header does
+    /// not ask for the checksum to be checked
+    pub fn checksum_valid(&self) -> Option<bool> {
+        if !self.header.checksum_valid() {
+            return None;
+        }
+        Some(
+            self.table()
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+                == 0,
+        )
+    }
+
+    /// Offset in the image of the address of an entry, none if it is not
+    /// in the image
+    pub fn entry_offset(&self, entry: &Entry) -> Option<usize> {
+        offset(self.data, entry.address)
+    }
+
+    /// Data of an entry whose size is in 16 byte units, none if it is not
+    /// in the image
+    pub fn entry_data(&self, entry: &Entry) -> Option<&'a [u8]> {
+        let start = self.entry_offset(entry)?;
+        self.data.get(start..start.checked_add(entry.size() * 16)?)
+    }
+
+    fn table(&self) -> &'a [u8] {
+        &self.data[self.offset..self.offset + self.header.size() * mem::size_of::<Entry>()]
+    }
+}
+
+/// Offset in the image of an address below 4G
+pub fn offset(data: &[u8], address: u64) -> Option<usize> {
+    let base = TOP.checked_sub(data.len() as u64)?;
+    if address >= base && address < TOP {
+        Some((address - base) as usize)
+    } else {
+        None
+    }
+}
+
+/// Revision of the microcode update at an address of a microcode entry,
+/// from the update header
+pub fn microcode_revision(data: &[u8], address: u64) -> Option<u32> {
+    let start = offset(data, address)?;
+    let revision = data.get(start + 4..start + 8)?;
+    Some(u32::from_le_bytes([
+        revision[0],
+        revision[1],
+        revision[2],
+        revision[3],
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{
+        IntelFixture, BOOT_POLICY_OFFSET, FIT_OFFSET, INTEL_SIZE, MICROCODE_OFFSET,
+    };
+
+    #[test]
+    fn entries_are_found_through_the_pointer() {
+        let image = IntelFixture {
+            boot_guard: true,
+            ..IntelFixture::default()
+        }
+        .build();
+        let fit = Fit::find(&image).unwrap();
+        assert_eq!(fit.offset(), FIT_OFFSET);
+        assert_eq!(fit.checksum_valid(), Some(true));
+        let entries = fit.entries();
+        let kinds: Vec<&str> = entries.iter().map(|entry| entry.description()).collect();
+        assert_eq!(kinds, ["Microcode Update", "Boot Policy Manifest"]);
+        assert_eq!(fit.entry_offset(entries[0]), Some(MICROCODE_OFFSET));
+        assert_eq!(fit.entry_data(entries[0]).unwrap().len(), 2048);
+        assert_eq!(microcode_revision(&image, entries[0].address), Some(0x42));
+        assert_eq!(fit.entry_offset(entries[1]), Some(BOOT_POLICY_OFFSET));
+
+        // The table as seen in an image of the last 4K of the flash
+        let end = &image[INTEL_SIZE - 0x1000..];
+        let fit = Fit::find(end).unwrap();
+        assert_eq!(fit.offset(), FIT_OFFSET - (INTEL_SIZE - 0x1000));
+        assert_eq!(fit.entry_offset(fit.entries()[0]), None);
+        assert!(fit.entry_data(fit.entries()[0]).is_none());
+    }
+
+    #[test]
+    fn invalid_tables_are_rejected() {
+        let mut image = IntelFixture::default().build();
+        image[FIT_OFFSET + 0x10] ^= 1;
+        assert_eq!(Fit::find(&image).unwrap().checksum_valid(), Some(false));
+        image[FIT_OFFSET] = b'-';
+        assert!(matches!(
+            Fit::find(&image),
+            Err(Error::SignatureMismatch {
+                offset: FIT_OFFSET,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Fit::find(&image[..0x20]),
+            Err(Error::Truncated { .. })
+        ));
+        let pointer = INTEL_SIZE - (TOP - POINTER_ADDRESS) as usize;
+        image[pointer..pointer + 8].copy_from_slice(&0x1000u64.to_le_bytes());
+        assert!(matches!(Fit::find(&image), Err(Error::NotFound(_))));
+    }
+}
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{string::String, vec::Vec};
+
+use super::{RegionKind, Rom};
+use crate::Error;
+
+/// Size of one copy of the GbE NVM image
+pub const BANK_SIZE: usize = 0x1000;
+
+/// Word holding the NVM checksum
+pub const CHECKSUM_WORD: usize = 0x3F;
+
+/// Sum of words 0x00 to 0x3F in a valid NVM image
+pub const CHECKSUM_SUM: u16 = 0xBABA;
+
+/// Word holding the signature bits of a valid NVM image
+pub const SIGNATURE_WORD: usize = 0x13;
+
+fn word(data: &[u8], index: usize) -> u16 {
+    data[index * 2] as u16 | (data[index * 2 + 1] as u16) << 8
+}
+
+/// Computes the checksum word for an NVM image
+pub fn checksum(bank: &[u8]) -> u16 {
+    let sum = (0..CHECKSUM_WORD).fold(0u16, |sum, i| sum.wrapping_add(word(bank, i)));
+    CHECKSUM_SUM.wrapping_sub(sum)
+}
+
+pub struct Gbe<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Gbe<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Gbe<'a>, Error> {
+        if data.len() < BANK_SIZE {
+            return Err(Error::Truncated {
+                need: BANK_SIZE,
+                have: data.len(),
+            });
+        }
+        Ok(Gbe { data })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// NVM images in the region, the second one is a backup copy
+    pub fn banks(&self) -> impl Iterator<Item = &'a [u8]> {
+        self.data.chunks_exact(BANK_SIZE).take(2)
+    }
+
+    /// True if the bank carries the NVM signature
+    pub fn bank_valid(bank: &[u8]) -> bool {
+        word(bank, SIGNATURE_WORD) >> 14 == 0b10
+    }
+
+    /// True if the checksum of the bank is correct
+    pub fn bank_checksum_valid(bank: &[u8]) -> bool {
+        word(bank, CHECKSUM_WORD) == checksum(bank)
+    }
+
+    /// MAC address of the first valid bank
+    pub fn mac(&self) -> Option<[u8; 6]> {
+        self.banks().find(|bank| Self::bank_valid(bank)).map(|bank| {
+            let mut mac = [0; 6];
+            mac.copy_from_slice(&bank[..6]);
+            mac
+        })
+    }
+}
+
+impl<'a> Rom<'a> {
+    /// Sets the MAC address in every valid bank of the GbE region and fixes
+    /// the NVM checksums, returning the modified image
+    pub fn set_mac(&self, mac: [u8; 6]) -> Result<Vec<u8>, Error> {
+        let (base, _limit) =
+            self.get_region_base_limit(RegionKind::Ethernet)?
+                .ok_or(Error::RegionNotFound {
+                    region: RegionKind::Ethernet,
+                })?;
+        let gbe = self.gbe()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::Ethernet,
+        })?;
+
+        let mut image = self.data.to_vec();
+        let mut count = 0;
+        for (i, bank) in gbe.banks().enumerate() {
+            if !Gbe::bank_valid(bank) {
+                continue;
+            }
+            let start = base + i * BANK_SIZE;
+            let new_bank = &mut image[start..start + BANK_SIZE];
+            new_bank[..6].copy_from_slice(&mac);
+            let checksum = checksum(new_bank);
+            new_bank[CHECKSUM_WORD * 2] = checksum as u8;
+            new_bank[CHECKSUM_WORD * 2 + 1] = (checksum >> 8) as u8;
+            count += 1;
+        }
+
+        if count == 0 {
+            return Err(Error::Invalid(String::from(
+                "GbE region has no valid NVM image",
+            )));
+        }
+        Ok(image)
+    }
+}
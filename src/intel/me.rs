@@ -0,0 +1,478 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use plain::Plain;
+
+use super::{RegionKind, Rom};
+use crate::error::{from_bytes, Error};
+
+/// Version of FPT headers with an 8-bit checksum
+pub const FPT_HEADER_VERSION_20: u8 = 0x20;
+
+/// Version of CPD headers with an 8-bit checksum
+pub const CPD_HEADER_VERSION_1: u8 = 1;
+
+/// Header of the flash partition table at the start of the ME region,
+/// optionally preceded by a 16-byte ROM bypass vector
+#[repr(packed)]
+pub struct FptHeader {
+    /// 0x00: "$FPT"
+    pub signature: [u8; 4],
+    /// 0x04: number of partition entries following the header
+    pub entries: u32,
+    /// 0x08: header version
+    pub header_version: u8,
+    /// 0x09: entry version
+    pub entry_version: u8,
+    /// 0x0A: header length
+    pub header_length: u8,
+    /// 0x0B: checksum of the header
+    pub checksum: u8,
+    pub flash_cycle_life: u16,
+    pub flash_cycle_limit: u16,
+    pub uma_size: u32,
+    pub flags: u32,
+    /// 0x18: version of the flash image tool
+    pub fitc_version: [u16; 4],
+}
+
+unsafe impl Plain for FptHeader {}
+
+/// Partition entry of the flash partition table
+#[repr(packed)]
+pub struct FptEntry {
+    pub name: [u8; 4],
+    pub owner: [u8; 4],
+    /// Offset of the partition in the ME region
+    pub offset: u32,
+    pub length: u32,
+    pub start_tokens: u32,
+    pub max_tokens: u32,
+    pub scratch_sectors: u32,
+    pub flags: u32,
+}
+
+unsafe impl Plain for FptEntry {}
+
+/// Header of a code partition directory, listing the modules of a partition
+#[repr(packed)]
+pub struct CpdHeader {
+    /// 0x00: "$CPD"
+    pub signature: [u8; 4],
+    /// 0x04: number of module entries following the header
+    pub entries: u32,
+    pub header_version: u8,
+    pub entry_version: u8,
+    pub header_length: u8,
+    /// 0x0B: checksum of the header and entries
+    pub checksum: u8,
+    /// 0x0C: name of the partition
+    pub partition_name: [u8; 4],
+}
+
+unsafe impl Plain for CpdHeader {}
+
+/// Module entry of a code partition directory
+#[repr(packed)]
+pub struct CpdEntry {
+    pub name: [u8; 12],
+    /// Bits 24:0 are the offset of the module from the CPD header, bit 25 is
+    /// set for Huffman compressed modules
+    pub offset: u32,
+    pub length: u32,
+    pub rsvd_14: u32,
+}
+
+unsafe impl Plain for CpdEntry {}
+
+impl CpdEntry {
+    pub fn offset(&self) -> usize {
+        (self.offset & 0x1FF_FFFF) as usize
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.offset & (1 << 25) != 0
+    }
+}
+
+/// Partitions `Rom::neuter_me` keeps, which the ME needs to bring up the
+/// platform
+pub const ESSENTIAL_PARTITIONS: &[&str] = &["FTPR"];
+
+/// Intel security advisory affecting ME firmware, with the versions it
+/// affects as ranges from the first affected to the first fixed
+/// major.minor.hotfix version of each release line
+pub struct Advisory {
+    pub id: &'static str,
+    pub title: &'static str,
+    pub affected: &'static [([u16; 3], [u16; 3])],
+}
+
+/// Published advisories affecting ME and CSME firmware. AMT issues only
+/// affect images with AMT enabled, which the version does not tell.
+pub const ADVISORIES: &[Advisory] = &[
+    Advisory {
+        id: "INTEL-SA-00075",
+        title: "AMT, ISM and SBT escalation of privilege",
+        affected: &[
+            ([6, 0, 0], [6, 2, 61]),
+            ([7, 0, 0], [7, 1, 91]),
+            ([8, 0, 0], [8, 1, 71]),
+            ([9, 0, 0], [9, 1, 41]),
+            ([9, 5, 0], [9, 5, 61]),
+            ([10, 0, 0], [10, 0, 55]),
+            ([11, 0, 0], [11, 0, 25]),
+            ([11, 5, 0], [11, 6, 27]),
+        ],
+    },
+    Advisory {
+        id: "INTEL-SA-00086",
+        title: "ME, SPS and TXE firmware escalation of privilege",
+        affected: &[
+            ([11, 0, 0], [11, 8, 50]),
+            ([11, 10, 0], [11, 11, 50]),
+            ([11, 20, 0], [11, 21, 50]),
+        ],
+    },
+    Advisory {
+        id: "INTEL-SA-00213",
+        title: "CSME, SPS, TXE, DAL and AMT escalation of privilege",
+        affected: &[
+            ([11, 0, 0], [11, 8, 65]),
+            ([11, 10, 0], [11, 11, 65]),
+            ([11, 20, 0], [11, 22, 65]),
+            ([12, 0, 0], [12, 0, 35]),
+        ],
+    },
+    Advisory {
+        id: "INTEL-SA-00241",
+        title: "CSME, SPS, TXE, AMT, PTT and DAL escalation of privilege",
+        affected: &[
+            ([11, 0, 0], [11, 8, 70]),
+            ([11, 10, 0], [11, 11, 70]),
+            ([11, 20, 0], [11, 22, 70]),
+            ([12, 0, 0], [12, 0, 45]),
+        ],
+    },
+    Advisory {
+        id: "INTEL-SA-00307",
+        title: "CSME escalation of privilege in the boot ROM (CVE-2019-0090)",
+        affected: &[
+            ([11, 0, 0], [11, 8, 71]),
+            ([11, 10, 0], [11, 12, 71]),
+            ([11, 20, 0], [11, 22, 71]),
+            ([12, 0, 0], [12, 0, 49]),
+            ([13, 0, 0], [13, 0, 21]),
+            ([14, 0, 0], [14, 0, 11]),
+        ],
+    },
+];
+
+/// Advisories affecting the ME firmware of a version as reported by
+/// `Me::version`, none if the version does not parse
+pub fn advisories(version: &str) -> Vec<&'static Advisory> {
+    let mut parts = version.split('.').map(|part| part.parse::<u16>());
+    let mut parsed = [0; 3];
+    for part in parsed.iter_mut() {
+        match parts.next() {
+            Some(Ok(number)) => *part = number,
+            _ => return Vec::new(),
+        }
+    }
+    ADVISORIES
+        .iter()
+        .filter(|advisory| {
+            advisory
+                .affected
+                .iter()
+                .any(|(first, fixed)| *first <= parsed && parsed < *fixed)
+        })
+        .collect()
+}
+
+/// Name of a partition or module, without trailing NUL bytes
+pub fn entry_name(name: &[u8]) -> String {
+    name.iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as char)
+        .collect()
+}
+
+/// 8-bit checksum making the sum of `data` zero, `checksum` being the offset
+/// of the checksum byte in it
+fn checksum8(data: &[u8], checksum: usize) -> u8 {
+    let sum = data
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != checksum)
+        .fold(0u8, |sum, (_, b)| sum.wrapping_add(*b));
+    0u8.wrapping_sub(sum)
+}
+
+/// Removes entry `index` of a table, moving the following entries up and
+/// erasing the freed last entry
+fn remove_entry(table: &mut [u8], index: usize, entry_size: usize) {
+    let start = index * entry_size;
+    table.copy_within(start + entry_size.., start);
+    let len = table.len();
+    for byte in table[len - entry_size..].iter_mut() {
+        *byte = 0xFF;
+    }
+}
+
+/// Flash partition table of an ME region
+pub struct Fpt<'a> {
+    data: &'a [u8],
+    offset: usize,
+    header: &'a FptHeader,
+}
+
+impl<'a> Fpt<'a> {
+    /// Finds the partition table at the start of the ME region `data`
+    pub fn new(data: &'a [u8]) -> Result<Fpt<'a>, Error> {
+        let offset = [0, 0x10]
+            .iter()
+            .copied()
+            .find(|offset| data.get(*offset..*offset + 4) == Some(b"$FPT"))
+            .ok_or(Error::SignatureMismatch {
+                structure: "FPT",
+                offset: 0,
+            })?;
+        let header: &FptHeader = from_bytes(data, offset)?;
+        let fpt = Fpt {
+            data,
+            offset,
+            header,
+        };
+        let need = fpt.entries_offset() + fpt.header.entries as usize * mem::size_of::<FptEntry>();
+        if need > data.len() {
+            return Err(Error::Truncated {
+                need,
+                have: data.len(),
+            });
+        }
+        Ok(fpt)
+    }
+
+    /// Offset of the header in the ME region
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn header(&self) -> &'a FptHeader {
+        self.header
+    }
+
+    /// Offset of the first entry in the ME region
+    pub fn entries_offset(&self) -> usize {
+        self.offset + (self.header.header_length as usize).max(mem::size_of::<FptHeader>())
+    }
+
+    pub fn entries(&self) -> Vec<&'a FptEntry> {
+        let start = self.entries_offset();
+        (0..self.header.entries as usize)
+            .filter_map(|i| {
+                let offset = start + i * mem::size_of::<FptEntry>();
+                plain::from_bytes(&self.data[offset..]).ok()
+            })
+            .collect()
+    }
+
+    /// Data of a partition, if it lies within the region
+    pub fn partition(&self, entry: &FptEntry) -> Option<&'a [u8]> {
+        let start = entry.offset as usize;
+        self.data.get(start..start.checked_add(entry.length as usize)?)
+    }
+}
+
+/// Code partition directory at the start of a partition
+pub struct Cpd<'a> {
+    data: &'a [u8],
+    header: &'a CpdHeader,
+}
+
+impl<'a> Cpd<'a> {
+    /// Reads the directory at the start of the partition `data`
+    pub fn new(data: &'a [u8]) -> Result<Cpd<'a>, Error> {
+        if data.get(..4) != Some(b"$CPD") {
+            return Err(Error::SignatureMismatch {
+                structure: "CPD",
+                offset: 0,
+            });
+        }
+        let header: &CpdHeader = from_bytes(data, 0)?;
+        let cpd = Cpd { data, header };
+        if cpd.entries_end() > data.len() {
+            return Err(Error::Truncated {
+                need: cpd.entries_end(),
+                have: data.len(),
+            });
+        }
+        Ok(cpd)
+    }
+
+    pub fn header(&self) -> &'a CpdHeader {
+        self.header
+    }
+
+    /// Offset of the first entry in the partition
+    pub fn entries_offset(&self) -> usize {
+        self.header.header_length as usize
+    }
+
+    /// Offset of the end of the entries in the partition
+    pub fn entries_end(&self) -> usize {
+        self.entries_offset() + self.header.entries as usize * mem::size_of::<CpdEntry>()
+    }
+
+    pub fn entries(&self) -> Vec<&'a CpdEntry> {
+        let start = self.entries_offset();
+        (0..self.header.entries as usize)
+            .filter_map(|i| {
+                let offset = start + i * mem::size_of::<CpdEntry>();
+                plain::from_bytes(&self.data[offset..]).ok()
+            })
+            .collect()
+    }
+
+    /// Data of a module, if it lies within the partition
+    pub fn module(&self, entry: &CpdEntry) -> Option<&'a [u8]> {
+        let start = entry.offset();
+        self.data.get(start..start.checked_add(entry.length as usize)?)
+    }
+}
+
+impl<'a> Rom<'a> {
+    fn me_base(&self) -> Result<usize, Error> {
+        self.get_region_base_limit(RegionKind::ManagementEngine)?
+            .map(|(base, _limit)| base)
+            .ok_or(Error::RegionNotFound {
+                region: RegionKind::ManagementEngine,
+            })
+    }
+
+    /// Removes the partition `name` from the ME region: its data is erased,
+    /// its entry is removed from the partition table and the table checksum
+    /// fixed. Returns the modified image.
+    pub fn remove_me_partition(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let base = self.me_base()?;
+        let me = self.me()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::ManagementEngine,
+        })?;
+        let fpt = Fpt::new(me.data())?;
+        if fpt.header().header_version != FPT_HEADER_VERSION_20 {
+            return Err(Error::UnsupportedVersion {
+                structure: "FPT header",
+                version: fpt.header().header_version as u32,
+            });
+        }
+        let entries = fpt.entries();
+        let index = entries
+            .iter()
+            .position(|entry| entry_name(&entry.name) == name)
+            .ok_or_else(|| Error::PartitionNotFound {
+                name: String::from(name),
+            })?;
+
+        let mut image = self.data.to_vec();
+        if let Some(partition) = fpt.partition(entries[index]) {
+            let start = base + entries[index].offset as usize;
+            for byte in image[start..start + partition.len()].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+
+        let table_start = base + fpt.entries_offset();
+        let table_end = table_start + entries.len() * mem::size_of::<FptEntry>();
+        remove_entry(
+            &mut image[table_start..table_end],
+            index,
+            mem::size_of::<FptEntry>(),
+        );
+        let header = base + fpt.offset();
+        let count = entries.len() as u32 - 1;
+        image[header + 4..header + 8].copy_from_slice(&count.to_le_bytes());
+        // The checksum covers the ROM bypass vector, if present, and the header
+        let header_end = header + fpt.header().header_length as usize;
+        image[header + 0xB] = checksum8(&image[base..header_end], header - base + 0xB);
+        Ok(image)
+    }
+
+    /// Removes every partition but the `ESSENTIAL_PARTITIONS` from the ME
+    /// region, as `remove_me_partition` does. Returns the modified image.
+    pub fn neuter_me(&self) -> Result<Vec<u8>, Error> {
+        let me = self.me()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::ManagementEngine,
+        })?;
+        let names: Vec<String> = Fpt::new(me.data())?
+            .entries()
+            .iter()
+            .map(|entry| entry_name(&entry.name))
+            .filter(|name| !ESSENTIAL_PARTITIONS.contains(&name.as_str()))
+            .collect();
+        let mut image = self.data.to_vec();
+        for name in names {
+            image = Rom::new(&image)?.remove_me_partition(&name)?;
+        }
+        Ok(image)
+    }
+
+    /// Removes the module `name` from the code partition directories of the
+    /// ME region: its data is erased, its entry is removed from the
+    /// directory and the directory checksum fixed. Returns the modified
+    /// image. The manifest of the partition is not updated.
+    pub fn remove_me_module(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let base = self.me_base()?;
+        let me = self.me()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::ManagementEngine,
+        })?;
+        let fpt = Fpt::new(me.data())?;
+
+        for partition_entry in fpt.entries() {
+            let directory = match fpt.partition(partition_entry).map(Cpd::new) {
+                Some(Ok(directory)) => directory,
+                Some(Err(err @ Error::Truncated { .. })) => return Err(err),
+                _ => continue,
+            };
+            let entries_offset = directory.entries_offset();
+            let entry_size = mem::size_of::<CpdEntry>();
+            let table_end = directory.entries_end();
+            let entries = directory.entries();
+            let index = match entries.iter().position(|entry| entry_name(&entry.name) == name) {
+                Some(index) => index,
+                None => continue,
+            };
+            if directory.header().header_version != CPD_HEADER_VERSION_1 {
+                return Err(Error::UnsupportedVersion {
+                    structure: "CPD header",
+                    version: directory.header().header_version as u32,
+                });
+            }
+
+            let mut image = self.data.to_vec();
+            let cpd = base + partition_entry.offset as usize;
+            let module = entries[index];
+            if directory.module(module).is_some() {
+                let start = module.offset();
+                let end = start + module.length as usize;
+                for byte in image[cpd + start..cpd + end].iter_mut() {
+                    *byte = 0xFF;
+                }
+            }
+            remove_entry(
+                &mut image[cpd + entries_offset..cpd + table_end],
+                index,
+                entry_size,
+            );
+            let count = entries.len() as u32 - 1;
+            image[cpd + 4..cpd + 8].copy_from_slice(&count.to_le_bytes());
+            let table_end = cpd + entries_offset + count as usize * entry_size;
+            image[cpd + 0xB] = checksum8(&image[cpd..table_end], 0xB);
+            return Ok(image);
+        }
+        Err(Error::ModuleNotFound {
+            name: String::from(name),
+        })
+    }
+}
@@ -0,0 +1,294 @@
+// SPDX-License-Identifier: MIT
+
+//! Microcode updates, found through the microcode entries of the FIT or
+//! carved from the volumes of the BIOS region. An update is for the
+//! processors of the CPUID signature and platform flags in its header, and of
+//! those of its extended signature table.
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use plain::Plain;
+use serde::{Deserialize, Serialize};
+
+use super::{fit, BiosVolumes, RegionKind, Rom};
+use crate::error::{from_bytes, Error};
+
+/// Update data size of headers with a data size of 0
+pub const DEFAULT_DATA_SIZE: usize = 2000;
+
+/// Total size of headers with a total size of 0
+pub const DEFAULT_TOTAL_SIZE: usize = 2048;
+
+/// Alignment of the updates carved from volumes
+pub const ALIGNMENT: usize = 16;
+
+#[repr(C, packed)]
+pub struct Header {
+    /// 1
+    pub header_version: u32,
+    pub revision: u32,
+    /// Date the update was built, as hex digits MMDDYYYY
+    pub date: u32,
+    /// CPUID signature of the processors the update is for
+    pub processor_signature: u32,
+    /// Makes the dwords of the update sum up to 0
+    pub checksum: u32,
+    /// 1
+    pub loader_revision: u32,
+    /// Platform IDs of the processors the update is for, one bit each
+    pub processor_flags: u32,
+    pub data_size: u32,
+    pub total_size: u32,
+    pub reserved: [u8; 12],
+}
+
+unsafe impl Plain for Header {}
+
+impl Header {
+    /// Reads a header of version 1 whose sizes fit together
+    pub fn new(data: &[u8]) -> Result<&Header, Error> {
+        let header: &Header = from_bytes(data, 0)?;
+        if { header.header_version } != 1 || { header.loader_revision } != 1 {
+            return Err(Error::Invalid(String::from(
+                "microcode header version invalid",
+            )));
+        }
+        if header.total_size() < mem::size_of::<Header>() + header.data_size()
+            || !header.total_size().is_multiple_of(4)
+        {
+            return Err(Error::Invalid(String::from("microcode size invalid")));
+        }
+        Ok(header)
+    }
+
+    pub fn data_size(&self) -> usize {
+        match self.data_size {
+            0 => DEFAULT_DATA_SIZE,
+            size => size as usize,
+        }
+    }
+
+    pub fn total_size(&self) -> usize {
+        match self.total_size {
+            0 => DEFAULT_TOTAL_SIZE,
+            size => size as usize,
+        }
+    }
+
+    /// Date as YYYY-MM-DD, none if the digits are no date
+    pub fn date(&self) -> Option<String> {
+        let digits = format!("{:08X}", { self.date });
+        let number = |range: core::ops::Range<usize>| digits[range].parse::<u32>().ok();
+        let (month, day, year) = (number(0..2)?, number(2..4)?, number(4..8)?);
+        if (1..=12).contains(&month) && (1..=31).contains(&day) && (1990..2100).contains(&year) {
+            Some(format!("{:04}-{:02}-{:02}", year, month, day))
+        } else {
+            None
+        }
+    }
+}
+
+/// Processor an update is for
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Signature {
+    pub cpuid: u32,
+    pub processor_flags: u32,
+}
+
+/// Microcode update in an image
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Update {
+    /// Offset of the update in the image
+    pub offset: usize,
+    /// Total size of the update
+    pub size: usize,
+    pub cpuid: u32,
+    pub processor_flags: u32,
+    pub revision: u32,
+    /// Date the update was built, as YYYY-MM-DD, none if the header has no
+    /// valid date
+    pub date: Option<String>,
+    pub checksum_valid: bool,
+    /// Whether an entry of the FIT points to the update
+    pub in_fit: bool,
+    /// Signatures of the extended signature table
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extended_signatures: Vec<Signature>,
+}
+
+impl Update {
+    /// Reads the update at an offset of the image
+    pub fn new(data: &[u8], offset: usize, in_fit: bool) -> Result<Self, Error> {
+        let header = Header::new(data.get(offset..).unwrap_or_default())?;
+        let size = header.total_size();
+        let update = data.get(offset..offset + size).ok_or(Error::Truncated {
+            need: offset + size,
+            have: data.len(),
+        })?;
+        let checksum_valid = update
+            .chunks_exact(4)
+            .map(|dword| u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]))
+            .fold(0u32, |sum, dword| sum.wrapping_add(dword))
+            == 0;
+        Ok(Update {
+            offset,
+            size,
+            cpuid: header.processor_signature,
+            processor_flags: header.processor_flags,
+            revision: header.revision,
+            date: header.date(),
+            checksum_valid,
+            in_fit,
+            extended_signatures: extended_signatures(update, header),
+        })
+    }
+
+    /// CPUID signatures of the header and the extended signature table
+    pub fn cpuids(&self) -> impl Iterator<Item = u32> + '_ {
+        core::iter::once(self.cpuid).chain(
+            self.extended_signatures
+                .iter()
+                .map(|signature| signature.cpuid),
+        )
+    }
+}
+
+/// Signatures of the table after the update data, if the total size leaves
+/// room for it: a count, a checksum and 12 reserved bytes, then the CPUID,
+/// platform flags and checksum of each signature
+fn extended_signatures(update: &[u8], header: &Header) -> Vec<Signature> {
+    let dword = |offset: usize| {
+        update
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let start = mem::size_of::<Header>() + header.data_size();
+    let count = match dword(start) {
+        Some(count) if start + 20 <= update.len() => count as usize,
+        _ => return Vec::new(),
+    };
+    (0..count)
+        .map_while(|i| {
+            let offset = start + 20 + i * 12;
+            Some(Signature {
+                cpuid: dword(offset)?,
+                processor_flags: dword(offset + 4)?,
+            })
+        })
+        .collect()
+}
+
+/// Updates the FIT points to, and those carved from the volumes of the BIOS
+/// regions, sorted by offset. Only updates with a valid date and checksum
+/// are carved.
+pub fn updates(rom: &Rom) -> Vec<Update> {
+    let data = rom.data();
+    let mut updates: Vec<Update> = Vec::new();
+    if let Ok(table) = rom.fit() {
+        for entry in table.entries() {
+            if entry.kind() != fit::MICROCODE {
+                continue;
+            }
+            let offset = match table.entry_offset(entry) {
+                Some(offset) => offset,
+                None => continue,
+            };
+            if updates.iter().all(|update| update.offset != offset) {
+                if let Ok(update) = Update::new(data, offset, true) {
+                    updates.push(update);
+                }
+            }
+        }
+    }
+
+    for kind in [RegionKind::Bios, RegionKind::SecondaryBios] {
+        let base = match rom.get_region_base_limit(kind) {
+            Ok(Some((base, _))) => base,
+            _ => continue,
+        };
+        let region = match rom.get_region(kind) {
+            Ok(Some(region)) => region,
+            _ => continue,
+        };
+        for volume in BiosVolumes::new(region) {
+            let start = base + volume.offset();
+            let end = start + volume.header().length as usize;
+            let mut offset = start.next_multiple_of(ALIGNMENT);
+            while offset < end {
+                let carved = match Update::new(data, offset, false) {
+                    Ok(update) if update.checksum_valid && update.date.is_some() => update,
+                    _ => {
+                        offset += ALIGNMENT;
+                        continue;
+                    }
+                };
+                offset += carved.size.next_multiple_of(ALIGNMENT);
+                if updates.iter().all(|update| update.offset != carved.offset) {
+                    updates.push(carved);
+                }
+            }
+        }
+    }
+    updates.sort_by_key(|update| update.offset);
+    updates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{IntelFixture, MICROCODE_OFFSET};
+
+    #[test]
+    fn updates_of_the_fit_are_sized_by_their_header() {
+        let image = IntelFixture::default().build();
+        let rom = Rom::new(&image).unwrap();
+        let updates = updates(&rom);
+        assert_eq!(updates.len(), 1);
+        let update = &updates[0];
+        assert_eq!(update.offset, MICROCODE_OFFSET);
+        assert_eq!(update.size, DEFAULT_TOTAL_SIZE);
+        assert_eq!((update.cpuid, update.revision), (0x0009_06A3, 0x42));
+        assert_eq!(update.date.as_deref(), Some("2024-01-15"));
+        assert!(update.checksum_valid && update.in_fit);
+        assert!(update.extended_signatures.is_empty());
+    }
+
+    /// Writes dwords at an offset of the update of the image
+    fn write(image: &mut [u8], offset: usize, dwords: &[u32]) {
+        for (i, dword) in dwords.iter().enumerate() {
+            let offset = MICROCODE_OFFSET + offset + i * 4;
+            image[offset..offset + 4].copy_from_slice(&dword.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn extended_signatures_follow_the_data() {
+        let mut image = IntelFixture::default().build();
+        // Data of 0x100 bytes followed by a table of two signatures
+        let size = mem::size_of::<Header>() + 0x100 + 20 + 2 * 12;
+        write(&mut image, 28, &[0x100, size as u32]);
+        write(
+            &mut image,
+            mem::size_of::<Header>() + 0x100,
+            &[2, 0, 0, 0, 0, 0x000A_0652, 0x20, 0, 0x000A_0653, 0x20, 0],
+        );
+        let update = Update::new(&image, MICROCODE_OFFSET, false).unwrap();
+        assert_eq!(update.size, size);
+        assert!(!update.checksum_valid);
+        let cpuids: Vec<u32> = update.cpuids().collect();
+        assert_eq!(cpuids, [0x0009_06A3, 0x000A_0652, 0x000A_0653]);
+
+        // Total size too small for the data, and a header of another version
+        write(&mut image, 32, &[0x100]);
+        assert!(matches!(
+            Update::new(&image, MICROCODE_OFFSET, false),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(
+            Update::new(&image, MICROCODE_OFFSET + 4, false),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
@@ -1,9 +1,17 @@
 // SPDX-License-Identifier: MIT
 
-use alloc::string::String;
-use core::{fmt, mem};
-
-#[derive(Copy, Clone, Debug)]
+use alloc::{
+    string::{String, ToString},
+    vec::{self, Vec},
+};
+use core::{cell::OnceCell, fmt, mem};
+use uefi::guid::SECTION_LZMA_COMPRESS_GUID;
+
+use crate::error::{from_bytes, Error};
+use crate::layout::Layout;
+use crate::lzma;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(usize)]
 pub enum RegionKind {
     Descriptor = 0,
@@ -12,11 +20,25 @@ pub enum RegionKind {
     Ethernet = 3,
     PlatformData = 4,
     Reserved5 = 5,
-    Reserved6 = 6,
+    SecondaryBios = 6,
     Reserved7 = 7,
     EmbeddedController = 8,
 }
 
+impl RegionKind {
+    pub const ALL: [RegionKind; 9] = [
+        RegionKind::Descriptor,
+        RegionKind::Bios,
+        RegionKind::ManagementEngine,
+        RegionKind::Ethernet,
+        RegionKind::PlatformData,
+        RegionKind::Reserved5,
+        RegionKind::SecondaryBios,
+        RegionKind::Reserved7,
+        RegionKind::EmbeddedController,
+    ];
+}
+
 impl fmt::Display for RegionKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let name = match self {
@@ -25,6 +47,7 @@ impl fmt::Display for RegionKind {
             RegionKind::ManagementEngine => "Intel ME",
             RegionKind::Ethernet => "GbE",
             RegionKind::PlatformData => "Platform Data",
+            RegionKind::SecondaryBios => "Secondary BIOS",
             RegionKind::EmbeddedController => "EC",
             _ => "Reserved",
         };
@@ -34,34 +57,50 @@ impl fmt::Display for RegionKind {
 
 pub const HAP: u32 = 0x10000;
 
+/// Bit of PCHSTRAP10 that disables the ME on chipsets before the 100 series
+pub const ALT_ME_DISABLE: u32 = 0x80;
+
+pub mod descriptor;
+pub mod diff;
+mod edit;
+mod extract;
 pub mod file;
+pub mod fit;
 pub mod flash;
+pub mod gbe;
+pub mod me;
+pub mod microcode;
+pub mod nvar;
+pub mod nvram;
+pub mod sbl;
 pub mod section;
+mod verify;
 pub mod volume;
 
 pub struct Rom<'a> {
     data: &'a [u8],
     descriptor: &'a flash::Descriptor,
+    /// Offsets of the volumes in the BIOS region, found on first use
+    volumes: OnceCell<Vec<usize>>,
 }
 
 impl<'a> Rom<'a> {
-    pub fn new(data: &'a [u8]) -> Result<Rom, String> {
+    pub fn new(data: &'a [u8]) -> Result<Rom<'a>, Error> {
         let mut i = 16;
 
         while i + mem::size_of::<flash::Descriptor>() <= data.len() {
             if data[i..i + 4] == [0x5a, 0xa5, 0xf0, 0x0f] {
                 return Ok(Rom {
                     data: &data[i - 16..],
-                    descriptor: plain::from_bytes(&data[i..]).map_err(|err| {
-                        format!("Flash descriptor invalid: {:?}", err)
-                    })?
+                    descriptor: from_bytes(data, i)?,
+                    volumes: OnceCell::new(),
                 });
             }
 
             i += 4;
         }
 
-        Err(format!("Flash descriptor not found"))
+        Err(Error::DescriptorNotFound)
     }
 
     pub fn data(&self) -> &'a [u8] {
@@ -72,36 +111,59 @@ impl<'a> Rom<'a> {
         self.descriptor
     }
 
-    pub fn flash_region(&self) -> Result<&'a flash::Region, String> {
-        let offset = (((self.descriptor.map0 >> 16) & 0xff) << 4) as usize;
+    /// Offset of the flash region table in the image
+    pub fn flash_region_offset(&self) -> usize {
+        (((self.descriptor.map0 >> 16) & 0xff) << 4) as usize
+    }
+
+    pub fn flash_region(&self) -> Result<&'a flash::Region, Error> {
+        let offset = self.flash_region_offset();
 
         if offset >= self.data.len() {
-            return Err(format!("Flash region table truncated"))
+            return Err(Error::Truncated {
+                need: offset + mem::size_of::<flash::Region>(),
+                have: self.data.len(),
+            });
         }
 
-        plain::from_bytes(&self.data[offset..]).map_err(|err| {
-            format!("Flash region table invalid: {:?}", err)
-        })
+        from_bytes(self.data, offset)
     }
 
-    pub fn flash_pchstrap(&self) -> Result<&'a flash::PchStrap, String> {
+    pub fn flash_pchstrap(&self) -> Result<&'a flash::PchStrap, Error> {
         let offset = (((self.descriptor.map1 >> 16) & 0xff) << 4) as usize;
 
         if offset >= self.data.len() {
-            return Err(format!("PCHSTRAP table truncated"))
+            return Err(Error::Truncated {
+                need: offset + mem::size_of::<flash::PchStrap>(),
+                have: self.data.len(),
+            });
         }
 
-        plain::from_bytes(&self.data[offset..]).map_err(|err| {
-            format!("PCHSTRAP table invalid: {:?}", err)
-        })
+        from_bytes(self.data, offset)
     }
 
-    pub fn high_assurance_platform(&self) -> Result<bool, String> {
+    pub fn flash_master(&self) -> Result<&'a flash::Master, Error> {
+        let offset = ((self.descriptor.map1 & 0xff) << 4) as usize;
+
+        if offset >= self.data.len() {
+            return Err(Error::Truncated {
+                need: offset + mem::size_of::<flash::Master>(),
+                have: self.data.len(),
+            });
+        }
+
+        from_bytes(self.data, offset)
+    }
+
+    /// Whether the HAP bit, or the AltMeDisable bit of older chipsets, is
+    /// set
+    pub fn high_assurance_platform(&self) -> Result<bool, Error> {
         let pchstrap = self.flash_pchstrap()?;
-        Ok(pchstrap.data[0] & HAP == HAP)
+        let (index, bit) = self.descriptor_version()?.hap_strap();
+        Ok(pchstrap.data[index] & bit == bit)
     }
 
-    pub fn get_region_base_limit(&self, kind: RegionKind) -> Result<Option<(usize, usize)>, String> {
+    pub fn get_region_base_limit(&self, kind: RegionKind) -> Result<Option<(usize, usize)>, Error> {
         let frba = self.flash_region()?;
 
         let reg = frba.data[kind as usize];
@@ -119,27 +181,74 @@ impl<'a> Rom<'a> {
         }
     }
 
-    pub fn get_region(&self, kind: RegionKind) -> Result<Option<&'a [u8]>, String> {
+    pub fn get_region(&self, kind: RegionKind) -> Result<Option<&'a [u8]>, Error> {
         if let Some((base, limit)) = self.get_region_base_limit(kind)? {
             if (limit as usize) < self.data.len() {
                 Ok(Some(&self.data[base as usize..limit as usize + 1]))
             } else {
-                Err(format!("{:?} region invalid: {} >= {}", kind, limit, self.data.len()))
+                Err(Error::Invalid(format!(
+                    "{:?} region invalid: {} >= {}",
+                    kind,
+                    limit,
+                    self.data.len()
+                )))
             }
         } else {
             Ok(None)
         }
     }
 
-    pub fn bios(&self) -> Result<Option<Bios<'a>>, String> {
+    /// Areas of the image claimed by the flash regions
+    pub fn layout(&self) -> Result<Layout, Error> {
+        let mut layout = Layout::new(self.data.len());
+        for kind in RegionKind::ALL.iter() {
+            if let Some((base, limit)) = self.get_region_base_limit(*kind)? {
+                layout.insert(kind.to_string(), base, limit + 1 - base);
+            }
+        }
+        Ok(layout)
+    }
+
+    /// BIOS region, its volumes being found once per `Rom`
+    pub fn bios(&self) -> Result<Option<Bios<'a>>, Error> {
         if let Some(data) = self.get_region(RegionKind::Bios)? {
-            Ok(Some(Bios { data }))
+            let volumes = self.volumes.get_or_init(|| BiosVolumes::index(data));
+            Ok(Some(Bios {
+                data,
+                volumes: OnceCell::from(volumes.clone()),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Second BIOS region of descriptors of server and multi-BIOS layouts.
+    /// Descriptors of chipsets with fewer regions may have a zero entry for
+    /// it, which claims the descriptor and is no BIOS region.
+    pub fn secondary_bios(&self) -> Result<Option<Bios<'a>>, Error> {
+        if let Some((0, _)) = self.get_region_base_limit(RegionKind::SecondaryBios)? {
+            return Ok(None);
+        }
+        match self.get_region(RegionKind::SecondaryBios)? {
+            Some(data) => Bios::new(data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn gbe(&self) -> Result<Option<gbe::Gbe<'a>>, Error> {
+        if let Some(data) = self.get_region(RegionKind::Ethernet)? {
+            Ok(Some(gbe::Gbe::new(data)?))
         } else {
             Ok(None)
         }
     }
 
-    pub fn me(&self) -> Result<Option<Me<'a>>, String> {
+    /// Firmware Interface Table, the end of the image being mapped at 4G
+    pub fn fit(&self) -> Result<fit::Fit<'a>, Error> {
+        fit::Fit::find(self.data)
+    }
+
+    pub fn me(&self) -> Result<Option<Me<'a>>, Error> {
         if let Some(data) = self.get_region(RegionKind::ManagementEngine)? {
             Ok(Some(Me { data }))
         } else {
@@ -150,70 +259,84 @@ impl<'a> Rom<'a> {
 
 pub struct Bios<'a> {
     data: &'a [u8],
+    /// Offsets of the volumes, found on first use
+    volumes: OnceCell<Vec<usize>>,
 }
 
 impl<'a> Bios<'a> {
-    pub fn new(data: &'a [u8]) -> Result<Bios, String> {
-        Ok(Bios { data })
+    pub fn new(data: &'a [u8]) -> Result<Bios<'a>, Error> {
+        Ok(Bios {
+            data,
+            volumes: OnceCell::new(),
+        })
     }
 
     pub fn data(&self) -> &'a [u8] {
         self.data
     }
 
-    pub fn volumes(&self) -> BiosVolumes {
-        BiosVolumes::new(self.data)
+    /// Flash map of the Slim Bootloader the region holds instead of
+    /// firmware volumes, if it holds one
+    pub fn slim_bootloader(&self) -> Option<sbl::FlashMap<'a>> {
+        sbl::FlashMap::find(self.data)
+    }
+
+    /// Volumes of the region. The region is searched for volumes on the
+    /// first call only.
+    pub fn volumes(&self) -> BiosVolumes<'a> {
+        let offsets = self.volumes.get_or_init(|| BiosVolumes::index(self.data));
+        BiosVolumes::indexed(self.data, offsets.clone())
     }
 }
 
 pub struct BiosVolumes<'a> {
     data: &'a [u8],
     i: usize,
+    /// Offsets of the volumes left, if they were found before
+    offsets: Option<vec::IntoIter<usize>>,
 }
 
 impl<'a> BiosVolumes<'a> {
     pub fn new(data: &'a [u8]) -> Self {
         Self {
             data,
-            i: 0
+            i: 0,
+            offsets: None,
+        }
+    }
+
+    /// Volumes at the offsets returned by `index` for the same data, which
+    /// are not searched for again
+    pub fn indexed(data: &'a [u8], offsets: Vec<usize>) -> Self {
+        Self {
+            data,
+            i: 0,
+            offsets: Some(offsets.into_iter()),
         }
     }
+
+    /// Offsets of the volumes in the data
+    pub fn index(data: &'a [u8]) -> Vec<usize> {
+        Self::new(data).map(|volume| volume.offset).collect()
+    }
 }
 
 impl<'a> Iterator for BiosVolumes<'a> {
     type Item = BiosVolume<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.i + mem::size_of::<volume::Header>() <= self.data.len() {
-            let header_data = &self.data[self.i..];
-            let header = plain::from_bytes::<volume::Header>(header_data).unwrap();
-
-            if header.valid() {
-                self.i += header.length as usize;
-
-                /*
-                self.i += mem::size_of::<volume::Header>();
-
-                while self.i + mem::size_of::<volume::BlockEntry>() <= self.data.len() {
-                    let block_entry = plain::from_bytes::<volume::BlockEntry>(&self.data[self.i..]).unwrap();
-                    self.i += mem::size_of::<volume::BlockEntry>();
+        if let Some(offsets) = self.offsets.as_mut() {
+            let offset = offsets.next()?;
+            return BiosVolume::new(self.data, offset);
+        }
 
-                    if block_entry.num_blocks == 0 && block_entry.block_length == 0 {
-                        break;
-                    } else {
-                        println!("    {}, {}", block_entry.num_blocks, block_entry.block_length);
-                    }
+        while self.i + mem::size_of::<volume::Header>() <= self.data.len() {
+            match BiosVolume::new(self.data, self.i) {
+                Some(volume) => {
+                    self.i += volume.header.length as usize;
+                    return Some(volume);
                 }
-
-                self.i += header.header_length as usize - mem::size_of::<volume::Header>();
-                */
-
-                return Some(BiosVolume {
-                    header,
-                    data: &header_data[header.header_length as usize .. header.length as usize]
-                });
-            } else {
-                self.i += 8;
+                None => self.i += 8,
             }
         }
 
@@ -221,12 +344,41 @@ impl<'a> Iterator for BiosVolumes<'a> {
     }
 }
 
+/// Rounds up to the 8 byte alignment of files in a volume
+fn align8(offset: usize) -> usize {
+    (offset + 7) & !7
+}
+
 pub struct BiosVolume<'a> {
+    offset: usize,
     header: &'a volume::Header,
     data: &'a [u8],
+    /// Offsets of the files, found on first use
+    files: OnceCell<Vec<usize>>,
 }
 
 impl<'a> BiosVolume<'a> {
+    /// Volume whose header is at `offset` of `data`, if the header is valid
+    /// and the volume lies within the data
+    fn new(data: &'a [u8], offset: usize) -> Option<Self> {
+        let header_data = data.get(offset..)?;
+        let header = plain::from_bytes::<volume::Header>(header_data).ok()?;
+        if !header.valid() || (header.header_length as usize) < mem::size_of::<volume::Header>() {
+            return None;
+        }
+        Some(BiosVolume {
+            offset,
+            header,
+            data: header_data.get(header.header_length as usize..header.length as usize)?,
+            files: OnceCell::new(),
+        })
+    }
+
+    /// Offset of the volume header in the data it was found in
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
     pub fn header(&self) -> &'a volume::Header {
         self.header
     }
@@ -235,56 +387,156 @@ impl<'a> BiosVolume<'a> {
         self.data
     }
 
-    pub fn files(&self) -> BiosFiles {
-        BiosFiles::new(self.data)
+    /// Space taken by the files of the volume and left free. Free runs are
+    /// pad files and the erased space after the last file, runs separated by
+    /// no more than the 8 byte alignment of files being merged.
+    pub fn usage(&self) -> volume::Usage {
+        let erase = if self
+            .header
+            .attributes()
+            .contains(volume::Attributes::ERASE_POLARITY)
+        {
+            0xFF
+        } else {
+            0x00
+        };
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        let mut end = 0;
+        for file in self.files() {
+            let header = file.header();
+            end = align8(file.offset() + header.size());
+            if header.kind() == file::HeaderKind::Ffs(file::KIND_PAD) {
+                runs.push((file.offset(), file.offset() + header.size()));
+            }
+        }
+        let erased = self
+            .data
+            .get(end..)
+            .unwrap_or_default()
+            .iter()
+            .take_while(|b| **b == erase)
+            .count();
+        if erased > 0 {
+            runs.push((end, end + erased));
+        }
+
+        let mut free = 0;
+        let mut largest_free = 0;
+        let mut run: Option<(usize, usize)> = None;
+        for (start, end) in runs {
+            run = match run {
+                Some((run_start, run_end)) if start <= align8(run_end) => Some((run_start, end)),
+                Some((run_start, run_end)) => {
+                    largest_free = largest_free.max(run_end - run_start);
+                    Some((start, end))
+                }
+                None => Some((start, end)),
+            };
+            free += end - start;
+        }
+        if let Some((run_start, run_end)) = run {
+            largest_free = largest_free.max(run_end - run_start);
+        }
+
+        volume::Usage {
+            size: self.data.len(),
+            used: self.data.len() - free,
+            free,
+            largest_free,
+        }
+    }
+
+    /// Files of the volume. The headers are walked on the first call only.
+    pub fn files(&self) -> BiosFiles<'a> {
+        let offsets = self.files.get_or_init(|| BiosFiles::index(self.data));
+        BiosFiles::indexed(self.data, offsets.clone())
     }
 }
 
 pub struct BiosFiles<'a> {
     data: &'a [u8],
     i: usize,
+    /// Offsets of the files left, if they were found before
+    offsets: Option<vec::IntoIter<usize>>,
 }
 
 impl<'a> BiosFiles<'a> {
     pub fn new(data: &'a [u8]) -> Self {
         Self {
             data,
-            i: 0
+            i: 0,
+            offsets: None,
         }
     }
+
+    /// Files at the offsets returned by `index` for the same data
+    pub fn indexed(data: &'a [u8], offsets: Vec<usize>) -> Self {
+        Self {
+            data,
+            i: 0,
+            offsets: Some(offsets.into_iter()),
+        }
+    }
+
+    /// Offsets of the files in the data
+    pub fn index(data: &'a [u8]) -> Vec<usize> {
+        Self::new(data).map(|file| file.offset).collect()
+    }
 }
 
 impl<'a> Iterator for BiosFiles<'a> {
     type Item = BiosFile<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.i + mem::size_of::<file::Header>() <= self.data.len() {
-            let header_data = &self.data[self.i..];
-            let header = plain::from_bytes::<file::Header>(header_data).unwrap();
+        if let Some(offsets) = self.offsets.as_mut() {
+            let offset = offsets.next()?;
+            return BiosFile::new(self.data, offset);
+        }
 
-            if header.size() == 0xFFFFFF {
+        // Files end at erased space or at the first file that does not fit
+        match BiosFile::new(self.data, self.i) {
+            Some(file) => {
+                self.i += align8(file.header.size());
+                Some(file)
+            }
+            None => {
                 self.i = self.data.len();
                 None
-            } else {
-                self.i += ((header.size() + 7) / 8) * 8;
-
-                Some(BiosFile {
-                    header,
-                    data: &header_data[mem::size_of::<file::Header>() .. header.size()]
-                })
             }
-        } else {
-            None
         }
     }
 }
 
 pub struct BiosFile<'a> {
+    offset: usize,
     header: &'a file::Header,
     data: &'a [u8],
+    /// Offsets of the sections, found on first use
+    sections: OnceCell<Vec<usize>>,
 }
 
 impl<'a> BiosFile<'a> {
+    /// File whose header is at `offset` of `data`, unless the space is
+    /// erased or the file does not fit in the data
+    fn new(data: &'a [u8], offset: usize) -> Option<Self> {
+        let header_data = data.get(offset..)?;
+        let header = plain::from_bytes::<file::Header>(header_data).ok()?;
+        if header.size() == 0xFFFFFF {
+            return None;
+        }
+        Some(BiosFile {
+            offset,
+            header,
+            data: header_data.get(mem::size_of::<file::Header>()..header.size())?,
+            sections: OnceCell::new(),
+        })
+    }
+
+    /// Offset of the file header in the volume data
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
     pub fn header(&self) -> &'a file::Header {
         self.header
     }
@@ -293,57 +545,95 @@ impl<'a> BiosFile<'a> {
         self.data
     }
 
-    pub fn sections(&self) -> BiosSections {
-        BiosSections::new(self.data)
+    /// Sections of the file. The headers are walked on the first call only.
+    pub fn sections(&self) -> BiosSections<'a> {
+        let offsets = self.sections.get_or_init(|| BiosSections::index(self.data));
+        BiosSections::indexed(self.data, offsets.clone())
     }
 }
 
 pub struct BiosSections<'a> {
     data: &'a [u8],
     i: usize,
+    /// Offsets of the sections left, if they were found before
+    offsets: Option<vec::IntoIter<usize>>,
 }
 
 impl<'a> BiosSections<'a> {
     pub fn new(data: &'a [u8]) -> Self {
         Self {
             data,
-            i: 0
+            i: 0,
+            offsets: None,
+        }
+    }
+
+    /// Sections at the offsets returned by `index` for the same data
+    pub fn indexed(data: &'a [u8], offsets: Vec<usize>) -> Self {
+        Self {
+            data,
+            i: 0,
+            offsets: Some(offsets.into_iter()),
         }
     }
+
+    /// Offsets of the sections in the data
+    pub fn index(data: &'a [u8]) -> Vec<usize> {
+        Self::new(data).map(|section| section.offset).collect()
+    }
 }
 
 impl<'a> Iterator for BiosSections<'a> {
     type Item = BiosSection<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.i + mem::size_of::<section::Header>() <= self.data.len() {
-            let header_data = &self.data[self.i..];
-            let header = plain::from_bytes::<section::Header>(header_data).unwrap();
+        if let Some(offsets) = self.offsets.as_mut() {
+            let offset = offsets.next()?;
+            return BiosSection::new(self.data, offset);
+        }
 
-            if header.size() == 0xFFFFFF {
+        // Sections end at erased space or at the first section that does
+        // not fit
+        match BiosSection::new(self.data, self.i) {
+            Some(section) => {
+                self.i += (section.header.size() + 3) & !3;
+                Some(section)
+            }
+            None => {
                 self.i = self.data.len();
                 None
-            } else {
-
-                self.i += ((header.size() + 3) / 4) * 4;
-
-                Some(BiosSection {
-                    header,
-                    data: &header_data[mem::size_of::<section::Header>() .. header.size()]
-                })
             }
-        } else {
-            None
         }
     }
 }
 
 pub struct BiosSection<'a> {
+    offset: usize,
     header: &'a section::Header,
     data: &'a [u8],
 }
 
 impl<'a> BiosSection<'a> {
+    /// Section whose header is at `offset` of `data`, unless the space is
+    /// erased or the section does not fit in the data
+    fn new(data: &'a [u8], offset: usize) -> Option<Self> {
+        let header_data = data.get(offset..)?;
+        let header = plain::from_bytes::<section::Header>(header_data).ok()?;
+        if header.size() == 0xFFFFFF {
+            return None;
+        }
+        Some(BiosSection {
+            offset,
+            header,
+            data: header_data.get(mem::size_of::<section::Header>()..header.size())?,
+        })
+    }
+
+    /// Offset of the section header in the file data
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
     pub fn header(&self) -> &'a section::Header {
         self.header
     }
@@ -351,6 +641,22 @@ impl<'a> BiosSection<'a> {
     pub fn data(&self) -> &'a [u8] {
         self.data
     }
+
+    /// Data of a GUID defined section compressed with LZMA, decompressed.
+    /// None for other sections.
+    pub fn decompress(&self) -> Option<Result<Vec<u8>, Error>> {
+        if self.header.kind() != section::HeaderKind::GuidDefined {
+            return None;
+        }
+        let guid_defined = plain::from_bytes::<section::GuidDefined>(self.data).ok()?;
+        if { guid_defined.guid } != SECTION_LZMA_COMPRESS_GUID {
+            return None;
+        }
+        let start = (guid_defined.data_offset as usize)
+            .saturating_sub(mem::size_of::<section::Header>())
+            .max(mem::size_of::<section::GuidDefined>());
+        Some(lzma::decompress(self.data.get(start..).unwrap_or_default()))
+    }
 }
 
 pub struct Me<'a> {
@@ -358,7 +664,7 @@ pub struct Me<'a> {
 }
 
 impl<'a> Me<'a> {
-    pub fn new(data: &'a [u8]) -> Result<Me, String> {
+    pub fn new(data: &'a [u8]) -> Result<Me<'a>, Error> {
         Ok(Me { data })
     }
 
@@ -0,0 +1,540 @@
+// SPDX-License-Identifier: MIT
+
+//! AMI NVAR variable stores, which Aptio firmware keeps in a raw file of a
+//! volume instead of a VSS store. Each entry starts with an `NVAR` header.
+//! Changes of a variable are appended as entries holding only data, linked
+//! from the entry before them through its `next` offset.
+
+use alloc::{string::String, vec::Vec};
+use core::{char, mem};
+use plain::Plain;
+use uefi::guid::Guid;
+
+use super::{file, volume, Bios, RegionKind, Rom};
+use crate::Error;
+
+/// Name of the file of volumes holding an NVAR store
+pub const NVAR_STORE_FILE_GUID: Guid = Guid(
+    0xcef5b9a3,
+    0x476d,
+    0x497f,
+    [0x9f, 0xdc, 0xe9, 0x81, 0x43, 0xe0, 0x42, 0x2c],
+);
+
+/// First bytes of every entry
+pub const SIGNATURE: [u8; 4] = *b"NVAR";
+
+/// `next` of the last entry of a variable
+pub const NEXT_NONE: u32 = 0xFF_FFFF;
+
+/// Entry attributes
+pub const ATTRIB_RUNTIME: u8 = 0x01;
+pub const ATTRIB_ASCII_NAME: u8 = 0x02;
+pub const ATTRIB_GUID: u8 = 0x04;
+pub const ATTRIB_DATA_ONLY: u8 = 0x08;
+pub const ATTRIB_EXT_HEADER: u8 = 0x10;
+pub const ATTRIB_HW_ERROR_RECORD: u8 = 0x20;
+pub const ATTRIB_AUTH_WRITE: u8 = 0x40;
+pub const ATTRIB_VALID: u8 = 0x80;
+
+#[repr(packed)]
+pub struct EntryHeader {
+    pub signature: [u8; 4],
+    /// Size of the entry, header included
+    pub size: u16,
+    next: [u8; 3],
+    pub attributes: u8,
+}
+
+impl EntryHeader {
+    /// Offset of the next entry of the variable from this one, `NEXT_NONE`
+    /// for the last
+    pub fn next(&self) -> u32 {
+        self.next[0] as u32 | (self.next[1] as u32) << 8 | (self.next[2] as u32) << 16
+    }
+}
+
+unsafe impl Plain for EntryHeader {}
+
+/// An entry of an NVAR store
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<'a> {
+    offset: usize,
+    attributes: u8,
+    next: u32,
+    /// The entry after its header
+    body: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Offset of the entry header in the store
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn attributes(&self) -> u8 {
+        self.attributes
+    }
+
+    pub fn valid(&self) -> bool {
+        self.attributes & ATTRIB_VALID != 0
+    }
+
+    /// Offset of the next entry of the variable in the store, if any
+    pub fn next_offset(&self) -> Option<usize> {
+        if self.next == NEXT_NONE || self.next == 0 {
+            None
+        } else {
+            Some(self.offset + self.next as usize)
+        }
+    }
+
+    /// Size of the entry, header included
+    pub fn size(&self) -> usize {
+        mem::size_of::<EntryHeader>() + self.body.len()
+    }
+
+    /// Body of the entry without its extended header, which ends the entry
+    /// and stores its size in its last two bytes
+    fn contents(&self) -> Option<&'a [u8]> {
+        if self.attributes & ATTRIB_EXT_HEADER == 0 {
+            return Some(self.body);
+        }
+        let size = self.body.len().checked_sub(2)?;
+        let ext = self.body[size] as usize | (self.body[size + 1] as usize) << 8;
+        self.body.get(..self.body.len().checked_sub(ext)?)
+    }
+}
+
+/// Entries of a store, in order
+pub struct Entries<'a> {
+    data: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header_data = self.data.get(self.i..)?;
+        let header = plain::from_bytes::<EntryHeader>(header_data).ok()?;
+        let hs = mem::size_of::<EntryHeader>();
+        let size = header.size as usize;
+        if header.signature != SIGNATURE || size < hs || size > header_data.len() {
+            return None;
+        }
+        let entry = Entry {
+            offset: self.i,
+            attributes: header.attributes,
+            next: header.next(),
+            body: &header_data[hs..size],
+        };
+        self.i += size;
+        Some(entry)
+    }
+}
+
+/// An NVAR store
+#[derive(Clone, Copy)]
+pub struct NvarStore<'a> {
+    offset: usize,
+    file: usize,
+    polarity: bool,
+    data: &'a [u8],
+}
+
+impl<'a> NvarStore<'a> {
+    /// Store of the data of the file whose header is at `file` in the BIOS
+    /// region, if the data starts with an entry
+    pub fn new(data: &'a [u8], file: usize, polarity: bool) -> Result<Self, Error> {
+        if !data.starts_with(&SIGNATURE) {
+            return Err(Error::Invalid(String::from(
+                "NVAR store does not start with an entry",
+            )));
+        }
+        Ok(Self {
+            offset: file + mem::size_of::<file::Header>(),
+            file,
+            polarity,
+            data,
+        })
+    }
+
+    /// Offset of the store in the BIOS region
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Offset of the header of the file holding the store in the BIOS
+    /// region
+    pub fn file_offset(&self) -> usize {
+        self.file
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// All entries of the store, including those of deleted variables
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            data: self.data,
+            i: 0,
+        }
+    }
+
+    /// Offset of the free space following the last entry
+    pub fn free_offset(&self) -> usize {
+        self.entries()
+            .last()
+            .map(|entry| entry.offset() + entry.size())
+            .unwrap_or(0)
+    }
+
+    /// GUID `index` of the GUIDs stored backwards from the end of the store,
+    /// which entries without their own GUID refer to
+    fn guid(&self, index: u8) -> Option<Guid> {
+        let size = mem::size_of::<Guid>();
+        let end = self.data.len().checked_sub(index as usize * size)?;
+        read_guid(self.data.get(end.checked_sub(size)?..end)?)
+    }
+
+    /// Variables of the store, each with the data of the last entry of its
+    /// chain, including deleted ones
+    pub fn variables(&self) -> Vec<NvarVariable<'a>> {
+        let entries: Vec<Entry<'a>> = self.entries().collect();
+        let mut variables = Vec::new();
+        for entry in entries.iter() {
+            if entry.attributes & ATTRIB_DATA_ONLY != 0 {
+                continue;
+            }
+            if let Some(variable) = self.variable(&entries, entry) {
+                variables.push(variable);
+            }
+        }
+        variables
+    }
+
+    /// Variable named by `head`, with the data of the last entry linked
+    /// from it
+    fn variable(&self, entries: &[Entry<'a>], head: &Entry<'a>) -> Option<NvarVariable<'a>> {
+        let body = head.contents()?;
+        let (guid, name_start) = if head.attributes & ATTRIB_GUID != 0 {
+            (read_guid(body)?, mem::size_of::<Guid>())
+        } else {
+            (self.guid(*body.first()?)?, 1)
+        };
+        let names = body.get(name_start..)?;
+        let (name, name_size) = if head.attributes & ATTRIB_ASCII_NAME != 0 {
+            let len = names.iter().position(|b| *b == 0)?;
+            (names[..len].iter().map(|b| *b as char).collect(), len + 1)
+        } else {
+            let len = names.chunks_exact(2).position(|c| c == [0, 0])?;
+            let name = char::decode_utf16(
+                names[..len * 2]
+                    .chunks_exact(2)
+                    .map(|c| c[0] as u16 | (c[1] as u16) << 8),
+            )
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+            (name, len * 2 + 2)
+        };
+
+        // Links are followed forward only, so a corrupt chain cannot loop
+        let mut last = *head;
+        let mut data_offset = head.offset + mem::size_of::<EntryHeader>() + name_start + name_size;
+        let mut data = &body[name_start + name_size..];
+        while let Some(next) = last.next_offset() {
+            match entries.iter().find(|entry| entry.offset == next) {
+                Some(entry) if entry.offset > last.offset => {
+                    last = *entry;
+                    data_offset = entry.offset + mem::size_of::<EntryHeader>();
+                    data = entry.contents()?;
+                }
+                _ => break,
+            }
+        }
+
+        Some(NvarVariable {
+            head: head.offset,
+            last: last.offset,
+            valid: head.valid() && last.valid(),
+            attributes: head.attributes,
+            guid,
+            name,
+            data_offset,
+            data,
+        })
+    }
+}
+
+/// A variable of an NVAR store
+pub struct NvarVariable<'a> {
+    head: usize,
+    last: usize,
+    valid: bool,
+    attributes: u8,
+    guid: Guid,
+    name: String,
+    data_offset: usize,
+    data: &'a [u8],
+}
+
+impl<'a> NvarVariable<'a> {
+    /// Offset of the entry naming the variable in the store
+    pub fn offset(&self) -> usize {
+        self.head
+    }
+
+    /// Offset of the last entry of the variable, holding its data
+    pub fn last_offset(&self) -> usize {
+        self.last
+    }
+
+    /// Offset of the variable data in the store
+    pub fn data_offset(&self) -> usize {
+        self.data_offset
+    }
+
+    /// Attributes of the entry naming the variable
+    pub fn attributes(&self) -> u8 {
+        self.attributes
+    }
+
+    pub fn guid(&self) -> Guid {
+        self.guid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// True unless the variable has been deleted
+    pub fn active(&self) -> bool {
+        self.valid
+    }
+}
+
+impl<'a> Bios<'a> {
+    /// NVAR stores found in the files of the volumes of the BIOS region
+    pub fn nvar_stores(&self) -> Vec<NvarStore<'a>> {
+        let mut stores = Vec::new();
+        for volume in self.volumes() {
+            let polarity = volume
+                .header()
+                .attributes()
+                .contains(volume::Attributes::ERASE_POLARITY);
+            let base = volume.offset() + volume.header().header_length as usize;
+            for file in volume.files() {
+                let guid = file.header().guid;
+                if guid != NVAR_STORE_FILE_GUID {
+                    continue;
+                }
+                if let Ok(store) = NvarStore::new(file.data(), base + file.offset(), polarity) {
+                    stores.push(store);
+                }
+            }
+        }
+        stores
+    }
+}
+
+impl<'a> Rom<'a> {
+    /// Offset of the BIOS region, the NVAR store holding the active
+    /// variable `name` and the variable
+    pub(super) fn find_nvar_variable(
+        &self,
+        guid: Option<Guid>,
+        name: &str,
+    ) -> Result<(usize, NvarStore<'a>, NvarVariable<'a>), Error> {
+        let (base, _limit) =
+            self.get_region_base_limit(RegionKind::Bios)?
+                .ok_or(Error::RegionNotFound {
+                    region: RegionKind::Bios,
+                })?;
+        let bios = self.bios()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::Bios,
+        })?;
+
+        let mut found: Option<(usize, NvarStore, NvarVariable)> = None;
+        for store in bios.nvar_stores() {
+            for var in store.variables() {
+                if !var.active() || var.name() != name || guid.is_some_and(|g| g != var.guid()) {
+                    continue;
+                }
+                match &found {
+                    Some((_, _, other)) if other.guid() != var.guid() => {
+                        return Err(Error::InvalidArgument(format!(
+                            "Variable {} is ambiguous: {} and {}",
+                            name,
+                            other.guid(),
+                            var.guid()
+                        )));
+                    }
+                    Some(_) => (),
+                    None => found = Some((base, store, var)),
+                }
+            }
+        }
+        found.ok_or_else(|| Error::VariableNotFound {
+            name: String::from(name),
+        })
+    }
+
+    /// Replaces the data of an NVAR variable, returning the modified image.
+    /// Data of the same size is rewritten in place, otherwise an entry
+    /// holding only the data is appended to the free space of the store and
+    /// linked from the last entry of the variable.
+    pub(super) fn set_nvar_variable(
+        &self,
+        guid: Option<Guid>,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let (base, store, var) = self.find_nvar_variable(guid, name)?;
+        let mut image = self.data.to_vec();
+        let store_offset = base + store.offset();
+
+        if data.len() == var.data().len() {
+            let start = store_offset + var.data_offset();
+            image[start..start + data.len()].copy_from_slice(data);
+        } else {
+            if var.attributes() & (ATTRIB_EXT_HEADER | ATTRIB_AUTH_WRITE) != 0 {
+                return Err(Error::Unsupported(format!(
+                    "Variable {} has an extended header, its size cannot change",
+                    name
+                )));
+            }
+            let size = mem::size_of::<EntryHeader>() + data.len();
+            if size > u16::MAX as usize {
+                return Err(Error::InvalidArgument(format!(
+                    "Variable data of {} bytes too large for an NVAR entry",
+                    data.len()
+                )));
+            }
+            let mut entry = Vec::with_capacity(size);
+            entry.extend_from_slice(&SIGNATURE);
+            entry.extend_from_slice(&(size as u16).to_le_bytes());
+            entry.extend_from_slice(&NEXT_NONE.to_le_bytes()[..3]);
+            entry.push(
+                var.attributes() & (ATTRIB_RUNTIME | ATTRIB_HW_ERROR_RECORD | ATTRIB_VALID)
+                    | ATTRIB_DATA_ONLY,
+            );
+            entry.extend_from_slice(data);
+
+            let free = store.free_offset();
+            if free + size > store.data().len()
+                || store.data()[free..free + size].iter().any(|b| *b != 0xFF)
+            {
+                return Err(Error::Conflict(format!(
+                    "Not enough free space in NVAR store for {} bytes",
+                    size
+                )));
+            }
+            let next = (free - var.last_offset()) as u32;
+            if next >= NEXT_NONE {
+                return Err(Error::Conflict(format!(
+                    "Free space of NVAR store too far from variable {}",
+                    name
+                )));
+            }
+
+            let start = store_offset + free;
+            image[start..start + size].copy_from_slice(&entry);
+            let link = store_offset + var.last_offset() + 6;
+            image[link..link + 3].copy_from_slice(&next.to_le_bytes()[..3]);
+        }
+        self.fix_nvar_file(&mut image, base, &store);
+        Ok(image)
+    }
+
+    /// Marks an NVAR variable as deleted, returning the modified image
+    pub(super) fn delete_nvar_variable(
+        &self,
+        guid: Option<Guid>,
+        name: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let (base, store, var) = self.find_nvar_variable(guid, name)?;
+        let mut image = self.data.to_vec();
+        image[base + store.offset() + var.offset() + 9] &= !ATTRIB_VALID;
+        self.fix_nvar_file(&mut image, base, &store);
+        Ok(image)
+    }
+
+    /// Recomputes the data checksum of the file holding a modified store,
+    /// if the file has one
+    fn fix_nvar_file(&self, image: &mut [u8], base: usize, store: &NvarStore) {
+        let start = base + store.file_offset();
+        let end = start + mem::size_of::<file::Header>() + store.data().len();
+        let attributes = file::Attributes::from_bits_truncate(image[start + 19]);
+        if attributes.contains(file::Attributes::ATTRIB_CHECKSUM) {
+            file::fix_checksums(&mut image[start..end], store.polarity);
+        }
+    }
+}
+
+/// GUID in the first 16 bytes of `data`
+fn read_guid(data: &[u8]) -> Option<Guid> {
+    let bytes = data.get(..mem::size_of::<Guid>())?;
+    let mut node = [0; 8];
+    node.copy_from_slice(&bytes[8..]);
+    Some(Guid(
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        node,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entry holding `body`, linked to the entry `next` bytes further
+    fn entry(attributes: u8, next: u32, body: &[u8]) -> Vec<u8> {
+        let size = mem::size_of::<EntryHeader>() + body.len();
+        let mut entry = SIGNATURE.to_vec();
+        entry.extend_from_slice(&(size as u16).to_le_bytes());
+        entry.extend_from_slice(&next.to_le_bytes()[..3]);
+        entry.push(attributes);
+        entry.extend_from_slice(body);
+        entry
+    }
+
+    #[test]
+    fn variables_follow_data_entries() {
+        let guid = Guid(0x1234_5678, 0x9abc, 0xdef0, [1, 2, 3, 4, 5, 6, 7, 8]);
+        // Setup, with its GUID stored at the end of the store, then updated
+        let setup = entry(ATTRIB_VALID | ATTRIB_ASCII_NAME, 18, b"\0Setup\0\x01");
+        let update = entry(ATTRIB_VALID | ATTRIB_DATA_ONLY, NEXT_NONE, b"\x02\x03");
+        // Lang, with its own GUID, then deleted
+        let mut lang_body = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        lang_body.extend_from_slice(b"L\0\0\0e");
+        let lang = entry(ATTRIB_GUID, NEXT_NONE, &lang_body);
+
+        let mut data = [setup, update, lang].concat();
+        data.resize(0x100 - 16, 0xFF);
+        data.extend_from_slice(&[0x78, 0x56, 0x34, 0x12, 0xbc, 0x9a, 0xf0, 0xde]);
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let store = NvarStore::new(&data, 0, true).unwrap();
+        assert_eq!(store.free_offset(), 18 + 12 + 31);
+        let variables = store.variables();
+        assert_eq!(variables.len(), 2);
+
+        assert_eq!(variables[0].name(), "Setup");
+        assert_eq!(variables[0].guid(), guid);
+        assert_eq!(variables[0].data(), [2, 3]);
+        assert_eq!(variables[0].data_offset(), 18 + 10);
+        assert_eq!(variables[0].last_offset(), 18);
+        assert!(variables[0].active());
+
+        assert_eq!(variables[1].name(), "L");
+        assert_eq!(variables[1].data(), b"e");
+        assert!(!variables[1].active());
+    }
+}
@@ -0,0 +1,503 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{char, mem};
+use plain::Plain;
+use serde::{Deserialize, Serialize};
+use uefi::guid::Guid;
+
+use super::{Bios, RegionKind, Rom};
+use crate::error::{from_bytes, Error};
+
+/// File system GUID of firmware volumes holding variable stores
+pub const NV_DATA_FV_GUID: Guid = Guid(
+    0xfff12b8d,
+    0x7696,
+    0x4c8b,
+    [0xa9, 0x85, 0x27, 0x47, 0x07, 0x5b, 0x4f, 0x50],
+);
+
+/// Signature of variable stores with plain variable headers
+pub const VARIABLE_GUID: Guid = Guid(
+    0xddcf3616,
+    0x3275,
+    0x4164,
+    [0x98, 0xb6, 0xfe, 0x85, 0x70, 0x7f, 0xfe, 0x7d],
+);
+
+/// Signature of variable stores with authenticated variable headers
+pub const AUTHENTICATED_VARIABLE_GUID: Guid = Guid(
+    0xaaf32c78,
+    0x947b,
+    0x439a,
+    [0xa1, 0x80, 0x2e, 0x14, 0x4e, 0xc3, 0x77, 0x92],
+);
+
+/// Start of every variable header
+pub const START_ID: u16 = 0x55AA;
+
+/// Variable states, bits are cleared as a variable moves through its life
+pub const VAR_IN_DELETED_TRANSITION: u8 = 0xFE;
+pub const VAR_DELETED: u8 = 0xFD;
+pub const VAR_HEADER_VALID_ONLY: u8 = 0x7F;
+pub const VAR_ADDED: u8 = 0x3F;
+
+#[repr(packed)]
+pub struct StoreHeader {
+    pub signature: Guid,
+    pub size: u32,
+    pub format: u8,
+    pub state: u8,
+    pub reserved: u16,
+    pub reserved1: u32,
+}
+
+unsafe impl Plain for StoreHeader {}
+
+#[repr(packed)]
+pub struct VariableHeader {
+    pub start_id: u16,
+    pub state: u8,
+    pub reserved: u8,
+    pub attributes: u32,
+    pub name_size: u32,
+    pub data_size: u32,
+    pub vendor_guid: Guid,
+}
+
+unsafe impl Plain for VariableHeader {}
+
+#[repr(packed)]
+pub struct AuthenticatedVariableHeader {
+    pub start_id: u16,
+    pub state: u8,
+    pub reserved: u8,
+    pub attributes: u32,
+    pub monotonic_count: u64,
+    pub timestamp: [u8; 16],
+    pub pub_key_index: u32,
+    pub name_size: u32,
+    pub data_size: u32,
+    pub vendor_guid: Guid,
+}
+
+unsafe impl Plain for AuthenticatedVariableHeader {}
+
+/// A VSS variable store
+#[derive(Clone, Copy)]
+pub struct VariableStore<'a> {
+    offset: usize,
+    header: &'a StoreHeader,
+    data: &'a [u8],
+}
+
+impl<'a> VariableStore<'a> {
+    /// Parses a variable store at the start of `data`
+    pub fn new(data: &'a [u8], offset: usize) -> Result<Self, Error> {
+        let header = from_bytes::<StoreHeader>(data, 0)?;
+        let signature = header.signature;
+        if signature != VARIABLE_GUID && signature != AUTHENTICATED_VARIABLE_GUID {
+            return Err(Error::SignatureMismatch {
+                structure: "Variable store",
+                offset,
+            });
+        }
+        let size = header.size as usize;
+        if size < mem::size_of::<StoreHeader>() || size > data.len() {
+            return Err(Error::Invalid(format!(
+                "Variable store size invalid: {:#X}",
+                size
+            )));
+        }
+        Ok(Self {
+            offset,
+            header,
+            data: &data[..size],
+        })
+    }
+
+    /// Offset of the store in the BIOS region
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn header(&self) -> &'a StoreHeader {
+        self.header
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn authenticated(&self) -> bool {
+        let signature = self.header.signature;
+        signature == AUTHENTICATED_VARIABLE_GUID
+    }
+
+    /// Size of the variable headers used by this store
+    pub fn variable_header_size(&self) -> usize {
+        if self.authenticated() {
+            mem::size_of::<AuthenticatedVariableHeader>()
+        } else {
+            mem::size_of::<VariableHeader>()
+        }
+    }
+
+    /// All variables in the store, including deleted ones
+    pub fn variables(&self) -> Variables<'a> {
+        Variables {
+            data: self.data,
+            authenticated: self.authenticated(),
+            i: mem::size_of::<StoreHeader>(),
+        }
+    }
+
+    /// Offset of the free space following the last variable in the store
+    pub fn free_offset(&self) -> usize {
+        self.variables()
+            .last()
+            .map(|var| var.end())
+            .unwrap_or(mem::size_of::<StoreHeader>())
+    }
+}
+
+pub struct Variables<'a> {
+    data: &'a [u8],
+    authenticated: bool,
+    i: usize,
+}
+
+impl<'a> Iterator for Variables<'a> {
+    type Item = Variable<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header_data = self.data.get(self.i..)?;
+        let (state, attributes, name_size, data_size, guid, hs) = if self.authenticated {
+            let header = plain::from_bytes::<AuthenticatedVariableHeader>(header_data).ok()?;
+            if header.start_id != START_ID {
+                return None;
+            }
+            (
+                header.state,
+                header.attributes,
+                header.name_size as usize,
+                header.data_size as usize,
+                header.vendor_guid,
+                mem::size_of::<AuthenticatedVariableHeader>(),
+            )
+        } else {
+            let header = plain::from_bytes::<VariableHeader>(header_data).ok()?;
+            if header.start_id != START_ID {
+                return None;
+            }
+            (
+                header.state,
+                header.attributes,
+                header.name_size as usize,
+                header.data_size as usize,
+                header.vendor_guid,
+                mem::size_of::<VariableHeader>(),
+            )
+        };
+
+        let name_end = hs.checked_add(name_size)?;
+        let data_end = name_end.checked_add(data_size)?;
+        if data_end > header_data.len() {
+            return None;
+        }
+
+        let name = char::decode_utf16(
+            header_data[hs..name_end]
+                .chunks_exact(2)
+                .map(|c| c[0] as u16 | (c[1] as u16) << 8)
+                .take_while(|c| *c != 0),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+
+        let offset = self.i;
+        self.i += data_end.div_ceil(4) * 4;
+
+        Some(Variable {
+            offset,
+            header_size: hs,
+            state,
+            attributes,
+            guid,
+            name,
+            name_size,
+            data: &header_data[name_end..data_end],
+        })
+    }
+}
+
+pub struct Variable<'a> {
+    offset: usize,
+    header_size: usize,
+    state: u8,
+    attributes: u32,
+    guid: Guid,
+    name: String,
+    name_size: usize,
+    data: &'a [u8],
+}
+
+impl<'a> Variable<'a> {
+    /// Offset of the variable header in the store
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Offset of the variable data in the store
+    pub fn data_offset(&self) -> usize {
+        self.offset + self.header_size + self.name_size
+    }
+
+    /// Offset following the variable, including alignment
+    pub fn end(&self) -> usize {
+        (self.data_offset() + self.data.len()).div_ceil(4) * 4
+    }
+
+    pub fn state(&self) -> u8 {
+        self.state
+    }
+
+    pub fn attributes(&self) -> u32 {
+        self.attributes
+    }
+
+    pub fn guid(&self) -> Guid {
+        self.guid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// True if the variable has been added and not deleted
+    pub fn active(&self) -> bool {
+        self.state == VAR_ADDED || self.state == VAR_ADDED & VAR_IN_DELETED_TRANSITION
+    }
+}
+
+impl<'a> Bios<'a> {
+    /// Variable stores found in firmware volumes of the BIOS region
+    pub fn variable_stores(&self) -> Vec<VariableStore<'a>> {
+        let mut stores = Vec::new();
+        for volume in self.volumes() {
+            let guid = volume.header().guid;
+            if guid != NV_DATA_FV_GUID {
+                continue;
+            }
+            let offset = volume.offset() + volume.header().header_length as usize;
+            if let Ok(store) = VariableStore::new(volume.data(), offset) {
+                stores.push(store);
+            }
+        }
+        stores
+    }
+}
+
+/// Attributes and data of a variable
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VariableValue {
+    pub attributes: u32,
+    pub data: Vec<u8>,
+}
+
+/// A variable added, changed or removed between two images. Added variables
+/// have no value before, removed ones no value after.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VariableDiff {
+    pub guid: String,
+    pub name: String,
+    pub before: Option<VariableValue>,
+    pub after: Option<VariableValue>,
+}
+
+/// Active variables of every VSS and NVAR store by GUID and name
+fn active_variables(bios: &Bios) -> BTreeMap<(String, String), VariableValue> {
+    let mut variables = BTreeMap::new();
+    for store in bios.variable_stores() {
+        for var in store.variables().filter(|var| var.active()) {
+            variables.insert(
+                (var.guid().to_string(), String::from(var.name())),
+                VariableValue {
+                    attributes: var.attributes(),
+                    data: var.data().to_vec(),
+                },
+            );
+        }
+    }
+    for store in bios.nvar_stores() {
+        for var in store.variables().iter().filter(|var| var.active()) {
+            variables.insert(
+                (var.guid().to_string(), String::from(var.name())),
+                VariableValue {
+                    attributes: var.attributes() as u32,
+                    data: var.data().to_vec(),
+                },
+            );
+        }
+    }
+    variables
+}
+
+/// Active variables whose attributes or data differ between the BIOS
+/// regions, or that are only in one of them, by GUID and name
+pub fn diff_variables(before: &Bios, after: &Bios) -> Vec<VariableDiff> {
+    let mut before = active_variables(before);
+    let mut diffs = Vec::new();
+    for (key, value) in active_variables(after) {
+        match before.remove(&key) {
+            Some(old) if old == value => (),
+            old => diffs.push((key, old, Some(value))),
+        }
+    }
+    for (key, old) in before {
+        diffs.push((key, Some(old), None));
+    }
+    diffs.sort_by(|a, b| a.0.cmp(&b.0));
+    diffs
+        .into_iter()
+        .map(|((guid, name), before, after)| VariableDiff {
+            guid,
+            name,
+            before,
+            after,
+        })
+        .collect()
+}
+
+impl<'a> Rom<'a> {
+    fn find_variable(
+        &self,
+        guid: Option<Guid>,
+        name: &str,
+    ) -> Result<(usize, VariableStore<'a>, Variable<'a>), Error> {
+        let (base, _limit) =
+            self.get_region_base_limit(RegionKind::Bios)?
+                .ok_or(Error::RegionNotFound {
+                    region: RegionKind::Bios,
+                })?;
+        let bios = self.bios()?.ok_or(Error::RegionNotFound {
+            region: RegionKind::Bios,
+        })?;
+
+        let mut found: Option<(usize, VariableStore, Variable)> = None;
+        for store in bios.variable_stores() {
+            for var in store.variables() {
+                if !var.active() || var.name() != name || guid.is_some_and(|g| g != var.guid()) {
+                    continue;
+                }
+                match &found {
+                    Some((_, _, other)) if other.guid() != var.guid() => {
+                        return Err(Error::InvalidArgument(format!(
+                            "Variable {} is ambiguous: {} and {}",
+                            name,
+                            other.guid(),
+                            var.guid()
+                        )));
+                    }
+                    Some(_) => (),
+                    None => found = Some((base + store.offset(), store, var)),
+                }
+            }
+        }
+        found.ok_or_else(|| Error::VariableNotFound {
+            name: String::from(name),
+        })
+    }
+
+    /// Replaces the data of a variable, returning the modified image. Data of
+    /// the same size is rewritten in place, otherwise the variable is marked
+    /// deleted and a new copy is appended to the free space of its store.
+    /// Variables of no VSS store are looked up in the NVAR stores, see
+    /// `nvar`.
+    pub fn set_variable(
+        &self,
+        guid: Option<Guid>,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let (store_offset, store, var) = match self.find_variable(guid, name) {
+            Err(Error::VariableNotFound { .. }) => return self.set_nvar_variable(guid, name, data),
+            found => found?,
+        };
+        let mut image = self.data.to_vec();
+
+        if data.len() == var.data().len() {
+            let start = store_offset + var.data_offset();
+            image[start..start + data.len()].copy_from_slice(data);
+            return Ok(image);
+        }
+
+        let hs = store.variable_header_size();
+        let mut new_var = store.data()[var.offset()..var.data_offset()].to_vec();
+        new_var[2] = VAR_ADDED;
+        // The data size is the field before the vendor GUID in both header types
+        let size_offset = hs - mem::size_of::<Guid>() - 4;
+        new_var[size_offset..size_offset + 4].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        new_var.extend_from_slice(data);
+
+        let free = store.free_offset();
+        if free + new_var.len() > store.data().len()
+            || store.data()[free..free + new_var.len()].iter().any(|b| *b != 0xFF)
+        {
+            return Err(Error::Conflict(format!(
+                "Not enough free space in variable store for {} bytes",
+                new_var.len()
+            )));
+        }
+
+        image[store_offset + var.offset() + 2] &= VAR_DELETED;
+        let start = store_offset + free;
+        image[start..start + new_var.len()].copy_from_slice(&new_var);
+        Ok(image)
+    }
+
+    /// Sets a single byte of a variable, for example a Setup option
+    pub fn set_variable_byte(
+        &self,
+        guid: Option<Guid>,
+        name: &str,
+        offset: usize,
+        value: u8,
+    ) -> Result<Vec<u8>, Error> {
+        let mut data = match self.find_variable(guid, name) {
+            Err(Error::VariableNotFound { .. }) => {
+                self.find_nvar_variable(guid, name)?.2.data().to_vec()
+            }
+            found => found?.2.data().to_vec(),
+        };
+        let byte = data.get_mut(offset).ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "Variable {} offset {:#X} out of range",
+                name, offset
+            ))
+        })?;
+        *byte = value;
+        self.set_variable(guid, name, &data)
+    }
+
+    /// Marks a variable of a VSS or NVAR store as deleted, returning the
+    /// modified image
+    pub fn delete_variable(&self, guid: Option<Guid>, name: &str) -> Result<Vec<u8>, Error> {
+        let (store_offset, _store, var) = match self.find_variable(guid, name) {
+            Err(Error::VariableNotFound { .. }) => return self.delete_nvar_variable(guid, name),
+            found => found?,
+        };
+        let mut image = self.data.to_vec();
+        image[store_offset + var.offset() + 2] &= VAR_DELETED;
+        Ok(image)
+    }
+}
+
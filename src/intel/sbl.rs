@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: MIT
+
+//! Slim Bootloader images, which have no firmware volumes in their BIOS
+//! region but a flash map of the stages and data the bootloader is built of.
+//! Some components, such as the extra payloads, are containers of further
+//! components.
+
+use alloc::{string::String, vec::Vec};
+use core::mem;
+use plain::Plain;
+
+use crate::error::{from_bytes, Error};
+
+/// Signature of the flash map, in Stage 1A
+pub const FLASH_MAP_SIGNATURE: [u8; 4] = *b"FLMP";
+
+#[repr(C, packed)]
+pub struct FlashMapHeader {
+    pub signature: [u8; 4],
+    pub version: u8,
+    /// Size of the header and the entries
+    pub length: u16,
+    pub attributes: u8,
+    /// Size of the bootloader image, which ends at the end of the region
+    pub rom_size: u32,
+    pub reserved: u32,
+}
+
+unsafe impl Plain for FlashMapHeader {}
+
+#[repr(C, packed)]
+pub struct FlashMapEntry {
+    /// Four character code of the component, such as `SG1A`
+    pub signature: [u8; 4],
+    pub flags: u32,
+    /// Offset of the component in the bootloader image
+    pub offset: u32,
+    pub size: u32,
+}
+
+unsafe impl Plain for FlashMapEntry {}
+
+impl FlashMapEntry {
+    pub fn name(&self) -> String {
+        fourcc(&self.signature)
+    }
+
+    pub fn description(&self) -> &'static str {
+        match &self.signature {
+            b"SG1A" => "Stage 1A",
+            b"SG1B" => "Stage 1B",
+            b"SG02" => "Stage 2",
+            b"ACM0" => "Boot Guard ACM",
+            b"UCOD" => "Microcode",
+            b"MRCD" => "MRC Training Data",
+            b"CNFG" => "Configuration Data",
+            b"VARS" => "Variables",
+            b"KEYH" => "Key Hash Store",
+            b"PYLD" => "Payload",
+            b"EPLD" => "Extra Payloads",
+            b"FWUP" => "Firmware Update Payload",
+            b"RSVD" => "Reserved",
+            b"EMTY" => "Empty",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Flash map of a Slim Bootloader image
+pub struct FlashMap<'a> {
+    /// The region holding the bootloader
+    data: &'a [u8],
+    /// Offset of the flash map in the region
+    offset: usize,
+    header: &'a FlashMapHeader,
+}
+
+impl<'a> FlashMap<'a> {
+    /// Searches the region for a flash map, which is 4 byte aligned and
+    /// describes an image no larger than the region
+    pub fn find(data: &'a [u8]) -> Option<FlashMap<'a>> {
+        (0..data.len().saturating_sub(mem::size_of::<FlashMapHeader>()))
+            .step_by(4)
+            .filter(|offset| data[*offset..].starts_with(&FLASH_MAP_SIGNATURE))
+            .find_map(|offset| FlashMap::new(data, offset).ok())
+    }
+
+    /// Parses the flash map at `offset` in the region
+    pub fn new(data: &'a [u8], offset: usize) -> Result<FlashMap<'a>, Error> {
+        let header: &FlashMapHeader = from_bytes(data, offset)?;
+        if header.signature != FLASH_MAP_SIGNATURE {
+            return Err(Error::SignatureMismatch {
+                structure: "Flash map",
+                offset,
+            });
+        }
+        let length = header.length as usize;
+        if length < mem::size_of::<FlashMapHeader>() || offset + length > data.len() {
+            return Err(Error::Truncated {
+                need: offset + length,
+                have: data.len(),
+            });
+        }
+        let rom_size = header.rom_size as usize;
+        if rom_size == 0 || rom_size > data.len() {
+            return Err(Error::Invalid(format!(
+                "Flash map of a {:#X} byte image in a {:#X} byte region",
+                rom_size,
+                data.len()
+            )));
+        }
+        Ok(FlashMap {
+            data,
+            offset,
+            header,
+        })
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn header(&self) -> &'a FlashMapHeader {
+        self.header
+    }
+
+    /// Offset of the bootloader image in the region
+    pub fn base(&self) -> usize {
+        self.data.len() - self.header.rom_size as usize
+    }
+
+    pub fn entries(&self) -> Vec<&'a FlashMapEntry> {
+        let start = self.offset + mem::size_of::<FlashMapHeader>();
+        let end = self.offset + self.header.length as usize;
+        self.data[start..end]
+            .chunks_exact(mem::size_of::<FlashMapEntry>())
+            .filter_map(|chunk| plain::from_bytes(chunk).ok())
+            .collect()
+    }
+
+    /// Offset of a component in the region
+    pub fn component_offset(&self, entry: &FlashMapEntry) -> usize {
+        self.base() + entry.offset as usize
+    }
+
+    /// Data of a component, none if it is outside the region
+    pub fn component(&self, entry: &FlashMapEntry) -> Option<&'a [u8]> {
+        let start = self.component_offset(entry);
+        self.data
+            .get(start..start.checked_add(entry.size as usize)?)
+    }
+}
+
+#[repr(C, packed)]
+pub struct ContainerHeader {
+    pub signature: [u8; 4],
+    pub version: u8,
+    pub svn: u8,
+    /// Offset of the component data from the start of the container
+    pub data_offset: u16,
+    pub data_size: u32,
+    pub auth_type: u8,
+    pub image_type: u8,
+    pub flags: u8,
+    /// Number of components
+    pub count: u8,
+}
+
+unsafe impl Plain for ContainerHeader {}
+
+/// Entry of a component in a container, followed by its hash
+#[repr(C, packed)]
+pub struct ContainerEntry {
+    pub name: [u8; 4],
+    /// Offset of the component from the start of the component data
+    pub offset: u32,
+    pub size: u32,
+    pub attribute: u8,
+    pub alignment: u8,
+    pub auth_type: u8,
+    pub hash_size: u8,
+}
+
+unsafe impl Plain for ContainerEntry {}
+
+impl ContainerEntry {
+    pub fn name(&self) -> String {
+        fourcc(&self.name)
+    }
+}
+
+/// Container of components, each with a hash the bootloader verifies. The
+/// components of the flash map that are containers, such as the extra
+/// payloads, are told by a header of an alphanumeric signature that fits
+/// them.
+pub struct Container<'a> {
+    data: &'a [u8],
+    header: &'a ContainerHeader,
+}
+
+impl<'a> Container<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Container<'a>, Error> {
+        let header: &ContainerHeader = from_bytes(data, 0)?;
+        let data_offset = header.data_offset as usize;
+        if !header.signature.iter().all(u8::is_ascii_alphanumeric)
+            || header.count == 0
+            || data_offset < mem::size_of::<ContainerHeader>()
+            || data_offset + header.data_size as usize > data.len()
+        {
+            return Err(Error::Invalid(String::from("Container header invalid")));
+        }
+        Ok(Container { data, header })
+    }
+
+    pub fn header(&self) -> &'a ContainerHeader {
+        self.header
+    }
+
+    pub fn name(&self) -> String {
+        fourcc(&self.header.signature)
+    }
+
+    /// Entries of the components, which follow the header up to the data
+    pub fn entries(&self) -> Result<Vec<&'a ContainerEntry>, Error> {
+        let end = self.header.data_offset as usize;
+        let mut offset = mem::size_of::<ContainerHeader>();
+        let mut entries = Vec::with_capacity(self.header.count as usize);
+        for _ in 0..self.header.count {
+            if offset + mem::size_of::<ContainerEntry>() > end {
+                return Err(Error::Truncated {
+                    need: offset + mem::size_of::<ContainerEntry>(),
+                    have: end,
+                });
+            }
+            let entry: &ContainerEntry = from_bytes(self.data, offset)?;
+            offset += mem::size_of::<ContainerEntry>() + entry.hash_size as usize;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Offset of a component in the container
+    pub fn component_offset(&self, entry: &ContainerEntry) -> usize {
+        self.header.data_offset as usize + entry.offset as usize
+    }
+
+    /// Data of a component, none if it is outside the container
+    pub fn component(&self, entry: &ContainerEntry) -> Option<&'a [u8]> {
+        let start = self.component_offset(entry);
+        self.data
+            .get(start..start.checked_add(entry.size as usize)?)
+    }
+}
+
+/// Four character code, with characters that are not printable replaced
+fn fourcc(code: &[u8; 4]) -> String {
+    code.iter()
+        .map(|b| {
+            if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
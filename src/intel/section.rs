@@ -1,9 +1,11 @@
 // SPDX-License-Identifier: MIT
 
 use plain::Plain;
+use serde::{Deserialize, Serialize};
 use uefi::guid::Guid;
 
-#[derive(Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum HeaderKind {
     Compression,
     GuidDefined,
@@ -23,20 +25,9 @@ pub enum HeaderKind {
     Unknown(u8)
 }
 
-//TODO: Extended size
-#[repr(packed)]
-pub struct Header {
-    size: [u8; 3],
-    kind: u8,
-}
-
-impl Header {
-    pub fn size(&self) -> usize {
-        self.size[0] as usize | (self.size[1] as usize) << 8 | (self.size[2] as usize) << 16
-    }
-
-    pub fn kind(&self) -> HeaderKind {
-        match self.kind {
+impl From<u8> for HeaderKind {
+    fn from(kind: u8) -> Self {
+        match kind {
             0x01 => HeaderKind::Compression,
             0x02 => HeaderKind::GuidDefined,
             0x03 => HeaderKind::Disposable,
@@ -57,8 +48,32 @@ impl Header {
     }
 }
 
+//TODO: Extended size
+#[repr(packed)]
+pub struct Header {
+    size: [u8; 3],
+    kind: u8,
+}
+
+impl Header {
+    pub fn size(&self) -> usize {
+        self.size[0] as usize | (self.size[1] as usize) << 8 | (self.size[2] as usize) << 16
+    }
+
+    pub fn kind(&self) -> HeaderKind {
+        HeaderKind::from(self.kind)
+    }
+}
+
 unsafe impl Plain for Header {}
 
+/// Sets the 24-bit size field of a raw section header
+pub fn set_size(section: &mut [u8], size: usize) {
+    section[0] = size as u8;
+    section[1] = (size >> 8) as u8;
+    section[2] = (size >> 16) as u8;
+}
+
 #[repr(packed)]
 pub struct GuidDefined {
     pub guid: Guid,
@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use super::{file, gbe, volume, RegionKind, Rom};
+
+impl<'a> Rom<'a> {
+    /// Checks that the regions lie within the image and that the volume,
+    /// file and GbE checksums are correct, returning a description of every
+    /// problem found
+    pub fn verify(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Err(err) = self.layout() {
+            problems.push(err.to_string());
+            return problems;
+        }
+
+        // Volumes are reported by their offset in the image
+        let base = match self.get_region_base_limit(RegionKind::Bios) {
+            Ok(Some((base, _))) => base,
+            _ => 0,
+        };
+        match self.bios() {
+            Ok(Some(bios)) => {
+                for volume in bios.volumes() {
+                    let start = volume.offset();
+                    let end = start + volume.header().header_length as usize;
+                    if !volume::checksum_valid(&bios.data()[start..end]) {
+                        problems.push(format!(
+                            "volume at {:#X} header checksum invalid",
+                            base + volume.offset()
+                        ));
+                    }
+                    for file in volume.files() {
+                        let start = file.offset();
+                        let end = start + file.header().size();
+                        let raw = match volume.data().get(start..end) {
+                            Some(raw) => raw,
+                            None => {
+                                problems.push(format!(
+                                    "file {} in volume at {:#X} out of bounds",
+                                    { file.header().guid },
+                                    base + volume.offset()
+                                ));
+                                continue;
+                            }
+                        };
+                        if !file::checksums_valid(raw) {
+                            problems.push(format!(
+                                "file {} in volume at {:#X} checksum invalid",
+                                { file.header().guid },
+                                base + volume.offset()
+                            ));
+                        }
+                    }
+                }
+            }
+            Ok(None) => (),
+            Err(err) => problems.push(err.to_string()),
+        }
+
+        match self.gbe() {
+            Ok(Some(gbe)) => {
+                for (i, bank) in gbe.banks().enumerate() {
+                    if gbe::Gbe::bank_valid(bank) && !gbe::Gbe::bank_checksum_valid(bank) {
+                        problems.push(format!("GbE bank {} checksum invalid", i));
+                    }
+                }
+            }
+            Ok(None) => (),
+            Err(err) => problems.push(err.to_string()),
+        }
+        problems
+    }
+}
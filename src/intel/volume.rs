@@ -2,6 +2,7 @@
 
 use bitflags::bitflags;
 use plain::Plain;
+use serde::{Deserialize, Serialize};
 use uefi::guid::Guid;
 
 bitflags! {
@@ -62,6 +63,56 @@ impl Header {
 
 unsafe impl Plain for Header {}
 
+/// Space of volumes taken by files and left free, in bytes. Pad files and
+/// the erased space after the last file are free, everything else is used.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Usage {
+    /// Size of the volume data following the header
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Largest run of free bytes, which bounds the size of a file that can
+    /// be inserted
+    pub largest_free: usize,
+}
+
+impl Usage {
+    /// Totals of the volumes, the largest free run being that of any volume
+    pub fn total<'a>(usages: impl IntoIterator<Item = &'a Usage>) -> Self {
+        usages
+            .into_iter()
+            .fold(Usage::default(), |total, usage| Usage {
+                size: total.size + usage.size,
+                used: total.used + usage.used,
+                free: total.free + usage.free,
+                largest_free: total.largest_free.max(usage.largest_free),
+            })
+    }
+}
+
+/// Recomputes the checksum of a raw volume header, which must be sliced to
+/// `header_length` bytes
+pub fn fix_checksum(header: &mut [u8]) {
+    header[0x32] = 0;
+    header[0x33] = 0;
+    let sum = header.chunks(2).fold(0u16, |sum, word| {
+        sum.wrapping_add(word[0] as u16 | (word[1] as u16) << 8)
+    });
+    let checksum = 0u16.wrapping_sub(sum);
+    header[0x32] = checksum as u8;
+    header[0x33] = (checksum >> 8) as u8;
+}
+
+/// True if the 16-bit sum of a raw volume header, sliced to `header_length`
+/// bytes, is zero
+pub fn checksum_valid(header: &[u8]) -> bool {
+    let sum = header.chunks(2).fold(0u16, |sum, word| {
+        sum.wrapping_add(word[0] as u16 | (*word.get(1).unwrap_or(&0) as u16) << 8)
+    });
+    sum == 0
+}
+
 #[repr(packed)]
 pub struct BlockEntry {
     pub num_blocks: u32,
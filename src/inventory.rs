@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: MIT
+
+//! Inventory of the PSP entries of an AMD image and the version of each, a
+//! bill of materials of the firmware the PSP loads
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::amd::{
+    self,
+    directory::{PspDirectoryEntry, PspKeyToken},
+    walk::RomVisitor,
+};
+use crate::identify::{psp_version, sha256};
+
+/// Entry of a PSP directory and what identifies its data, displayed as its
+/// version, its key ID after `key` or `-` if the data has neither
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct PspComponent {
+    /// Offset of the PSP directory holding the entry
+    pub directory: usize,
+    pub kind: u8,
+    pub sub_program: u8,
+    pub instance: u8,
+    pub description: String,
+    /// Version in the PSP binary header of the data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Key ID in hex of key entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// SHA-256 digest of the data, none if the entry holds a value or its
+    /// data is outside the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl fmt::Display for PspComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.version, &self.key_id) {
+            (Some(version), _) => write!(f, "{}", version),
+            (None, Some(key_id)) => write!(f, "key {}", key_id),
+            (None, None) => write!(f, "-"),
+        }
+    }
+}
+
+/// Every entry of every PSP directory of the image, in the order the
+/// directories are walked
+pub fn inventory(rom: &amd::Rom) -> Vec<PspComponent> {
+    let mut collector = Collector {
+        components: Vec::new(),
+    };
+    rom.walk(&mut collector);
+    collector.components
+}
+
+struct Collector {
+    components: Vec<PspComponent>,
+}
+
+impl RomVisitor for Collector {
+    fn visit_psp_entry(
+        &mut self,
+        directory: usize,
+        entry: &PspDirectoryEntry,
+        _offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        let key_id = data
+            .filter(|_| entry.is_key())
+            .and_then(PspKeyToken::new)
+            .map(|token| token.key_id);
+        self.components.push(PspComponent {
+            directory,
+            kind: entry.kind,
+            sub_program: entry.sub_program,
+            instance: entry.instance(),
+            description: String::from(entry.description()),
+            version: data.and_then(psp_version),
+            key_id,
+            sha256: data.map(sha256),
+        });
+    }
+}
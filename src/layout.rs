@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: MIT
+
+use alloc::{string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+/// A named range of bytes in a flash image
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Area {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl Area {
+    pub fn end(&self) -> usize {
+        self.offset + self.size
+    }
+}
+
+/// Non-overlapping areas of a flash image, sorted by offset
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Layout {
+    size: usize,
+    areas: Vec<Area>,
+}
+
+impl Layout {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            areas: Vec::new(),
+        }
+    }
+
+    /// Size of the image
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn areas(&self) -> &[Area] {
+        &self.areas
+    }
+
+    /// Adds an area, returning false if it is empty, out of bounds or
+    /// overlaps an area that was added before
+    pub fn insert(&mut self, name: String, offset: usize, size: usize) -> bool {
+        let end = match offset.checked_add(size) {
+            Some(end) if size > 0 && end <= self.size => end,
+            _ => return false,
+        };
+        let i = self.areas.partition_point(|area| area.offset < offset);
+        if i > 0 && self.areas[i - 1].end() > offset {
+            return false;
+        }
+        if i < self.areas.len() && self.areas[i].offset < end {
+            return false;
+        }
+        self.areas.insert(i, Area { name, offset, size });
+        true
+    }
+
+    /// Ranges of the image not covered by any area
+    pub fn gaps(&self) -> Vec<(usize, usize)> {
+        let mut gaps = Vec::new();
+        let mut offset = 0;
+        for area in self.areas.iter() {
+            if area.offset > offset {
+                gaps.push((offset, area.offset - offset));
+            }
+            offset = area.end();
+        }
+        if self.size > offset {
+            gaps.push((offset, self.size - offset));
+        }
+        gaps
+    }
+
+    /// Covers every gap with an area named "Padding", so that the areas
+    /// describe the whole image
+    pub fn fill_gaps(&mut self) {
+        for (offset, size) in self.gaps() {
+            self.insert(String::from("Padding"), offset, size);
+        }
+    }
+}
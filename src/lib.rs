@@ -1,9 +1,56 @@
 // SPDX-License-Identifier: MIT
 
-#![no_std]
+// The std prelude is kept with std, the schemars derives relying on it
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "alloc"))]
+compile_error!("romulan requires the alloc feature");
 
 #[macro_use]
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 pub mod amd;
+#[doc(hidden)]
+pub mod analysis;
+#[doc(hidden)]
+pub mod audit;
+pub mod cbfs;
+pub(crate) mod compare;
+#[doc(hidden)]
+pub mod coverage;
+#[doc(hidden)]
+pub mod description;
+#[doc(hidden)]
+pub mod entropy;
+pub mod error;
+pub mod extract;
+pub mod fmap;
+pub mod handler;
+pub mod identify;
+pub mod image;
 pub mod intel;
+#[doc(hidden)]
+pub mod inventory;
+pub mod layout;
+pub(crate) mod lz4;
+pub(crate) mod lzma;
+pub mod metainfo;
+#[doc(hidden)]
+pub mod patch;
+pub mod prelude;
+pub mod report;
+pub mod source;
+#[doc(hidden)]
+pub mod summary;
+#[doc(hidden)]
+pub mod timeline;
+#[doc(hidden)]
+pub mod tree;
+pub mod wrapper;
+#[cfg(any(test, feature = "std"))]
+#[doc(hidden)]
+pub mod testutil;
+
+pub use error::Error;
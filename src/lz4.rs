@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: MIT
+
+//! Decoder of the LZ4 frames firmware is compressed with, such as the CBFS
+//! files of coreboot images. A frame starts with a magic number and a
+//! descriptor of its options, followed by blocks of LZ4 sequences or stored
+//! bytes, each prefixed by its size, up to a block of size zero.
+//!
+//! Checksums are skipped, not verified. Blocks may refer to the data of the
+//! blocks before them, the whole output serving as the dictionary.
+
+use alloc::vec::Vec;
+
+use crate::Error;
+
+/// Magic number of a frame, in little endian
+pub const MAGIC: u32 = 0x184D_2204;
+
+/// Bit of a block size telling that the block is stored, not compressed
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+
+/// Length of the match of a sequence at least
+const MIN_MATCH: usize = 4;
+
+/// Output reserved up front at most, against sizes of corrupt frames
+const MAX_RESERVE: usize = 0x100_0000;
+
+/// Options of a frame, from its frame descriptor
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Descriptor {
+    /// Whether each block is followed by a checksum
+    pub block_checksum: bool,
+    /// Whether the last block is followed by a checksum of the content
+    pub content_checksum: bool,
+    /// Size of the decompressed data, if the frame tells it
+    pub content_size: Option<u64>,
+    /// Size of the descriptor, from the magic number to the header checksum
+    pub size: usize,
+}
+
+impl Descriptor {
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let byte = |i: usize| {
+            data.get(i).copied().ok_or(Error::Truncated {
+                need: i + 1,
+                have: data.len(),
+            })
+        };
+        let magic = u32::from_le_bytes([byte(0)?, byte(1)?, byte(2)?, byte(3)?]);
+        if magic != MAGIC {
+            return Err(Error::SignatureMismatch {
+                structure: "LZ4 frame",
+                offset: 0,
+            });
+        }
+        let flags = byte(4)?;
+        if flags >> 6 != 0b01 {
+            return Err(Error::UnsupportedVersion {
+                structure: "LZ4 frame",
+                version: (flags >> 6) as u32,
+            });
+        }
+        let mut size = 6;
+        let content_size = if flags & 1 << 3 != 0 {
+            let mut bytes = [0; 8];
+            for (i, byte_) in bytes.iter_mut().enumerate() {
+                *byte_ = byte(size + i)?;
+            }
+            size += 8;
+            Some(u64::from_le_bytes(bytes))
+        } else {
+            None
+        };
+        if flags & 1 != 0 {
+            // Dictionary ID
+            size += 4;
+        }
+        // Header checksum
+        byte(size)?;
+        Ok(Descriptor {
+            block_checksum: flags & 1 << 4 != 0,
+            content_checksum: flags & 1 << 2 != 0,
+            content_size,
+            size: size + 1,
+        })
+    }
+}
+
+/// Decompresses a frame
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let descriptor = Descriptor::new(data)?;
+    let mut output = Vec::with_capacity(
+        descriptor
+            .content_size
+            .map_or(0, |size| (size as usize).min(MAX_RESERVE)),
+    );
+    let mut i = descriptor.size;
+    loop {
+        let size = data.get(i..i + 4).ok_or(Error::Truncated {
+            need: i + 4,
+            have: data.len(),
+        })?;
+        let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+        i += 4;
+        if size == 0 {
+            break;
+        }
+        let len = (size & !BLOCK_UNCOMPRESSED) as usize;
+        let block = data.get(i..i + len).ok_or(Error::Truncated {
+            need: i + len,
+            have: data.len(),
+        })?;
+        if size & BLOCK_UNCOMPRESSED != 0 {
+            output.extend_from_slice(block);
+        } else {
+            decompress_block(block, &mut output)?;
+        }
+        i += len;
+        if descriptor.block_checksum {
+            i += 4;
+        }
+    }
+    if let Some(size) = descriptor.content_size {
+        if output.len() as u64 != size {
+            return Err(Error::Invalid(format!(
+                "LZ4 content of {:#X} bytes instead of {:#X}",
+                output.len(),
+                size
+            )));
+        }
+    }
+    Ok(output)
+}
+
+/// Decompresses the sequences of a block, appending to `output`, whose
+/// bytes matches may refer to
+pub fn decompress_block(block: &[u8], output: &mut Vec<u8>) -> Result<(), Error> {
+    let corrupt = |offset: usize| Error::Corrupt {
+        structure: "LZ4 block",
+        offset,
+    };
+    let mut i = 0;
+    while i < block.len() {
+        let token = block[i];
+        i += 1;
+
+        let literals = length(block, &mut i, (token >> 4) as usize).ok_or_else(|| corrupt(i))?;
+        let end = i.checked_add(literals).ok_or_else(|| corrupt(i))?;
+        output.extend_from_slice(block.get(i..end).ok_or_else(|| corrupt(i))?);
+        i = end;
+        // The last sequence has literals only
+        if i == block.len() {
+            break;
+        }
+
+        let offset = block.get(i..i + 2).ok_or_else(|| corrupt(i))?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        i += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(corrupt(i - 2));
+        }
+        let len =
+            length(block, &mut i, (token & 0xF) as usize).ok_or_else(|| corrupt(i))? + MIN_MATCH;
+        // Matches may overlap the bytes they produce
+        let start = output.len() - offset;
+        for j in 0..len {
+            let byte = output[start + j];
+            output.push(byte);
+        }
+    }
+    Ok(())
+}
+
+/// Length of the literals or the match of a sequence, continued by the
+/// bytes after the token while they are 255 if the token has 15
+fn length(block: &[u8], i: &mut usize, len: usize) -> Option<usize> {
+    let mut len = len;
+    if len == 0xF {
+        loop {
+            let byte = *block.get(*i)?;
+            *i += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 0xFF {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `lz4 -9 --content-size` of `plaintext()`, with a content checksum
+    const FRAME: [u8; 53] = [
+        0x04, 0x22, 0x4D, 0x18, 0x6C, 0x40, 0x4D, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFA,
+        0x1A, 0x00, 0x00, 0x00, 0x3F, 0x61, 0x62, 0x63, 0x03, 0x00, 0x26, 0xF0, 0x02, 0x72, 0x6F,
+        0x6D, 0x75, 0x6C, 0x61, 0x6E, 0x20, 0x72, 0x6F, 0x6D, 0x75, 0x6C, 0x61, 0x6E, 0x21, 0x0A,
+        0x00, 0x00, 0x00, 0x00, 0xBF, 0x99, 0x7D, 0xD5,
+    ];
+
+    fn plaintext() -> Vec<u8> {
+        let mut plaintext = b"abc".repeat(20);
+        plaintext.extend_from_slice(b"romulan romulan!\n");
+        plaintext
+    }
+
+    #[test]
+    fn decompress_extends_overlapping_matches() {
+        let descriptor = Descriptor::new(&FRAME).unwrap();
+        assert_eq!(descriptor.content_size, Some(77));
+        assert!(descriptor.content_checksum && !descriptor.block_checksum);
+        assert_eq!(descriptor.size, 15);
+        // The block repeats "abc" by a match of 57 bytes at offset 3
+        assert_eq!(decompress(&FRAME).unwrap(), plaintext());
+
+        let mut output = Vec::new();
+        decompress_block(
+            &[0x35, b'a', b'b', b'c', 0x03, 0x00, 0x10, b'!'],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, b"abcabcabcabc!");
+    }
+
+    #[test]
+    fn decompress_copies_stored_blocks() {
+        let mut frame = vec![0x04, 0x22, 0x4D, 0x18, 0x60, 0x40, 0x82];
+        frame.extend_from_slice(&(3 | BLOCK_UNCOMPRESSED).to_le_bytes());
+        frame.extend_from_slice(b"abc");
+        // A compressed block matching the stored one
+        frame.extend_from_slice(&3u32.to_le_bytes());
+        frame.extend_from_slice(&[0x00, 0x03, 0x00]);
+        frame.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(decompress(&frame).unwrap(), b"abcabca");
+    }
+
+    #[test]
+    fn decompress_rejects_invalid_blocks() {
+        // Match before the start of the output
+        let mut output = b"ab".to_vec();
+        assert!(decompress_block(&[0x10, b'c', 0x04, 0x00], &mut output).is_err());
+        assert!(decompress_block(&[0x00, 0x00, 0x00], &mut Vec::new()).is_err());
+        // Literals past the end of the block
+        assert!(decompress_block(&[0x50, b'a'], &mut Vec::new()).is_err());
+        assert!(decompress_block(&[0xF0, 0xFF], &mut Vec::new()).is_err());
+
+        for len in 0..FRAME.len() - 4 {
+            assert!(decompress(&FRAME[..len]).is_err());
+        }
+        let mut frame = FRAME;
+        frame[7] = 0x4E;
+        assert!(matches!(decompress(&frame), Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,512 @@
+// SPDX-License-Identifier: MIT
+
+//! Decoder of the LZMA streams firmware is compressed with, such as the data
+//! of UEFI GUID defined sections of `SECTION_LZMA_COMPRESS_GUID`. A stream
+//! starts with the 13 byte header of the `.lzma` format: the properties
+//! byte, the dictionary size and the uncompressed size, all ones if the
+//! stream ends with an end marker instead.
+//!
+//! The decoder follows the LZMA specification of the LZMA SDK, the whole
+//! output serving as the dictionary.
+
+use alloc::vec::Vec;
+
+use crate::Error;
+
+/// Size of the `.lzma` header
+pub const HEADER_SIZE: usize = 13;
+
+/// Output reserved up front at most, against sizes of corrupt headers
+const MAX_RESERVE: usize = 0x100_0000;
+
+const NUM_BIT_MODEL_TOTAL_BITS: u32 = 11;
+const BIT_MODEL_TOTAL: u16 = 1 << NUM_BIT_MODEL_TOTAL_BITS;
+const NUM_MOVE_BITS: u32 = 5;
+const PROB_INIT: u16 = BIT_MODEL_TOTAL / 2;
+const TOP_VALUE: u32 = 1 << 24;
+
+const NUM_STATES: usize = 12;
+const NUM_POS_BITS_MAX: usize = 4;
+const NUM_LEN_TO_POS_STATES: usize = 4;
+const NUM_ALIGN_BITS: usize = 4;
+const START_POS_MODEL_INDEX: u32 = 4;
+const END_POS_MODEL_INDEX: u32 = 14;
+const NUM_FULL_DISTANCES: usize = 1 << (END_POS_MODEL_INDEX >> 1);
+const MATCH_MIN_LEN: usize = 2;
+
+/// Properties of a stream, from its header
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Properties {
+    /// Literal context bits
+    pub lc: u8,
+    /// Literal position bits
+    pub lp: u8,
+    /// Position bits
+    pub pb: u8,
+    pub dictionary_size: u32,
+    /// Size of the decompressed data, none if the stream has an end marker
+    pub size: Option<u64>,
+}
+
+impl Properties {
+    pub fn new(header: &[u8]) -> Result<Self, Error> {
+        if header.len() < HEADER_SIZE {
+            return Err(Error::Truncated {
+                need: HEADER_SIZE,
+                have: header.len(),
+            });
+        }
+        let mut d = header[0];
+        if d >= 9 * 5 * 5 {
+            return Err(Error::Corrupt {
+                structure: "LZMA",
+                offset: 0,
+            });
+        }
+        let lc = d % 9;
+        d /= 9;
+        let lp = d % 5;
+        let pb = d / 5;
+        let mut dictionary_size = [0; 4];
+        dictionary_size.copy_from_slice(&header[1..5]);
+        let mut size = [0; 8];
+        size.copy_from_slice(&header[5..13]);
+        let size = u64::from_le_bytes(size);
+        Ok(Properties {
+            lc,
+            lp,
+            pb,
+            dictionary_size: u32::from_le_bytes(dictionary_size).max(1 << 12),
+            size: if size == u64::MAX { None } else { Some(size) },
+        })
+    }
+}
+
+/// Decompresses a stream with a `.lzma` header
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let properties = Properties::new(data)?;
+    Decoder::new(properties, &data[HEADER_SIZE..])?.decode()
+}
+
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    i: usize,
+    range: u32,
+    code: u32,
+    /// Whether more bytes were needed than the data has
+    overrun: bool,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, Error> {
+        if data.len() < 5 {
+            return Err(Error::Truncated {
+                need: HEADER_SIZE + 5,
+                have: HEADER_SIZE + data.len(),
+            });
+        }
+        if data[0] != 0 {
+            return Err(Error::Corrupt {
+                structure: "LZMA",
+                offset: HEADER_SIZE,
+            });
+        }
+        let mut decoder = RangeDecoder {
+            data,
+            i: 1,
+            range: 0xFFFF_FFFF,
+            code: 0,
+            overrun: false,
+        };
+        for _ in 0..4 {
+            decoder.code = decoder.code << 8 | decoder.next() as u32;
+        }
+        if decoder.code == decoder.range {
+            return Err(Error::Corrupt {
+                structure: "LZMA",
+                offset: HEADER_SIZE,
+            });
+        }
+        Ok(decoder)
+    }
+
+    /// Next byte of the data, zero past its end
+    fn next(&mut self) -> u8 {
+        match self.data.get(self.i) {
+            Some(byte) => {
+                self.i += 1;
+                *byte
+            }
+            None => {
+                self.overrun = true;
+                0
+            }
+        }
+    }
+
+    fn normalize(&mut self) {
+        if self.range < TOP_VALUE {
+            self.range <<= 8;
+            self.code = self.code << 8 | self.next() as u32;
+        }
+    }
+
+    fn direct_bits(&mut self, count: u32) -> u32 {
+        let mut result = 0u32;
+        for _ in 0..count {
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let t = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & t);
+            self.normalize();
+            result = (result << 1).wrapping_add(t.wrapping_add(1));
+        }
+        result
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> u32 {
+        let bound = (self.range >> NUM_BIT_MODEL_TOTAL_BITS) * *prob as u32;
+        let bit = if self.code < bound {
+            *prob += (BIT_MODEL_TOTAL - *prob) >> NUM_MOVE_BITS;
+            self.range = bound;
+            0
+        } else {
+            *prob -= *prob >> NUM_MOVE_BITS;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        self.normalize();
+        bit
+    }
+
+    /// Decodes a symbol of `count` bits with the probabilities of a bit tree
+    fn tree(&mut self, probs: &mut [u16], count: u32) -> u32 {
+        let mut m = 1;
+        for _ in 0..count {
+            m = (m << 1) + self.bit(&mut probs[m as usize]);
+        }
+        m - (1 << count)
+    }
+
+    /// Decodes a symbol of `count` bits, least significant bit first
+    fn reverse_tree(&mut self, probs: &mut [u16], count: u32) -> u32 {
+        let mut m = 1;
+        let mut symbol = 0;
+        for i in 0..count {
+            let bit = self.bit(&mut probs[m as usize]);
+            m = (m << 1) + bit;
+            symbol |= bit << i;
+        }
+        symbol
+    }
+}
+
+/// Probabilities of match lengths
+struct LengthDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+    mid: [[u16; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+    high: [u16; 1 << 8],
+}
+
+impl LengthDecoder {
+    fn new() -> Self {
+        LengthDecoder {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+            mid: [[PROB_INIT; 1 << 3]; 1 << NUM_POS_BITS_MAX],
+            high: [PROB_INIT; 1 << 8],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> usize {
+        if rc.bit(&mut self.choice) == 0 {
+            return rc.tree(&mut self.low[pos_state], 3) as usize;
+        }
+        if rc.bit(&mut self.choice2) == 0 {
+            return 8 + rc.tree(&mut self.mid[pos_state], 3) as usize;
+        }
+        16 + rc.tree(&mut self.high, 8) as usize
+    }
+}
+
+struct Decoder<'a> {
+    properties: Properties,
+    rc: RangeDecoder<'a>,
+    literals: Vec<u16>,
+    pos_slot: [[u16; 1 << 6]; NUM_LEN_TO_POS_STATES],
+    pos: [u16; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+    align: [u16; 1 << NUM_ALIGN_BITS],
+    is_match: [u16; NUM_STATES << NUM_POS_BITS_MAX],
+    is_rep: [u16; NUM_STATES],
+    is_rep_g0: [u16; NUM_STATES],
+    is_rep_g1: [u16; NUM_STATES],
+    is_rep_g2: [u16; NUM_STATES],
+    is_rep0_long: [u16; NUM_STATES << NUM_POS_BITS_MAX],
+    length: LengthDecoder,
+    rep_length: LengthDecoder,
+    output: Vec<u8>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(properties: Properties, data: &'a [u8]) -> Result<Self, Error> {
+        let reserve = properties
+            .size
+            .map_or(0, |size| (size as usize).min(MAX_RESERVE));
+        Ok(Decoder {
+            properties,
+            rc: RangeDecoder::new(data)?,
+            literals: vec![PROB_INIT; 0x300 << (properties.lc + properties.lp)],
+            pos_slot: [[PROB_INIT; 1 << 6]; NUM_LEN_TO_POS_STATES],
+            pos: [PROB_INIT; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX as usize],
+            align: [PROB_INIT; 1 << NUM_ALIGN_BITS],
+            is_match: [PROB_INIT; NUM_STATES << NUM_POS_BITS_MAX],
+            is_rep: [PROB_INIT; NUM_STATES],
+            is_rep_g0: [PROB_INIT; NUM_STATES],
+            is_rep_g1: [PROB_INIT; NUM_STATES],
+            is_rep_g2: [PROB_INIT; NUM_STATES],
+            is_rep0_long: [PROB_INIT; NUM_STATES << NUM_POS_BITS_MAX],
+            length: LengthDecoder::new(),
+            rep_length: LengthDecoder::new(),
+            output: Vec::with_capacity(reserve),
+        })
+    }
+
+    /// Byte `distance` bytes back from the end of the output, 1 being the
+    /// last byte
+    fn byte(&self, distance: usize) -> u8 {
+        self.output[self.output.len() - distance]
+    }
+
+    fn literal(&mut self, state: usize, rep0: usize) {
+        let lc = self.properties.lc as u32;
+        let lp = self.properties.lp as u32;
+        let previous = match self.output.last() {
+            Some(byte) => *byte as usize,
+            None => 0,
+        };
+        let lit_state = ((self.output.len() & ((1 << lp) - 1)) << lc) + (previous >> (8 - lc));
+        let probs = &mut self.literals[0x300 * lit_state..0x300 * (lit_state + 1)];
+
+        let mut symbol = 1;
+        if state >= 7 {
+            let mut match_byte = self.output[self.output.len() - rep0 - 1] as usize;
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = self.rc.bit(&mut probs[((1 + match_bit) << 8) + symbol]) as usize;
+                symbol = symbol << 1 | bit;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+        while symbol < 0x100 {
+            symbol = symbol << 1 | self.rc.bit(&mut probs[symbol]) as usize;
+        }
+        self.output.push((symbol - 0x100) as u8);
+    }
+
+    fn distance(&mut self, length: usize) -> u32 {
+        let len_state = length.min(NUM_LEN_TO_POS_STATES - 1);
+        let pos_slot = self.rc.tree(&mut self.pos_slot[len_state], 6);
+        if pos_slot < START_POS_MODEL_INDEX {
+            return pos_slot;
+        }
+        let direct_bits = (pos_slot >> 1) - 1;
+        let mut distance = (2 | (pos_slot & 1)) << direct_bits;
+        if pos_slot < END_POS_MODEL_INDEX {
+            let base = (distance - pos_slot) as usize;
+            distance += self.rc.reverse_tree(&mut self.pos[base..], direct_bits);
+        } else {
+            distance += self.rc.direct_bits(direct_bits - NUM_ALIGN_BITS as u32) << NUM_ALIGN_BITS;
+            distance += self.rc.reverse_tree(&mut self.align, NUM_ALIGN_BITS as u32);
+        }
+        distance
+    }
+
+    fn decode(mut self) -> Result<Vec<u8>, Error> {
+        let corrupt = |rc: &RangeDecoder| {
+            Err(Error::Corrupt {
+                structure: "LZMA",
+                offset: HEADER_SIZE + rc.i,
+            })
+        };
+        let truncated = |rc: &RangeDecoder| {
+            Err(Error::Truncated {
+                need: HEADER_SIZE + rc.i + 1,
+                have: HEADER_SIZE + rc.data.len(),
+            })
+        };
+        let size = self.properties.size;
+        let remaining =
+            |output: &Vec<u8>| size.map(|size| size.saturating_sub(output.len() as u64));
+        let pb_mask = (1 << self.properties.pb) - 1;
+        let mut state = 0;
+        let mut reps = [0usize; 4];
+
+        loop {
+            if self.rc.overrun {
+                return truncated(&self.rc);
+            }
+            if remaining(&self.output) == Some(0) {
+                return Ok(self.output);
+            }
+
+            let pos_state = self.output.len() & pb_mask;
+            if self
+                .rc
+                .bit(&mut self.is_match[(state << NUM_POS_BITS_MAX) + pos_state])
+                == 0
+            {
+                self.literal(state, reps[0]);
+                state = match state {
+                    0..=3 => 0,
+                    4..=9 => state - 3,
+                    _ => state - 6,
+                };
+                continue;
+            }
+
+            let length = if self.rc.bit(&mut self.is_rep[state]) != 0 {
+                if self.output.is_empty() {
+                    return corrupt(&self.rc);
+                }
+                if self.rc.bit(&mut self.is_rep_g0[state]) == 0 {
+                    let long = &mut self.is_rep0_long[(state << NUM_POS_BITS_MAX) + pos_state];
+                    if self.rc.bit(long) == 0 {
+                        state = if state < 7 { 9 } else { 11 };
+                        let byte = self.byte(reps[0] + 1);
+                        self.output.push(byte);
+                        continue;
+                    }
+                } else {
+                    let distance = if self.rc.bit(&mut self.is_rep_g1[state]) == 0 {
+                        reps[1]
+                    } else {
+                        let distance = if self.rc.bit(&mut self.is_rep_g2[state]) == 0 {
+                            reps[2]
+                        } else {
+                            let distance = reps[3];
+                            reps[3] = reps[2];
+                            distance
+                        };
+                        reps[2] = reps[1];
+                        distance
+                    };
+                    reps[1] = reps[0];
+                    reps[0] = distance;
+                }
+                state = if state < 7 { 8 } else { 11 };
+                self.rep_length.decode(&mut self.rc, pos_state)
+            } else {
+                reps[3] = reps[2];
+                reps[2] = reps[1];
+                reps[1] = reps[0];
+                let length = self.length.decode(&mut self.rc, pos_state);
+                state = if state < 7 { 7 } else { 10 };
+                let distance = self.distance(length);
+                if distance == 0xFFFF_FFFF {
+                    // End marker
+                    if self.rc.overrun {
+                        return truncated(&self.rc);
+                    }
+                    return match remaining(&self.output) {
+                        None | Some(0) if self.rc.code == 0 => Ok(self.output),
+                        _ => corrupt(&self.rc),
+                    };
+                }
+                reps[0] = distance as usize;
+                if distance >= self.properties.dictionary_size || reps[0] >= self.output.len() {
+                    return corrupt(&self.rc);
+                }
+                length
+            };
+
+            let mut length = length + MATCH_MIN_LEN;
+            let mut truncated = false;
+            if let Some(remaining) = remaining(&self.output) {
+                if remaining < length as u64 {
+                    length = remaining as usize;
+                    truncated = true;
+                }
+            }
+            let start = self.output.len() - reps[0] - 1;
+            for i in 0..length {
+                let byte = self.output[start + i];
+                self.output.push(byte);
+            }
+            if truncated {
+                return corrupt(&self.rc);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `lzma --format=lzma` of `plaintext()`, ending with an end marker
+    const STREAM: [u8; 80] = [
+        0x5D, 0x00, 0x00, 0x80, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x2A,
+        0x1A, 0x08, 0xA2, 0x03, 0x25, 0x66, 0xF1, 0x4B, 0x78, 0xC5, 0xA2, 0x05, 0xFF, 0x2E, 0xE6,
+        0xD9, 0xD2, 0x20, 0x1A, 0xAD, 0x34, 0xF8, 0xE2, 0x1D, 0xE8, 0x41, 0x36, 0xFA, 0xDC, 0x06,
+        0x69, 0xBB, 0x3C, 0xE4, 0x10, 0x34, 0x27, 0x09, 0xEB, 0xB3, 0x66, 0xE3, 0xED, 0x37, 0x5A,
+        0xE8, 0x14, 0x93, 0x32, 0x33, 0x26, 0x7E, 0x88, 0x97, 0x65, 0x40, 0x0A, 0x78, 0xA0, 0x08,
+        0xBF, 0xFE, 0xD1, 0xF4, 0x00,
+    ];
+
+    fn plaintext() -> Vec<u8> {
+        let mut plaintext = b"The quick brown fox jumps over the lazy dog. ".repeat(3);
+        plaintext.extend_from_slice(b"romulan romulan romulan!\n");
+        plaintext
+    }
+
+    #[test]
+    fn decompress_stops_at_the_size_of_the_header() {
+        let mut stream = STREAM;
+        let size = plaintext().len() as u64;
+        stream[5..HEADER_SIZE].copy_from_slice(&size.to_le_bytes());
+        let properties = Properties::new(&stream).unwrap();
+        assert_eq!((properties.lc, properties.lp, properties.pb), (3, 0, 2));
+        assert_eq!(properties.dictionary_size, 0x80_0000);
+        assert_eq!(properties.size, Some(size));
+        assert_eq!(decompress(&stream).unwrap(), plaintext());
+    }
+
+    #[test]
+    fn decompress_stops_at_the_end_marker() {
+        assert_eq!(Properties::new(&STREAM).unwrap().size, None);
+        assert_eq!(decompress(&STREAM).unwrap(), plaintext());
+    }
+
+    #[test]
+    fn decompress_rejects_truncated_and_corrupt_streams() {
+        assert!(matches!(
+            decompress(&STREAM[..STREAM.len() - 1]),
+            Err(Error::Truncated { .. })
+        ));
+        assert!(matches!(
+            decompress(&STREAM[..HEADER_SIZE]),
+            Err(Error::Truncated { .. })
+        ));
+        let mut invalid = STREAM;
+        invalid[0] = 225;
+        assert!(decompress(&invalid).is_err());
+
+        for len in 0..STREAM.len() {
+            assert!(decompress(&STREAM[..len]).is_err());
+        }
+        // A corrupt stream is an error, or decodes to other data, but never
+        // panics
+        for bit in HEADER_SIZE * 8..STREAM.len() * 8 {
+            let mut corrupt = STREAM;
+            corrupt[bit / 8] ^= 1 << (bit % 8);
+            assert_ne!(decompress(&corrupt).ok(), Some(plaintext()));
+        }
+    }
+}
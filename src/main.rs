@@ -1,189 +1,1865 @@
 // SPDX-License-Identifier: MIT
 
 use romulan::amd;
+use romulan::amd::directory::{self, soft_fuse_bit_name, Directory};
+use romulan::analysis::Analysis;
+use romulan::audit;
+use romulan::cbfs;
+use romulan::coverage::CoverageMap;
+use romulan::description::Description;
+use romulan::fmap;
+use romulan::handler::Target;
+use romulan::identify::{self, Database};
 use romulan::intel;
-use romulan::intel::{section, volume};
-use romulan::intel::{BiosFile, BiosSection, BiosSections, BiosVolume, BiosVolumes};
+use romulan::intel::{nvram, section};
+use romulan::inventory;
+use romulan::layout::Area;
+use romulan::metainfo;
+use romulan::patch;
+use romulan::report;
+use romulan::summary::Summary;
+use romulan::testutil::{self, AmdFixture};
+use romulan::timeline::{self, Timeline};
+use romulan::tree;
+use romulan::wrapper;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::rand_core::OsRng;
+use rsa::RsaPrivateKey;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::path::Path;
 use std::io::{Read, Write};
-use std::process::{Command, Stdio};
-use std::{env, fs, io, mem, process, thread};
-use uefi::guid::SECTION_LZMA_COMPRESS_GUID;
-
-fn dump_lzma(compressed_data: &[u8], padding: &str) {
-    // For some reason, xz2 does not work with this data
-    let mut child = Command::new("xz")
-        .arg("--decompress")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap();
-
-    let data = {
-        let mut stdout = child.stdout.take().unwrap();
-        let read_thread = thread::spawn(move || -> io::Result<Vec<u8>> {
-            let mut data = Vec::<u8>::new();
-            stdout.read_to_end(&mut data)?;
-            Ok(data)
-        });
+use std::{env, fs, io, mem, process};
+use uefi::guid::Guid;
+
+mod batch;
+mod flash;
+mod split;
+mod verify;
+
+/// Prints a section, `data` being the data its offset is relative to
+fn read(path: impl AsRef<Path>) -> Result<Vec<u8>, String> {
+    let path = path.as_ref();
+    let mut data = Vec::new();
+    fs::File::open(path)
+        .map_err(|err| format!("failed to open {}: {}", path.display(), err))?
+        .read_to_end(&mut data)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    Ok(data)
+}
+
+/// Image only read, mapped instead of read if built with the mmap feature
+#[cfg(feature = "mmap")]
+fn map(path: impl AsRef<Path>) -> Result<memmap2::Mmap, String> {
+    let path = path.as_ref();
+    let file = fs::File::open(path)
+        .map_err(|err| format!("failed to open {}: {}", path.display(), err))?;
+    // The image may change under the mapping, the parsers do not rely on
+    // data read twice being the same
+    unsafe { memmap2::Mmap::map(&file) }
+        .map_err(|err| format!("failed to map {}: {}", path.display(), err))
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map(path: impl AsRef<Path>) -> Result<Vec<u8>, String> {
+    read(path)
+}
 
-        {
-            let mut stdin = child.stdin.take().unwrap();
-            let _write_result = stdin.write_all(compressed_data);
+/// Adds every file below `dir` to the database, named by
+/// `identify::reference_identity` after the directory holding it. Hidden
+/// files and directories, such as `.git`, are skipped.
+fn add_reference(database: &mut Database, dir: &Path) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("failed to read {}: {}", dir.display(), err))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("failed to read {}: {}", dir.display(), err))?;
+        paths.push(entry.path());
+    }
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            add_reference(database, &path)?;
+            continue;
         }
+        let data =
+            fs::read(&path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let program = dir.file_name().map(|program| program.to_string_lossy());
+        database.add(
+            &data,
+            identify::reference_identity(program.as_deref(), &name, &data),
+        );
+    }
+    Ok(())
+}
 
-        read_thread.join().unwrap().unwrap()
+/// Serialization of the analysis written to stdout
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+/// Writes a value to stdout in a serialized format
+fn emit<T: serde::Serialize>(value: &T, format: Format) -> Result<(), String> {
+    let bytes = match format {
+        Format::Text => unreachable!("text is printed by the caller"),
+        Format::Json => {
+            let mut text = serde_json::to_string_pretty(value)
+                .map_err(|err| format!("failed to serialize: {}", err))?;
+            text.push('\n');
+            text.into_bytes()
+        }
+        #[cfg(feature = "cbor")]
+        Format::Cbor => {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(value, &mut bytes)
+                .map_err(|err| format!("failed to serialize: {}", err))?;
+            bytes
+        }
+        // Maps with field names, like the JSON output, instead of arrays
+        #[cfg(feature = "msgpack")]
+        Format::MessagePack => {
+            rmp_serde::to_vec_named(value).map_err(|err| format!("failed to serialize: {}", err))?
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err(String::from("output format not enabled in this build")),
     };
+    io::stdout()
+        .write_all(&bytes)
+        .map_err(|err| format!("failed to write output: {}", err))
+}
+
+/// Prints the JSON Schema of the output named `name`: `report`, the analysis
+/// of --json, or `patch`, the changes between two images as `patch::diff`
+/// returns them
+#[cfg(feature = "schema")]
+fn print_schema(name: &str) -> Result<(), String> {
+    let schema = match name {
+        "report" => schemars::schema_for!(report::Report),
+        "patch" => schemars::schema_for!(Vec<patch::Patch>),
+        _ => {
+            return Err(format!(
+                "unknown output '{}', expected report or patch",
+                name
+            ))
+        }
+    };
+    emit(&schema, Format::Json)
+}
 
-    let status = child.wait().unwrap();
-    if status.success() {
-        println!("{}Decompressed: {} K", padding, data.len() / 1024);
+#[cfg(not(feature = "schema"))]
+fn print_schema(_name: &str) -> Result<(), String> {
+    Err(String::from("JSON Schema output not enabled in this build"))
+}
 
-        for section in BiosSections::new(&data) {
-            dump_section(&section, &format!("{}    ", padding));
+/// Prints the entropy of windows of `window` bytes every half window,
+/// serialized or as one line per span of windows of the same class. High
+/// entropy spans outside of every area of the layout are marked.
+fn print_entropy(path: &str, window: usize, format: Format) -> Result<(), String> {
+    let data = map(path)?;
+    let mut analysis = Analysis::new(&data, window, (window / 2).max(1));
+    if let Ok(layout) = split::layout(&data) {
+        analysis.locate(&layout);
+    }
+    if format != Format::Text {
+        return emit(&analysis, format);
+    }
+    for span in analysis.spans.iter() {
+        print!(
+            "{:08X} {:08X} {:<6} {:.3}",
+            span.offset, span.size, span.class, span.entropy
+        );
+        if span.unreferenced() {
+            println!(" unreferenced");
+        } else {
+            println!();
         }
-    } else {
-        println!("{}Error: {}", padding, status);
     }
+    Ok(())
 }
 
-fn dump_guid_defined(section_data: &[u8], padding: &str) {
-    let header = plain::from_bytes::<section::GuidDefined>(section_data).unwrap();
-    let data_offset = header.data_offset;
-    let data = &section_data[(data_offset as usize)..];
-    let guid = header.guid;
-    let len = data.len() / 1024;
-    println!("{}  {}: {} K", padding, guid, len);
+/// Prints the firmware components of the image and their versions, as
+/// AppStream metainfo or serialized
+fn print_metainfo(path: &str, format: Format, database: &Database) -> Result<(), String> {
+    let data = map(path)?;
+    let components = metainfo::components(&report::Report::unwrapped(&data, database)?);
+    if format != Format::Text {
+        return emit(&components, format);
+    }
+    print!("{}", metainfo::metainfo(&components));
+    Ok(())
+}
 
-    #[allow(clippy::single_match)]
-    match guid {
-        SECTION_LZMA_COMPRESS_GUID => {
-            let compressed_data = &section_data[mem::size_of::<section::GuidDefined>()..];
-            dump_lzma(compressed_data, &format!("{}    ", padding));
+/// Prints the holes no structure of the image claims, serialized or as one
+/// line per hole with its fill byte, or its entropy and first bytes
+fn print_coverage(path: &str, format: Format) -> Result<(), String> {
+    let data = map(path)?;
+    let data = wrapper::unwrap(&data)?.1;
+    let report = CoverageMap::of(data)
+        .map_err(|err| format!("{}", err))?
+        .report(data);
+    if format != Format::Text {
+        return emit(&report, format);
+    }
+    println!(
+        "Covered {:#X} of {:#X} bytes, {} holes",
+        report.covered,
+        report.size,
+        report.holes.len()
+    );
+    for hole in report.holes.iter() {
+        print!("{:08X} {:08X}", hole.offset, hole.size);
+        match hole.fill {
+            Some(fill) => println!(" fill {:02X}", fill),
+            None => {
+                let head = hole.head.iter().map(|byte| format!("{:02X}", byte));
+                println!(
+                    " entropy {:.3}: {}",
+                    hole.entropy,
+                    head.collect::<Vec<_>>().join(" ")
+                );
+            }
         }
-        _ => (),
     }
+    Ok(())
 }
 
-fn dump_section(section: &BiosSection, padding: &str) {
-    let header = section.header();
-    let kind = header.kind();
-    let data = section.data();
-    let len = data.len() / 1024;
-    println!("{}{:?}:  {} K", padding, kind, len);
+/// Prints the vendor, platform, size and component versions of the image,
+/// serialized or on one line after its path
+fn print_summary(path: &str, format: Format) -> Result<(), String> {
+    let data = map(path)?;
+    let summary = Summary::new(&data);
+    if format != Format::Text {
+        return emit(&summary, format);
+    }
+    println!("{}: {}", path, summary);
+    Ok(())
+}
 
-    match kind {
-        section::HeaderKind::GuidDefined => {
-            dump_guid_defined(data, &format!("{}    ", padding));
+/// Prints the hierarchy of the image, serialized or as a tree under its
+/// path
+fn print_tree(path: &str, format: Format) -> Result<(), String> {
+    let data = map(path)?;
+    let (_, data) = wrapper::unwrap(&data)?;
+    let mut root = tree::tree(data);
+    if format != Format::Text {
+        return emit(&root, format);
+    }
+    root.name = String::from(path);
+    print!("{}", root);
+    Ok(())
+}
+
+/// Prints the stored and computed checksum of every AMD directory,
+/// serialized or as one line per directory, failing if any is invalid
+fn print_checksums(path: &str, format: Format) -> Result<(), String> {
+    let data = map(path)?;
+    let rom = amd::Rom::new(wrapper::unwrap(&data)?.1)?;
+    let checksums = rom.verify_checksums();
+    if format != Format::Text {
+        emit(&checksums, format)?;
+    } else {
+        for checksum in checksums.iter() {
+            match checksum.checksum.check(checksum.offset) {
+                Ok(()) => println!(
+                    "{:<22} {:#08X}: checksum {:08X} ok",
+                    checksum.directory, checksum.offset, checksum.checksum.stored
+                ),
+                Err(err) => println!(
+                    "{:<22} {:#08X}: {}",
+                    checksum.directory, checksum.offset, err
+                ),
+            }
+        }
+    }
+    Ok(rom.verify_checksum()?)
+}
+
+/// Prints every entry of the PSP directories with its version or key ID,
+/// serialized or as a table of a row per entry
+fn print_versions(path: &str, format: Format) -> Result<(), String> {
+    let data = map(path)?;
+    let rom = amd::Rom::new(wrapper::unwrap(&data)?.1)?;
+    let components = inventory::inventory(&rom);
+    if format != Format::Text {
+        return emit(&components, format);
+    }
+    let width = components
+        .iter()
+        .map(|component| component.description.len())
+        .max()
+        .unwrap_or(0)
+        .max("Description".len());
+    println!(
+        "Directory  Type  Sub  {:<width$}  Version",
+        "Description",
+        width = width
+    );
+    for component in components.iter() {
+        println!(
+            "{:#08X}   {:02X}.{:X}  {:<3}  {:<width$}  {}",
+            component.directory,
+            component.kind,
+            component.instance,
+            component.sub_program,
+            component.description,
+            component,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// Prints the findings of the security checks, serialized or as one line
+/// of severity, check and title per finding followed by its evidence
+fn print_audit(path: &str, format: Format) -> Result<(), String> {
+    let data = map(path)?;
+    let findings = audit::audit(wrapper::unwrap(&data)?.1);
+    if format != Format::Text {
+        return emit(&findings, format);
+    }
+    for finding in findings.iter() {
+        println!(
+            "{:<6} {:<26} {}",
+            finding.severity, finding.id, finding.title
+        );
+        println!("       {}", finding.evidence);
+    }
+    Ok(())
+}
+
+fn bios_region<'a>(data: &'a [u8], path: &str) -> Result<intel::Bios<'a>, String> {
+    intel::Rom::new(data)?
+        .bios()?
+        .ok_or_else(|| format!("{}: BIOS region not found", path))
+}
+
+fn print_variable(sign: char, guid: &str, name: &str, value: &nvram::VariableValue) {
+    let hex: Vec<String> = value
+        .data
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    println!(
+        "{} {}:{} attributes {:08X} size {:#X}: {}",
+        sign,
+        guid,
+        name,
+        value.attributes,
+        value.data.len(),
+        hex.join(" ")
+    );
+}
+
+/// Prints the NVRAM variables added, changed or removed from the first image
+/// to the second, serialized or as the values before (-) and after (+)
+fn print_variable_diff(before: &str, after: &str, format: Format) -> Result<(), String> {
+    let before_data = map(before)?;
+    let after_data = map(after)?;
+    let diffs = nvram::diff_variables(
+        &bios_region(&before_data, before)?,
+        &bios_region(&after_data, after)?,
+    );
+    if format != Format::Text {
+        return emit(&diffs, format);
+    }
+    for diff in diffs.iter() {
+        if let Some(value) = &diff.before {
+            print_variable('-', &diff.guid, &diff.name, value);
+        }
+        if let Some(value) = &diff.after {
+            print_variable('+', &diff.guid, &diff.name, value);
         }
-        section::HeaderKind::VolumeImage => {
-            for volume in BiosVolumes::new(data) {
-                dump_volume(&volume, &format!("{}    ", padding));
+    }
+    Ok(())
+}
+
+/// Prints the regions, ME version and partitions, microcode revisions, BIOS
+/// volumes and files and CBFS files that differ between two Intel images,
+/// serialized or as the modules and files before (-) and after (+) under each
+/// partition, volume and CBFS area
+fn print_intel_diff(before: &intel::Rom, after: &intel::Rom, format: Format) -> Result<(), String> {
+    let diff = intel::diff::diff(before, after);
+    if format != Format::Text {
+        return emit(&diff, format);
+    }
+    print!("{}", diff);
+    Ok(())
+}
+
+/// Prints the differences between two Intel images, or else the EFS
+/// pointers, AMD directories and entries that differ from the first image
+/// to the second, serialized or as the entries before (-) and after (+)
+/// under each directory. Coreboot images of neither vendor are compared by
+/// their CBFS files alone. AMD directories that fail to parse are skipped
+/// with a warning. AMD images are compared from the EFS at the first of
+/// `efs` for the first image and the second for the second, or the first
+/// for both, instead of their first EFS.
+fn print_diff(before: &str, after: &str, efs: &[usize], format: Format) -> Result<(), String> {
+    let before_data = map(before)?;
+    let after_data = map(after)?;
+    let before_data = wrapper::unwrap(&before_data)?.1;
+    let after_data = wrapper::unwrap(&after_data)?.1;
+    if let (Ok(before), Ok(after)) = (intel::Rom::new(before_data), intel::Rom::new(after_data)) {
+        return print_intel_diff(&before, &after, format);
+    }
+    let rom = |data, efs: Option<&usize>| match efs {
+        Some(offset) => amd::Rom::with_efs(data, *offset),
+        None => amd::Rom::new(data),
+    };
+    let roms = (
+        rom(before_data, efs.first()),
+        rom(after_data, efs.get(1).or_else(|| efs.first())),
+    );
+    if roms.0.is_err() || roms.1.is_err() {
+        if let (Some(_), Some(_)) = (fmap::Fmap::find(before_data), fmap::Fmap::find(after_data)) {
+            let diff = cbfs::diff(before_data, after_data);
+            if format != Format::Text {
+                return emit(&diff, format);
             }
+            let mut text = String::new();
+            report::render_cbfs_diff(&mut text, &diff).map_err(|err| err.to_string())?;
+            print!("{}", text);
+            return Ok(());
         }
-        _ => (),
     }
+    let diff = amd::diff::diff(&roms.0?, &roms.1?);
+    for error in diff.errors.iter() {
+        let (path, offset) = match (error.before, error.after) {
+            (Some(offset), _) => (before, offset),
+            (_, offset) => (after, offset.unwrap_or_default()),
+        };
+        eprintln!(
+            "romulan: warning: {}: {} at {:#X} skipped: {}",
+            path, error.path, offset, error.error
+        );
+    }
+    if format != Format::Text {
+        return emit(&diff, format);
+    }
+    print!("{}", diff);
+    Ok(())
 }
 
-fn dump_file(file: &BiosFile, polarity: bool, padding: &str) {
-    let header = file.header();
-    let guid = header.guid;
-    let data = file.data();
-    let len = data.len() / 1024;
-    let kind = header.kind();
-    let attributes = header.attributes();
-    let alignment = header.alignment();
-    let state = header.state(polarity);
-    println!("{}{}: {} K", padding, guid, len);
-    println!("{}  Kind: {:?}", padding, kind);
-    println!("{}  Attrib: {:?}", padding, attributes);
-    println!("{}  Align: {}", padding, alignment);
-    println!("{}  State: {:?}", padding, state);
+/// Prints the versions of the components of the images, in the given order,
+/// serialized or as a table of a row per component. Versions that differ
+/// from those of the image before are marked with a `*`.
+fn print_timeline(paths: &[String], format: Format) -> Result<(), String> {
+    let mut timeline = Timeline::new();
+    for path in paths {
+        let data = map(path)?;
+        let (_, data) = wrapper::unwrap(&data).map_err(|err| format!("{}: {}", path, err))?;
+        timeline.push(path.clone(), timeline::versions(data));
+    }
+    if format != Format::Text {
+        return emit(&timeline, format);
+    }
 
-    if header.sectioned() {
-        for section in file.sections() {
-            dump_section(&section, &format!("{}    ", padding));
+    let mut rows = vec![(String::from("Component"), timeline.images.clone())];
+    for (component, versions) in timeline.components.iter() {
+        let changes = timeline.changes(component);
+        let cells = versions
+            .iter()
+            .enumerate()
+            .map(|(i, version)| match version {
+                Some(version) if changes.contains(&i) && i > 0 => format!("*{}", version),
+                Some(version) => version.clone(),
+                None => String::from("-"),
+            })
+            .collect();
+        rows.push((component.clone(), cells));
+    }
+    let first = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..timeline.images.len())
+        .map(|i| {
+            rows.iter()
+                .map(|(_, cells)| cells[i].len())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for (name, cells) in rows.iter() {
+        let mut line = format!("{:<width$}", name, width = first);
+        for (cell, width) in cells.iter().zip(widths.iter()) {
+            line.push_str(&format!("  {:<width$}", cell, width = width));
         }
+        println!("{}", line.trim_end());
     }
+    Ok(())
 }
 
-fn dump_volume(volume: &BiosVolume, padding: &str) {
-    let header = volume.header();
-    let guid = header.guid;
-    let header_len = header.header_length;
-    let len = volume.data().len() / 1024;
-    let attributes = header.attributes();
-    println!("{}{}: {}, {} K", padding, guid, header_len, len);
-    println!("{}  Attrib: {:?}", padding, attributes);
+fn romulan(
+    path: &str,
+    format: Format,
+    database: &Database,
+    verbose: bool,
+    efs: Option<usize>,
+    flash_size: Option<usize>,
+) -> Result<(), String> {
+    // println!("{}", path);
+
+    let data = map(path)?;
+    let mut report = report::Report::unwrapped(&data, database)?;
+    if efs.is_some() || flash_size.is_some() {
+        let data = wrapper::unwrap(&data)?.1;
+        let mut rom = match efs {
+            Some(offset) => amd::Rom::with_efs(data, offset)?,
+            None => amd::Rom::new(data)?,
+        };
+        if let Some(flash_size) = flash_size {
+            let platform = amd::platform::Platform {
+                flash_size,
+                ..rom.platform()
+            };
+            rom = amd::Rom::with_efs_and_platform(data, rom.efs_offset(), platform)?;
+        }
+        report.amd = Some(report::AmdReport::with_database(&rom, database));
+    }
 
-    let polarity = attributes.contains(volume::Attributes::ERASE_POLARITY);
-    for file in volume.files() {
-        dump_file(&file, polarity, &format!("{}    ", padding));
+    if format != Format::Text {
+        return emit(&report, format);
     }
+
+    let mut text = String::new();
+    if verbose {
+        report.render_verbose(&mut text)
+    } else {
+        report.render(&mut text)
+    }
+    .map_err(|err| err.to_string())?;
+    print!("{}", text);
+    Ok(())
 }
 
-fn intel_analyze(data: &Vec<u8>) -> Result<(), String> {
-    let rom = intel::Rom::new(&data);
-    match rom {
-        Ok(rom) => {
-            if rom.high_assurance_platform()? {
-                println!("  HAP: set");
-            } else {
-                println!("  HAP: not set");
-            }
+enum Edit {
+    ReplaceSection {
+        guid: Guid,
+        kind: section::HeaderKind,
+        path: String,
+    },
+    InsertFile {
+        volume: usize,
+        path: String,
+    },
+    SetVariable {
+        guid: Option<Guid>,
+        name: String,
+        path: String,
+    },
+    SetVariableByte {
+        guid: Option<Guid>,
+        name: String,
+        offset: usize,
+        value: u8,
+    },
+    DeleteVariable {
+        guid: Option<Guid>,
+        name: String,
+    },
+    SetMac {
+        mac: [u8; 6],
+    },
+    SetFuseBit {
+        bit: u8,
+        value: bool,
+    },
+    InsertFuses {
+        directory: usize,
+        value: u64,
+    },
+    ResignPsp {
+        kind: u8,
+        key: String,
+    },
+    ResignBios {
+        key: String,
+    },
+    MoveEntry {
+        directory: usize,
+        index: usize,
+        offset: usize,
+    },
+    ReplaceEntry {
+        directory: usize,
+        index: usize,
+        path: String,
+    },
+    DeleteEntry {
+        directory: usize,
+        index: usize,
+    },
+    Compact {
+        directory: usize,
+    },
+    MoveDirectory {
+        from: usize,
+        to: usize,
+    },
+    ApplyPatch {
+        path: String,
+    },
+    SetPromontory {
+        low_power: bool,
+        path: String,
+    },
+    ResizeRegion {
+        kind: intel::RegionKind,
+        size: usize,
+    },
+    Scrub,
+    MergePsp {
+        path: String,
+    },
+    RemoveMePartition {
+        name: String,
+    },
+    RemoveMeModule {
+        name: String,
+    },
+    NeuterMe,
+    SetHap {
+        value: bool,
+    },
+    CreateBiosLevel2 {
+        parent: usize,
+        offset: usize,
+    },
+}
 
-            if let Some(bios) = rom.bios()? {
-                println!("  BIOS: {} K", bios.data().len() / 1024);
-                for volume in bios.volumes() {
-                    dump_volume(&volume, "    ");
+/// Reads an RSA private key in PKCS#8 or PKCS#1 format, PEM or DER encoded
+fn read_key(path: &str) -> Result<RsaPrivateKey, String> {
+    let data = read(path)?;
+    let key = match std::str::from_utf8(&data) {
+        Ok(pem) => RsaPrivateKey::from_pkcs8_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+            .ok(),
+        Err(_) => RsaPrivateKey::from_pkcs8_der(&data)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_der(&data))
+            .ok(),
+    };
+    key.ok_or_else(|| format!("failed to parse RSA private key {}", path))
+}
+
+/// Prints the Soft Fuse Chain entries of an AMD image with the names of the
+/// bits that are set
+fn print_soft_fuses(data: &[u8]) -> Result<(), String> {
+    let rom = amd::Rom::new(data)?;
+    for &(offset, ref directory) in rom.directories() {
+        if let Directory::Psp(dir) | Directory::PspLevel2(dir) = directory {
+            for entry in dir.entries().iter().filter(|entry| entry.kind == 0x0B) {
+                let fuses = entry.value;
+                println!("Soft Fuse Chain in {:#X}: {:#018X}", offset, fuses);
+                for bit in (0..64).filter(|bit| fuses & 1 << bit != 0) {
+                    println!("  Bit {}: {}", bit, soft_fuse_bit_name(bit));
                 }
-            } else {
-                println!("  BIOS: None");
             }
+        }
+    }
+    Ok(())
+}
 
-            if let Some(me) = rom.me()? {
-                println!("  ME: {} K", me.data().len() / 1024);
-                if let Some(version) = me.version() {
-                    println!("    Version: {}", version);
-                } else {
-                    println!("    Version: Unknown");
-                }
-            } else {
-                println!("  ME: None");
+impl Edit {
+    /// True if the edit may change the given area of the image, as laid out
+    /// by `split::layout`
+    fn touches(&self, area: &Area) -> bool {
+        let directory = area.name.ends_with("Directory");
+        match self {
+            Edit::ReplaceSection { .. }
+            | Edit::InsertFile { .. }
+            | Edit::SetVariable { .. }
+            | Edit::SetVariableByte { .. }
+            | Edit::DeleteVariable { .. } => area.name == "BIOS",
+            Edit::SetMac { .. } => area.name == "GbE",
+            Edit::RemoveMePartition { .. } | Edit::RemoveMeModule { .. } => area.name == "Intel ME",
+            Edit::NeuterMe => area.name == "Intel ME",
+            Edit::SetHap { .. } => area.name == "Flash Descriptor",
+            Edit::ResizeRegion { kind, .. } => {
+                area.name == kind.to_string() || area.name == "Flash Descriptor"
+            }
+            Edit::SetFuseBit { .. } => directory && area.name.starts_with("PSP"),
+            Edit::MergePsp { .. } => area.name.starts_with("PSP") || area.name == "EFS",
+            Edit::InsertFuses { directory, .. } => area.offset == *directory,
+            Edit::ResignPsp { kind, .. } => area.name.starts_with(&format!("PSP {:02X} ", kind)),
+            Edit::ResignBios { .. } => {
+                area.name.starts_with("BIOS 07 ") || directory && area.name.starts_with("BIOS")
+            }
+            // Moved entry data may land anywhere in free space
+            Edit::MoveEntry { directory: offset, .. } | Edit::Compact { directory: offset } => {
+                area.offset == *offset || !directory && area.name != "EFS"
+            }
+            // Replaced entry data may land anywhere in free space, deleted
+            // entry data is erased
+            Edit::ReplaceEntry { directory: offset, .. }
+            | Edit::DeleteEntry { directory: offset, .. } => {
+                area.offset == *offset || !directory && area.name != "EFS"
+            }
+            // Directories referring to the moved one are rewritten
+            Edit::MoveDirectory { .. } => directory || area.name == "EFS",
+            Edit::CreateBiosLevel2 { parent, .. } => area.offset == *parent,
+            Edit::ApplyPatch { .. } => true,
+            Edit::SetPromontory { .. } => area.name == "EFS",
+            // Only ranges outside of every area are erased
+            Edit::Scrub => false,
+        }
+    }
+
+    fn apply(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Edit::ReplaceSection { guid, kind, path } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.replace_section(*guid, *kind, &read(path)?)?)
+            }
+            Edit::InsertFile { volume, path } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.insert_file(*volume, &read(path)?)?)
+            }
+            Edit::SetVariable { guid, name, path } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.set_variable(*guid, name, &read(path)?)?)
+            }
+            Edit::SetVariableByte {
+                guid,
+                name,
+                offset,
+                value,
+            } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.set_variable_byte(*guid, name, *offset, *value)?)
+            }
+            Edit::DeleteVariable { guid, name } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.delete_variable(*guid, name)?)
+            }
+            Edit::SetMac { mac } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.set_mac(*mac)?)
+            }
+            Edit::RemoveMePartition { name } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.remove_me_partition(name)?)
+            }
+            Edit::RemoveMeModule { name } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.remove_me_module(name)?)
+            }
+            Edit::NeuterMe => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.neuter_me()?)
+            }
+            Edit::SetHap { value } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.set_hap(*value)?)
+            }
+            Edit::ResizeRegion { kind, size } => {
+                let rom = intel::Rom::new(data)?;
+                Ok(rom.resize_region(*kind, *size)?)
+            }
+            Edit::SetFuseBit { bit, value } => {
+                let rom = amd::Rom::new(data)?;
+                let image = rom.set_soft_fuse_bit(*bit, *value)?;
+                println!(
+                    "Soft Fuse Chain bit {} {}: {}",
+                    bit,
+                    if *value { "set" } else { "cleared" },
+                    soft_fuse_bit_name(*bit)
+                );
+                print_soft_fuses(&image)?;
+                Ok(image)
+            }
+            Edit::InsertFuses { directory, value } => {
+                let rom = amd::Rom::new(data)?;
+                let image = rom.insert_soft_fuse_chain(*directory, *value)?;
+                print_soft_fuses(&image)?;
+                Ok(image)
+            }
+            Edit::MoveEntry {
+                directory,
+                index,
+                offset,
+            } => {
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.move_entry(*directory, *index, *offset)?)
+            }
+            Edit::ReplaceEntry {
+                directory,
+                index,
+                path,
+            } => {
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.replace_entry(*directory, *index, &read(path)?)?)
+            }
+            Edit::DeleteEntry { directory, index } => {
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.delete_entry(*directory, *index)?)
+            }
+            Edit::Compact { directory } => {
+                let rom = amd::Rom::new(data)?;
+                let (image, reclaimed) = rom.compact_directory(*directory)?;
+                println!(
+                    "Directory {:#X} compacted: {:#X} bytes reclaimed",
+                    directory, reclaimed
+                );
+                Ok(image)
+            }
+            Edit::CreateBiosLevel2 { parent, offset } => {
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.create_bios_level2(*parent, *offset)?)
+            }
+            Edit::MoveDirectory { from, to } => {
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.move_directory(*from, *to)?)
+            }
+            Edit::Scrub => match intel::Rom::new(data) {
+                Ok(rom) => Ok(rom.scrub()?),
+                Err(_) => Ok(amd::Rom::new(data)?.scrub()?),
+            },
+            Edit::ApplyPatch { path } => {
+                let text = fs::read_to_string(path)
+                    .map_err(|err| format!("failed to read {}: {}", path, err))?;
+                let mut image = data.to_vec();
+                patch::apply(&mut image, &patch::from_text(&text)?)?;
+                Ok(image)
+            }
+            Edit::MergePsp { path } => {
+                let other = map(path)?;
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.merge_psp(&amd::Rom::new(&other)?)?)
+            }
+            Edit::SetPromontory { low_power, path } => {
+                let firmware =
+                    fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+                let rom = amd::Rom::new(data)?;
+                let (image, offset) = rom.set_promontory(*low_power, &firmware)?;
+                println!("Promontory firmware placed at {:#X}", offset);
+                Ok(image)
+            }
+            Edit::ResignPsp { kind, key } => {
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.resign_psp_entries(&mut OsRng, &read_key(key)?, *kind)?)
+            }
+            Edit::ResignBios { key } => {
+                let rom = amd::Rom::new(data)?;
+                Ok(rom.resign_bios(&mut OsRng, &read_key(key)?)?)
             }
-            Ok(())
         }
-        Err(err) => Err(format!("No Intel inside - {}", err)),
     }
 }
 
-fn amd_analyze(data: &Vec<u8>) -> Result<(), String> {
-    let rom = amd::Rom::new(&data);
-    match rom {
-        Ok(rom) => {
-            println!("{}", serde_json::to_string(rom.efs()).unwrap());
-            Ok(())
+fn parse_guid(s: &str) -> Result<Guid, String> {
+    let err = || format!("invalid GUID '{}'", s);
+    let parts: Vec<&str> = s.split('-').collect();
+    if parts.len() != 5 || parts[3].len() != 4 || parts[4].len() != 12 {
+        return Err(err());
+    }
+    let mut bytes = [0; 8];
+    let tail = format!("{}{}", parts[3], parts[4]);
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&tail[i * 2..i * 2 + 2], 16).map_err(|_| err())?;
+    }
+    Ok(Guid(
+        u32::from_str_radix(parts[0], 16).map_err(|_| err())?,
+        u16::from_str_radix(parts[1], 16).map_err(|_| err())?,
+        u16::from_str_radix(parts[2], 16).map_err(|_| err())?,
+        bytes,
+    ))
+}
+
+/// Parses a variable given as `name` or `guid:name`
+fn parse_variable(s: &str) -> Result<(Option<Guid>, String), String> {
+    match s.split_once(':') {
+        Some((guid, name)) => Ok((Some(parse_guid(guid)?), name.to_string())),
+        None => Ok((None, s.to_string())),
+    }
+}
+
+fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+    let err = || format!("invalid MAC address '{}'", s);
+    let mut mac = [0; 6];
+    let mut parts = s.split(|c| c == ':' || c == '-');
+    for byte in mac.iter_mut() {
+        let part = parts.next().ok_or_else(err)?;
+        *byte = u8::from_str_radix(part, 16).map_err(|_| err())?;
+    }
+    if parts.next().is_some() {
+        return Err(err());
+    }
+    Ok(mac)
+}
+
+fn parse_number(s: &str) -> Result<usize, String> {
+    let parsed = if let Some(hex) = s.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", s))
+}
+
+fn parse_section_kind(s: &str) -> Result<section::HeaderKind, String> {
+    (0..=255u8)
+        .map(section::HeaderKind::from)
+        .find(|kind| format!("{:?}", kind).eq_ignore_ascii_case(s))
+        .ok_or_else(|| format!("invalid section type '{}'", s))
+}
+
+/// Parses the directory and type of an entry, such as `psp:0x08` or
+/// `bios:0x62`
+fn parse_entry_target(s: &str) -> Result<Target, String> {
+    let err = || format!("invalid entry '{}', expected psp:<type> or bios:<type>", s);
+    let (directory, kind) = s.split_once(':').ok_or_else(err)?;
+    let kind = u8::try_from(parse_number(kind)?).map_err(|_| err())?;
+    match directory.to_lowercase().as_str() {
+        "psp" => Ok(Target::PspEntry(kind)),
+        "bios" => Ok(Target::BiosEntry(kind)),
+        _ => Err(err()),
+    }
+}
+
+/// Parses the features of a generated test image
+fn parse_fixture(features: &str) -> Result<AmdFixture, String> {
+    let mut fixture = AmdFixture::default();
+    for feature in features.split(',') {
+        match feature.split_once('=') {
+            Some(("size", size)) => fixture.size = parse_number(size)?,
+            Some(("window", offset)) => fixture.window_offset = parse_number(offset)?,
+            Some(("region", offset)) => fixture.image_offset = parse_number(offset)?,
+            _ => match feature {
+                "combo" => fixture.combo = true,
+                "level2" => fixture.level2 = true,
+                "ab" => fixture.ab_recovery = true,
+                "none" => (),
+                _ => return Err(format!("unknown fixture feature '{}'", feature)),
+            },
         }
-        Err(err) => Err(format!("No AMD inside - {}", err)),
     }
+    let window = fixture.size.min(amd::address::MMIO_WINDOW_SIZE);
+    if !fixture.size.is_power_of_two() || fixture.size < 0x20_0000 {
+        return Err(format!("invalid fixture size {:#X}", fixture.size));
+    }
+    if fixture.window_offset % window != 0 || fixture.window_offset + window > fixture.size {
+        return Err(format!(
+            "invalid fixture window {:#X}",
+            fixture.window_offset
+        ));
+    }
+    if fixture.image_offset % 0x1000 != 0
+        || fixture.image_offset > fixture.window_offset + testutil::EFS_OFFSET
+    {
+        return Err(format!(
+            "invalid fixture region {:#X}",
+            fixture.image_offset
+        ));
+    }
+    Ok(fixture)
 }
 
-fn romulan(path: &str) -> Result<(), String> {
-    // println!("{}", path);
+/// Parses a flash region name: bios, me, gbe, pd or ec
+fn parse_region(s: &str) -> Result<intel::RegionKind, String> {
+    match s.to_lowercase().as_str() {
+        "bios" => Ok(intel::RegionKind::Bios),
+        "me" => Ok(intel::RegionKind::ManagementEngine),
+        "gbe" => Ok(intel::RegionKind::Ethernet),
+        "pd" => Ok(intel::RegionKind::PlatformData),
+        "ec" => Ok(intel::RegionKind::EmbeddedController),
+        _ => Err(format!("unknown region '{}'", s)),
+    }
+}
 
-    let mut data = Vec::new();
-    fs::File::open(path)
-        .map_err(|err| format!("failed to open {}: {}", path, err))?
-        .read_to_end(&mut data)
-        .map_err(|err| format!("failed to read {}: {}", path, err))?;
+/// Entry written by `--get`
+struct Get {
+    target: Target,
+    sub_program: Option<u8>,
+    instance: Option<u8>,
+    decompress: bool,
+    strip_header: bool,
+}
 
-    let _r = intel_analyze(&data);
-    let _r = amd_analyze(&data);
-    Ok(())
+/// Writes the data of the one AMD directory entry selected by `get` to
+/// `output`, or to stdout. Compressed data is decompressed with `decompress`,
+/// which leaves the header behind, and `strip_header` removes the PSP
+/// binary header of data that is not.
+fn get_entry(path: &str, get: &Get, output: Option<&str>) -> Result<(), String> {
+    let data = map(path)?;
+    let (_, data) = wrapper::unwrap(&data)?;
+    let rom = amd::Rom::new(data)?;
+    let found = rom.find_entries(get.target, get.sub_program, get.instance);
+    let entry = match found.as_slice() {
+        [entry] => entry,
+        [] => return Err(String::from("no entry matches")),
+        _ => {
+            let entries = found
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "sub program {} instance {} at {:#X}",
+                        entry.sub_program, entry.instance, entry.offset
+                    )
+                })
+                .collect::<Vec<_>>();
+            return Err(format!(
+                "{} entries match, select one with --sub-program or --instance: {}",
+                found.len(),
+                entries.join(", ")
+            ));
+        }
+    };
+    let compressed = directory::zlib_stream(entry.data).is_some();
+    let bytes = if get.decompress && compressed {
+        directory::payload(entry.data).ok_or("entry data does not decompress")?
+    } else if get.strip_header {
+        let start = mem::size_of::<directory::PspBinaryHeader>();
+        Cow::Borrowed(
+            entry
+                .data
+                .get(start..)
+                .ok_or("entry data is shorter than a PSP binary header")?,
+        )
+    } else {
+        Cow::Borrowed(entry.data)
+    };
+    match output {
+        Some(output) => {
+            fs::write(output, &bytes).map_err(|err| format!("failed to write {}: {}", output, err))
+        }
+        None => io::stdout()
+            .write_all(&bytes)
+            .map_err(|err| format!("failed to write output: {}", err)),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("romulan [options] <file>...");
+    eprintln!("  --json                                  print the analysis as JSON");
+    eprintln!("  --cbor                                  write the analysis as CBOR");
+    eprintln!("  --msgpack                               write the analysis as MessagePack");
+    eprintln!("  --verbose                               also print the volumes of AMD BIOS binaries");
+    eprintln!("  --efs <offset>                          analyze or diff from the AMD EFS at <offset>, given");
+    eprintln!("                                          twice with --diff for the old and new image, or the");
+    eprintln!("                                          two EFS of a single image");
+    eprintln!("  --flash-size <bytes>                    analyze AMD images for a flash of <bytes> instead");
+    eprintln!("                                          of the size detected from the image");
+    eprintln!(
+        "  --schema <output>                       print the JSON Schema of report or patch output"
+    );
+    eprintln!("  --entropy <size>                        print the spans of high and low entropy, in windows");
+    eprintln!("                                          of <size> bytes, and the unreferenced ones");
+    eprintln!("  --database <file>                       name AMD entries by the SHA-256 digests in <file>");
+    eprintln!("  --timeline <file>...                    print the component versions of releases in order");
+    eprintln!(
+        "  --batch <dir>                           write a JSON line per image of <dir>, or a JSON"
+    );
+    eprintln!("                                          report per image to the directory --output names");
+    eprintln!(
+        "  --recursive                             with --batch, also analyze the subdirectories"
+    );
+    eprintln!("  --glob <pattern>                        with --batch, analyze the images named like <pattern>");
+    eprintln!("  --diff <old> <new>                      print the directories or volumes that differ between images");
+    eprintln!("  --diff-variables <old> <new>            print the NVRAM variables that differ between images");
+    eprintln!("  --audit                                 check the security settings of the image");
+    eprintln!("  --summary                               print the vendor, platform and versions");
+    eprintln!("  --tree                                  print the hierarchy of the image as a tree");
+    eprintln!("  --coverage                              print the ranges no structure of the image claims");
+    eprintln!("  --verify                                check the checksums of the AMD directories");
+    eprintln!("  --versions                              print the version or key ID of every PSP entry");
+    eprintln!("  --metainfo                              print the firmware components as AppStream metainfo");
+    eprintln!("  --build-database <dir>                  write a --database of the blobs in <dir> to --output");
+    eprintln!("  --read <programmer>                     read the flash with flashrom or em100, then analyze it");
+    eprintln!("  --save <file>                           keep the image read by --read in <file>");
+    eprintln!("  --output <file>                         write the edited image to <file>");
+    eprintln!("  --patch <file>                          write the changes of the edits to <file>");
+    eprintln!("  --apply-patch <patch>                   apply a patch written by --patch");
+    eprintln!("  --split <dir>                           write each region to a file in <dir>");
+    eprintln!("  --assemble <manifest>                   rebuild an image from a split manifest");
+    eprintln!("  --extract <dir>                         write every component to a file in <dir>");
+    eprintln!("  --get <psp|bios>:<type>                 write the data of one AMD directory entry to");
+    eprintln!("                                          --output, or to stdout");
+    eprintln!("  --sub-program <n>                       select the --get entry of a sub program");
+    eprintln!("  --instance <n>                          select the --get entry of an instance");
+    eprintln!("  --decompress                            write the --get entry decompressed");
+    eprintln!("  --strip-header                          write the --get entry without its PSP header");
+    eprintln!(
+        "  --describe <file>                       write a JSON description of the image to <file>"
+    );
+    eprintln!("  --build <description>                   rebuild an image from a JSON description");
+    eprintln!("  --gen-fixture <features>                generate an AMD test image, features are a");
+    eprintln!("                                          comma separated list of combo, level2 and ab,");
+    eprintln!("                                          or none, and of size=<bytes> of the flash,");
+    eprintln!("                                          window=<offset> of the 16M holding the EFS and");
+    eprintln!("                                          region=<offset> of the flash the image starts at");
+    eprintln!("  --replace-section <guid> <type> <data>  replace a section of an FFS file");
+    eprintln!("  --insert-file <volume> <file>           insert an FFS file into a volume");
+    eprintln!("  --set-variable <[guid:]name> <data>     replace the data of an NVRAM variable");
+    eprintln!("  --set-variable-byte <[guid:]name> <offset> <value>");
+    eprintln!("                                          set one byte of an NVRAM variable");
+    eprintln!("  --delete-variable <[guid:]name>         delete an NVRAM variable");
+    eprintln!("  --merge-psp <image>                     replace the PSP tree with that of <image>");
+    eprintln!("  --set-promontory <file>                 place promontory firmware and point the EFS at it");
+    eprintln!("  --set-lp-promontory <file>              same for low power promontory firmware");
+    eprintln!("  --scrub                                 erase the ranges outside of every area");
+    eprintln!("  --remove-me-partition <name>            remove a partition from the ME region");
+    eprintln!("  --remove-me-module <name>               remove a module from an ME partition");
+    eprintln!("  --neuter-me                             remove the ME partitions but FTPR");
+    eprintln!("  --set-hap                               set the HAP or AltMeDisable bit");
+    eprintln!("  --clear-hap                             clear the HAP or AltMeDisable bit");
+    eprintln!("  --resize-region <region> <size>         resize bios, me, gbe, pd or ec region");
+    eprintln!("  --set-mac <mac>                         set the MAC address in the GbE region");
+    eprintln!("  --set-fuse-bit <bit>                    set a PSP Soft Fuse Chain bit");
+    eprintln!("  --clear-fuse-bit <bit>                  clear a PSP Soft Fuse Chain bit");
+    eprintln!("  --insert-fuses <directory> <value>      add a PSP Soft Fuse Chain entry");
+    eprintln!("  --move-entry <directory> <index> <offset>");
+    eprintln!("                                          move the data of an AMD directory entry");
+    eprintln!("  --replace-entry <directory> <index> <data>");
+    eprintln!("                                          replace the data of an AMD directory entry");
+    eprintln!("  --delete-entry <directory> <index>      remove an entry from an AMD directory");
+    eprintln!("  --move-directory <from> <to>            move an AMD directory and its references");
+    eprintln!("  --create-bios-level2 <parent> <offset>  add a BIOS level 2 directory at <offset>");
+    eprintln!("  --compact <directory>                   pack the data of an AMD directory's entries");
+    eprintln!("  --resign-psp <type> <key>               re-sign PSP binaries of a type with <key>");
+    eprintln!("  --resign-bios <key>                     re-sign the BIOS binary with <key>");
+    process::exit(1);
 }
 
 fn main() {
-    for arg in env::args().skip(1) {
-        if let Err(err) = romulan(&arg) {
+    let mut files = Vec::new();
+    let mut edits = Vec::new();
+    let mut output = None;
+    let mut split_dir = None;
+    let mut extract_dir = None;
+    let mut get = None;
+    let mut sub_program = None;
+    let mut instance = None;
+    let mut decompress = false;
+    let mut strip_header = false;
+    let mut manifest = None;
+    let mut describe = None;
+    let mut description = None;
+    let mut fixture = None;
+    let mut patch_output = None;
+    let mut format = Format::Text;
+    let mut programmer = None;
+    let mut reference = None;
+    let mut save = None;
+    let mut entropy = None;
+    let mut schema = None;
+    let mut components = false;
+    let mut security = false;
+    let mut summary = false;
+    let mut hierarchy = false;
+    let mut coverage = false;
+    let mut checksums = false;
+    let mut versions = false;
+    let mut variables = false;
+    let mut differences = false;
+    let mut timeline = false;
+    let mut verbose = false;
+    let mut efs = Vec::new();
+    let mut flash_size = None;
+    let mut batch = None;
+    let mut recursive = false;
+    let mut glob = None;
+    let mut database = Database::new();
+
+    let mut args = env::args().skip(1);
+    let next = |args: &mut dyn Iterator<Item = String>| args.next().unwrap_or_else(|| usage());
+    while let Some(arg) = args.next() {
+        let parsed: Result<(), String> = match arg.as_str() {
+            "--output" => {
+                output = Some(next(&mut args));
+                Ok(())
+            }
+            "--json" => {
+                format = Format::Json;
+                Ok(())
+            }
+            "--timeline" => {
+                timeline = true;
+                Ok(())
+            }
+            "--verbose" => {
+                verbose = true;
+                Ok(())
+            }
+            "--batch" => {
+                batch = Some(next(&mut args));
+                Ok(())
+            }
+            "--recursive" => {
+                recursive = true;
+                Ok(())
+            }
+            "--glob" => {
+                glob = Some(next(&mut args));
+                Ok(())
+            }
+            "--efs" => (|| {
+                efs.push(parse_number(&next(&mut args))?);
+                Ok(())
+            })(),
+            "--flash-size" => (|| {
+                let size = parse_number(&next(&mut args))?;
+                if !size.is_power_of_two() {
+                    return Err(format!("flash size {:#X} is not a power of two", size));
+                }
+                flash_size = Some(size);
+                Ok(())
+            })(),
+            "--diff" => {
+                differences = true;
+                Ok(())
+            }
+            "--diff-variables" => {
+                variables = true;
+                Ok(())
+            }
+            "--verify" => {
+                checksums = true;
+                Ok(())
+            }
+            "--versions" => {
+                versions = true;
+                Ok(())
+            }
+            "--summary" => {
+                summary = true;
+                Ok(())
+            }
+            "--tree" => {
+                hierarchy = true;
+                Ok(())
+            }
+            "--coverage" => {
+                coverage = true;
+                Ok(())
+            }
+            "--audit" => {
+                security = true;
+                Ok(())
+            }
+            "--metainfo" => {
+                components = true;
+                Ok(())
+            }
+            "--schema" => {
+                schema = Some(next(&mut args));
+                Ok(())
+            }
+            "--build-database" => {
+                reference = Some(next(&mut args));
+                Ok(())
+            }
+            "--read" => {
+                programmer = Some(next(&mut args));
+                Ok(())
+            }
+            "--save" => {
+                save = Some(next(&mut args));
+                Ok(())
+            }
+            "--cbor" => {
+                format = Format::Cbor;
+                Ok(())
+            }
+            "--msgpack" => {
+                format = Format::MessagePack;
+                Ok(())
+            }
+            "--entropy" => (|| {
+                let block_size = parse_number(&next(&mut args))?;
+                if block_size == 0 {
+                    return Err(String::from("entropy window is 0"));
+                }
+                entropy = Some(block_size);
+                Ok(())
+            })(),
+            "--database" => (|| {
+                let path = next(&mut args);
+                let text = fs::read_to_string(&path)
+                    .map_err(|err| format!("failed to read {}: {}", path, err))?;
+                let loaded = serde_json::from_str::<Database>(&text)
+                    .map_err(|err| format!("failed to parse {}: {}", path, err))?;
+                database.merge(loaded);
+                Ok(())
+            })(),
+            "--patch" => {
+                patch_output = Some(next(&mut args));
+                Ok(())
+            }
+            "--apply-patch" => {
+                let path = next(&mut args);
+                edits.push(Edit::ApplyPatch { path });
+                Ok(())
+            }
+            "--merge-psp" => {
+                let path = next(&mut args);
+                edits.push(Edit::MergePsp { path });
+                Ok(())
+            }
+            "--set-promontory" | "--set-lp-promontory" => {
+                let path = next(&mut args);
+                let low_power = arg == "--set-lp-promontory";
+                edits.push(Edit::SetPromontory { low_power, path });
+                Ok(())
+            }
+            "--split" => {
+                split_dir = Some(next(&mut args));
+                Ok(())
+            }
+            "--extract" => {
+                extract_dir = Some(next(&mut args));
+                Ok(())
+            }
+            "--get" => (|| {
+                get = Some(parse_entry_target(&next(&mut args))?);
+                Ok(())
+            })(),
+            "--sub-program" | "--instance" => (|| {
+                let value = next(&mut args);
+                let parsed = u8::try_from(parse_number(&value)?)
+                    .map_err(|_| format!("invalid {} '{}'", &arg[2..], value))?;
+                match arg.as_str() {
+                    "--sub-program" => sub_program = Some(parsed),
+                    _ => instance = Some(parsed),
+                }
+                Ok(())
+            })(),
+            "--decompress" => {
+                decompress = true;
+                Ok(())
+            }
+            "--strip-header" => {
+                strip_header = true;
+                Ok(())
+            }
+            "--assemble" => {
+                manifest = Some(next(&mut args));
+                Ok(())
+            }
+            "--describe" => {
+                describe = Some(next(&mut args));
+                Ok(())
+            }
+            "--build" => {
+                description = Some(next(&mut args));
+                Ok(())
+            }
+            "--gen-fixture" => (|| {
+                fixture = Some(parse_fixture(&next(&mut args))?);
+                Ok(())
+            })(),
+            "--replace-section" => (|| {
+                let guid = parse_guid(&next(&mut args))?;
+                let kind = parse_section_kind(&next(&mut args))?;
+                let path = next(&mut args);
+                edits.push(Edit::ReplaceSection { guid, kind, path });
+                Ok(())
+            })(),
+            "--insert-file" => (|| {
+                let volume = parse_number(&next(&mut args))?;
+                let path = next(&mut args);
+                edits.push(Edit::InsertFile { volume, path });
+                Ok(())
+            })(),
+            "--set-variable" => (|| {
+                let (guid, name) = parse_variable(&next(&mut args))?;
+                let path = next(&mut args);
+                edits.push(Edit::SetVariable { guid, name, path });
+                Ok(())
+            })(),
+            "--set-variable-byte" => (|| {
+                let (guid, name) = parse_variable(&next(&mut args))?;
+                let offset = parse_number(&next(&mut args))?;
+                let value = next(&mut args);
+                let value = u8::try_from(parse_number(&value)?)
+                    .map_err(|_| format!("invalid byte '{}'", value))?;
+                edits.push(Edit::SetVariableByte {
+                    guid,
+                    name,
+                    offset,
+                    value,
+                });
+                Ok(())
+            })(),
+            "--delete-variable" => (|| {
+                let (guid, name) = parse_variable(&next(&mut args))?;
+                edits.push(Edit::DeleteVariable { guid, name });
+                Ok(())
+            })(),
+            "--scrub" => {
+                edits.push(Edit::Scrub);
+                Ok(())
+            }
+            "--remove-me-partition" => {
+                let name = next(&mut args);
+                edits.push(Edit::RemoveMePartition { name });
+                Ok(())
+            }
+            "--remove-me-module" => {
+                let name = next(&mut args);
+                edits.push(Edit::RemoveMeModule { name });
+                Ok(())
+            }
+            "--neuter-me" => {
+                edits.push(Edit::NeuterMe);
+                Ok(())
+            }
+            "--set-hap" | "--clear-hap" => {
+                edits.push(Edit::SetHap {
+                    value: arg == "--set-hap",
+                });
+                Ok(())
+            }
+            "--resize-region" => (|| {
+                let kind = parse_region(&next(&mut args))?;
+                let size = parse_number(&next(&mut args))?;
+                edits.push(Edit::ResizeRegion { kind, size });
+                Ok(())
+            })(),
+            "--set-mac" => (|| {
+                let mac = parse_mac(&next(&mut args))?;
+                edits.push(Edit::SetMac { mac });
+                Ok(())
+            })(),
+            "--set-fuse-bit" | "--clear-fuse-bit" => (|| {
+                let bit = next(&mut args);
+                let bit = u8::try_from(parse_number(&bit)?)
+                    .map_err(|_| format!("invalid bit '{}'", bit))?;
+                let value = arg == "--set-fuse-bit";
+                edits.push(Edit::SetFuseBit { bit, value });
+                Ok(())
+            })(),
+            "--insert-fuses" => (|| {
+                let directory = parse_number(&next(&mut args))?;
+                let value = parse_number(&next(&mut args))? as u64;
+                edits.push(Edit::InsertFuses { directory, value });
+                Ok(())
+            })(),
+            "--resign-psp" => (|| {
+                let kind = next(&mut args);
+                let kind = u8::try_from(parse_number(&kind)?)
+                    .map_err(|_| format!("invalid entry type '{}'", kind))?;
+                let key = next(&mut args);
+                edits.push(Edit::ResignPsp { kind, key });
+                Ok(())
+            })(),
+            "--move-entry" => (|| {
+                let directory = parse_number(&next(&mut args))?;
+                let index = parse_number(&next(&mut args))?;
+                let offset = parse_number(&next(&mut args))?;
+                edits.push(Edit::MoveEntry {
+                    directory,
+                    index,
+                    offset,
+                });
+                Ok(())
+            })(),
+            "--replace-entry" => (|| {
+                let directory = parse_number(&next(&mut args))?;
+                let index = parse_number(&next(&mut args))?;
+                let path = next(&mut args);
+                edits.push(Edit::ReplaceEntry {
+                    directory,
+                    index,
+                    path,
+                });
+                Ok(())
+            })(),
+            "--delete-entry" => (|| {
+                let directory = parse_number(&next(&mut args))?;
+                let index = parse_number(&next(&mut args))?;
+                edits.push(Edit::DeleteEntry { directory, index });
+                Ok(())
+            })(),
+            "--compact" => (|| {
+                let directory = parse_number(&next(&mut args))?;
+                edits.push(Edit::Compact { directory });
+                Ok(())
+            })(),
+            "--create-bios-level2" => (|| {
+                let parent = parse_number(&next(&mut args))?;
+                let offset = parse_number(&next(&mut args))?;
+                edits.push(Edit::CreateBiosLevel2 { parent, offset });
+                Ok(())
+            })(),
+            "--move-directory" => (|| {
+                let from = parse_number(&next(&mut args))?;
+                let to = parse_number(&next(&mut args))?;
+                edits.push(Edit::MoveDirectory { from, to });
+                Ok(())
+            })(),
+            "--resign-bios" => {
+                let key = next(&mut args);
+                edits.push(Edit::ResignBios { key });
+                Ok(())
+            }
+            "--help" => usage(),
+            _ => {
+                files.push(arg);
+                Ok(())
+            }
+        };
+        if let Err(err) = parsed {
+            eprintln!("romulan: {}", err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(name) = schema {
+        if !files.is_empty() {
+            usage();
+        }
+        if let Err(err) = print_schema(&name) {
+            eprintln!("romulan: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(reference) = reference {
+        let output = match (files.is_empty(), output) {
+            (true, Some(output)) => output,
+            _ => usage(),
+        };
+        let mut database = Database::new();
+        let result = add_reference(&mut database, Path::new(&reference)).and_then(|()| {
+            let text = serde_json::to_string_pretty(&database)
+                .map_err(|err| format!("failed to serialize: {}", err))?;
+            fs::write(&output, text).map_err(|err| format!("failed to write {}: {}", output, err))
+        });
+        if let Err(err) = result {
+            eprintln!("romulan: {}: {}", reference, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(programmer) = programmer {
+        if !files.is_empty() {
+            usage();
+        }
+        let dump = flash::Dump::new();
+        let path = match &save {
+            Some(save) => Path::new(save),
+            None => dump.path(),
+        };
+        let result = flash::read(&programmer, path).and_then(|()| {
+            let path = path.to_string_lossy();
+            match entropy {
+                Some(block_size) => print_entropy(&path, block_size, format),
+                None if security => print_audit(&path, format),
+                None if summary => print_summary(&path, format),
+                None if hierarchy => print_tree(&path, format),
+                None if coverage => print_coverage(&path, format),
+                None if checksums => print_checksums(&path, format),
+                None if versions => print_versions(&path, format),
+                None if components => print_metainfo(&path, format, &database),
+                None => romulan(
+                    &path,
+                    format,
+                    &database,
+                    verbose,
+                    efs.first().copied(),
+                    flash_size,
+                ),
+            }
+        });
+        if let Err(err) = result {
+            // process::exit does not run destructors
+            drop(dump);
+            eprintln!("romulan: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(dir) = batch {
+        if !files.is_empty() {
+            usage();
+        }
+        let dir = Path::new(&dir);
+        let result = batch::files(dir, recursive, glob.as_deref())
+            .and_then(|paths| batch::run(dir, &paths, &database, output.as_deref().map(Path::new)));
+        match result {
+            Ok(0) => return,
+            Ok(failed) => eprintln!("romulan: {}: {} images failed", dir.display(), failed),
+            Err(err) => eprintln!("romulan: {}: {}", dir.display(), err),
+        }
+        process::exit(1);
+    }
+
+    if let Some(fixture) = fixture {
+        let output = match (files.is_empty(), output) {
+            (true, Some(output)) => output,
+            _ => usage(),
+        };
+        if let Err(err) = fs::write(&output, fixture.build()) {
+            eprintln!("romulan: failed to write {}: {}", output, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(manifest) = manifest {
+        let output = match (files.is_empty(), output) {
+            (true, Some(output)) => output,
+            _ => usage(),
+        };
+        if let Err(err) = split::assemble(Path::new(&manifest)).and_then(|data| {
+            fs::write(&output, data).map_err(|err| format!("failed to write {}: {}", output, err))
+        }) {
+            eprintln!("romulan: {}: {}", manifest, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(description) = description {
+        let output = match (files.is_empty(), output) {
+            (true, Some(output)) => output,
+            _ => usage(),
+        };
+        let result = fs::read_to_string(&description)
+            .map_err(|err| format!("failed to read: {}", err))
+            .and_then(|text| {
+                serde_json::from_str::<Description>(&text)
+                    .map_err(|err| format!("invalid description: {}", err))
+            })
+            .and_then(|description| Ok(description.build()?))
+            .and_then(|data| {
+                fs::write(&output, data)
+                    .map_err(|err| format!("failed to write {}: {}", output, err))
+            });
+        if let Err(err) = result {
+            eprintln!("romulan: {}: {}", description, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(describe) = describe {
+        let path = match files.as_slice() {
+            [path] => path,
+            _ => usage(),
+        };
+        let result = map(path).and_then(|data| {
+            let text = serde_json::to_string_pretty(&Description::new(&data))
+                .map_err(|err| format!("failed to serialize: {}", err))?;
+            fs::write(&describe, text)
+                .map_err(|err| format!("failed to write {}: {}", describe, err))
+        });
+        if let Err(err) = result {
+            eprintln!("romulan: {}: {}", path, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(dir) = split_dir {
+        let path = match files.as_slice() {
+            [path] => path,
+            _ => usage(),
+        };
+        if let Err(err) = map(path).and_then(|data| split::split(&data, Path::new(&dir))) {
+            eprintln!("romulan: {}: {}", path, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(dir) = extract_dir {
+        let path = match files.as_slice() {
+            [path] => path,
+            _ => usage(),
+        };
+        if let Err(err) = map(path).and_then(|data| split::extract(&data, Path::new(&dir))) {
+            eprintln!("romulan: {}: {}", path, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(target) = get {
+        let path = match (files.as_slice(), edits.is_empty()) {
+            ([path], true) => path,
+            _ => usage(),
+        };
+        let get = Get {
+            target,
+            sub_program,
+            instance,
+            decompress,
+            strip_header,
+        };
+        if let Err(err) = get_entry(path, &get, output.as_deref()) {
+            eprintln!("romulan: {}: {}", path, err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if !edits.is_empty() {
+        let path = match (files.as_slice(), &output, &patch_output) {
+            ([path], Some(_), _) | ([path], _, Some(_)) => path,
+            _ => usage(),
+        };
+        let result = read(path).and_then(|original| {
+            let edited = edits.iter().try_fold(original.clone(), |data, edit| -> Result<_, String> {
+                let edited = edit.apply(&data)?;
+                verify::write_back(&data, &edited, |area| edit.touches(area))?;
+                Ok(edited)
+            })?;
+            if let Some(patch_output) = &patch_output {
+                let text = patch::to_text(&patch::diff(&original, &edited)?);
+                fs::write(patch_output, text)
+                    .map_err(|err| format!("failed to write {}: {}", patch_output, err))?;
+            }
+            if let Some(output) = &output {
+                fs::write(output, &edited)
+                    .map_err(|err| format!("failed to write {}: {}", output, err))?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => return,
+            Err(err) => {
+                eprintln!("romulan: {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(block_size) = entropy {
+        for arg in files {
+            if let Err(err) = print_entropy(&arg, block_size, format) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if timeline {
+        if files.is_empty() {
+            usage();
+        }
+        if let Err(err) = print_timeline(&files, format) {
+            eprintln!("romulan: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if differences {
+        let (before, after) = match (files.as_slice(), efs.len()) {
+            ([before, after], _) => (before, after),
+            ([image], 2) => (image, image),
+            _ => usage(),
+        };
+        if let Err(err) = print_diff(before, after, &efs, format) {
+            eprintln!("romulan: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if variables {
+        let (before, after) = match files.as_slice() {
+            [before, after] => (before, after),
+            _ => usage(),
+        };
+        if let Err(err) = print_variable_diff(before, after, format) {
+            eprintln!("romulan: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if security {
+        for arg in files {
+            if let Err(err) = print_audit(&arg, format) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if summary {
+        for arg in files {
+            if let Err(err) = print_summary(&arg, format) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if hierarchy {
+        for arg in files {
+            if let Err(err) = print_tree(&arg, format) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if coverage {
+        for arg in files {
+            if let Err(err) = print_coverage(&arg, format) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if checksums {
+        for arg in files {
+            if let Err(err) = print_checksums(&arg, format) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if versions {
+        for arg in files {
+            if let Err(err) = print_versions(&arg, format) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if components {
+        for arg in files {
+            if let Err(err) = print_metainfo(&arg, format, &database) {
+                eprintln!("romulan: {}: {}", arg, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    for arg in files {
+        if let Err(err) = romulan(
+            &arg,
+            format,
+            &database,
+            verbose,
+            efs.first().copied(),
+            flash_size,
+        ) {
             eprintln!("romulan: {}: {}", arg, err);
             process::exit(1);
         }
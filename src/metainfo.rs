@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: MIT
+
+//! Firmware components of an image with their versions, written as AppStream
+//! metainfo like the metadata fwupd and the LVFS use, so distributions can
+//! track which firmware versions the images they ship contain
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use serde::{Deserialize, Serialize};
+
+use crate::extract::camel_case;
+use crate::report::Report;
+
+/// Firmware component found in an image
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Component {
+    /// AppStream ID of the component, in reverse DNS notation
+    pub id: String,
+    pub name: String,
+    pub version: Option<String>,
+    /// GUIDs fwupd updates the component by, if known
+    pub guids: Vec<String>,
+    /// Lowercase hex SHA-256 digest of the component, if it is one blob
+    pub sha256: Option<String>,
+}
+
+/// Components of the analyzed image: the Intel ME and the AMD entries
+/// identified by a database. Copies of an entry, such as those of A/B
+/// recovery directories, are listed once.
+pub fn components(report: &Report) -> Vec<Component> {
+    let mut components: Vec<Component> = Vec::new();
+    if let Some(me) = report.intel.as_ref().and_then(|intel| intel.me.as_ref()) {
+        components.push(Component {
+            id: String::from("com.intel.ManagementEngine"),
+            name: String::from("Intel Management Engine"),
+            version: me.version.clone(),
+            guids: Vec::new(),
+            sha256: None,
+        });
+    }
+    if let Some(amd) = &report.amd {
+        for component in amd.components.iter() {
+            let identity = &component.identity;
+            let found = Component {
+                id: format!("com.amd.{}", camel_case(&identity.name)),
+                name: identity.name.clone(),
+                version: identity.version.clone(),
+                guids: identity.guids.clone(),
+                sha256: Some(component.sha256.clone()),
+            };
+            if !components.contains(&found) {
+                components.push(found);
+            }
+        }
+    }
+    components
+}
+
+/// AppStream metainfo XML of the components, one `component` of type
+/// `firmware` each, with its GUIDs as flashed firmware and its version as
+/// a release
+pub fn metainfo(components: &[Component]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<components origin=\"romulan\">\n");
+    for component in components {
+        xml.push_str("  <component type=\"firmware\">\n");
+        let _ = writeln!(xml, "    <id>{}</id>", escape(&component.id));
+        let _ = writeln!(xml, "    <name>{}</name>", escape(&component.name));
+        if !component.guids.is_empty() {
+            xml.push_str("    <provides>\n");
+            for guid in component.guids.iter() {
+                let _ = writeln!(
+                    xml,
+                    "      <firmware type=\"flashed\">{}</firmware>",
+                    escape(guid)
+                );
+            }
+            xml.push_str("    </provides>\n");
+        }
+        if let Some(version) = &component.version {
+            xml.push_str("    <releases>\n");
+            match &component.sha256 {
+                Some(sha256) => {
+                    let _ = writeln!(xml, "      <release version=\"{}\">", escape(version));
+                    let _ = writeln!(
+                        xml,
+                        "        <checksum type=\"sha256\" target=\"content\">{}</checksum>",
+                        sha256
+                    );
+                    xml.push_str("      </release>\n");
+                }
+                None => {
+                    let _ = writeln!(xml, "      <release version=\"{}\"/>", escape(version));
+                }
+            }
+            xml.push_str("    </releases>\n");
+        }
+        xml.push_str("  </component>\n");
+    }
+    xml.push_str("</components>\n");
+    xml
+}
+
+/// Text with the characters XML reserves replaced by entities
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,117 @@
+// SPDX-License-Identifier: MIT
+
+//! Differences between images as runs of changed bytes, in a text format of
+//! one `OFFSET BYTES` line per run, both in hex
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+use serde::{Deserialize, Serialize};
+
+use crate::{compare, Error};
+
+/// Runs of changed bytes closer than this are merged into one
+const MERGE_DISTANCE: usize = 8;
+
+/// Bytes per line of the text format
+const LINE_BYTES: usize = 32;
+
+/// Bytes to write at an offset of the image
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Patch {
+    pub offset: usize,
+    pub data: Vec<u8>,
+}
+
+/// Runs of bytes that differ between two images of the same size
+pub fn diff(original: &[u8], edited: &[u8]) -> Result<Vec<Patch>, Error> {
+    if original.len() != edited.len() {
+        return Err(Error::InvalidArgument(format!(
+            "image sizes differ: {:#X} and {:#X}",
+            original.len(),
+            edited.len()
+        )));
+    }
+
+    let mut patches: Vec<Patch> = Vec::new();
+    let mut i = 0;
+    // Equal blocks are skipped with one comparison, so images that differ
+    // in a few places are diffed at the speed of memcmp
+    while let Some(start) = compare::first_difference(original, edited, i) {
+        i = start;
+        while i < edited.len() && original[i] != edited[i] {
+            i += 1;
+        }
+        match patches.last_mut() {
+            Some(last) if start - (last.offset + last.data.len()) < MERGE_DISTANCE => {
+                let end = last.offset + last.data.len();
+                last.data.extend_from_slice(&edited[end..i]);
+            }
+            _ => patches.push(Patch {
+                offset: start,
+                data: edited[start..i].to_vec(),
+            }),
+        }
+    }
+    Ok(patches)
+}
+
+/// Writes the patches to the image
+pub fn apply(image: &mut [u8], patches: &[Patch]) -> Result<(), Error> {
+    for patch in patches {
+        let end = patch.offset + patch.data.len();
+        if end > image.len() {
+            return Err(Error::InvalidArgument(format!(
+                "patch at {:#X} ends past the image at {:#X}",
+                patch.offset,
+                image.len()
+            )));
+        }
+        image[patch.offset..end].copy_from_slice(&patch.data);
+    }
+    Ok(())
+}
+
+/// Formats the patches as text, splitting long runs into several lines
+pub fn to_text(patches: &[Patch]) -> String {
+    let mut text = String::from("# romulan patch: offset and bytes to write, in hex\n");
+    for patch in patches {
+        for (i, chunk) in patch.data.chunks(LINE_BYTES).enumerate() {
+            let _ = write!(text, "{:08X} ", patch.offset + i * LINE_BYTES);
+            for byte in chunk {
+                let _ = write!(text, "{:02x}", byte);
+            }
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Parses patches from text written by `to_text`. Empty lines and lines
+/// starting with `#` are ignored.
+pub fn from_text(text: &str) -> Result<Vec<Patch>, Error> {
+    let mut patches = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || Error::Invalid(format!("line {}: invalid patch '{}'", number + 1, line));
+        let mut parts = line.split_whitespace();
+        let offset = parts
+            .next()
+            .and_then(|offset| usize::from_str_radix(offset, 16).ok())
+            .ok_or_else(invalid)?;
+        let hex = parts.next().ok_or_else(invalid)?;
+        if parts.next().is_some() || !hex.is_ascii() || hex.len() % 2 != 0 {
+            return Err(invalid());
+        }
+        let data = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        patches.push(Patch { offset, data });
+    }
+    Ok(patches)
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: MIT
+
+//! Types meant to be used by downstream crates, which are kept stable
+//! across releases. `use romulan::prelude::*;` imports all of them.
+//!
+//! Items reached through other paths may move or change between releases,
+//! in particular the raw structures of `intel` and the modules hidden from
+//! the documentation.
+
+pub use crate::amd::address::{FlashAddress, MmioAddress};
+pub use crate::amd::builder::RomBuilder;
+pub use crate::amd::directory::{
+    AddressMode, BiosComboDirectory, BiosDirectory, BiosDirectoryBuilder, BiosDirectoryEntry,
+    Checksum, ComboDirectoryEntry, Directory, PspComboDirectory, PspDirectory,
+    PspDirectoryBuilder, PspDirectoryEntry,
+};
+pub use crate::amd::flash::EFS;
+pub use crate::amd::platform::Platform;
+pub use crate::amd::stream::StreamedRom;
+pub use crate::amd::walk::RomVisitor;
+pub use crate::amd::Rom as AmdRom;
+pub use crate::error::Error;
+pub use crate::extract::{manifest, Extraction, ManifestEntry, Metadata, Naming};
+pub use crate::handler::{Field, Handler, Registry, Target};
+pub use crate::identify::{Database, Identity};
+pub use crate::image::{open, FirmwareImage, Vendor};
+pub use crate::intel::{RegionKind, Rom as IntelRom};
+pub use crate::layout::{Area, Layout};
+pub use crate::metainfo::Component;
+pub use crate::report::{AmdReport, IntelReport, Report, SCHEMA_VERSION};
+pub use crate::source::Source;
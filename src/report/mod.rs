@@ -0,0 +1,1045 @@
+// SPDX-License-Identifier: MIT
+
+//! Analysis of an image as plain data, filled in by the library for callers
+//! to render or serialize instead of walking the image themselves
+//!
+//! The JSON serialization of `Report` is a stable interface. Fields may be
+//! added at any time, but removing, renaming or changing the meaning of one
+//! requires bumping `SCHEMA_VERSION`.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::mem;
+use serde::{Deserialize, Serialize};
+
+use crate::amd::{
+    self,
+    directory::{
+        payload, zlib_stream, BiosDirectoryEntry, Checksum, Directory, PspDirectoryEntry,
+        PspKeyToken,
+    },
+    flash,
+    microcode::{self, MicrocodePatch},
+    platform::Platform,
+    usage::DirectoryUsage,
+    verify::SignatureCheck,
+    walk::RomVisitor,
+};
+use crate::cbfs::{self, Cbfs};
+use crate::fmap::Fmap;
+use crate::identify::{self, psp_version, Database, Identity};
+use crate::intel::{
+    self, descriptor, file, fit, me, sbl, section, volume, BiosSections, BiosVolumes,
+};
+use crate::wrapper::{self, Layer};
+use crate::Error;
+
+mod render;
+
+pub use self::render::render_cbfs_diff;
+
+/// Version of the serialized report, stored in `Report::schema_version`
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Analysis of an image, with a report for every vendor found in it
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Report {
+    /// `SCHEMA_VERSION` of the code that made the report
+    pub schema_version: u32,
+    /// Size of the image in bytes
+    pub size: usize,
+    /// Wrappers peeled off the image, outermost first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wrappers: Vec<Layer>,
+    pub intel: Option<IntelReport>,
+    pub amd: Option<AmdReport>,
+    /// Flash map of coreboot images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fmap: Option<FmapReport>,
+}
+
+impl Report {
+    /// Analyzes the image as both an Intel and an AMD image. A vendor whose
+    /// structures are not found or fail to parse has no report.
+    pub fn new(data: &[u8]) -> Self {
+        Self::with_database(data, &Database::new())
+    }
+
+    /// Like `new`, the AMD report naming the entries `database` knows
+    pub fn with_database(data: &[u8], database: &Database) -> Self {
+        Report {
+            schema_version: SCHEMA_VERSION,
+            size: data.len(),
+            wrappers: Vec::new(),
+            intel: intel::Rom::new(data)
+                .and_then(|rom| IntelReport::new(&rom))
+                .ok(),
+            amd: amd::Rom::new(data)
+                .ok()
+                .map(|rom| AmdReport::with_database(&rom, database)),
+            fmap: Fmap::find(data).map(|map| FmapReport::new(&map)),
+        }
+    }
+
+    /// Like `with_database`, analyzing the image wrapped by a vendor update
+    /// package or capsule after peeling the wrappers off
+    pub fn unwrapped(data: &[u8], database: &Database) -> Result<Self, Error> {
+        let (wrappers, data) = wrapper::unwrap(data)?;
+        Ok(Report {
+            wrappers,
+            ..Self::with_database(data, database)
+        })
+    }
+}
+
+/// Flash map and its areas. Offsets are in the image.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FmapReport {
+    /// Offset of the flash map
+    pub offset: usize,
+    pub name: String,
+    pub version: String,
+    /// Address the flash is mapped at
+    pub base: u64,
+    pub size: usize,
+    pub areas: Vec<FmapAreaReport>,
+}
+
+impl FmapReport {
+    pub fn new(map: &Fmap) -> Self {
+        let header = map.header();
+        FmapReport {
+            offset: map.offset(),
+            name: map.name(),
+            version: format!("{}.{}", header.version_major, header.version_minor),
+            base: header.base,
+            size: header.size as usize,
+            areas: map
+                .entries()
+                .iter()
+                .map(|entry| FmapAreaReport {
+                    name: entry.name(),
+                    offset: entry.offset as usize,
+                    size: entry.size as usize,
+                    flags: entry.flags,
+                    cbfs: map
+                        .area_data(entry)
+                        .and_then(|data| Cbfs::new(data).ok())
+                        .map(|cbfs| {
+                            cbfs.files()
+                                .iter()
+                                .map(|file| CbfsFileReport::new(file, entry.offset as usize))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FmapAreaReport {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+    /// `fmap::AREA_*` flags
+    pub flags: u16,
+    /// Files of the CBFS of the area, if it holds one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cbfs: Vec<CbfsFileReport>,
+}
+
+/// File of a CBFS, empty space included
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CbfsFileReport {
+    pub name: String,
+    /// `cbfs::TYPE_*` type
+    pub kind: u32,
+    pub description: String,
+    /// Offset of the file data in the image
+    pub offset: usize,
+    pub size: usize,
+    pub compression: String,
+    /// Size of the data decompressed, if the file tells it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decompressed_size: Option<usize>,
+}
+
+impl CbfsFileReport {
+    /// Report of a file of the CBFS of the area at `area_offset`
+    pub fn new(file: &cbfs::CbfsFile, area_offset: usize) -> Self {
+        let kind = file.header().kind();
+        let (compression, decompressed_size) = file.compression();
+        CbfsFileReport {
+            name: String::from(file.name()),
+            kind,
+            description: String::from(cbfs::type_name(kind)),
+            offset: area_offset + file.data_offset(),
+            size: file.data().len(),
+            compression: String::from(cbfs::compression_name(compression)),
+            decompressed_size,
+        }
+    }
+}
+
+/// Analysis of an Intel image, by region
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IntelReport {
+    pub high_assurance_platform: bool,
+    pub descriptor: DescriptorReport,
+    /// Firmware Interface Table, if the image has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fit: Option<FitReport>,
+    /// Microcode updates of the FIT and the BIOS volumes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub microcode: Vec<intel::microcode::Update>,
+    pub bios: Option<BiosReport>,
+    /// BIOS region 2 of descriptors with two BIOS regions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secondary_bios: Option<BiosReport>,
+    pub gbe: Option<GbeReport>,
+    pub me: Option<MeReport>,
+}
+
+impl IntelReport {
+    pub fn new(rom: &intel::Rom) -> Result<Self, Error> {
+        let bios = rom.bios()?.map(|bios| BiosReport::new(&bios));
+        let secondary_bios = rom.secondary_bios()?.map(|bios| BiosReport::new(&bios));
+
+        let gbe = rom.gbe()?.map(|gbe| GbeReport {
+            size: gbe.data().len(),
+            mac: gbe.mac(),
+        });
+
+        let me = rom.me()?.map(|me| MeReport {
+            size: me.data().len(),
+            advisories: me
+                .version()
+                .map(|version| {
+                    me::advisories(&version)
+                        .iter()
+                        .map(|advisory| String::from(advisory.id))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            version: me.version(),
+            partitions: match me::Fpt::new(me.data()) {
+                Ok(fpt) => fpt
+                    .entries()
+                    .iter()
+                    .map(|entry| PartitionReport {
+                        name: me::entry_name(&entry.name),
+                        offset: entry.offset as usize,
+                        length: entry.length as usize,
+                        modules: match fpt.partition(entry).map(me::Cpd::new) {
+                            Some(Ok(cpd)) => cpd
+                                .entries()
+                                .iter()
+                                .map(|module| ModuleReport {
+                                    name: me::entry_name(&module.name),
+                                    offset: module.offset(),
+                                    length: module.length as usize,
+                                    compressed: module.is_compressed(),
+                                })
+                                .collect(),
+                            _ => Vec::new(),
+                        },
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+        });
+
+        Ok(IntelReport {
+            high_assurance_platform: rom.high_assurance_platform()?,
+            descriptor: DescriptorReport::new(rom)?,
+            fit: rom.fit().ok().map(|fit| FitReport::new(rom.data(), &fit)),
+            microcode: intel::microcode::updates(rom),
+            bios,
+            secondary_bios,
+            gbe,
+            me,
+        })
+    }
+}
+
+/// Contents of the flash descriptor
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DescriptorReport {
+    pub version: descriptor::Version,
+    pub components: Vec<descriptor::Component>,
+    /// Regions and the padding between them, sorted by offset
+    pub regions: Vec<descriptor::RegionEntry>,
+    pub masters: Vec<descriptor::MasterAccess>,
+    pub pch_straps: Vec<u32>,
+    pub processor_straps: Vec<u32>,
+}
+
+impl DescriptorReport {
+    pub fn new(rom: &intel::Rom) -> Result<Self, Error> {
+        Ok(DescriptorReport {
+            version: rom.descriptor_version()?,
+            components: rom.components()?,
+            regions: rom.regions()?,
+            masters: rom.masters()?,
+            pch_straps: rom.pch_straps()?,
+            processor_straps: rom.processor_straps()?,
+        })
+    }
+}
+
+/// Firmware Interface Table and the components it points to
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FitReport {
+    /// Offset of the table in the image
+    pub offset: usize,
+    pub version: u16,
+    /// Whether the table checksum is valid, none if it is not checked
+    pub checksum_valid: Option<bool>,
+    pub entries: Vec<FitEntryReport>,
+}
+
+impl FitReport {
+    pub fn new(data: &[u8], fit: &fit::Fit) -> Self {
+        FitReport {
+            offset: fit.offset(),
+            version: fit.header().version,
+            checksum_valid: fit.checksum_valid(),
+            entries: fit
+                .entries()
+                .iter()
+                .map(|entry| FitEntryReport {
+                    kind: entry.kind(),
+                    description: String::from(entry.description()),
+                    address: entry.address,
+                    offset: fit.entry_offset(entry),
+                    size: entry.size() * 16,
+                    version: entry.version,
+                    microcode_revision: match entry.kind() {
+                        fit::MICROCODE => fit::microcode_revision(data, entry.address),
+                        _ => None,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Entry of the Firmware Interface Table
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FitEntryReport {
+    pub kind: u8,
+    pub description: String,
+    pub address: u64,
+    /// Offset of the address in the image, none if it is outside of it
+    pub offset: Option<usize>,
+    /// Size in bytes, zero for entries sized by their data, such as
+    /// microcode updates
+    pub size: usize,
+    pub version: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub microcode_revision: Option<u32>,
+}
+
+/// Firmware volumes and variable stores of the BIOS region. Offsets are in
+/// the BIOS region.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BiosReport {
+    pub size: usize,
+    /// Totals of the volumes of the region, nested volumes left out
+    pub usage: volume::Usage,
+    pub volumes: Vec<VolumeReport>,
+    pub variable_stores: Vec<VariableStoreReport>,
+    /// Slim Bootloader the region holds instead of firmware volumes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slim_bootloader: Option<SblReport>,
+}
+
+impl BiosReport {
+    pub fn new(bios: &intel::Bios) -> Self {
+        let volumes = volumes(bios.data(), 0);
+        BiosReport {
+            size: bios.data().len(),
+            usage: volume::Usage::total(volumes.iter().map(|volume| &volume.usage)),
+            volumes,
+            variable_stores: bios
+                .variable_stores()
+                .iter()
+                .map(|store| VariableStoreReport {
+                    offset: store.offset(),
+                    size: store.data().len(),
+                    authenticated: store.authenticated(),
+                    nvar: false,
+                    variables: store
+                        .variables()
+                        .filter(|var| var.active())
+                        .map(|var| VariableReport {
+                            guid: var.guid().to_string(),
+                            name: String::from(var.name()),
+                            size: var.data().len(),
+                            attributes: var.attributes(),
+                        })
+                        .collect(),
+                })
+                .chain(bios.nvar_stores().iter().map(|store| {
+                    VariableStoreReport {
+                        offset: store.offset(),
+                        size: store.data().len(),
+                        authenticated: false,
+                        nvar: true,
+                        variables: store
+                            .variables()
+                            .iter()
+                            .filter(|var| var.active())
+                            .map(|var| VariableReport {
+                                guid: var.guid().to_string(),
+                                name: String::from(var.name()),
+                                size: var.data().len(),
+                                attributes: var.attributes() as u32,
+                            })
+                            .collect(),
+                    }
+                }))
+                .collect(),
+            slim_bootloader: bios.slim_bootloader().map(|map| SblReport::new(&map)),
+        }
+    }
+}
+
+/// Flash map of a Slim Bootloader. Offsets are in the BIOS region.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SblReport {
+    /// Offset of the flash map
+    pub offset: usize,
+    /// Offset and size of the bootloader image, which ends at the end of
+    /// the region
+    pub base: usize,
+    pub size: usize,
+    pub components: Vec<SblComponentReport>,
+}
+
+impl SblReport {
+    pub fn new(map: &sbl::FlashMap) -> Self {
+        let components = map
+            .entries()
+            .iter()
+            .map(|entry| SblComponentReport {
+                name: entry.name(),
+                description: String::from(entry.description()),
+                offset: map.component_offset(entry),
+                size: entry.size as usize,
+                flags: entry.flags,
+                components: map
+                    .component(entry)
+                    .and_then(|data| sbl::Container::new(data).ok())
+                    .and_then(|container| {
+                        let entries = container.entries().ok()?;
+                        Some(
+                            entries
+                                .iter()
+                                .map(|component| SblComponentReport {
+                                    name: component.name(),
+                                    description: String::new(),
+                                    offset: map.component_offset(entry)
+                                        + container.component_offset(component),
+                                    size: component.size as usize,
+                                    flags: component.attribute as u32,
+                                    components: Vec::new(),
+                                })
+                                .collect(),
+                        )
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect();
+        SblReport {
+            offset: map.offset(),
+            base: map.base(),
+            size: map.header().rom_size as usize,
+            components,
+        }
+    }
+}
+
+/// Component of a Slim Bootloader flash map or of a container
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SblComponentReport {
+    /// Four character code of the component
+    pub name: String,
+    /// Description of flash map components, empty for those of containers
+    pub description: String,
+    pub offset: usize,
+    pub size: usize,
+    /// Flags of flash map components, attribute of those of containers
+    pub flags: u32,
+    /// Components of the container the component is, if it is one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<SblComponentReport>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VolumeReport {
+    /// Offset of the volume header
+    pub offset: usize,
+    pub guid: String,
+    pub header_length: u16,
+    /// Size of the volume data following the header
+    pub size: usize,
+    /// Bits of `volume::Attributes`
+    pub attributes: u32,
+    pub usage: volume::Usage,
+    pub files: Vec<FileReport>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileReport {
+    /// Offset of the file data following the header
+    pub offset: usize,
+    pub guid: String,
+    /// Size of the file data
+    pub size: usize,
+    pub kind: file::HeaderKind,
+    /// Bits of `file::Attributes`
+    pub attributes: u8,
+    pub alignment: u8,
+    /// Bits of `file::State`, with the erase polarity of the volume applied
+    pub state: u8,
+    /// Sections of sectioned files
+    pub sections: Vec<SectionReport>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SectionReport {
+    /// Offset of the section data following the header
+    pub offset: usize,
+    pub kind: section::HeaderKind,
+    /// Size of the section data
+    pub size: usize,
+    /// Header of GUID defined sections
+    pub guid_defined: Option<GuidDefinedReport>,
+    /// Volumes of volume image sections
+    pub volumes: Vec<VolumeReport>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GuidDefinedReport {
+    /// GUID of the tool the section data is encoded with
+    pub guid: String,
+    /// Size of the encoded data
+    pub size: usize,
+    /// Data of sections compressed with LZMA, decompressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decompressed: Option<DecompressedReport>,
+    /// Why the data of a section compressed with LZMA does not decompress
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decompress_error: Option<String>,
+}
+
+/// Decompressed data of a section
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DecompressedReport {
+    pub size: usize,
+    /// Sections of the decompressed data, offsets being in it
+    pub sections: Vec<SectionReport>,
+}
+
+/// A variable store and its active variables
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VariableStoreReport {
+    pub offset: usize,
+    pub size: usize,
+    pub authenticated: bool,
+    /// AMI NVAR store rather than a VSS store
+    #[serde(default)]
+    pub nvar: bool,
+    pub variables: Vec<VariableReport>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VariableReport {
+    pub guid: String,
+    pub name: String,
+    /// Size of the variable data
+    pub size: usize,
+    pub attributes: u32,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GbeReport {
+    pub size: usize,
+    /// MAC address of the first valid bank
+    pub mac: Option<[u8; 6]>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MeReport {
+    pub size: usize,
+    pub version: Option<String>,
+    /// IDs of the Intel security advisories affecting the version
+    pub advisories: Vec<String>,
+    /// Entries of the flash partition table, if one is found
+    pub partitions: Vec<PartitionReport>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PartitionReport {
+    pub name: String,
+    /// Offset of the partition in the ME region
+    pub offset: usize,
+    pub length: usize,
+    /// Modules of the code partition directory of code partitions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<ModuleReport>,
+}
+
+/// Module of a code partition directory
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ModuleReport {
+    pub name: String,
+    /// Offset of the module in the partition
+    pub offset: usize,
+    pub length: usize,
+    /// Whether the module is Huffman compressed
+    pub compressed: bool,
+}
+
+/// Analysis of an AMD image: its EFS and every directory reachable from it
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AmdReport {
+    pub platform: Platform,
+    pub efs_offset: usize,
+    pub efs: flash::EFS,
+    /// Offsets of the other EFS of the image, such as the copies of A/B
+    /// recovery layouts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_efs: Vec<usize>,
+    /// SPI settings of the EFS for the generation of the image
+    pub spi: flash::SpiConfig,
+    /// SPI settings of family 19h, if the EFS sets them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spi_19: Option<flash::SpiConfig>,
+    pub directories: Vec<DirectoryReport>,
+    /// Entries whose data is a known blob
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub components: Vec<ComponentReport>,
+    /// BIOS binaries (0x62) holding UEFI volumes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bios_binaries: Vec<BiosBinaryReport>,
+    /// Signatures of the signed PSP binaries and key tokens
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<SignatureCheck>,
+}
+
+impl AmdReport {
+    pub fn new(rom: &amd::Rom) -> Self {
+        Self::with_database(rom, &Database::new())
+    }
+
+    /// Like `new`, with the identity of every entry whose data `database`
+    /// knows
+    pub fn with_database(rom: &amd::Rom, database: &Database) -> Self {
+        let mut identifier = Identifier {
+            database,
+            components: Vec::new(),
+        };
+        if !database.is_empty() {
+            rom.walk(&mut identifier);
+        }
+        let mut collector = EntryCollector {
+            entries: Vec::new(),
+            binaries: Vec::new(),
+        };
+        rom.walk(&mut collector);
+        let usage = rom.directory_usage();
+        AmdReport {
+            platform: rom.platform(),
+            efs_offset: rom.efs_offset(),
+            efs: rom.efs(),
+            other_efs: amd::Rom::scan_all(rom.data())
+                .into_iter()
+                .map(|(offset, _)| offset)
+                .filter(|offset| *offset != rom.efs_offset())
+                .collect(),
+            spi: rom.platform().spi_config(&rom.efs()),
+            spi_19: rom.platform().spi_config_19(&rom.efs()),
+            directories: rom
+                .directories()
+                .iter()
+                .map(|(offset, directory)| DirectoryReport {
+                    offset: *offset,
+                    directory: directory.clone().into_owned(),
+                    checksum: directory.verify_checksum(),
+                    entries: collector
+                        .entries
+                        .iter()
+                        .filter(|(o, _)| o == offset)
+                        .map(|(_, entry)| entry.clone())
+                        .collect(),
+                    usage: usage
+                        .iter()
+                        .find(|(o, _)| o == offset)
+                        .map(|(_, usage)| *usage),
+                })
+                .collect(),
+            components: identifier.components,
+            bios_binaries: collector.binaries,
+            signatures: rom.verify_signatures(),
+        }
+    }
+}
+
+/// BIOS binary of an AMD image and the UEFI volumes in it, decompressed if
+/// it is compressed
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BiosBinaryReport {
+    /// Offset of the entry data in the image
+    pub offset: usize,
+    /// Size of the entry data in the image
+    pub size: usize,
+    pub compressed: bool,
+    /// Volumes of the binary, offsets being in the decompressed binary
+    pub volumes: Vec<VolumeReport>,
+}
+
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DirectoryReport {
+    /// Offset of the directory in the image
+    pub offset: usize,
+    pub directory: Directory<'static>,
+    /// Checksum stored in the header and computed over the directory
+    pub checksum: Checksum,
+    /// Where the data of each entry of a PSP or BIOS directory is and what
+    /// it is, in the order of the entries of `directory`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<EntryReport>,
+    /// Space of PSP and BIOS directories, combo directories have none
+    pub usage: Option<DirectoryUsage>,
+}
+
+/// Entry of a PSP or BIOS directory
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct EntryReport {
+    pub kind: u8,
+    pub sub_program: u8,
+    pub instance: u8,
+    pub description: String,
+    /// Offset of the entry data in the image, none if the entry holds a
+    /// value or its address does not resolve
+    pub offset: Option<usize>,
+    /// Size of the entry data, none if the entry holds a value
+    pub size: Option<usize>,
+    /// Version in the PSP binary header of the data, or patch level of a
+    /// microcode patch
+    pub version: Option<String>,
+    /// Key token of key entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PspKeyToken>,
+    /// Patches of microcode entries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub microcode: Vec<MicrocodePatch>,
+}
+
+impl EntryReport {
+    /// Reports a PSP entry, with the offset and data `RomVisitor` is given
+    pub fn psp(entry: &PspDirectoryEntry, offset: Option<usize>, data: Option<&[u8]>) -> Self {
+        EntryReport {
+            kind: entry.kind,
+            sub_program: entry.sub_program,
+            instance: entry.instance(),
+            description: String::from(entry.description()),
+            offset,
+            size: match entry.size {
+                0xFFFF_FFFF => None,
+                size => Some(size as usize),
+            },
+            version: data.and_then(psp_version),
+            key: data.filter(|_| entry.is_key()).and_then(PspKeyToken::new),
+            microcode: Vec::new(),
+        }
+    }
+
+    /// Reports a BIOS entry, like `psp`
+    pub fn bios(entry: &BiosDirectoryEntry, offset: Option<usize>, data: Option<&[u8]>) -> Self {
+        // Microcode patches start with their date and patch level
+        let version = match (entry.kind, data.and_then(|data| data.get(4..8))) {
+            (0x66, Some(level)) => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(level);
+                Some(format!("{:08X}", u32::from_le_bytes(bytes)))
+            }
+            _ => None,
+        };
+        EntryReport {
+            kind: entry.kind,
+            sub_program: entry.sub_program,
+            instance: entry.instance(),
+            description: String::from(entry.description()),
+            offset,
+            size: Some(entry.size as usize),
+            version,
+            // BIOS signing key
+            key: data
+                .filter(|_| entry.kind == 0x05)
+                .and_then(PspKeyToken::new),
+            microcode: match (entry.kind, data) {
+                (0x66, Some(data)) => microcode::patches(data),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+/// Collects the entries of every directory, with the offset of their
+/// directory
+struct EntryCollector {
+    entries: Vec<(usize, EntryReport)>,
+    binaries: Vec<BiosBinaryReport>,
+}
+
+impl RomVisitor for EntryCollector {
+    fn visit_psp_entry(
+        &mut self,
+        directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        self.entries
+            .push((directory, EntryReport::psp(entry, offset, data)));
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        directory: usize,
+        entry: &BiosDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        self.entries
+            .push((directory, EntryReport::bios(entry, offset, data)));
+
+        // A/B directories may point to the same binary
+        let (offset, data) = match (entry.kind, offset, data) {
+            (0x62, Some(offset), Some(data))
+                if self.binaries.iter().all(|binary| binary.offset != offset) =>
+            {
+                (offset, data)
+            }
+            _ => return,
+        };
+        if let Some(payload) = payload(data) {
+            let volumes = volumes(&payload, 0);
+            if !volumes.is_empty() {
+                self.binaries.push(BiosBinaryReport {
+                    offset,
+                    size: data.len(),
+                    compressed: zlib_stream(data).is_some(),
+                    volumes,
+                });
+            }
+        }
+    }
+}
+
+/// Directory entry whose data was identified
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ComponentReport {
+    /// Offset of the directory of the entry in the image
+    pub directory: usize,
+    pub kind: u8,
+    /// Offset of the entry data in the image
+    pub offset: usize,
+    pub size: usize,
+    /// Lowercase hex SHA-256 digest of the entry data
+    pub sha256: String,
+    pub identity: Identity,
+}
+
+/// Collects the entries whose data is in a database
+struct Identifier<'d> {
+    database: &'d Database,
+    components: Vec<ComponentReport>,
+}
+
+impl<'d> Identifier<'d> {
+    fn identify(&mut self, directory: usize, kind: u8, offset: Option<usize>, data: Option<&[u8]>) {
+        let (offset, data) = match (offset, data) {
+            (Some(offset), Some(data)) if !data.is_empty() => (offset, data),
+            _ => return,
+        };
+        let sha256 = identify::sha256(data);
+        if let Some(identity) = self.database.get(&sha256) {
+            self.components.push(ComponentReport {
+                directory,
+                kind,
+                offset,
+                size: data.len(),
+                sha256,
+                identity: identity.clone(),
+            });
+        }
+    }
+}
+
+impl<'d> RomVisitor for Identifier<'d> {
+    fn visit_psp_entry(
+        &mut self,
+        directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if !entry.is_directory() {
+            self.identify(directory, entry.kind, offset, data);
+        }
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        directory: usize,
+        entry: &BiosDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if entry.kind != 0x70 {
+            self.identify(directory, entry.kind, offset, data);
+        }
+    }
+}
+
+/// Reports the firmware volumes found in `data`, offsets being relative to
+/// `data` plus `offset`
+pub fn volumes(data: &[u8], offset: usize) -> Vec<VolumeReport> {
+    BiosVolumes::new(data)
+        .map(|volume| {
+            let header = volume.header();
+            let attributes = header.attributes();
+            let polarity = attributes.contains(volume::Attributes::ERASE_POLARITY);
+            let data_offset = offset + volume.offset() + header.header_length as usize;
+            VolumeReport {
+                offset: offset + volume.offset(),
+                guid: { header.guid }.to_string(),
+                header_length: header.header_length,
+                size: volume.data().len(),
+                attributes: attributes.bits(),
+                usage: volume.usage(),
+                files: volume
+                    .files()
+                    .map(|file| {
+                        let header = file.header();
+                        let file_offset =
+                            data_offset + file.offset() + mem::size_of::<file::Header>();
+                        FileReport {
+                            offset: file_offset,
+                            guid: { header.guid }.to_string(),
+                            size: file.data().len(),
+                            kind: header.kind(),
+                            attributes: header.attributes().bits(),
+                            alignment: header.alignment(),
+                            state: header.state(polarity).bits(),
+                            sections: if header.sectioned() {
+                                sections(file.data(), file_offset)
+                            } else {
+                                Vec::new()
+                            },
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// Reports the sections found in `data`, offsets being relative to `data`
+/// plus `offset`
+pub fn sections(data: &[u8], offset: usize) -> Vec<SectionReport> {
+    BiosSections::new(data)
+        .map(|section| {
+            let kind = section.header().kind();
+            let section_offset = offset + section.offset() + mem::size_of::<section::Header>();
+            let guid_defined = match kind {
+                section::HeaderKind::GuidDefined => {
+                    plain::from_bytes::<section::GuidDefined>(section.data())
+                        .ok()
+                        .map(|header| {
+                            let (decompressed, decompress_error) = match section.decompress() {
+                                Some(Ok(data)) => (
+                                    Some(DecompressedReport {
+                                        size: data.len(),
+                                        sections: sections(&data, 0),
+                                    }),
+                                    None,
+                                ),
+                                Some(Err(err)) => (None, Some(err.to_string())),
+                                None => (None, None),
+                            };
+                            GuidDefinedReport {
+                                guid: { header.guid }.to_string(),
+                                size: section
+                                    .data()
+                                    .len()
+                                    .saturating_sub(header.data_offset as usize),
+                                decompressed,
+                                decompress_error,
+                            }
+                        })
+                }
+                _ => None,
+            };
+            let volumes = match kind {
+                section::HeaderKind::VolumeImage => volumes(section.data(), section_offset),
+                _ => Vec::new(),
+            };
+            SectionReport {
+                offset: section_offset,
+                kind,
+                size: section.data().len(),
+                guid_defined,
+                volumes,
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_in_schema_deserializes() {
+        let report: Report = serde_json::from_str(include_str!("../../schema/report.json"))
+            .expect("schema/report.json does not match Report");
+        assert_eq!(report.schema_version, SCHEMA_VERSION);
+        assert!(report.amd.is_some());
+    }
+}
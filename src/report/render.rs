@@ -0,0 +1,826 @@
+// SPDX-License-Identifier: MIT
+
+//! Text rendering of the reports, as the command line tool prints them
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+use super::{
+    AmdReport, BiosReport, DescriptorReport, EntryReport, FileReport, FitReport, FmapReport,
+    IntelReport, Report, SectionReport, VolumeReport,
+};
+use crate::amd;
+use crate::cbfs;
+use crate::fmap;
+use crate::intel::{self, file, volume};
+
+impl Report {
+    /// Renders the wrappers peeled off the image and the analysis of every
+    /// vendor found in it
+    pub fn render(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        self.render_with(w, false)
+    }
+
+    /// Like `render`, with the volumes of AMD BIOS binaries
+    pub fn render_verbose(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        self.render_with(w, true)
+    }
+
+    fn render_with(&self, w: &mut impl fmt::Write, verbose: bool) -> fmt::Result {
+        for layer in self.wrappers.iter() {
+            match &layer.name {
+                Some(name) => writeln!(
+                    w,
+                    "{}: {} at {:#X}, {} bytes",
+                    layer.wrapper, name, layer.offset, layer.size
+                )?,
+                None => writeln!(
+                    w,
+                    "{}: image at {:#X}, {} bytes",
+                    layer.wrapper, layer.offset, layer.size
+                )?,
+            }
+        }
+        if let Some(intel) = &self.intel {
+            intel.render(w)?;
+        }
+        if let Some(amd) = &self.amd {
+            amd.render_with(w, verbose)?;
+        }
+        if let Some(fmap) = &self.fmap {
+            fmap.render(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
+
+impl IntelReport {
+    /// Renders the HAP bit, the descriptor, the FIT, the microcode updates
+    /// and the regions
+    pub fn render(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        if self.high_assurance_platform {
+            writeln!(w, "  HAP: set")?;
+        } else {
+            writeln!(w, "  HAP: not set")?;
+        }
+        render_descriptor(w, &self.descriptor)?;
+        if let Some(fit) = &self.fit {
+            render_fit(w, fit)?;
+        }
+        render_microcode(w, &self.microcode)?;
+
+        if let Some(bios) = &self.bios {
+            render_bios(w, "BIOS", bios)?;
+        } else {
+            writeln!(w, "  BIOS: None")?;
+        }
+        if let Some(bios) = &self.secondary_bios {
+            render_bios(w, "Secondary BIOS", bios)?;
+        }
+
+        if let Some(gbe) = &self.gbe {
+            writeln!(w, "  GbE: {} K", gbe.size / 1024)?;
+            if let Some(mac) = &gbe.mac {
+                writeln!(w, "    MAC: {}", format_mac(mac))?;
+            } else {
+                writeln!(w, "    MAC: Unknown")?;
+            }
+        } else {
+            writeln!(w, "  GbE: None")?;
+        }
+
+        if let Some(me) = &self.me {
+            writeln!(w, "  ME: {} K", me.size / 1024)?;
+            if let Some(version) = &me.version {
+                writeln!(w, "    Version: {}", version)?;
+            } else {
+                writeln!(w, "    Version: Unknown")?;
+            }
+            for advisory in me.advisories.iter() {
+                writeln!(w, "    Affected by: {}", advisory)?;
+            }
+            for partition in me.partitions.iter() {
+                writeln!(
+                    w,
+                    "    {}: {:#X}, {} K",
+                    partition.name,
+                    partition.offset,
+                    partition.length / 1024
+                )?;
+                for module in partition.modules.iter() {
+                    write!(
+                        w,
+                        "      {}: {:#X}, {:#X} bytes",
+                        module.name, module.offset, module.length
+                    )?;
+                    if module.compressed {
+                        writeln!(w, ", compressed")?;
+                    } else {
+                        writeln!(w)?;
+                    }
+                }
+            }
+        } else {
+            writeln!(w, "  ME: None")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for IntelReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
+
+fn render_descriptor(w: &mut impl fmt::Write, descriptor: &DescriptorReport) -> fmt::Result {
+    writeln!(w, "  Descriptor: {:?}", descriptor.version)?;
+    for component in descriptor.components.iter() {
+        match component.density {
+            Some(density) => {
+                writeln!(w, "    Component {}: {} K", component.index, density / 1024)?
+            }
+            None => writeln!(w, "    Component {}: Unknown density", component.index)?,
+        }
+    }
+    for region in descriptor.regions.iter() {
+        writeln!(
+            w,
+            "    {:#010X}..{:#010X} {}",
+            region.offset,
+            region.offset + region.size,
+            region.name
+        )?;
+    }
+    for master in descriptor.masters.iter() {
+        writeln!(w, "    {} master: {:08X}", master.name, master.value)?;
+        writeln!(w, "      Read: {}", master.read.join(", "))?;
+        writeln!(w, "      Write: {}", master.write.join(", "))?;
+    }
+    for (i, strap) in descriptor.pch_straps.iter().enumerate() {
+        writeln!(w, "    PCHSTRAP{}: {:08X}", i, strap)?;
+    }
+    for (i, strap) in descriptor.processor_straps.iter().enumerate() {
+        writeln!(w, "    PROCSTRAP{}: {:08X}", i, strap)?;
+    }
+    Ok(())
+}
+
+fn render_fit(w: &mut impl fmt::Write, fit: &FitReport) -> fmt::Result {
+    write!(
+        w,
+        "  FIT at {:#X}: {} entries",
+        fit.offset,
+        fit.entries.len()
+    )?;
+    match fit.checksum_valid {
+        Some(true) => writeln!(w, ", checksum ok")?,
+        Some(false) => writeln!(w, ", checksum invalid")?,
+        None => writeln!(w)?,
+    }
+    for entry in fit.entries.iter() {
+        write!(
+            w,
+            "    {:08X} {:02X} {}",
+            entry.address, entry.kind, entry.description
+        )?;
+        if entry.size > 0 {
+            write!(w, ", size {:#X}", entry.size)?;
+        }
+        match entry.microcode_revision {
+            Some(revision) => writeln!(w, ", revision {:#X}", revision)?,
+            None => writeln!(w)?,
+        }
+    }
+    Ok(())
+}
+
+fn render_microcode(w: &mut impl fmt::Write, updates: &[intel::microcode::Update]) -> fmt::Result {
+    if updates.is_empty() {
+        return Ok(());
+    }
+    writeln!(w, "  Microcode: {} updates", updates.len())?;
+    for update in updates.iter() {
+        write!(
+            w,
+            "    {:08X} CPUID {:08X}, flags {:#04X}, revision {:#X}, {}, {:#X} bytes",
+            update.offset,
+            update.cpuid,
+            update.processor_flags,
+            update.revision,
+            update.date.as_deref().unwrap_or("no date"),
+            update.size
+        )?;
+        for signature in update.extended_signatures.iter() {
+            write!(w, ", CPUID {:08X}", signature.cpuid)?;
+        }
+        if update.in_fit {
+            write!(w, ", in FIT")?;
+        }
+        if update.checksum_valid {
+            writeln!(w)?;
+        } else {
+            writeln!(w, ", checksum invalid")?;
+        }
+    }
+    Ok(())
+}
+
+fn render_bios(w: &mut impl fmt::Write, name: &str, bios: &BiosReport) -> fmt::Result {
+    writeln!(w, "  {}: {} K", name, bios.size / 1024)?;
+    render_usage(w, &bios.usage, "    ")?;
+    for volume in bios.volumes.iter() {
+        render_volume(w, volume, "    ")?;
+    }
+    for store in bios.variable_stores.iter() {
+        let format = if store.nvar { "NVAR" } else { "VSS" };
+        writeln!(
+            w,
+            "  NVRAM: {:#X}, {} K, {}",
+            store.offset,
+            store.size / 1024,
+            format
+        )?;
+        for var in store.variables.iter() {
+            writeln!(
+                w,
+                "    {}:{}: {} B, Attrib: {:#X}",
+                var.guid, var.name, var.size, var.attributes
+            )?;
+        }
+    }
+    if let Some(sbl) = &bios.slim_bootloader {
+        writeln!(
+            w,
+            "    Slim Bootloader: {:#X}, {} K, flash map at {:#X}",
+            sbl.base,
+            sbl.size / 1024,
+            sbl.offset
+        )?;
+        for component in sbl.components.iter() {
+            writeln!(
+                w,
+                "      {} {}: {:#X}, {} K, flags {:#X}",
+                component.name,
+                component.description,
+                component.offset,
+                component.size / 1024,
+                component.flags
+            )?;
+            for inner in component.components.iter() {
+                writeln!(
+                    w,
+                    "        {}: {:#X}, {} B",
+                    inner.name, inner.offset, inner.size
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn render_usage(w: &mut impl fmt::Write, usage: &volume::Usage, padding: &str) -> fmt::Result {
+    writeln!(
+        w,
+        "{}Used: {} K, Free: {} K, Largest free: {} K",
+        padding,
+        usage.used / 1024,
+        usage.free / 1024,
+        usage.largest_free / 1024
+    )
+}
+
+fn render_volume(w: &mut impl fmt::Write, volume: &VolumeReport, padding: &str) -> fmt::Result {
+    writeln!(
+        w,
+        "{}{}: {}, {} K",
+        padding,
+        volume.guid,
+        volume.header_length,
+        volume.size / 1024
+    )?;
+    writeln!(
+        w,
+        "{}  Attrib: {:?}",
+        padding,
+        volume::Attributes::from_bits_truncate(volume.attributes)
+    )?;
+    render_usage(w, &volume.usage, &format!("{}  ", padding))?;
+
+    for file in volume.files.iter() {
+        render_file(w, file, &format!("{}    ", padding))?;
+    }
+    Ok(())
+}
+
+fn render_file(w: &mut impl fmt::Write, file: &FileReport, padding: &str) -> fmt::Result {
+    writeln!(w, "{}{}: {} K", padding, file.guid, file.size / 1024)?;
+    writeln!(w, "{}  Kind: {:?}", padding, file.kind)?;
+    writeln!(
+        w,
+        "{}  Attrib: {:?}",
+        padding,
+        file::Attributes::from_bits_truncate(file.attributes)
+    )?;
+    writeln!(w, "{}  Align: {}", padding, file.alignment)?;
+    writeln!(
+        w,
+        "{}  State: {:?}",
+        padding,
+        file::State::from_bits_truncate(file.state)
+    )?;
+
+    for section in file.sections.iter() {
+        render_section(w, section, &format!("{}    ", padding))?;
+    }
+    Ok(())
+}
+
+fn render_section(w: &mut impl fmt::Write, section: &SectionReport, padding: &str) -> fmt::Result {
+    writeln!(
+        w,
+        "{}{:?}:  {} K",
+        padding,
+        section.kind,
+        section.size / 1024
+    )?;
+
+    if let Some(guid_defined) = &section.guid_defined {
+        let padding = format!("{}    ", padding);
+        writeln!(
+            w,
+            "{}  {}: {} K",
+            padding,
+            guid_defined.guid,
+            guid_defined.size / 1024
+        )?;
+
+        let padding = format!("{}    ", padding);
+        if let Some(decompressed) = &guid_defined.decompressed {
+            writeln!(w, "{}Decompressed: {} K", padding, decompressed.size / 1024)?;
+            for section in decompressed.sections.iter() {
+                render_section(w, section, &format!("{}    ", padding))?;
+            }
+        }
+        if let Some(err) = &guid_defined.decompress_error {
+            writeln!(w, "{}Error: {}", padding, err)?;
+        }
+    }
+
+    for volume in section.volumes.iter() {
+        render_volume(w, volume, &format!("{}    ", padding))?;
+    }
+    Ok(())
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+impl AmdReport {
+    /// Renders the EFS, the space of the directories, the known components,
+    /// the keys, the microcode patches, the BIOS binaries and the
+    /// signatures
+    pub fn render(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        self.render_with(w, false)
+    }
+
+    /// Like `render`, with the volumes of the BIOS binaries
+    pub fn render_verbose(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        self.render_with(w, true)
+    }
+
+    fn render_with(&self, w: &mut impl fmt::Write, verbose: bool) -> fmt::Result {
+        #[cfg(feature = "std")]
+        writeln!(
+            w,
+            "{}",
+            serde_json::to_string(&self.efs).map_err(|_| fmt::Error)?
+        )?;
+        let generation = match self.platform.generation {
+            amd::platform::Generation::First => "first",
+            amd::platform::Generation::Second => "second",
+        };
+        writeln!(
+            w,
+            "EFS at {:#X}, {} generation",
+            self.efs_offset, generation
+        )?;
+        if !self.other_efs.is_empty() {
+            let offsets: Vec<String> = self
+                .other_efs
+                .iter()
+                .map(|offset| format!("{:#X}", offset))
+                .collect();
+            writeln!(w, "  other EFS at {}", offsets.join(", "))?;
+        }
+        for (field, pointer) in self.efs.pointer_fields().iter() {
+            if *field != "second_gen" && amd::flash::pointer_set(*pointer) {
+                writeln!(w, "  {:<22} {:08X}", field, pointer)?;
+            }
+        }
+        writeln!(w, "  SPI {}", self.spi)?;
+        if let Some(spi) = &self.spi_19 {
+            writeln!(w, "  SPI family 19h {}", spi)?;
+        }
+        for directory in self.directories.iter() {
+            let usage = match &directory.usage {
+                Some(usage) => usage,
+                None => continue,
+            };
+            write!(
+                w,
+                "{:08X} {}: entries {:#X}, span {:#X}..{:#X}",
+                directory.offset,
+                directory.directory.name(),
+                usage.entries,
+                usage.start,
+                usage.end
+            )?;
+            match usage.headroom {
+                Some(headroom) => {
+                    writeln!(w, ", headroom {:#X} of {:#X}", headroom, usage.max_size)?
+                }
+                None => writeln!(w)?,
+            }
+        }
+        for component in self.components.iter() {
+            write!(
+                w,
+                "{:08X} {:02X} {}",
+                component.offset, component.kind, component.identity.name
+            )?;
+            match &component.identity.version {
+                Some(version) => writeln!(w, " {}", version)?,
+                None => writeln!(w)?,
+            }
+        }
+        // Key entries, once for those shared between directories
+        let mut keys = Vec::new();
+        for entry in self
+            .directories
+            .iter()
+            .flat_map(|directory| directory.entries.iter())
+        {
+            if let (Some(offset), Some(key)) = (entry.offset, &entry.key) {
+                if !keys.contains(&offset) {
+                    keys.push(offset);
+                    writeln!(
+                        w,
+                        "{:08X} {:02X} {}: {}",
+                        offset, entry.kind, entry.description, key
+                    )?;
+                }
+            }
+        }
+        // Microcode patches, once per entry like keys
+        let mut patches = Vec::new();
+        for entry in self
+            .directories
+            .iter()
+            .flat_map(|directory| directory.entries.iter())
+        {
+            let offset = match entry.offset {
+                Some(offset) if !entry.microcode.is_empty() && !patches.contains(&offset) => offset,
+                _ => continue,
+            };
+            patches.push(offset);
+            for patch in entry.microcode.iter() {
+                writeln!(
+                    w,
+                    "{:08X} {:02X} {}: {}",
+                    offset + patch.offset,
+                    entry.kind,
+                    entry.description,
+                    patch
+                )?;
+            }
+        }
+        for binary in self.bios_binaries.iter() {
+            write!(
+                w,
+                "{:08X} 62 BIOS Binary: {} volumes",
+                binary.offset,
+                binary.volumes.len()
+            )?;
+            if binary.compressed {
+                writeln!(w, ", compressed")?;
+            } else {
+                writeln!(w)?;
+            }
+            if !verbose {
+                continue;
+            }
+            for volume in binary.volumes.iter() {
+                render_volume(w, volume, "    ")?;
+            }
+        }
+        for check in self.signatures.iter() {
+            writeln!(
+                w,
+                "{:08X} {:02X} signature by key {}: {}",
+                check.offset, check.kind, check.key_id, check.status
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AmdReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
+
+impl FmapReport {
+    /// Renders the map, its areas and their CBFS files
+    pub fn render(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(
+            w,
+            "FMAP at {:#X}: {}, version {}, {:#X} bytes at {:#X}",
+            self.offset, self.name, self.version, self.size, self.base
+        )?;
+        for area in self.areas.iter() {
+            write!(w, "  {:08X} {:08X} {}", area.offset, area.size, area.name)?;
+            let flags = fmap::flag_names(area.flags);
+            if flags.is_empty() {
+                writeln!(w)?;
+            } else {
+                writeln!(w, ", {}", flags.join(", "))?;
+            }
+            for file in area.cbfs.iter() {
+                let name = match file.name.as_str() {
+                    "" => "(empty)",
+                    name => name,
+                };
+                write!(
+                    w,
+                    "    {:08X} {:08X} {}: {}",
+                    file.offset, file.size, name, file.description
+                )?;
+                match file.decompressed_size {
+                    _ if file.compression == "none" => writeln!(w)?,
+                    Some(size) => writeln!(w, ", {} to {:#X} bytes", file.compression, size)?,
+                    None => writeln!(w, ", {}", file.compression)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FmapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
+
+/// Describes an AMD directory entry on one line
+impl fmt::Display for EntryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02X}.{:02X}.{:X} {}",
+            self.kind, self.sub_program, self.instance, self.description
+        )?;
+        if let Some(offset) = self.offset {
+            write!(f, " at {:#X}", offset)?;
+        }
+        if let Some(size) = self.size {
+            write!(f, ", {:#X} bytes", size)?;
+        }
+        // The version of microcode entries is the level of their first patch
+        match &self.version {
+            Some(version) if self.microcode.is_empty() => write!(f, ", version {}", version)?,
+            _ => (),
+        }
+        for patch in self.microcode.iter() {
+            write!(
+                f,
+                ", microcode {:#010X} ({})",
+                patch.patch_level, patch.date
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Describes an Intel BIOS file on one line
+impl fmt::Display for intel::diff::FileEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:#X}, {:#X} bytes, sha256 {}",
+            self.guid, self.offset, self.size, self.sha256
+        )
+    }
+}
+
+/// Describes an ME module on one line
+impl fmt::Display for intel::diff::MeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:#X}, {:#X} bytes, sha256 {}",
+            self.name, self.offset, self.length, self.sha256
+        )
+    }
+}
+
+/// Describes a CBFS file before (-) and after (+), a line for each
+impl fmt::Display for cbfs::FileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (sign, entry) in [("-", &self.before), ("+", &self.after)] {
+            if let Some(entry) = entry {
+                writeln!(
+                    f,
+                    "  {} {}: {} at {:#X}, {:#X} bytes, sha256 {}",
+                    sign, entry.name, entry.kind, entry.offset, entry.size, entry.sha256
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders the CBFS files before (-) and after (+) under each area
+pub fn render_cbfs_diff(w: &mut impl fmt::Write, diff: &[cbfs::FileDiff]) -> fmt::Result {
+    let mut area = None;
+    for file in diff.iter() {
+        let entry = match file.before.as_ref().or(file.after.as_ref()) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if area != Some(&entry.area) {
+            writeln!(w, "CBFS {}:", entry.area)?;
+            area = Some(&entry.area);
+        }
+        write!(w, "{}", file)?;
+    }
+    Ok(())
+}
+
+fn render_offset(offset: Option<usize>) -> String {
+    match offset {
+        Some(offset) => format!("{:#X}", offset),
+        None => String::from("none"),
+    }
+}
+
+impl intel::diff::RomDiff {
+    /// Renders the regions, ME version and partitions, microcode revisions,
+    /// BIOS volumes and files and CBFS files that differ, as the modules
+    /// and files before (-) and after (+) under each partition, volume and
+    /// CBFS area
+    pub fn render(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        let range = |region: &Option<intel::descriptor::RegionEntry>| match region {
+            Some(region) => format!("{:#X}..{:#X}", region.offset, region.offset + region.size),
+            None => String::from("none"),
+        };
+        for region in self.regions.iter() {
+            write!(
+                w,
+                "{}: {} -> {}",
+                region.name,
+                range(&region.before),
+                range(&region.after)
+            )?;
+            if region.data_changed {
+                writeln!(w, ", data changed")?;
+            } else {
+                writeln!(w)?;
+            }
+        }
+        if let Some(me) = &self.me {
+            let version = |version: &Option<String>| match version {
+                Some(version) => version.clone(),
+                None => String::from("none"),
+            };
+            writeln!(
+                w,
+                "ME version: {} -> {}",
+                version(&me.before),
+                version(&me.after)
+            )?;
+        }
+        let entry = |entry: &Option<intel::diff::MeEntry>| match entry {
+            Some(entry) => format!("{:#X}..{:#X}", entry.offset, entry.offset + entry.length),
+            None => String::from("none"),
+        };
+        for partition in self.me_partitions.iter() {
+            writeln!(
+                w,
+                "ME partition {}: {} -> {}",
+                partition.name,
+                entry(&partition.before),
+                entry(&partition.after)
+            )?;
+            for module in partition.modules.iter() {
+                if let Some(before) = &module.before {
+                    writeln!(w, "  - {}", before)?;
+                }
+                if let Some(after) = &module.after {
+                    writeln!(w, "  + {}", after)?;
+                }
+            }
+        }
+        let revision = |revision: Option<u32>| match revision {
+            Some(revision) => format!("{:#X}", revision),
+            None => String::from("none"),
+        };
+        for microcode in self.microcode.iter() {
+            writeln!(
+                w,
+                "Microcode CPUID {:08X}: {} -> {}",
+                microcode.cpuid,
+                revision(microcode.before),
+                revision(microcode.after)
+            )?;
+        }
+        for volume in self.volumes.iter() {
+            writeln!(
+                w,
+                "{}: {} -> {}",
+                volume.guid,
+                render_offset(volume.before),
+                render_offset(volume.after)
+            )?;
+            for file in volume.files.iter() {
+                if let Some(before) = &file.before {
+                    writeln!(w, "  - {}", before)?;
+                }
+                if let Some(after) = &file.after {
+                    writeln!(w, "  + {}", after)?;
+                }
+            }
+        }
+        render_cbfs_diff(w, &self.cbfs)
+    }
+}
+
+impl fmt::Display for intel::diff::RomDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
+
+impl amd::diff::RomDiff {
+    /// Renders the EFS pointers, directories, entries and CBFS files that
+    /// differ, as the entries before (-) and after (+) under each directory
+    /// and the files under each CBFS area. Directories that failed to parse
+    /// are left out.
+    pub fn render(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        for efs in self.efs.iter() {
+            writeln!(
+                w,
+                "EFS {}: {:08X} -> {:08X}",
+                efs.field, efs.before, efs.after
+            )?;
+        }
+        for directory in self.directories.iter() {
+            writeln!(
+                w,
+                "{}: {} -> {}",
+                directory.path,
+                render_offset(directory.before),
+                render_offset(directory.after)
+            )?;
+            for (side, checksum) in [
+                ("before", directory.before_checksum),
+                ("after", directory.after_checksum),
+            ] {
+                if let Some(checksum) = checksum.filter(|checksum| !checksum.is_valid()) {
+                    writeln!(
+                        w,
+                        "  checksum {:08X} invalid {}, computed {:08X}",
+                        checksum.stored, side, checksum.computed
+                    )?;
+                }
+            }
+            for entry in directory.entries.iter() {
+                if let Some(before) = &entry.before {
+                    writeln!(w, "  - {}", before)?;
+                }
+                if let Some(after) = &entry.after {
+                    writeln!(w, "  + {}", after)?;
+                }
+            }
+        }
+        render_cbfs_diff(w, &self.cbfs)
+    }
+}
+
+impl fmt::Display for amd::diff::RomDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f)
+    }
+}
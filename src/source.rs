@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: MIT
+
+//! Images read on demand, for parsers that only need the bytes of the
+//! structures they resolve instead of the whole image in memory
+
+use alloc::vec::Vec;
+
+use crate::Error;
+
+/// Random access to the bytes of an image
+pub trait Source {
+    /// Size of the image in bytes
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills `buf` with the bytes at `offset`, failing if they are not all in
+    /// the image
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Reads `size` bytes at `offset`
+    fn read(&self, offset: usize, size: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0; size];
+        self.read_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Range of `size` bytes at `offset` in an image of `len` bytes
+fn checked(offset: usize, size: usize, len: usize) -> Result<core::ops::Range<usize>, Error> {
+    match offset.checked_add(size) {
+        Some(end) if end <= len => Ok(offset..end),
+        _ => Err(Error::Truncated {
+            need: offset.saturating_add(size),
+            have: len,
+        }),
+    }
+}
+
+impl Source for [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        let range = checked(offset, buf.len(), <[u8]>::len(self))?;
+        buf.copy_from_slice(&self[range]);
+        Ok(())
+    }
+}
+
+impl Source for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        self.as_slice().read_at(offset, buf)
+    }
+}
+
+impl<S: Source + ?Sized> Source for &S {
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        (**self).read_at(offset, buf)
+    }
+}
+
+/// Image read from a file or any other seekable reader
+#[cfg(feature = "std")]
+pub struct Reader<R> {
+    inner: core::cell::RefCell<R>,
+    len: usize,
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + std::io::Seek> Reader<R> {
+    /// Reads the image from `inner`, which holds it from its start to its end
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let len = inner
+            .seek(std::io::SeekFrom::End(0))
+            .map_err(|err| Error::Invalid(format!("failed to seek: {}", err)))?;
+        Ok(Reader {
+            inner: core::cell::RefCell::new(inner),
+            len: len as usize,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + std::io::Seek> Source for Reader<R> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        checked(offset, buf.len(), self.len)?;
+        let mut inner = self.inner.borrow_mut();
+        inner
+            .seek(std::io::SeekFrom::Start(offset as u64))
+            .and_then(|_| inner.read_exact(buf))
+            .map_err(|err| {
+                Error::Invalid(format!(
+                    "failed to read {:#X} bytes at {:#X}: {}",
+                    buf.len(),
+                    offset,
+                    err
+                ))
+            })
+    }
+}
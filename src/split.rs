@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: MIT
+
+use romulan::amd;
+use romulan::cbfs;
+use romulan::extract::{self, Extraction, Naming};
+use romulan::fmap::Fmap;
+use romulan::handler::Registry;
+use romulan::identify::sha256;
+use romulan::image;
+use romulan::intel;
+use romulan::layout::Layout;
+use romulan::wrapper;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path};
+
+#[derive(Deserialize, Serialize)]
+struct ManifestArea {
+    name: String,
+    offset: usize,
+    size: usize,
+    file: String,
+    /// Lowercase hex SHA-256 digest of the file, checked when assembling.
+    /// Manifests written before it was added lack it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+/// Describes how to reassemble an image from the files written by `split`
+#[derive(Deserialize, Serialize)]
+struct Manifest {
+    size: usize,
+    areas: Vec<ManifestArea>,
+}
+
+/// Areas of an Intel image by flash region, or of an AMD image by directory
+/// and entry
+pub fn layout(data: &[u8]) -> Result<Layout, String> {
+    let image = image::open(data)?;
+    let layout = image.regions()?;
+    let offset = data.len() - image.data().len();
+    if offset == 0 {
+        return Ok(layout);
+    }
+    // The structures were found past the start of the data
+    let mut shifted = Layout::new(data.len());
+    for area in layout.areas() {
+        shifted.insert(area.name.clone(), offset + area.offset, area.size);
+    }
+    Ok(shifted)
+}
+
+/// Writes every area of the image to its own file in `dir`, along with a
+/// `manifest.json` describing the layout and the digest of every file
+pub fn split(data: &[u8], dir: &Path) -> Result<(), String> {
+    let mut layout = layout(data)?;
+    layout.fill_gaps();
+
+    fs::create_dir_all(dir)
+        .map_err(|err| format!("failed to create {}: {}", dir.display(), err))?;
+
+    let mut manifest = Manifest {
+        size: layout.size(),
+        areas: Vec::new(),
+    };
+    for area in layout.areas() {
+        let file = format!(
+            "{:08X}_{}.bin",
+            area.offset,
+            area.name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+        );
+        let path = dir.join(&file);
+        let contents = &data[area.offset..area.end()];
+        fs::write(&path, contents)
+            .map_err(|err| format!("failed to write {}: {}", path.display(), err))?;
+        manifest.areas.push(ManifestArea {
+            name: area.name.clone(),
+            offset: area.offset,
+            size: area.size,
+            file,
+            sha256: Some(sha256(contents)),
+        });
+    }
+
+    let path = dir.join("manifest.json");
+    let json = serde_json::to_string_pretty(&manifest).map_err(|err| err.to_string())?;
+    fs::write(&path, json).map_err(|err| format!("failed to write {}: {}", path.display(), err))
+}
+
+/// Rebuilds an image from a manifest written by `split`. Every byte of the
+/// image must be covered by exactly one area, and every file must match the
+/// digest the manifest lists for it.
+pub fn assemble(manifest_path: &Path) -> Result<Vec<u8>, String> {
+    let json = fs::read_to_string(manifest_path)
+        .map_err(|err| format!("failed to read {}: {}", manifest_path.display(), err))?;
+    let manifest: Manifest = serde_json::from_str(&json)
+        .map_err(|err| format!("failed to parse {}: {}", manifest_path.display(), err))?;
+    let dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut layout = Layout::new(manifest.size);
+    let mut data = vec![0xFF; manifest.size];
+    for area in manifest.areas.iter() {
+        if !layout.insert(area.name.clone(), area.offset, area.size) {
+            return Err(format!(
+                "area {} at {:#X} overlaps another area or is out of bounds",
+                area.name, area.offset
+            ));
+        }
+        let path = dir.join(area_file(&area.file)?);
+        let contents =
+            fs::read(&path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        if contents.len() != area.size {
+            return Err(format!(
+                "{} is {:#X} bytes, expected {:#X}",
+                path.display(),
+                contents.len(),
+                area.size
+            ));
+        }
+        if let Some(expected) = &area.sha256 {
+            let digest = sha256(&contents);
+            if digest != *expected {
+                return Err(format!(
+                    "{} has SHA-256 {}, expected {}",
+                    path.display(),
+                    digest,
+                    expected
+                ));
+            }
+        }
+        data[area.offset..area.offset + area.size].copy_from_slice(&contents);
+    }
+
+    if let Some((offset, size)) = layout.gaps().first() {
+        return Err(format!("{:#X} bytes at {:#X} not covered by any area", size, offset));
+    }
+    Ok(data)
+}
+
+/// Checks that the file of an area names a file of the manifest directory,
+/// as `split` writes them, so a manifest can not read files elsewhere
+fn area_file(file: &str) -> Result<&Path, String> {
+    let path = Path::new(file);
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(path),
+        _ => Err(format!("invalid area file {:?}", file)),
+    }
+}
+
+/// Components of an Intel image by region, volume, file and section, or of
+/// an AMD image by directory and entry, entries being named by their type,
+/// sub program and instance, along with the files of the CBFS areas of a
+/// flash map, which images of neither vendor may have alone
+fn extraction(data: &[u8]) -> Result<Extraction, String> {
+    let (_, data) = wrapper::unwrap(data)?;
+    let map = Fmap::find(data);
+    let mut extraction = if let Ok(rom) = intel::Rom::new(data) {
+        rom.extract_all()
+    } else {
+        match amd::Rom::new(data) {
+            Ok(rom) => rom.extract_named(&Registry::new(), Naming::Entry),
+            Err(_) if map.is_some() => Extraction::new(),
+            Err(err) => return Err(err.into()),
+        }
+    };
+    if let Some(map) = map {
+        extraction.extend(cbfs::extract_all(&map));
+    }
+    Ok(extraction)
+}
+
+/// Name of a file for a component path, characters other than those of
+/// words being replaced in each of its components. Paths with empty, `.` or
+/// `..` components are rejected, as they would not name a file under the
+/// directory of the extraction.
+fn file_name(path: &str) -> Result<String, String> {
+    let mut names = Vec::new();
+    for name in path.split('/') {
+        if matches!(name, "" | "." | "..") {
+            return Err(format!("invalid component path {:?}", path));
+        }
+        names.push(
+            name.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || " .-_".contains(c) {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>(),
+        );
+    }
+    Ok(names.join("/"))
+}
+
+/// Writes every component of the image to `<path>.bin` in `dir`, a
+/// component found in another being in the directory named after it, with
+/// its metadata in `<path>.json` next to it, and a `manifest.json` listing
+/// every component
+pub fn extract(data: &[u8], dir: &Path) -> Result<(), String> {
+    let extraction = extraction(data)?;
+    let manifest = extract::manifest(&extraction);
+    for entry in manifest.iter() {
+        let name = file_name(&entry.path)?;
+        let path = dir.join(format!("{}.bin", name));
+        if !path.starts_with(dir) {
+            return Err(format!("{} is outside {}", path.display(), dir.display()));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("failed to create {}: {}", parent.display(), err))?;
+        }
+        fs::write(&path, &extraction[&entry.path].1)
+            .map_err(|err| format!("failed to write {}: {}", path.display(), err))?;
+        write_json(&dir.join(format!("{}.json", name)), entry)?;
+    }
+    fs::create_dir_all(dir)
+        .map_err(|err| format!("failed to create {}: {}", dir.display(), err))?;
+    write_json(&dir.join("manifest.json"), &manifest)
+}
+
+fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|err| err.to_string())?;
+    fs::write(path, json).map_err(|err| format!("failed to write {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_name_replaces_characters() {
+        assert_eq!(
+            file_name("AMD/00020000 PSP/01 PSP Boot Loader").unwrap(),
+            "AMD/00020000 PSP/01 PSP Boot Loader"
+        );
+        assert_eq!(file_name("FMAP/RW:A/x*y").unwrap(), "FMAP/RW_A/x_y");
+        assert_eq!(
+            file_name("FMAP/COREBOOT/.._.._revpwn_escaped").unwrap(),
+            "FMAP/COREBOOT/.._.._revpwn_escaped"
+        );
+    }
+
+    #[test]
+    fn file_name_rejects_traversal() {
+        for path in ["FMAP/../escaped", "FMAP/./x", "FMAP//x", "/x", "x/", ".."] {
+            assert!(file_name(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn area_file_rejects_other_directories() {
+        assert!(area_file("00000000_BIOS.bin").is_ok());
+        for file in ["", ".", "..", "../x.bin", "/etc/passwd", "a/b.bin"] {
+            assert!(area_file(file).is_err(), "{}", file);
+        }
+    }
+}
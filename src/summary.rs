@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: MIT
+
+//! One line description of an image for triage: its vendor, platform, size,
+//! component versions and whether the ME is disabled and Boot Guard set up
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::amd::{self, platform::Generation};
+use crate::image::Vendor;
+use crate::intel;
+use crate::timeline::{self, Versions};
+use crate::wrapper::{self, Wrapper};
+
+/// Facts of an image worth a glance, any of which may be unknown
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Summary {
+    /// Wrappers peeled off the image, outermost first
+    pub wrappers: Vec<Wrapper>,
+    pub vendor: Option<Vendor>,
+    /// Chipset series or SoC generation the image is for
+    pub platform: Option<String>,
+    /// Size of the flash the image is for in bytes
+    pub size: usize,
+    /// Version of every component found, as in `timeline::versions`
+    pub versions: Versions,
+    /// Whether the High Assurance Platform bit disables the ME
+    pub hap: Option<bool>,
+    /// Whether a Boot Guard boot policy manifest is present
+    pub boot_guard: Option<bool>,
+    /// Why the image could not be analyzed, such as an encrypted wrapper
+    pub error: Option<String>,
+}
+
+impl Summary {
+    /// Summarizes the image, peeling its wrappers off first
+    pub fn new(data: &[u8]) -> Self {
+        let (layers, data) = match wrapper::unwrap(data) {
+            Ok(unwrapped) => unwrapped,
+            Err(err) => {
+                return Summary {
+                    size: data.len(),
+                    error: Some(format!("{}", err)),
+                    ..Summary::default()
+                }
+            }
+        };
+        let mut summary = Summary {
+            wrappers: layers.iter().map(|layer| layer.wrapper).collect(),
+            size: data.len(),
+            versions: timeline::versions(data),
+            ..Summary::default()
+        };
+        if let Ok(rom) = intel::Rom::new(data) {
+            summary.vendor = Some(Vendor::Intel);
+            summary.platform = summary
+                .versions
+                .get("ME")
+                .and_then(|version| intel_platform(version))
+                .map(String::from);
+            summary.size = rom.data().len();
+            summary.hap = rom.high_assurance_platform().ok();
+            summary.boot_guard = Some(data.windows(8).any(|window| window == b"__ACBP__"));
+        } else if let Ok(rom) = amd::Rom::new(data) {
+            let platform = rom.platform();
+            summary.vendor = Some(Vendor::Amd);
+            summary.platform = Some(String::from(match platform.generation {
+                Generation::First => "family 15h or 17h models 00h-2Fh",
+                Generation::Second => "family 17h models 30h+ or 19h",
+            }));
+            summary.size = platform.flash_size;
+        }
+        summary
+    }
+}
+
+/// Chipset series the major version of an ME ships with
+fn intel_platform(version: &str) -> Option<&'static str> {
+    let major: u16 = version.split('.').next()?.parse().ok()?;
+    Some(match major {
+        7 => "6 series",
+        8 => "7 series",
+        9 => "8/9 series",
+        10 => "9 series",
+        11 => "100/200 series",
+        12 => "300 series",
+        13 | 14 => "400 series",
+        15 => "500 series",
+        16 => "600/700 series",
+        _ => return None,
+    })
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts: Vec<String> = Vec::new();
+        match self.vendor {
+            Some(vendor) => parts.push(format!("{}", vendor)),
+            None => parts.push(String::from("unknown")),
+        }
+        if let Some(platform) = &self.platform {
+            parts.push(platform.clone());
+        }
+        parts.push(if self.size & 0xF_FFFF == 0 {
+            format!("{}M", self.size / (1024 * 1024))
+        } else {
+            format!("{}K", self.size / 1024)
+        });
+        for (component, version) in self.versions.iter() {
+            parts.push(format!("{} {}", component, version));
+        }
+        if let Some(hap) = self.hap {
+            parts.push(format!("HAP {}", if hap { "on" } else { "off" }));
+        }
+        if let Some(boot_guard) = self.boot_guard {
+            parts.push(format!(
+                "Boot Guard {}",
+                if boot_guard { "on" } else { "off" }
+            ));
+        }
+        if !self.wrappers.is_empty() {
+            let wrappers: Vec<String> = self
+                .wrappers
+                .iter()
+                .rev()
+                .map(|wrapper| format!("{}", wrapper))
+                .collect();
+            parts.push(format!("in {}", wrappers.join(" in ")));
+        }
+        if let Some(error) = &self.error {
+            parts.push(error.clone());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
@@ -0,0 +1,476 @@
+// SPDX-License-Identifier: MIT
+
+//! Generators for small, deterministic firmware images, for tests of this
+//! crate and of tools built on it
+
+use alloc::vec::Vec;
+use core::mem;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::amd::directory::{
+    BiosDirectoryEntry, ComboDirectoryEntry, ComboDirectoryHeader, DirectoryBuilder,
+    DirectoryEntry, PspBinaryHeader, PspDirectoryEntry,
+};
+use crate::amd::edit::fix_checksum;
+use crate::amd::flash::EFS;
+use crate::amd::platform::{Generation, Platform};
+use crate::intel::{fit, HAP};
+use crate::{cbfs, fmap};
+
+/// Offsets of the structures in the 16M of generated AMD images holding them
+pub const EFS_OFFSET: usize = 0x20000;
+pub const PSP_COMBO_OFFSET: usize = 0x21000;
+pub const PSP_OFFSET: usize = 0x22000;
+pub const BIOS_COMBO_OFFSET: usize = 0x23000;
+pub const BIOS_OFFSET: usize = 0x24000;
+pub const PSP_LEVEL2_OFFSET: usize = 0x40000;
+pub const PSP_LEVEL2B_OFFSET: usize = 0x48000;
+pub const BIOS_LEVEL2_OFFSET: usize = 0x50000;
+pub const PAYLOAD_OFFSET: usize = 0x100000;
+
+/// Describes the AMD image to generate
+#[derive(Clone, Copy, Debug)]
+pub struct AmdFixture {
+    /// Size of the flash, a power of two of at least 2M
+    pub size: usize,
+    /// Reference the directories through combo directories
+    pub combo: bool,
+    /// Add level 2 PSP and BIOS directories
+    pub level2: bool,
+    /// Add A/B recovery level 2 PSP directories, implies `level2`
+    pub ab_recovery: bool,
+    /// Offset of the 16M of the flash holding the structures, which are
+    /// memory mapped below 4G, a multiple of 16M for flashes over 16M
+    pub window_offset: usize,
+    /// Bytes at the start of the flash left out of the image, which is then
+    /// a region of the flash as dumped on its own
+    pub image_offset: usize,
+}
+
+impl Default for AmdFixture {
+    fn default() -> Self {
+        Self {
+            size: 0x100_0000,
+            combo: false,
+            level2: false,
+            ab_recovery: false,
+            window_offset: 0,
+            image_offset: 0,
+        }
+    }
+}
+
+/// Flash being generated. Offsets are in the 16M holding the structures.
+struct Image {
+    data: Vec<u8>,
+    platform: Platform,
+    next_payload: usize,
+}
+
+impl Image {
+    /// Memory mapped address of an offset, the flash ends at 4G
+    fn address(&self, offset: usize) -> u64 {
+        self.platform
+            .mmio_address(self.platform.window_offset + offset)
+            .expect("fixture offset within the flash")
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        let offset = self.platform.window_offset + offset;
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    /// Places a payload at the next free 4K aligned offset, returning its
+    /// address
+    fn payload(&mut self, data: &[u8]) -> u64 {
+        let offset = self.next_payload;
+        self.write(offset, data);
+        self.next_payload = (offset + data.len() + 0xFFF) & !0xFFF;
+        self.address(offset)
+    }
+
+    fn directory<E: DirectoryEntry>(&mut self, offset: usize, magic: &[u8; 4], entries: &[E]) {
+        let builder = entries
+            .iter()
+            .fold(DirectoryBuilder::with_magic(*magic), |builder, entry| {
+                builder.entry(*entry)
+            });
+        let directory = builder
+            .build(self.platform.window_offset + offset, &self.platform)
+            .expect("fixture directory without payloads");
+        self.write(offset, &directory);
+    }
+
+    fn combo(&mut self, offset: usize, magic: &[u8; 4], directories: &[u64]) {
+        let header = ComboDirectoryHeader {
+            magic: u32::from_le_bytes(*magic),
+            checksum: 0,
+            entries: directories.len() as u32,
+            look_up_mode: 0,
+            rsvd_10: 0,
+            rsvd_14: 0,
+            rsvd_18: 0,
+            rsvd_1c: 0,
+        };
+        self.write(offset, header.as_bytes());
+        let mut entry_offset = offset + mem::size_of::<ComboDirectoryHeader>();
+        for (i, directory) in directories.iter().enumerate() {
+            let entry = ComboDirectoryEntry {
+                id_select: 0,
+                id: 0xBC0A_0000 + i as u32,
+                directory: *directory,
+            };
+            self.write(entry_offset, entry.as_bytes());
+            entry_offset += mem::size_of::<ComboDirectoryEntry>();
+        }
+        let start = self.platform.window_offset + offset;
+        fix_checksum(&mut self.data, start, entry_offset - offset);
+    }
+}
+
+/// Deterministic contents for a payload of the given type
+fn pattern(kind: u8, size: usize) -> Vec<u8> {
+    (0..size).map(|i| kind.wrapping_add(i as u8)).collect()
+}
+
+/// A signed PSP binary with a header, a body and an empty signature
+fn psp_binary(kind: u8, version: u32, body: usize) -> Vec<u8> {
+    let mut header = PspBinaryHeader::new(&[0; 0x100]).unwrap();
+    header.magic = u32::from_le_bytes(*b"$PS1");
+    header.size_signed = body as u32;
+    header.signed = 1;
+    header.version = version;
+    header.size_total = (mem::size_of::<PspBinaryHeader>() + body + 0x100) as u32;
+    header.kind = kind;
+    let mut binary = header.as_bytes().to_vec();
+    binary.extend(pattern(kind, body));
+    binary.extend(vec![0; 0x100]);
+    binary
+}
+
+fn psp_entry(kind: u8, size: usize, value: u64) -> PspDirectoryEntry {
+    PspDirectoryEntry {
+        kind,
+        sub_program: 0,
+        rom_id: 0,
+        rsvd_03: 0,
+        size: size as u32,
+        value,
+    }
+}
+
+fn bios_entry(kind: u8, size: usize, source: u64, destination: u64) -> BiosDirectoryEntry {
+    BiosDirectoryEntry {
+        kind,
+        region_kind: 0,
+        flags: 0,
+        sub_program: 0,
+        size: size as u32,
+        source,
+        destination,
+    }
+}
+
+impl AmdFixture {
+    /// Generates the image
+    pub fn build(&self) -> Vec<u8> {
+        let mut image = Image {
+            data: vec![0xFF; self.size],
+            platform: Platform {
+                generation: Generation::Second,
+                flash_size: self.size,
+                window_offset: self.window_offset,
+                image_offset: 0,
+            },
+            next_payload: PAYLOAD_OFFSET,
+        };
+        let level2 = self.level2 || self.ab_recovery;
+
+        // PSP directories
+        let public_key = pattern(0x00, 0x240);
+        let boot_loader = psp_binary(0x01, 0x0011_2233, 0x400);
+        let smu = psp_binary(0x08, 0x0037_4100, 0x800);
+        let public_key = psp_entry(0x00, public_key.len(), image.payload(&public_key));
+        let boot_loader = psp_entry(0x01, boot_loader.len(), image.payload(&boot_loader));
+        let smu = psp_entry(0x08, smu.len(), image.payload(&smu));
+        let soft_fuse = psp_entry(0x0B, 0xFFFF_FFFF, 1);
+
+        let mut psp = vec![public_key, boot_loader, soft_fuse];
+        if level2 {
+            let level2 = [public_key, boot_loader, smu, soft_fuse];
+            if self.ab_recovery {
+                image.directory(PSP_LEVEL2_OFFSET, b"$PL2", &level2);
+                image.directory(PSP_LEVEL2B_OFFSET, b"$PL2", &level2);
+                psp.push(psp_entry(0x48, 0x8000, image.address(PSP_LEVEL2_OFFSET)));
+                psp.push(psp_entry(0x4A, 0x8000, image.address(PSP_LEVEL2B_OFFSET)));
+            } else {
+                image.directory(PSP_LEVEL2_OFFSET, b"$PL2", &level2);
+                psp.push(psp_entry(0x40, 0x8000, image.address(PSP_LEVEL2_OFFSET)));
+            }
+        } else {
+            psp.push(smu);
+        }
+        image.directory(PSP_OFFSET, b"$PSP", &psp);
+
+        // BIOS directories
+        let apcb = pattern(0x60, 0x1000);
+        let binary = pattern(0x62, 0x4000);
+        let microcode = pattern(0x66, 0xC80);
+        let apcb = bios_entry(0x60, apcb.len(), image.payload(&apcb), !0);
+        let apob = bios_entry(0x61, 0, 0, 0x0400_0000);
+        let binary = bios_entry(0x62, binary.len(), image.payload(&binary), 0x09D0_0000);
+        let microcode = bios_entry(0x66, microcode.len(), image.payload(&microcode), !0);
+
+        let mut bios = vec![apcb, apob];
+        if level2 {
+            image.directory(BIOS_LEVEL2_OFFSET, b"$BL2", &[apcb, apob, binary, microcode]);
+            bios.push(bios_entry(0x70, 0x8000, image.address(BIOS_LEVEL2_OFFSET), !0));
+        } else {
+            bios.extend_from_slice(&[binary, microcode]);
+        }
+        image.directory(BIOS_OFFSET, b"$BHD", &bios);
+
+        let (psp_pointer, bios_pointer) = if self.combo {
+            let psp = image.address(PSP_OFFSET);
+            let bios = image.address(BIOS_OFFSET);
+            image.combo(PSP_COMBO_OFFSET, b"2PSP", &[psp]);
+            image.combo(BIOS_COMBO_OFFSET, b"2BHD", &[bios]);
+            (image.address(PSP_COMBO_OFFSET), image.address(BIOS_COMBO_OFFSET))
+        } else {
+            (image.address(PSP_OFFSET), image.address(BIOS_OFFSET))
+        };
+
+        let mut efs = EFS::read_from(&[0xFF; mem::size_of::<EFS>()][..]).unwrap();
+        efs.magic = 0x55AA_55AA;
+        efs.psp = psp_pointer as u32;
+        efs.bios = bios_pointer as u32;
+        efs.second_gen = 0xFFFF_FFFE;
+        image.write(EFS_OFFSET, efs.as_bytes());
+
+        image.data.split_off(self.image_offset)
+    }
+}
+
+/// Offsets of the flash map and of the `COREBOOT` area holding the CBFS in
+/// generated coreboot images
+pub const FMAP_OFFSET: usize = 0x1000;
+pub const CBFS_OFFSET: usize = 0x2000;
+
+/// Size of generated coreboot images
+pub const COREBOOT_SIZE: usize = 0x1_0000;
+
+/// File of the CBFS of a generated coreboot image: its name, type,
+/// compression and data as stored
+pub type CbfsFixtureFile<'a> = (&'a str, u32, u32, &'a [u8]);
+
+/// Generates a coreboot image with a flash map of its `FMAP` and `COREBOOT`
+/// areas, the CBFS of the latter holding `files`. Compressed files have a
+/// compression attribute. The rest of the image is erased.
+pub fn coreboot_image(files: &[CbfsFixtureFile]) -> Vec<u8> {
+    let mut image = vec![0xFF; COREBOOT_SIZE];
+
+    let mut fmap = Vec::new();
+    fmap.extend_from_slice(&fmap::SIGNATURE);
+    fmap.extend_from_slice(&[fmap::VERSION_MAJOR, 1]);
+    fmap.extend_from_slice(&(0x1_0000_0000 - COREBOOT_SIZE as u64).to_le_bytes());
+    fmap.extend_from_slice(&(COREBOOT_SIZE as u32).to_le_bytes());
+    fmap.extend_from_slice(&name32("FLASH"));
+    fmap.extend_from_slice(&2u16.to_le_bytes());
+    let areas = [
+        (
+            FMAP_OFFSET,
+            CBFS_OFFSET - FMAP_OFFSET,
+            "FMAP",
+            fmap::AREA_STATIC,
+        ),
+        (CBFS_OFFSET, COREBOOT_SIZE - CBFS_OFFSET, "COREBOOT", 0),
+    ];
+    for (offset, size, name, flags) in areas {
+        fmap.extend_from_slice(&(offset as u32).to_le_bytes());
+        fmap.extend_from_slice(&(size as u32).to_le_bytes());
+        fmap.extend_from_slice(&name32(name));
+        fmap.extend_from_slice(&flags.to_le_bytes());
+    }
+    image[FMAP_OFFSET..FMAP_OFFSET + fmap.len()].copy_from_slice(&fmap);
+
+    let mut offset = CBFS_OFFSET;
+    for (name, kind, compression, data) in files {
+        let name_len = (name.len() + 1).div_ceil(16) * 16;
+        let header = mem::size_of::<cbfs::FileHeader>();
+        let attributes = if *compression == cbfs::COMPRESSION_NONE {
+            0
+        } else {
+            16
+        };
+        let data_offset = header + name_len + attributes;
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&cbfs::FILE_MAGIC);
+        file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        file.extend_from_slice(&kind.to_be_bytes());
+        let attributes_offset = if attributes == 0 {
+            0
+        } else {
+            header + name_len
+        };
+        file.extend_from_slice(&(attributes_offset as u32).to_be_bytes());
+        file.extend_from_slice(&(data_offset as u32).to_be_bytes());
+        file.extend_from_slice(name.as_bytes());
+        file.resize(header + name_len, 0);
+        if attributes != 0 {
+            file.extend_from_slice(&cbfs::ATTRIBUTE_COMPRESSION.to_be_bytes());
+            file.extend_from_slice(&(attributes as u32).to_be_bytes());
+            file.extend_from_slice(&compression.to_be_bytes());
+            file.extend_from_slice(&0u32.to_be_bytes());
+        }
+        file.extend_from_slice(data);
+        image[offset..offset + file.len()].copy_from_slice(&file);
+        offset += file.len().div_ceil(cbfs::ALIGNMENT) * cbfs::ALIGNMENT;
+    }
+    image
+}
+
+/// Name of a flash map or area, NUL padded
+fn name32(name: &str) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    bytes
+}
+
+/// Size of generated Intel images, and the offsets of their ME and BIOS
+/// regions, of the FIT and of the structures it points to
+pub const INTEL_SIZE: usize = 0x1_0000;
+pub const ME_REGION_OFFSET: usize = 0x1000;
+pub const BIOS_REGION_OFFSET: usize = 0x8000;
+pub const MICROCODE_OFFSET: usize = 0x9000;
+pub const BOOT_POLICY_OFFSET: usize = 0xA000;
+pub const FIT_OFFSET: usize = 0xF000;
+
+/// Describes the Intel image to generate: a descriptor of the second layout
+/// with a descriptor, an ME and a BIOS region, the gap between the latter
+/// two left as padding, and a FIT pointing to a microcode update in the BIOS
+/// region
+#[derive(Clone, Copy, Debug)]
+pub struct IntelFixture {
+    /// First flash master entry, the access of the host CPU to the regions
+    pub mstr1: u32,
+    /// Set the HAP bit of the first PCH strap
+    pub hap: bool,
+    /// Version of the ME firmware, in its partition table
+    pub me_version: Option<[u16; 4]>,
+    /// Add a Boot Guard boot policy manifest to the FIT
+    pub boot_guard: bool,
+}
+
+impl Default for IntelFixture {
+    fn default() -> Self {
+        Self {
+            // Host reads the descriptor and BIOS regions, writes the BIOS
+            // region
+            mstr1: 0x0020_0300,
+            hap: false,
+            me_version: None,
+            boot_guard: false,
+        }
+    }
+}
+
+impl IntelFixture {
+    /// Generates the image
+    pub fn build(&self) -> Vec<u8> {
+        let mut image = vec![0xFF; INTEL_SIZE];
+        let mut write = |offset: usize, data: &[u8]| {
+            image[offset..offset + data.len()].copy_from_slice(data);
+        };
+        let dwords = |dwords: &[u32]| -> Vec<u8> {
+            dwords
+                .iter()
+                .flat_map(|dword| dword.to_le_bytes())
+                .collect()
+        };
+
+        // Signature, then the component table at 0x30, the region table at
+        // 0x40, the master table at 0x80 with 2 masters, 2 PCH straps at
+        // 0x100 and a processor strap at 0x200
+        write(
+            0x10,
+            &dwords(&[0x0FF0_A55A, 0x0004_0003, 0x0210_0208, 0x0000_0120]),
+        );
+        write(0x30, &dwords(&[0]));
+        let unused = 0x0000_7FFF;
+        let region = |offset: usize, end: usize| ((end - 1) >> 12 << 16 | offset >> 12) as u32;
+        write(
+            0x40,
+            &dwords(&[
+                region(0, ME_REGION_OFFSET),
+                region(BIOS_REGION_OFFSET, INTEL_SIZE),
+                region(ME_REGION_OFFSET, 0x4000),
+                unused,
+                unused,
+                unused,
+                unused,
+                unused,
+                unused,
+            ]),
+        );
+        write(0x80, &dwords(&[self.mstr1, 0x0040_0D00, 0, 0, 0]));
+        let mut straps = [0; 18];
+        if self.hap {
+            straps[0] = HAP;
+        }
+        write(0x100, &dwords(&straps));
+        write(0x200, &dwords(&[0x0000_00AB]));
+
+        if let Some(version) = self.me_version {
+            write(ME_REGION_OFFSET, b"$FPT");
+            let version: Vec<u8> = version.iter().flat_map(|part| part.to_le_bytes()).collect();
+            write(ME_REGION_OFFSET + 0x18, &version);
+        }
+
+        // Microcode update of the default sizes whose dwords sum up to 0
+        let mut update = dwords(&[1, 0x42, 0x0115_2024, 0x0009_06A3, 0, 1, 0x80, 0, 0]);
+        update.resize(48, 0);
+        update.extend(pattern(0x10, 2000));
+        let sum = update
+            .chunks_exact(4)
+            .map(|dword| u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]))
+            .fold(0u32, |sum, dword| sum.wrapping_add(dword));
+        update[16..20].copy_from_slice(&sum.wrapping_neg().to_le_bytes());
+        write(MICROCODE_OFFSET, &update);
+
+        let base = fit::TOP - INTEL_SIZE as u64;
+        let entry = |address: u64, size: usize, kind: u8| {
+            let mut entry = address.to_le_bytes().to_vec();
+            entry.extend_from_slice(&(size as u32).to_le_bytes()[..3]);
+            entry.extend_from_slice(&[0, 0, 1, kind, 0]);
+            entry
+        };
+        let mut entries = vec![entry(
+            base + MICROCODE_OFFSET as u64,
+            update.len() / 16,
+            fit::MICROCODE,
+        )];
+        if self.boot_guard {
+            write(BOOT_POLICY_OFFSET, b"__ACBP__");
+            entries.push(entry(
+                base + BOOT_POLICY_OFFSET as u64,
+                0x10,
+                fit::BOOT_POLICY_MANIFEST,
+            ));
+        }
+        // Header whose checksum is valid
+        let mut table = entry(
+            u64::from_le_bytes(fit::SIGNATURE),
+            entries.len() + 1,
+            fit::HEADER | 0x80,
+        );
+        table.extend(entries.concat());
+        let sum = table.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        table[15] = sum.wrapping_neg();
+        write(FIT_OFFSET, &table);
+        let pointer = (fit::POINTER_ADDRESS - base) as usize;
+        write(pointer, &(base + FIT_OFFSET as u64).to_le_bytes());
+
+        image
+    }
+}
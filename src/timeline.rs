@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: MIT
+
+//! Versions of the firmware components of a series of images, such as every
+//! BIOS release of a board, telling in which release each component changed
+
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use core::fmt::Write;
+use serde::{Deserialize, Serialize};
+
+use crate::amd::{
+    self,
+    directory::{BiosDirectoryEntry, PspDirectoryEntry},
+    walk::RomVisitor,
+};
+use crate::identify::psp_version;
+use crate::intel;
+
+/// Version of every component found in an image, by component name
+pub type Versions = BTreeMap<String, String>;
+
+/// Versions of the components of an image: the Intel ME, the AGESA, the
+/// AMD microcode patches and the PSP binaries with a versioned header, such
+/// as the SMU firmware
+pub fn versions(data: &[u8]) -> Versions {
+    let mut versions = Versions::new();
+    if let Ok(Some(me)) = intel::Rom::new(data).and_then(|rom| rom.me()) {
+        if let Some(version) = me.version() {
+            versions.insert(String::from("ME"), version);
+        }
+    }
+    if let Some(agesa) = agesa_version(data) {
+        versions.insert(String::from("AGESA"), agesa);
+    }
+    if let Ok(rom) = amd::Rom::new(data) {
+        let mut collector = Collector {
+            versions: &mut versions,
+            microcode: Vec::new(),
+        };
+        rom.walk(&mut collector);
+        let mut microcode = collector.microcode;
+        microcode.sort_unstable();
+        microcode.dedup();
+        if !microcode.is_empty() {
+            let mut patches = String::new();
+            for (i, patch) in microcode.iter().enumerate() {
+                if i > 0 {
+                    patches.push_str(", ");
+                }
+                let _ = write!(patches, "{:08X}", patch);
+            }
+            versions.insert(String::from("Microcode"), patches);
+        }
+    }
+    versions
+}
+
+/// AGESA version string, which follows an `AGESA!V9` signature and a NUL
+/// byte in the BIOS
+fn agesa_version(data: &[u8]) -> Option<String> {
+    const SIGNATURE: &[u8] = b"AGESA!V";
+    let start = data
+        .windows(SIGNATURE.len())
+        .position(|window| window == SIGNATURE)?;
+    let name = &data[start..];
+    let name = &name[name.iter().position(|b| *b == 0)? + 1..];
+    let name = &name[..name.iter().position(|b| *b == 0)?];
+    if name.is_empty() || !name.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return None;
+    }
+    Some(name.iter().map(|b| *b as char).collect())
+}
+
+/// Collects the versions of PSP binaries and the patch levels of microcode
+struct Collector<'v> {
+    versions: &'v mut Versions,
+    microcode: Vec<u32>,
+}
+
+impl<'v> RomVisitor for Collector<'v> {
+    fn visit_psp_entry(
+        &mut self,
+        _directory: usize,
+        entry: &PspDirectoryEntry,
+        _offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        if let Some(version) = data.and_then(psp_version) {
+            self.versions
+                .entry(String::from(entry.description()))
+                .or_insert(version);
+        }
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        _directory: usize,
+        entry: &BiosDirectoryEntry,
+        _offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        // Microcode patches start with their date and patch level
+        if entry.kind == 0x66 {
+            if let Some(level) = data.and_then(|data| data.get(4..8)) {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(level);
+                self.microcode.push(u32::from_le_bytes(bytes));
+            }
+        }
+    }
+}
+
+/// Versions of the components of a series of images, in order
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Timeline {
+    /// Names of the images
+    pub images: Vec<String>,
+    /// Version of every component in each image, none if the image does not
+    /// have the component
+    pub components: BTreeMap<String, Vec<Option<String>>>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an image with the versions of its components
+    pub fn push(&mut self, image: String, versions: Versions) {
+        let count = self.images.len();
+        for (component, version) in versions {
+            self.components
+                .entry(component)
+                .or_insert_with(|| vec![None; count])
+                .push(Some(version));
+        }
+        self.images.push(image);
+        for versions in self.components.values_mut() {
+            versions.resize(count + 1, None);
+        }
+    }
+
+    /// Indexes of the images in which the version of `component` differs
+    /// from that of the image before, the first image having it included
+    pub fn changes(&self, component: &str) -> Vec<usize> {
+        let versions = match self.components.get(component) {
+            Some(versions) => versions,
+            None => return Vec::new(),
+        };
+        (0..versions.len())
+            .filter(|i| match i {
+                0 => versions[0].is_some(),
+                i => versions[*i] != versions[i - 1],
+            })
+            .collect()
+    }
+}
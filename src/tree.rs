@@ -0,0 +1,383 @@
+// SPDX-License-Identifier: MIT
+
+//! The whole image as one hierarchy: the regions of an Intel image down to
+//! the files and sections of the BIOS volumes and the modules of the ME
+//! partitions, the EFS of an AMD image down to the entries of its
+//! directories and the volumes of the BIOS binary, and the areas of a flash
+//! map down to their CBFS files. `Node` renders it with tree glyphs.
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, mem};
+use serde::{Deserialize, Serialize};
+
+use crate::amd::{
+    self,
+    directory::{self, BiosDirectoryEntry, ComboDirectoryEntry, Directory, PspDirectoryEntry},
+    flash,
+    walk::RomVisitor,
+};
+use crate::cbfs;
+use crate::fmap::{self, Fmap};
+use crate::intel::{self, file, me, section, BiosSections, BiosVolumes, RegionKind};
+
+/// Component of the image and the components in it. Offsets are in the
+/// image, except below a decompressed node, where they are in the data it
+/// decompresses to.
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Node {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// Size of the component, none for entries holding a value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn new(name: String, offset: Option<usize>, size: Option<usize>) -> Self {
+        Node {
+            name,
+            offset,
+            size,
+            children: Vec::new(),
+        }
+    }
+
+    fn with_children(mut self, children: Vec<Node>) -> Self {
+        self.children = children;
+        self
+    }
+
+    fn fmt_children(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        for (i, child) in self.children.iter().enumerate() {
+            let (branch, indent) = if i + 1 == self.children.len() {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            writeln!(f, "{}{}{}", prefix, branch, child.line())?;
+            child.fmt_children(f, &format!("{}{}", prefix, indent))?;
+        }
+        Ok(())
+    }
+
+    fn line(&self) -> String {
+        match (self.size, self.offset) {
+            (Some(size), Some(offset)) => {
+                format!("{}, {:#X} bytes at {:#X}", self.name, size, offset)
+            }
+            (Some(size), None) => format!("{}, {:#X} bytes", self.name, size),
+            (None, Some(offset)) => format!("{} at {:#X}", self.name, offset),
+            (None, None) => self.name.clone(),
+        }
+    }
+}
+
+/// The node and its children, a line each
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.line())?;
+        self.fmt_children(f, "")
+    }
+}
+
+/// Tree of an image, whose children are the Intel regions, the AMD EFS and
+/// the flash map found in it
+pub fn tree(data: &[u8]) -> Node {
+    let mut children = Vec::new();
+    if let Ok(rom) = intel::Rom::new(data) {
+        children.extend(intel_regions(&rom));
+    }
+    if let Ok(rom) = amd::Rom::new(data) {
+        children.push(amd_efs(&rom));
+    }
+    if let Some(map) = Fmap::find(data) {
+        children.push(flash_map(&map));
+    }
+    Node::new(String::from("Image"), Some(0), Some(data.len())).with_children(children)
+}
+
+fn intel_regions(rom: &intel::Rom) -> Vec<Node> {
+    let regions = match rom.regions() {
+        Ok(regions) => regions,
+        Err(_) => return Vec::new(),
+    };
+    regions
+        .iter()
+        .map(|region| {
+            let node = Node::new(region.name.clone(), Some(region.offset), Some(region.size));
+            let data = match rom.data().get(region.offset..region.offset + region.size) {
+                Some(data) => data,
+                None => return node,
+            };
+            let children = match region.index.map(|index| RegionKind::ALL[index]) {
+                Some(RegionKind::Bios) | Some(RegionKind::SecondaryBios) => {
+                    volumes(data, region.offset)
+                }
+                Some(RegionKind::ManagementEngine) => me_partitions(data, region.offset),
+                _ => Vec::new(),
+            };
+            node.with_children(children)
+        })
+        .collect()
+}
+
+/// Firmware volumes in `data`, offsets being relative to `data` plus
+/// `offset`
+fn volumes(data: &[u8], offset: usize) -> Vec<Node> {
+    BiosVolumes::new(data)
+        .map(|volume| {
+            let header = volume.header();
+            let data_offset = offset + volume.offset() + header.header_length as usize;
+            let files = volume
+                .files()
+                .map(|file| {
+                    let header = file.header();
+                    let file_offset = data_offset + file.offset() + mem::size_of::<file::Header>();
+                    let name = format!("{} {:?}", { header.guid }, header.kind());
+                    let sections = if header.sectioned() {
+                        sections(file.data(), file_offset)
+                    } else {
+                        Vec::new()
+                    };
+                    Node::new(name, Some(file_offset), Some(file.data().len()))
+                        .with_children(sections)
+                })
+                .collect();
+            Node::new(
+                format!("Volume {}", { header.guid }),
+                Some(offset + volume.offset()),
+                Some(volume.data().len()),
+            )
+            .with_children(files)
+        })
+        .collect()
+}
+
+/// Sections in `data`, offsets being relative to `data` plus `offset`.
+/// Sections compressed with LZMA have the sections they decompress to.
+fn sections(data: &[u8], offset: usize) -> Vec<Node> {
+    BiosSections::new(data)
+        .map(|section| {
+            let kind = section.header().kind();
+            let section_offset = offset + section.offset() + mem::size_of::<section::Header>();
+            let mut name = match plain::from_bytes::<section::GuidDefined>(section.data()) {
+                Ok(header) if kind == section::HeaderKind::GuidDefined => {
+                    format!("{:?} {}", kind, { header.guid })
+                }
+                _ => format!("{:?}", kind),
+            };
+            let children = match (kind, section.decompress()) {
+                (_, Some(Ok(decompressed))) => {
+                    name.push_str(", decompressed");
+                    sections(&decompressed, 0)
+                }
+                (section::HeaderKind::VolumeImage, _) => volumes(section.data(), section_offset),
+                _ => Vec::new(),
+            };
+            Node::new(name, Some(section_offset), Some(section.data().len()))
+                .with_children(children)
+        })
+        .collect()
+}
+
+/// Partitions of the ME region at `offset` and the modules of their code
+/// partition directories
+fn me_partitions(data: &[u8], offset: usize) -> Vec<Node> {
+    let fpt = match me::Fpt::new(data) {
+        Ok(fpt) => fpt,
+        Err(_) => return Vec::new(),
+    };
+    fpt.entries()
+        .iter()
+        .map(|entry| {
+            let partition_offset = offset + entry.offset as usize;
+            let modules = match fpt.partition(entry).map(me::Cpd::new) {
+                Some(Ok(cpd)) => cpd
+                    .entries()
+                    .iter()
+                    .map(|module| {
+                        let mut name = me::entry_name(&module.name);
+                        if module.is_compressed() {
+                            name.push_str(", compressed");
+                        }
+                        Node::new(
+                            name,
+                            Some(partition_offset + module.offset()),
+                            Some(module.length as usize),
+                        )
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            Node::new(
+                me::entry_name(&entry.name),
+                Some(partition_offset),
+                Some(entry.length as usize),
+            )
+            .with_children(modules)
+        })
+        .collect()
+}
+
+/// EFS and the directories reachable from it, a directory taking the place
+/// of the entry pointing to it, named after both
+fn amd_efs(rom: &amd::Rom) -> Node {
+    let mut builder = AmdTree { stack: Vec::new() };
+    rom.walk(&mut builder);
+    builder.stack.pop().unwrap_or_else(|| {
+        Node::new(
+            String::from("EFS"),
+            Some(rom.efs_offset()),
+            Some(mem::size_of::<flash::EFS>()),
+        )
+    })
+}
+
+struct AmdTree {
+    /// The EFS and the directories being visited, innermost last
+    stack: Vec<Node>,
+}
+
+impl AmdTree {
+    fn push_child(&mut self, node: Node) {
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children.push(node);
+        }
+    }
+}
+
+impl RomVisitor for AmdTree {
+    fn visit_efs(&mut self, offset: usize, _efs: &flash::EFS) {
+        self.stack.push(Node::new(
+            String::from("EFS"),
+            Some(offset),
+            Some(mem::size_of::<flash::EFS>()),
+        ));
+    }
+
+    fn visit_directory(&mut self, offset: usize, _depth: usize, directory: &Directory) {
+        self.stack.push(Node::new(
+            String::from(directory.name()),
+            Some(offset),
+            Some(directory.size()),
+        ));
+    }
+
+    fn leave_directory(&mut self, offset: usize, _depth: usize, _directory: &Directory) {
+        let mut node = match self.stack.pop() {
+            Some(node) => node,
+            None => return,
+        };
+        let parent = match self.stack.last_mut() {
+            Some(parent) => parent,
+            None => return,
+        };
+        // Directories are visited right after the entry pointing to them
+        match parent.children.last_mut() {
+            Some(entry) if entry.offset == Some(offset) && entry.children.is_empty() => {
+                node.name = format!("{}: {}", entry.name, node.name);
+                *entry = node;
+            }
+            _ => parent.children.push(node),
+        }
+    }
+
+    fn visit_psp_entry(
+        &mut self,
+        _directory: usize,
+        entry: &PspDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        let name = format!("{:02X} {}", entry.kind, entry.description());
+        self.push_child(Node::new(name, offset, data.map(<[u8]>::len)));
+    }
+
+    fn visit_bios_entry(
+        &mut self,
+        _directory: usize,
+        entry: &BiosDirectoryEntry,
+        offset: Option<usize>,
+        data: Option<&[u8]>,
+    ) {
+        let mut node = Node::new(
+            format!("{:02X} {}", entry.kind, entry.description()),
+            offset,
+            data.map(<[u8]>::len),
+        );
+        if let (0x62, Some(offset), Some(data)) = (entry.kind, offset, data) {
+            let compressed = directory::zlib_stream(data).is_some();
+            if let Some(payload) = directory::payload(data) {
+                if compressed {
+                    node.name.push_str(", decompressed");
+                    node.children = volumes(&payload, 0);
+                } else {
+                    node.children = volumes(&payload, offset);
+                }
+            }
+        }
+        self.push_child(node);
+    }
+
+    fn visit_combo_entry(
+        &mut self,
+        _directory: usize,
+        entry: &ComboDirectoryEntry,
+        offset: Option<usize>,
+    ) {
+        let select = match entry.id_select {
+            0 => "PSP ID",
+            _ => "chip ID",
+        };
+        let name = format!("{} {:08X}", select, { entry.id });
+        self.push_child(Node::new(name, offset, None));
+    }
+}
+
+/// Flash map, its areas and the files of those holding a CBFS
+fn flash_map(map: &Fmap) -> Node {
+    let areas: Vec<Node> = map
+        .entries()
+        .iter()
+        .map(|entry| {
+            let area_offset = entry.offset as usize;
+            let files = match map.area_data(entry).map(cbfs::Cbfs::new) {
+                Some(Ok(cbfs)) => cbfs
+                    .files()
+                    .iter()
+                    .map(|file| {
+                        let kind = file.header().kind();
+                        let name = match file.name() {
+                            "" => "(empty)",
+                            name => name,
+                        };
+                        let mut name = format!("{}: {}", name, cbfs::type_name(kind));
+                        let (compression, _) = file.compression();
+                        if compression != cbfs::COMPRESSION_NONE {
+                            name = format!("{}, {}", name, cbfs::compression_name(compression));
+                        }
+                        Node::new(
+                            name,
+                            Some(area_offset + file.data_offset()),
+                            Some(file.data().len()),
+                        )
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            Node::new(entry.name(), Some(area_offset), Some(entry.size as usize))
+                .with_children(files)
+        })
+        .collect();
+    let size = mem::size_of::<fmap::Header>() + areas.len() * mem::size_of::<fmap::AreaEntry>();
+    Node::new(
+        format!("FMAP {}", map.name()),
+        Some(map.offset()),
+        Some(size),
+    )
+    .with_children(areas)
+}
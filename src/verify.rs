@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: MIT
+
+use romulan::layout::Area;
+use romulan::{image, intel};
+
+use crate::split;
+
+/// Problems found by the verify pass of the image
+fn problems(data: &[u8]) -> Result<Vec<String>, String> {
+    Ok(image::open(data)?.verify())
+}
+
+/// Checks an edited image before it is written. It must parse like the
+/// original, must not fail verification where the original passed, and every
+/// area of the original that `touches` does not claim must be unchanged.
+pub fn write_back<F: Fn(&Area) -> bool>(
+    original: &[u8],
+    edited: &[u8],
+    touches: F,
+) -> Result<(), String> {
+    if edited.len() != original.len() {
+        return Err(format!(
+            "edited image is {:#X} bytes, expected {:#X}",
+            edited.len(),
+            original.len()
+        ));
+    }
+    if intel::Rom::new(original).is_ok() != intel::Rom::new(edited).is_ok() {
+        return Err(String::from("edited image no longer parses as the original"));
+    }
+
+    let before = problems(original)?;
+    let after = problems(edited).map_err(|err| format!("edited image invalid: {}", err))?;
+    if let Some(problem) = after.iter().find(|problem| !before.contains(problem)) {
+        return Err(format!("edited image invalid: {}", problem));
+    }
+
+    for area in split::layout(original)?.areas() {
+        if !touches(area) && original[area.offset..area.end()] != edited[area.offset..area.end()] {
+            return Err(format!(
+                "edit changed {} at {:#X}, which it should not touch",
+                area.name, area.offset
+            ));
+        }
+    }
+    Ok(())
+}
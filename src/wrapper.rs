@@ -0,0 +1,596 @@
+// SPDX-License-Identifier: MIT
+
+//! Vendor update packages wrapping a flash image, such as UEFI capsules and
+//! the capsules and executables of AMI, Dell, Lenovo and Insyde, peeled off
+//! before the image is analyzed
+
+use alloc::{string::String, vec::Vec};
+use core::{fmt, mem};
+use plain::Plain;
+use serde::{Deserialize, Serialize};
+use uefi::guid::Guid;
+
+use crate::entropy::entropy;
+use crate::{amd, intel, Error};
+
+/// Header GUID of UEFI capsules
+pub const EFI_CAPSULE_GUID: Guid = Guid(
+    0x3b6686bd,
+    0x0d76,
+    0x4030,
+    [0xb7, 0x0e, 0xb5, 0x51, 0x9e, 0x2f, 0xc5, 0xa0],
+);
+
+/// Header GUID of UEFI capsules holding Firmware Management Protocol images
+pub const EFI_FMP_CAPSULE_GUID: Guid = Guid(
+    0x6dcbd5ed,
+    0xe82d,
+    0x4c44,
+    [0xbd, 0xa1, 0x71, 0x94, 0x19, 0x9a, 0xd9, 0x2a],
+);
+
+/// Header GUID of Intel capsules, laid out like UEFI capsules
+pub const INTEL_CAPSULE_GUID: Guid = Guid(
+    0x539182b9,
+    0xabb5,
+    0x4391,
+    [0xb6, 0x9a, 0xe3, 0xa9, 0x43, 0xf7, 0x2f, 0xcc],
+);
+
+/// Header GUIDs of Lenovo capsules, laid out like UEFI capsules
+pub const LENOVO_CAPSULE_GUID: Guid = Guid(
+    0xe20bafd3,
+    0x9914,
+    0x4f4f,
+    [0x95, 0x37, 0x31, 0x29, 0xe0, 0x90, 0xeb, 0x3c],
+);
+pub const LENOVO2_CAPSULE_GUID: Guid = Guid(
+    0x25b5fe76,
+    0x8243,
+    0x4a5c,
+    [0xa9, 0xbd, 0x7e, 0xe3, 0x24, 0x61, 0x98, 0xb5],
+);
+
+/// Header GUIDs of AMI Aptio capsules, with and without a signature
+pub const APTIO_SIGNED_CAPSULE_GUID: Guid = Guid(
+    0x4a3ca68b,
+    0x7723,
+    0x48fb,
+    [0x80, 0x3d, 0x57, 0x8c, 0xc1, 0xfe, 0xc4, 0x4d],
+);
+pub const APTIO_UNSIGNED_CAPSULE_GUID: Guid = Guid(
+    0x14eebb90,
+    0x890a,
+    0x43db,
+    [0xae, 0xd1, 0x5d, 0x3c, 0x45, 0x88, 0xa4, 0x18],
+);
+
+/// Signature of the header of the BIOS image in Insyde iFlash executables
+const INSYDE_SIGNATURE: &[u8] = b"$_IFLASH_BIOSIMG";
+
+/// Signature of Dell PFS update packages
+const PFS_SIGNATURE: &[u8] = b"PFS.HDR.";
+
+/// Marker followed by the size and the zlib stream of the PFS package in
+/// Dell update executables
+const DELL_ZLIB_MARKER: &[u8] = &[
+    0xAA, 0xEE, 0xAA, 0x76, 0x1B, 0xEC, 0xBB, 0x20, 0xF1, 0xE6, 0x51,
+];
+
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+
+/// Wrappers peeled off one image at most, so a wrapper pointing at itself
+/// does not loop forever
+const MAX_DEPTH: usize = 8;
+
+/// Bits per byte above which data with no known structure is taken to be
+/// encrypted or compressed
+const ENCRYPTED_ENTROPY: f32 = 7.9;
+
+/// Kind of package wrapping an image
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Wrapper {
+    UefiCapsule,
+    /// UEFI capsule of Firmware Management Protocol images, of which the
+    /// first is the payload
+    FmpCapsule,
+    IntelCapsule,
+    LenovoCapsule,
+    AmiCapsule,
+    /// BIOS image of an Insyde iFlash executable
+    Insyde,
+    /// Largest entry of a Dell PFS update package
+    DellPfs,
+    /// Largest file of a zip archive
+    Zip,
+}
+
+impl fmt::Display for Wrapper {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::UefiCapsule => "UEFI capsule",
+            Self::FmpCapsule => "UEFI FMP capsule",
+            Self::IntelCapsule => "Intel capsule",
+            Self::LenovoCapsule => "Lenovo capsule",
+            Self::AmiCapsule => "AMI Aptio capsule",
+            Self::Insyde => "Insyde iFlash image",
+            Self::DellPfs => "Dell PFS package",
+            Self::Zip => "zip archive",
+        };
+        f.pad(name)
+    }
+}
+
+/// A wrapper peeled off an image
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Layer {
+    pub wrapper: Wrapper,
+    /// Offset of the payload in the wrapper
+    pub offset: usize,
+    /// Size of the payload in bytes
+    pub size: usize,
+    /// Name of the payload in the wrapper, such as the name of a zip file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Layer {
+    fn new(wrapper: Wrapper, offset: usize, size: usize) -> Self {
+        Layer {
+            wrapper,
+            offset,
+            size,
+            name: None,
+        }
+    }
+}
+
+#[repr(C, packed)]
+pub struct CapsuleHeader {
+    pub guid: Guid,
+    pub header_size: u32,
+    pub flags: u32,
+    pub image_size: u32,
+}
+
+unsafe impl Plain for CapsuleHeader {}
+
+/// Peels every wrapper off the image, outermost first, returning the
+/// wrappers and the image they wrapped. Data that is not wrapped is returned
+/// as is. A wrapper that is recognized but cannot be peeled off, such as a
+/// compressed or encrypted one, is an `Error::Unsupported`, as is data of
+/// no known structure that looks encrypted.
+pub fn unwrap(data: &[u8]) -> Result<(Vec<Layer>, &[u8]), Error> {
+    let mut layers: Vec<Layer> = Vec::new();
+    let mut data = data;
+    while layers.len() < MAX_DEPTH {
+        let layer = match peel(data) {
+            Ok(Some(layer)) => layer,
+            Ok(None) => break,
+            Err(err) => return Err(within(&layers, err)),
+        };
+        data = &data[layer.offset..layer.offset + layer.size];
+        layers.push(layer);
+    }
+    if !is_image(data) && entropy(data) > ENCRYPTED_ENTROPY {
+        return Err(within(
+            &layers,
+            Error::Unsupported(String::from("no image found, the data is encrypted")),
+        ));
+    }
+    Ok((layers, data))
+}
+
+/// Error of a wrapper inside `layers`, naming the wrappers it is in
+fn within(layers: &[Layer], err: Error) -> Error {
+    if layers.is_empty() {
+        return err;
+    }
+    let chain: Vec<String> = layers
+        .iter()
+        .rev()
+        .map(|layer| format!("{}", layer.wrapper))
+        .collect();
+    Error::Unsupported(format!("{} in {}", err, chain.join(" in ")))
+}
+
+/// Whether the data holds a flash descriptor or an EFS
+fn is_image(data: &[u8]) -> bool {
+    intel::Rom::new(data).is_ok() || amd::Rom::new(data).is_ok()
+}
+
+/// The outermost wrapper of the data, if it is wrapped. Capsules, PFS
+/// packages and zip archives start at the start of the data, Insyde
+/// executables are searched for the header of the image they embed, and
+/// Dell executables for their PFS package unless the data already is an
+/// image.
+pub fn peel(data: &[u8]) -> Result<Option<Layer>, Error> {
+    if let Ok(header) = plain::from_bytes::<CapsuleHeader>(data) {
+        let guid = header.guid;
+        let wrapper = if guid == EFI_CAPSULE_GUID {
+            Some(Wrapper::UefiCapsule)
+        } else if guid == EFI_FMP_CAPSULE_GUID {
+            Some(Wrapper::FmpCapsule)
+        } else if guid == INTEL_CAPSULE_GUID {
+            Some(Wrapper::IntelCapsule)
+        } else if guid == LENOVO_CAPSULE_GUID || guid == LENOVO2_CAPSULE_GUID {
+            Some(Wrapper::LenovoCapsule)
+        } else if guid == APTIO_SIGNED_CAPSULE_GUID || guid == APTIO_UNSIGNED_CAPSULE_GUID {
+            Some(Wrapper::AmiCapsule)
+        } else {
+            None
+        };
+        if let Some(wrapper) = wrapper {
+            return capsule(data, header, wrapper).map(Some);
+        }
+    }
+    if data.starts_with(PFS_SIGNATURE) {
+        return pfs(data).map(Some);
+    }
+    if data.starts_with(ZIP_SIGNATURE) {
+        return zip(data).map(Some);
+    }
+    if let Some(offset) = find(data, INSYDE_SIGNATURE) {
+        return insyde(data, offset).map(Some);
+    }
+    if is_image(data) {
+        return Ok(None);
+    }
+    if let Some(offset) = find(data, PFS_SIGNATURE) {
+        let mut layer = pfs(&data[offset..])?;
+        layer.offset += offset;
+        return Ok(Some(layer));
+    }
+    if find(data, DELL_ZLIB_MARKER).is_some() {
+        return Err(Error::Unsupported(String::from(
+            "Dell update executable with a zlib compressed PFS package",
+        )));
+    }
+    Ok(None)
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, Error> {
+    let bytes = bytes_at(data, offset, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, Error> {
+    let bytes = bytes_at(data, offset, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn bytes_at(data: &[u8], offset: usize, size: usize) -> Result<&[u8], Error> {
+    offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(Error::Truncated {
+            need: offset.saturating_add(size),
+            have: data.len(),
+        })
+}
+
+/// Range of `size` bytes at `offset` in the data, as a layer
+fn layer(wrapper: Wrapper, data: &[u8], offset: usize, size: usize) -> Result<Layer, Error> {
+    bytes_at(data, offset, size)?;
+    Ok(Layer::new(wrapper, offset, size))
+}
+
+/// Payload of a capsule, following its header up to the end of the capsule.
+/// The image of AMI capsules is at the offset following the header, and
+/// that of FMP capsules at the offset of the first payload item.
+fn capsule(data: &[u8], header: &CapsuleHeader, wrapper: Wrapper) -> Result<Layer, Error> {
+    let end = (header.image_size as usize).min(data.len());
+    let start = match wrapper {
+        Wrapper::AmiCapsule => u16_at(data, mem::size_of::<CapsuleHeader>())? as usize,
+        _ => header.header_size as usize,
+    };
+    if start >= end {
+        return Err(Error::Invalid(format!(
+            "{} payload at {:#X} past its end at {:#X}",
+            wrapper, start, end
+        )));
+    }
+    if wrapper == Wrapper::FmpCapsule {
+        let (offset, size) = fmp_image(&data[start..end])?;
+        return layer(wrapper, data, start + offset, size);
+    }
+    layer(wrapper, data, start, end - start)
+}
+
+/// Offset and size of the first image of an FMP capsule payload, skipping
+/// its image header and its authentication header, if it is signed
+fn fmp_image(data: &[u8]) -> Result<(usize, usize), Error> {
+    let drivers = u16_at(data, 4)? as usize;
+    let items = u16_at(data, 6)? as usize;
+    if items == 0 {
+        return Err(Error::NotFound(String::from("FMP capsule image")));
+    }
+    let item = bytes_at(data, 8 + drivers * 8, 8)?;
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(item);
+    let item = u64::from_le_bytes(bytes) as usize;
+
+    let header_size = match u32_at(data, item)? {
+        1 => 32,
+        2 => 40,
+        3 => 48,
+        version => {
+            return Err(Error::UnsupportedVersion {
+                structure: "FMP image header",
+                version,
+            })
+        }
+    };
+    let mut offset = item + header_size;
+    let mut size = u32_at(data, item + 24)? as usize;
+    // EFI_FIRMWARE_IMAGE_AUTHENTICATION: a monotonic count, then a
+    // WIN_CERTIFICATE_UEFI_GUID of revision 0x0200 and type 0x0EF1
+    if u16_at(data, offset + 12) == Ok(0x0200) && u16_at(data, offset + 14) == Ok(0x0EF1) {
+        let auth = 8 + u32_at(data, offset + 8)? as usize;
+        offset += auth;
+        size = size.saturating_sub(auth);
+    }
+    bytes_at(data, offset, size)?;
+    Ok((offset, size))
+}
+
+/// BIOS image of an Insyde iFlash executable, following the signature, the
+/// total size and the image size
+fn insyde(data: &[u8], offset: usize) -> Result<Layer, Error> {
+    let size = u32_at(data, offset + INSYDE_SIGNATURE.len() + 4)? as usize;
+    layer(
+        Wrapper::Insyde,
+        data,
+        offset + INSYDE_SIGNATURE.len() + 8,
+        size,
+    )
+}
+
+/// Largest entry of a PFS package. Entries follow the header of the
+/// signature, a version and the size of the entries, each an entry header
+/// followed by the data, the data signature, the metadata and the metadata
+/// signature of the sizes it lists.
+fn pfs(data: &[u8]) -> Result<Layer, Error> {
+    let end = (16 + u32_at(data, 12)? as usize).min(data.len());
+    let mut largest: Option<Layer> = None;
+    let mut offset = 16;
+    while offset + 20 <= end {
+        let header_size = match u32_at(data, offset + 16)? {
+            1 => 72,
+            2 => 88,
+            version => {
+                return Err(Error::UnsupportedVersion {
+                    structure: "Dell PFS entry",
+                    version,
+                })
+            }
+        };
+        let sizes = offset + 40;
+        let size = u32_at(data, sizes)? as usize;
+        let rest = u32_at(data, sizes + 4)? as usize
+            + u32_at(data, sizes + 8)? as usize
+            + u32_at(data, sizes + 12)? as usize;
+        let start = offset + header_size;
+        let found = layer(Wrapper::DellPfs, data, start, size)?;
+        if largest.as_ref().is_none_or(|largest| size > largest.size) {
+            largest = Some(found);
+        }
+        offset = start + size + rest;
+    }
+    largest.ok_or_else(|| Error::NotFound(String::from("Dell PFS entry")))
+}
+
+/// Largest file of a zip archive, read from the local file headers. Only
+/// files stored without compression or encryption can be peeled off.
+fn zip(data: &[u8]) -> Result<Layer, Error> {
+    let mut largest: Option<(Layer, u16, u16)> = None;
+    let mut offset = 0;
+    while bytes_at(data, offset, 4).ok() == Some(ZIP_SIGNATURE) {
+        let flags = u16_at(data, offset + 6)?;
+        let method = u16_at(data, offset + 8)?;
+        let size = u32_at(data, offset + 18)? as usize;
+        let name_size = u16_at(data, offset + 26)? as usize;
+        let extra_size = u16_at(data, offset + 28)? as usize;
+        // Sizes follow the data if bit 3 is set, so the next header can not
+        // be found
+        if flags & 1 << 3 != 0 {
+            return Err(Error::Unsupported(String::from(
+                "zip archive with data descriptors",
+            )));
+        }
+        let name = bytes_at(data, offset + 30, name_size)?;
+        let start = offset + 30 + name_size + extra_size;
+        let mut found = layer(Wrapper::Zip, data, start, size)?;
+        found.name = Some(name.iter().map(|b| *b as char).collect());
+        if largest
+            .as_ref()
+            .is_none_or(|(largest, _, _)| size > largest.size)
+        {
+            largest = Some((found, flags, method));
+        }
+        offset = start + size;
+    }
+    let (found, flags, method) =
+        largest.ok_or_else(|| Error::NotFound(String::from("zip file")))?;
+    let name = found.name.clone().unwrap_or_default();
+    if flags & 1 != 0 {
+        Err(Error::Unsupported(format!("zip file {} encrypted", name)))
+    } else if method != 0 {
+        Err(Error::Unsupported(format!(
+            "zip file {} compressed with method {}",
+            name, method
+        )))
+    } else {
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::AmdFixture;
+
+    fn image() -> Vec<u8> {
+        AmdFixture {
+            size: 0x20_0000,
+            ..AmdFixture::default()
+        }
+        .build()
+    }
+
+    /// Capsule of the GUID, its payload following a header of `header_size`
+    /// bytes
+    fn capsule(guid: Guid, header_size: usize, payload: &[u8]) -> Vec<u8> {
+        let mut capsule = guid.0.to_le_bytes().to_vec();
+        capsule.extend_from_slice(&guid.1.to_le_bytes());
+        capsule.extend_from_slice(&guid.2.to_le_bytes());
+        capsule.extend_from_slice(&guid.3);
+        capsule.extend_from_slice(&(header_size as u32).to_le_bytes());
+        capsule.extend_from_slice(&0u32.to_le_bytes());
+        capsule.extend_from_slice(&((header_size + payload.len()) as u32).to_le_bytes());
+        capsule.resize(header_size, 0);
+        capsule.extend_from_slice(payload);
+        capsule
+    }
+
+    /// Zip archive of one file, compressed with `method` as far as its
+    /// header tells
+    fn zip(name: &str, method: u16, data: &[u8]) -> Vec<u8> {
+        let mut zip = ZIP_SIGNATURE.to_vec();
+        zip.extend_from_slice(&[20, 0, 0, 0]);
+        zip.extend_from_slice(&method.to_le_bytes());
+        zip.extend_from_slice(&[0; 8]);
+        zip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&0u16.to_le_bytes());
+        zip.extend_from_slice(name.as_bytes());
+        zip.extend_from_slice(data);
+        zip
+    }
+
+    #[test]
+    fn images_are_not_unwrapped() {
+        let image = image();
+        let (layers, data) = unwrap(&image).unwrap();
+        assert!(layers.is_empty());
+        assert_eq!(data.len(), image.len());
+    }
+
+    #[test]
+    fn nested_wrappers_are_peeled_off() {
+        let image = image();
+        let uefi = capsule(EFI_CAPSULE_GUID, 0x20, &image);
+        let (layers, data) = unwrap(&uefi).unwrap();
+        assert_eq!(
+            layers,
+            [Layer::new(Wrapper::UefiCapsule, 0x20, image.len())]
+        );
+        assert!(data == &image[..]);
+
+        // AMI capsules give the offset of the image after the header
+        let mut ami = capsule(APTIO_UNSIGNED_CAPSULE_GUID, 0x40, &image);
+        ami[28..30].copy_from_slice(&0x40u16.to_le_bytes());
+        let archive = zip("BIOS.ROM", 0, &ami);
+        let (layers, data) = unwrap(&archive).unwrap();
+        let wrappers: Vec<Wrapper> = layers.iter().map(|layer| layer.wrapper).collect();
+        assert_eq!(wrappers, [Wrapper::Zip, Wrapper::AmiCapsule]);
+        assert_eq!(layers[0].name.as_deref(), Some("BIOS.ROM"));
+        assert_eq!(layers[1].offset, 0x40);
+        assert!(data == &image[..]);
+
+        let mut insyde = vec![b'M', b'Z'];
+        insyde.resize(0x400, 0);
+        insyde.extend_from_slice(INSYDE_SIGNATURE);
+        insyde.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        insyde.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        insyde.extend_from_slice(&image);
+        let (layers, data) = unwrap(&insyde).unwrap();
+        assert_eq!(layers, [Layer::new(Wrapper::Insyde, 0x418, image.len())]);
+        assert!(data == &image[..]);
+    }
+
+    #[test]
+    fn first_image_of_fmp_capsules_is_peeled_off() {
+        let image = image();
+        // No drivers and one item, whose image header of version 2 gives the
+        // size of the image
+        let mut payload = [1u32.to_le_bytes(), [0, 0, 1, 0]].concat();
+        payload.extend_from_slice(&16u64.to_le_bytes());
+        let mut header = [0; 40];
+        header[0..4].copy_from_slice(&2u32.to_le_bytes());
+        header[24..28].copy_from_slice(&(image.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&header);
+        payload.extend_from_slice(&image);
+        let fmp = capsule(EFI_FMP_CAPSULE_GUID, 0x20, &payload);
+        let (layers, data) = unwrap(&fmp).unwrap();
+        assert_eq!(
+            layers,
+            [Layer::new(Wrapper::FmpCapsule, 0x20 + 56, image.len())]
+        );
+        assert!(data == &image[..]);
+    }
+
+    #[test]
+    fn largest_pfs_entry_is_peeled_off() {
+        let image = image();
+        let mut pfs = PFS_SIGNATURE.to_vec();
+        pfs.extend_from_slice(&1u32.to_le_bytes());
+        pfs.extend_from_slice(&0u32.to_le_bytes());
+        for data in [&b"version 1.2.3"[..], &image] {
+            let mut header = [0; 72];
+            header[16..20].copy_from_slice(&1u32.to_le_bytes());
+            header[40..44].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            pfs.extend_from_slice(&header);
+            pfs.extend_from_slice(data);
+        }
+        let size = (pfs.len() - 16) as u32;
+        pfs[12..16].copy_from_slice(&size.to_le_bytes());
+        let offset = 16 + 72 + 13 + 72;
+
+        let (layers, data) = unwrap(&pfs).unwrap();
+        assert_eq!(layers, [Layer::new(Wrapper::DellPfs, offset, image.len())]);
+        assert!(data == &image[..]);
+
+        // PFS packages are searched for in executables, unless they hold
+        // an image as is
+        let mut executable = b"MZ".to_vec();
+        executable.resize(0x1000, 0);
+        executable.extend_from_slice(&pfs);
+        assert!(unwrap(&executable).unwrap().0.is_empty());
+        executable.truncate(0x1000 + 16 + 72 + 13);
+        let (layers, data) = unwrap(&executable).unwrap();
+        assert_eq!(layers, [Layer::new(Wrapper::DellPfs, 0x1000 + 16 + 72, 13)]);
+        assert_eq!(data, b"version 1.2.3");
+    }
+
+    #[test]
+    fn wrappers_that_cannot_be_peeled_off_are_unsupported() {
+        let image = image();
+        let archive = zip("BIOS.ROM", 8, &image);
+        let err = unwrap(&archive).unwrap_err();
+        assert_eq!(
+            err,
+            Error::Unsupported(String::from("zip file BIOS.ROM compressed with method 8"))
+        );
+
+        // The error names the wrappers it is in
+        let uefi = capsule(EFI_CAPSULE_GUID, 0x20, &archive);
+        match unwrap(&uefi) {
+            Err(Error::Unsupported(message)) => assert!(message.ends_with(" in UEFI capsule")),
+            other => panic!("{:?}", other),
+        }
+
+        let encrypted: Vec<u8> = (0..0x10000).map(|i| (i * 167) as u8).collect();
+        assert!(matches!(unwrap(&encrypted), Err(Error::Unsupported(_))));
+        let executable = [&b"MZ"[..], DELL_ZLIB_MARKER].concat();
+        assert!(matches!(unwrap(&executable), Err(Error::Unsupported(_))));
+    }
+}